@@ -0,0 +1,145 @@
+//! Lists of accounts to check in one run
+
+use super::creds::docker_credentials;
+use super::err::{DrlErr, DrlResult, ExitCode};
+use futures::future::join_all;
+use std::env;
+use std::future::Future;
+use std::io::BufRead;
+use std::time::Duration;
+use tokio::sync::{Mutex, Semaphore};
+use tokio::time::{self, Instant};
+
+/// Reads one username per line from `reader`
+///
+/// Blank lines and lines starting with `#` are skipped, and surrounding whitespace is trimmed
+///
+/// # Arguments
+///
+/// * `reader` - source of the usernames, usually stdin
+pub fn read_users<R: BufRead>(reader: R) -> DrlResult<Vec<String>> {
+    let mut users = Vec::new();
+
+    for line in reader.lines() {
+        let line = match line {
+            Ok(l) => l,
+            Err(e) => {
+                let msg = format!("failed to read users: {}", e);
+                let err = DrlErr::new(msg, ExitCode::Input);
+                return Err(err);
+            }
+        };
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        users.push(line.to_string());
+    }
+
+    Ok(users)
+}
+
+/// Name of the environment variable holding the password for `user`
+///
+/// The username is upper cased and anything that can't be part of a variable name is replaced
+/// with `_`, so `ci-bot` with prefix `HUB_PASS_` becomes `HUB_PASS_CI_BOT`
+///
+/// # Arguments
+///
+/// * `prefix` - prefix of the variable name
+/// * `user` - username to look up
+pub fn password_var(prefix: &str, user: &str) -> String {
+    let suffix: String = user
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    format!("{}{}", prefix, suffix)
+}
+
+/// Looks up the password for `user` from the environment
+///
+/// Without the variable, the credentials of `docker login` are used if they are for `user`,
+/// see `creds::docker_credentials`
+///
+/// # Errors
+///
+/// Returns `ExitCode::Input` if neither has the password
+///
+/// # Arguments
+///
+/// * `prefix` - prefix of the variable name, see `password_var`
+/// * `user` - username to look up
+pub fn env_password(prefix: &str, user: &str) -> DrlResult<String> {
+    let var = password_var(prefix, user);
+    if let Ok(pass) = env::var(&var) {
+        return Ok(pass);
+    }
+
+    // docker login keeps a single Docker Hub account, which may be this one
+    let msg = match docker_credentials() {
+        Ok(Some(creds)) if creds.user == user => return Ok(creds.pass),
+        Ok(_) => format!(
+            "no password for {} in {} or the docker credentials",
+            user, var
+        ),
+        Err(e) => format!("no password for {} in {}, and {}", user, var, e.msg),
+    };
+    let err = DrlErr::new(msg, ExitCode::Input);
+    Err(err)
+}
+
+/// Runs `check` for every item, with at most `concurrency` running at once
+///
+/// Results are returned in the order of `items`
+///
+/// # Arguments
+///
+/// * `items` - inputs to `check`, usually usernames
+/// * `concurrency` - maximum number of checks in flight, at least 1
+/// * `stagger` - minimum delay between starting two checks
+/// * `check` - the check to run for each item
+pub async fn run_bounded<I, F, Fut>(
+    items: I,
+    concurrency: usize,
+    stagger: Duration,
+    check: F,
+) -> Vec<Fut::Output>
+where
+    I: IntoIterator,
+    F: Fn(I::Item) -> Fut,
+    Fut: Future,
+{
+    let permits = Semaphore::new(concurrency.max(1));
+    let next_launch = Mutex::new(Instant::now());
+
+    let checks = items.into_iter().map(|item| {
+        let permits = &permits;
+        let next_launch = &next_launch;
+        let check = &check;
+
+        async move {
+            // the semaphore is never closed
+            let _permit = permits.acquire().await.expect("semaphore closed");
+
+            // holding the lock while sleeping keeps launches apart
+            {
+                let mut next = next_launch.lock().await;
+                time::sleep_until(*next).await;
+                *next = Instant::now() + stagger;
+            }
+
+            check(item).await
+        }
+    });
+
+    join_all(checks).await
+}
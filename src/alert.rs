@@ -0,0 +1,161 @@
+//! Alerts for `--alert-below`, sent to a webhook and/or a command once the limit runs low
+//!
+//! The webhook gets a JSON object with a `text` field, which Slack and ntfy show as it is, along
+//! with the limit. The command runs through the shell with the same in `ALERT_VARS`
+
+use super::api::DrlClient;
+use super::err::{DrlErr, DrlResult, ExitCode};
+use super::limit::Limit;
+use super::threshold::Threshold;
+use reqwest::Url;
+use serde::Serialize;
+use tokio::process::Command;
+
+/// Environment variables the command gets: user, remaining, total, window, source and text
+pub const ALERT_VARS: &[&str] = &[
+    "DOCKER_RL_ALERT_USER",
+    "DOCKER_RL_ALERT_REMAINING",
+    "DOCKER_RL_ALERT_TOTAL",
+    "DOCKER_RL_ALERT_WINDOW_SECONDS",
+    "DOCKER_RL_ALERT_SOURCE",
+    "DOCKER_RL_ALERT_TEXT",
+];
+
+/// A limit that went below the alert threshold
+#[derive(Serialize, Debug, Clone)]
+pub struct Alert {
+    /// What happened, e.g. `docker-rl: ci-bot has 3/100 pulls left, 3 remaining is below 10`
+    pub text: String,
+    /// User the limit was checked for, `None` for anonymous
+    pub user: Option<String>,
+    /// The limit
+    #[serde(flatten)]
+    pub limit: Limit,
+}
+
+impl Alert {
+    /// Alert for `limit` if it's below `threshold`, `None` if it isn't
+    ///
+    /// # Arguments
+    ///
+    /// * `threshold` - `Threshold` to alert below
+    /// * `user` - user the limit was checked for, `None` for anonymous
+    /// * `limit` - `Limit` to check
+    pub fn below(threshold: &Threshold, user: Option<&str>, limit: &Limit) -> Option<Alert> {
+        let reason = threshold.check(limit).err()?;
+        let text = format!(
+            "docker-rl: {} has {} pulls left, {}",
+            user.unwrap_or("anonymous"),
+            limit,
+            reason.msg
+        );
+        Some(Alert {
+            text,
+            user: user.map(String::from),
+            limit: limit.clone(),
+        })
+    }
+
+    /// Values of `ALERT_VARS`, in the same order
+    pub fn vars(&self) -> Vec<(&'static str, String)> {
+        let values = vec![
+            self.user.clone().unwrap_or_default(),
+            self.limit.remaining.to_string(),
+            self.limit.total.to_string(),
+            self.limit.window.as_secs().to_string(),
+            self.limit.source.clone().unwrap_or_default(),
+            self.text.clone(),
+        ];
+        ALERT_VARS.iter().copied().zip(values).collect()
+    }
+}
+
+/// Where `Alert`s go
+#[derive(Debug, Clone, Default)]
+pub struct Alerter {
+    /// Alert when the limit goes below this
+    pub threshold: Threshold,
+    /// URL to post the alert to as JSON
+    pub webhook: Option<Url>,
+    /// Shell command to run with `ALERT_VARS` set
+    pub exec: Option<String>,
+}
+
+impl Alerter {
+    /// Sends `alert` to the webhook and runs the command, whichever are set
+    ///
+    /// Both are tried even if the first fails
+    ///
+    /// # Errors
+    ///
+    /// Returns `ExitCode::Connection` if the webhook can't be reached or rejects the alert, and
+    /// `ExitCode::Input` if the command can't be run or fails
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - `DrlClient` to post with
+    /// * `alert` - `Alert` to send
+    pub async fn send(&self, client: &DrlClient, alert: &Alert) -> DrlResult<()> {
+        let posted = match &self.webhook {
+            Some(url) => post(client, url, alert).await,
+            None => Ok(()),
+        };
+        let ran = match &self.exec {
+            Some(cmd) => run(cmd, alert).await,
+            None => Ok(()),
+        };
+        posted.and(ran)
+    }
+}
+
+/// Posts `alert` as JSON to `url` with `client`
+async fn post(client: &DrlClient, url: &Url, alert: &Alert) -> DrlResult<()> {
+    let http = client.http();
+    let resp = match http.client.post(url.as_str()).json(alert).send().await {
+        Ok(r) => r,
+        Err(e) => return Err(http.connect_error("the webhook", e)),
+    };
+
+    if !resp.status().is_success() {
+        let msg = format!("webhook rejected the alert: {}", resp.status());
+        let err = DrlErr::new(msg, ExitCode::Connection);
+        return Err(err);
+    }
+
+    Ok(())
+}
+
+/// Runs `cmd` through the shell with `ALERT_VARS` set for `alert`
+async fn run(cmd: &str, alert: &Alert) -> DrlResult<()> {
+    let mut command = shell(cmd);
+    command.envs(alert.vars());
+
+    match command.status().await {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => {
+            let msg = format!("alert command failed: {}", status);
+            let err = DrlErr::new(msg, ExitCode::Input);
+            Err(err)
+        }
+        Err(e) => {
+            let msg = format!("failed to run alert command: {}", e);
+            let err = DrlErr::new(msg, ExitCode::Input);
+            Err(err)
+        }
+    }
+}
+
+/// `Command` running `cmd` through the platform's shell
+#[cfg(unix)]
+pub(crate) fn shell(cmd: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(cmd);
+    command
+}
+
+#[cfg(not(unix))]
+pub(crate) fn shell(cmd: &str) -> Command {
+    let mut command = Command::new("cmd");
+    command.arg("/C").arg(cmd);
+    command
+}
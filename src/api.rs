@@ -0,0 +1,307 @@
+//! `DrlClient`, the entry point for using `docker-rl` as a library
+//!
+//! The free functions in `token` and `limit` check Docker Hub with reqwest's defaults, and
+//! create a new `Client` for every call. A `DrlClient` carries its own settings and reuses one
+//! `Client`, and with it the connections, for everything it sends
+//!
+//! Everything it sends goes to its `Registry`, so pointing one at a local mock server tests the
+//! whole check offline:
+//!
+//! ```no_run
+//! # async fn check() -> libdocker_rl::err::DrlResult<()> {
+//! use libdocker_rl::api::DrlClient;
+//! use libdocker_rl::registry::Registry;
+//! use reqwest::Url;
+//!
+//! let url = Url::parse("http://127.0.0.1:8080").unwrap();
+//! let realm = url.join("/token").unwrap();
+//! let registry = Registry::new(url).with_realm(realm, Some("mock"));
+//! let client = DrlClient::builder().registry(registry).build()?;
+//!
+//! let token = client.token(None).await?;
+//! let limit = client.limit(&token).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use super::cacert::CaBundle;
+use super::client::{BasicAuth, ClientConfig, Http};
+use super::err::{DrlErr, DrlResult, ExitCode};
+use super::hub::HUB_URL;
+use super::identity::ClientIdentity;
+use super::image::{fetch_image, ImageCheck};
+use super::limit::{fetch_limit, fetch_limit_peek, Limit, Probe};
+use super::registry::Registry;
+use super::retry::RetryPolicy;
+use super::token::{
+    fetch_anon_token, fetch_userpass_token, validate_access_token, Scope, Token, TokenProvider,
+};
+use reqwest::{Client, Method, Url};
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Settings for a `DrlClient`, see `DrlClient::builder`
+#[derive(Debug, Clone, Default)]
+pub struct DrlClientBuilder {
+    pub(crate) config: ClientConfig,
+    user_agent: Option<String>,
+    pub(crate) registry: Registry,
+    scope: Scope,
+    hub_url: Option<String>,
+}
+
+impl DrlClientBuilder {
+    /// Takes the proxy, client certificate, timeouts and retries from `config`
+    pub fn config(mut self, config: ClientConfig) -> DrlClientBuilder {
+        self.config = config;
+        self
+    }
+
+    /// Sends every request through `proxy`, instead of the one from the environment
+    pub fn proxy(mut self, proxy: Url) -> DrlClientBuilder {
+        self.config.proxy = Some(proxy);
+        self
+    }
+
+    /// Sends basic credentials to the proxy
+    pub fn proxy_auth(mut self, auth: BasicAuth) -> DrlClientBuilder {
+        self.config.proxy_auth = Some(auth);
+        self
+    }
+
+    /// Offers a client certificate to every server
+    pub fn identity(mut self, identity: ClientIdentity) -> DrlClientBuilder {
+        self.config.identity = Some(identity);
+        self
+    }
+
+    /// Trusts the CA certificates of `bundle` on top of the system's
+    pub fn ca_bundle(mut self, bundle: CaBundle) -> DrlClientBuilder {
+        self.config.ca_bundle = Some(bundle);
+        self
+    }
+
+    /// Accepts any server certificate, for testing only
+    pub fn insecure(mut self, insecure: bool) -> DrlClientBuilder {
+        self.config.insecure = insecure;
+        self
+    }
+
+    /// Gives up on a request after `timeout`, from connecting to the end of the body
+    pub fn timeout(mut self, timeout: Duration) -> DrlClientBuilder {
+        self.config.timeout = Some(timeout);
+        self
+    }
+
+    /// Gives up on connecting after `timeout`
+    pub fn connect_timeout(mut self, timeout: Duration) -> DrlClientBuilder {
+        self.config.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Connects from `addr`, and only to servers of its IP version
+    ///
+    /// `IpStack::unspecified` keeps to one IP version from any local address
+    pub fn local_address(mut self, addr: IpAddr) -> DrlClientBuilder {
+        self.config.local_address = Some(addr);
+        self
+    }
+
+    /// Sends `user_agent` as the `User-Agent` of every request
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> DrlClientBuilder {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Retries connection errors, timeouts and `5xx` responses as `retry` says
+    pub fn retry(mut self, retry: RetryPolicy) -> DrlClientBuilder {
+        self.config.retry = retry;
+        self
+    }
+
+    /// Checks `registry` instead of Docker Hub
+    ///
+    /// Registries other than Docker Hub have to be discovered first, see `DrlClient::discover`
+    pub fn registry(mut self, registry: Registry) -> DrlClientBuilder {
+        self.registry = registry;
+        self
+    }
+
+    /// Requests tokens with `scope`, pull by default
+    pub fn scope(mut self, scope: Scope) -> DrlClientBuilder {
+        self.scope = scope;
+        self
+    }
+
+    /// Looks up plans on the Docker Hub API at `url` instead of `hub::HUB_URL`
+    pub fn hub_url(mut self, url: Url) -> DrlClientBuilder {
+        self.hub_url = Some(url.as_str().trim_end_matches('/').to_string());
+        self
+    }
+
+    /// Creates the `DrlClient`
+    ///
+    /// # Errors
+    ///
+    /// Returns `ExitCode::Input` if the settings can't be used, e.g. proxy credentials without
+    /// a proxy, or the HTTP client can't be created
+    pub fn build(self) -> DrlResult<DrlClient> {
+        self.config.validate()?;
+
+        let mut builder = self.config.builder();
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent.as_str());
+        }
+
+        Ok(DrlClient {
+            http: Http::new(&self.config, builder)?,
+            config: self.config,
+            registry: self.registry,
+            scope: self.scope,
+            hub_url: self.hub_url.unwrap_or_else(|| String::from(HUB_URL)),
+        })
+    }
+}
+
+/// Checks the rate limit, reusing one `Client` for every request
+#[derive(Debug, Clone)]
+pub struct DrlClient {
+    http: Http,
+    config: ClientConfig,
+    registry: Registry,
+    scope: Scope,
+    hub_url: String,
+}
+
+impl DrlClient {
+    /// Starts a `DrlClient` for Docker Hub, with reqwest's defaults
+    pub fn builder() -> DrlClientBuilder {
+        DrlClientBuilder::default()
+    }
+
+    /// The shared `Client`
+    pub fn client(&self) -> &Client {
+        &self.http.client
+    }
+
+    /// The settings the client was built with
+    pub fn config(&self) -> &ClientConfig {
+        &self.config
+    }
+
+    /// The `Registry` that is checked
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// The `Scope` tokens are requested with
+    pub fn scope(&self) -> &Scope {
+        &self.scope
+    }
+
+    /// Base URL of the Docker Hub API plans are looked up on, without a trailing `/`
+    pub fn hub_url(&self) -> &str {
+        &self.hub_url
+    }
+
+    /// The client along with how its requests are retried, for the other modules
+    pub(crate) fn http(&self) -> &Http {
+        &self.http
+    }
+
+    /// Asks the registry for its token service, see `Registry::discover`
+    ///
+    /// # Errors
+    ///
+    /// Returns `ExitCode::Connection` if the registry can't be reached or its challenge can't
+    /// be parsed
+    pub async fn discover(mut self) -> DrlResult<DrlClient> {
+        self.registry = self.registry.discover(&self.http).await?;
+        Ok(self)
+    }
+
+    /// Gets a token, anonymous unless `creds` are given
+    ///
+    /// # Arguments
+    ///
+    /// * `creds` - user and password, `None` for an anonymous token
+    pub async fn token(&self, creds: Option<(&str, &str)>) -> DrlResult<Token> {
+        match creds {
+            Some((user, pass)) => {
+                fetch_userpass_token(&self.http, &self.registry, user, pass, &self.scope).await
+            }
+            None => fetch_anon_token(&self.http, &self.registry, &self.scope).await,
+        }
+    }
+
+    /// Gets a token with a Docker Hub access token, see `token::get_pat_token`
+    ///
+    /// # Errors
+    ///
+    /// Returns `ExitCode::Input` if `pat` isn't an access token, and `ExitCode::Unauthorized` if
+    /// it was rejected
+    ///
+    /// # Arguments
+    ///
+    /// * `user` - username, or the organization for an organization token
+    /// * `pat` - the access token, `dckr_pat_...` or `dckr_oat_...`
+    pub async fn pat_token(&self, user: &str, pat: &str) -> DrlResult<Token> {
+        if let Err(msg) = validate_access_token(pat) {
+            let err = DrlErr::new(msg, ExitCode::Input);
+            return Err(err);
+        }
+        self.token(Some((user, pat))).await
+    }
+
+    /// Gets the rate limit without using any of it up, see `limit::peek_limit`
+    ///
+    /// # Arguments
+    ///
+    /// * `t` - `Token` from `token`
+    ///
+    /// # Errors
+    ///
+    /// See `limit::get_limit`
+    pub async fn limit(&self, t: &Token) -> DrlResult<Limit> {
+        self.peek(t).await?.limited()
+    }
+
+    /// Gets the rate limit along with the response details around it, see `limit::peek_limit`
+    ///
+    /// # Arguments
+    ///
+    /// * `t` - `Token` from `token`
+    pub async fn peek(&self, t: &Token) -> DrlResult<Probe> {
+        fetch_limit_peek(&self.http, &self.registry, t).await
+    }
+
+    /// Gets the rate limit with `method`, see `limit::probe_limit`
+    ///
+    /// # Arguments
+    ///
+    /// * `t` - `Token` from `token`
+    /// * `method` - `Method` of the manifest request, only `GET` uses up a pull
+    pub async fn probe(&self, t: &Token, method: Method) -> DrlResult<Probe> {
+        fetch_limit(&self.http, &self.registry, t, method).await
+    }
+
+    /// Whether a pull of the image of the registry counts against the limit, see
+    /// `image::get_limit_for`
+    ///
+    /// # Arguments
+    ///
+    /// * `t` - `Token` from `token`, scoped to the repository of the image
+    pub async fn image(&self, t: &Token) -> DrlResult<ImageCheck> {
+        fetch_image(&self.http, &self.registry, t).await
+    }
+
+    /// A `TokenProvider` sharing this client, for checking the limit again and again
+    ///
+    /// # Arguments
+    ///
+    /// * `creds` - user and password, `None` for anonymous tokens
+    pub fn provider(&self, creds: Option<(String, String)>) -> TokenProvider {
+        let (http, registry) = (self.http.clone(), self.registry.clone());
+        TokenProvider::shared(http, registry, creds, self.scope.clone())
+    }
+}
@@ -0,0 +1,207 @@
+//! Polling less often while the checks keep failing, for `--watch` and `--serve`
+//!
+//! A registry that is down would otherwise be asked again every interval, with a warning for
+//! each failure. Once enough checks in a row have failed, the time between them doubles for
+//! every further failure, up to a cap, and is back to the interval after the first success
+
+use super::duration::format_duration;
+use std::fmt;
+use std::time::Duration;
+
+/// Failed checks in a row before polling slows down
+pub const DEFAULT_AFTER: u32 = 3;
+
+/// Longest time between two checks while backing off, unless given
+pub const DEFAULT_MAX: Duration = Duration::from_secs(30 * 60);
+
+/// When and how far to back off
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackoffPolicy {
+    /// Failed checks in a row before the time between them is stretched
+    pub after: u32,
+    /// Longest time between two checks, never less than the interval
+    pub max: Duration,
+}
+
+impl Default for BackoffPolicy {
+    fn default() -> BackoffPolicy {
+        BackoffPolicy {
+            after: DEFAULT_AFTER,
+            max: DEFAULT_MAX,
+        }
+    }
+}
+
+/// How far polling has backed off, from the checks made so far
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    policy: BackoffPolicy,
+    interval: Duration,
+    failures: u32,
+}
+
+impl Backoff {
+    /// Starts without any failures, polling every `interval`
+    ///
+    /// # Arguments
+    ///
+    /// * `interval` - time between checks while they succeed
+    /// * `policy` - `BackoffPolicy` for when they fail
+    pub fn new(interval: Duration, policy: BackoffPolicy) -> Backoff {
+        Backoff {
+            policy,
+            interval,
+            failures: 0,
+        }
+    }
+
+    /// Counts a check, returning the time until the next one
+    ///
+    /// # Arguments
+    ///
+    /// * `failed` - whether the check failed
+    pub fn record(&mut self, failed: bool) -> Duration {
+        self.failures = if failed {
+            self.failures.saturating_add(1)
+        } else {
+            0
+        };
+        self.delay()
+    }
+
+    /// Failed checks in a row, 0 after a success
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Time until the next check
+    ///
+    /// The interval doubles with every failure from `BackoffPolicy::after` on, up to
+    /// `BackoffPolicy::max`
+    pub fn delay(&self) -> Duration {
+        if self.failures < self.policy.after.max(1) {
+            return self.interval;
+        }
+
+        let doublings = self.failures - self.policy.after.max(1) + 1;
+        let factor = 2u32.saturating_pow(doublings);
+        let max = self.policy.max.max(self.interval);
+        self.interval.saturating_mul(factor).min(max)
+    }
+
+    /// Whether the next check is later than the interval
+    pub fn backing_off(&self) -> bool {
+        self.delay() > self.interval
+    }
+}
+
+impl fmt::Display for Backoff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let next = format_duration(self.delay());
+        match self.failures {
+            0 => write!(f, "next check in {}", next),
+            1 => write!(f, "1 failed check, next in {}", next),
+            n if self.backing_off() => {
+                write!(
+                    f,
+                    "{} failed checks in a row, backing off, next in {}",
+                    n, next
+                )
+            }
+            n => write!(f, "{} failed checks in a row, next in {}", n, next),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn backoff() -> Backoff {
+        let policy = BackoffPolicy {
+            after: 3,
+            max: 10 * MINUTE,
+        };
+        Backoff::new(MINUTE, policy)
+    }
+
+    #[test]
+    fn keeps_to_the_interval_until_enough_failures() {
+        let mut b = backoff();
+        assert_eq!(b.delay(), MINUTE);
+        assert_eq!(b.record(true), MINUTE);
+        assert_eq!(b.record(true), MINUTE);
+        assert_eq!(b.failures(), 2);
+        assert!(!b.backing_off());
+    }
+
+    #[test]
+    fn doubles_up_to_the_cap() {
+        let mut b = backoff();
+        let delays: Vec<Duration> = (0..7).map(|_| b.record(true)).collect();
+        let minutes: Vec<u64> = delays.iter().map(|d| d.as_secs() / 60).collect();
+        assert_eq!(minutes, [1, 1, 2, 4, 8, 10, 10]);
+        assert!(b.backing_off());
+        assert_eq!(b.failures(), 7);
+    }
+
+    #[test]
+    fn snaps_back_after_a_success() {
+        let mut b = backoff();
+        for _ in 0..5 {
+            b.record(true);
+        }
+        assert_eq!(b.record(false), MINUTE);
+        assert_eq!(b.failures(), 0);
+        assert!(!b.backing_off());
+
+        // the count starts over, it doesn't pick up where it left off
+        assert_eq!(b.record(true), MINUTE);
+    }
+
+    #[test]
+    fn never_overflows() {
+        let mut b = Backoff::new(
+            MINUTE,
+            BackoffPolicy {
+                after: 1,
+                max: Duration::MAX,
+            },
+        );
+        for _ in 0..100 {
+            b.record(true);
+        }
+        let longest = b.delay();
+        assert!(longest > MINUTE * 1_000_000);
+        assert_eq!(b.record(true), longest);
+    }
+
+    #[test]
+    fn cap_below_the_interval_keeps_the_interval() {
+        let mut b = Backoff::new(
+            MINUTE,
+            BackoffPolicy {
+                after: 1,
+                max: MINUTE / 2,
+            },
+        );
+        assert_eq!(b.record(true), MINUTE);
+        assert!(!b.backing_off());
+    }
+
+    #[test]
+    fn describes_the_state() {
+        let mut b = backoff();
+        assert_eq!(b.to_string(), "next check in 1m");
+        b.record(true);
+        assert_eq!(b.to_string(), "1 failed check, next in 1m");
+        b.record(true);
+        b.record(true);
+        assert_eq!(
+            b.to_string(),
+            "3 failed checks in a row, backing off, next in 2m"
+        );
+    }
+}
@@ -0,0 +1,279 @@
+//! Blocking versions of the `token`, `limit` and `api` functions, for callers without an async
+//! runtime
+//!
+//! This is not `reqwest::blocking`: every call runs the async version to completion on a
+//! tokio runtime, so the two can't drift apart, and tokio is a dependency with or without the
+//! feature. The calls share one multi-thread runtime with a single worker thread, named
+//! `docker-rl-blocking`, built on the first call and kept until the process exits. That costs
+//! the thread and the connections it keeps open, but spares each call from building a runtime
+//! and a connection pool of its own. Calls from several threads run side by side.
+//!
+//! Blocking on a runtime from within another stalls or panics, so inside any tokio runtime,
+//! in an async task, under `Runtime::block_on` or in `spawn_blocking` alike, every call
+//! returns `ExitCode::Input` without doing anything. Use the async functions there, or call
+//! from a thread of your own
+//!
+//! Only built with the `blocking` feature
+
+use super::api;
+use super::err::{DrlErr, DrlResult, ExitCode};
+use super::limit::{self, Limit, Probe};
+use super::registry::Registry;
+use super::token::{self, Scope, Token};
+use reqwest::{Client, Method};
+use std::future::Future;
+use std::sync::OnceLock;
+use tokio::runtime::{self, Handle, Runtime};
+
+/// The runtime every call blocks on, or why it couldn't be built
+static RUNTIME: OnceLock<Result<Runtime, String>> = OnceLock::new();
+
+/// The shared runtime, built on the first call
+///
+/// # Errors
+///
+/// Returns `ExitCode::Input` if it's called from within an async runtime, where blocking on
+/// another would stall it, or if the runtime can't be built
+fn runtime() -> DrlResult<&'static Runtime> {
+    if Handle::try_current().is_ok() {
+        let msg = "blocking functions can't be called from within an async runtime, use the \
+                   async ones instead";
+        return Err(DrlErr::new(msg.to_string(), ExitCode::Input));
+    }
+
+    let built = RUNTIME.get_or_init(|| {
+        runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("docker-rl-blocking")
+            .enable_all()
+            .build()
+            .map_err(|e| e.to_string())
+    });
+    match built {
+        Ok(rt) => Ok(rt),
+        Err(e) => {
+            let msg = format!("failed to create runtime: {}", e);
+            let err = DrlErr::new(msg, ExitCode::Input);
+            Err(err)
+        }
+    }
+}
+
+/// Runs `future` to completion on the shared runtime
+fn block_on<T>(future: impl Future<Output = DrlResult<T>>) -> DrlResult<T> {
+    runtime()?.block_on(future)
+}
+
+/// Get anonymous token from `docker.io`, see `token::get_anon_token`
+///
+/// # Errors
+///
+/// Those of `token::get_anon_token`, and `ExitCode::Input` inside an async runtime
+pub fn get_anon_token() -> DrlResult<Token> {
+    block_on(token::get_anon_token())
+}
+
+/// Get anonymous token from `docker.io` for `scope`, see `token::get_anon_token_scoped`
+///
+/// # Errors
+///
+/// Those of `token::get_anon_token_scoped`, and `ExitCode::Input` inside an async runtime
+///
+/// # Arguments
+///
+/// * `scope` - `Scope` to request
+pub fn get_anon_token_scoped(scope: &Scope) -> DrlResult<Token> {
+    block_on(token::get_anon_token_scoped(scope))
+}
+
+/// Get token from `docker.io` with user/pass, see `token::get_userpass_token`
+///
+/// # Errors
+///
+/// Those of `token::get_userpass_token`, and `ExitCode::Input` inside an async runtime
+///
+/// # Arguments
+///
+/// * `user` - `String` with username
+/// * `pass` - `String` with passphrase
+pub fn get_userpass_token(user: String, pass: String) -> DrlResult<Token> {
+    block_on(token::get_userpass_token(user, pass))
+}
+
+/// Get token from `docker.io` with user/pass for `scope`, see
+/// `token::get_userpass_token_scoped`
+///
+/// # Errors
+///
+/// Those of `token::get_userpass_token_scoped`, and `ExitCode::Input` inside an async runtime
+///
+/// # Arguments
+///
+/// * `user` - `String` with username
+/// * `pass` - `String` with passphrase
+/// * `scope` - `Scope` to request
+pub fn get_userpass_token_scoped(user: String, pass: String, scope: &Scope) -> DrlResult<Token> {
+    block_on(token::get_userpass_token_scoped(user, pass, scope))
+}
+
+/// Get token from `docker.io` with an access token, see `token::get_pat_token`
+///
+/// # Errors
+///
+/// Those of `token::get_pat_token`, and `ExitCode::Input` inside an async runtime
+///
+/// # Arguments
+///
+/// * `user` - `String` with username, or the organization for an organization token
+/// * `pat` - `String` with the access token
+pub fn get_pat_token(user: String, pat: String) -> DrlResult<Token> {
+    block_on(token::get_pat_token(user, pat))
+}
+
+/// Get token from `docker.io` with an access token for `scope`, see
+/// `token::get_pat_token_scoped`
+///
+/// # Errors
+///
+/// Those of `token::get_pat_token_scoped`, and `ExitCode::Input` inside an async runtime
+///
+/// # Arguments
+///
+/// * `user` - `String` with username, or the organization for an organization token
+/// * `pat` - `String` with the access token
+/// * `scope` - `Scope` to request
+pub fn get_pat_token_scoped(user: String, pat: String, scope: &Scope) -> DrlResult<Token> {
+    block_on(token::get_pat_token_scoped(user, pat, scope))
+}
+
+/// Gets rate limit from `docker.io`, see `limit::get_limit`
+///
+/// # Errors
+///
+/// Those of `limit::get_limit`, and `ExitCode::Input` inside an async runtime
+///
+/// # Arguments
+///
+/// * `t` - `Token` JWT token from `docker.io`
+pub fn get_limit(t: &Token) -> DrlResult<Limit> {
+    block_on(limit::get_limit(t))
+}
+
+/// Gets rate limit from `docker.io` without using any of it up, see `limit::peek_limit`
+///
+/// # Errors
+///
+/// Those of `limit::peek_limit`, and `ExitCode::Input` inside an async runtime
+///
+/// # Arguments
+///
+/// * `t` - `Token` JWT token from `docker.io`
+pub fn peek_limit(t: &Token) -> DrlResult<Probe> {
+    block_on(limit::peek_limit(t))
+}
+
+/// Gets rate limit from `docker.io` with `method`, see `limit::probe_limit`
+///
+/// # Errors
+///
+/// Those of `limit::probe_limit`, and `ExitCode::Input` inside an async runtime
+///
+/// # Arguments
+///
+/// * `t` - `Token` JWT token from `docker.io`
+/// * `method` - `Method` of the manifest request
+pub fn probe_limit(t: &Token, method: Method) -> DrlResult<Probe> {
+    block_on(limit::probe_limit(t, method))
+}
+
+/// Blocking `api::DrlClient`, on the shared runtime so the connections are reused
+pub struct DrlClient {
+    inner: api::DrlClient,
+}
+
+impl DrlClient {
+    /// Wraps `client`, built with `api::DrlClient::builder`
+    ///
+    /// # Errors
+    ///
+    /// Returns `ExitCode::Input` if called from within an async runtime, or if the runtime
+    /// can't be created
+    pub fn new(client: api::DrlClient) -> DrlResult<DrlClient> {
+        runtime()?;
+        Ok(DrlClient { inner: client })
+    }
+
+    /// The shared `Client`
+    pub fn client(&self) -> &Client {
+        self.inner.client()
+    }
+
+    /// The `Registry` that is checked
+    pub fn registry(&self) -> &Registry {
+        self.inner.registry()
+    }
+
+    /// Asks the registry for its token service, see `api::DrlClient::discover`
+    ///
+    /// # Errors
+    ///
+    /// Those of `api::DrlClient::discover`, and `ExitCode::Input` inside an async runtime
+    pub fn discover(self) -> DrlResult<DrlClient> {
+        let inner = block_on(self.inner.discover())?;
+        Ok(DrlClient { inner })
+    }
+
+    /// Gets a token, anonymous unless `creds` are given, see `api::DrlClient::token`
+    ///
+    /// # Errors
+    ///
+    /// Those of `api::DrlClient::token`, and `ExitCode::Input` inside an async runtime
+    ///
+    /// # Arguments
+    ///
+    /// * `creds` - user and password, `None` for an anonymous token
+    pub fn token(&self, creds: Option<(&str, &str)>) -> DrlResult<Token> {
+        block_on(self.inner.token(creds))
+    }
+
+    /// Gets the rate limit without using any of it up, see `api::DrlClient::limit`
+    ///
+    /// # Errors
+    ///
+    /// Those of `api::DrlClient::limit`, and `ExitCode::Input` inside an async runtime
+    ///
+    /// # Arguments
+    ///
+    /// * `t` - `Token` from `token`
+    pub fn limit(&self, t: &Token) -> DrlResult<Limit> {
+        block_on(self.inner.limit(t))
+    }
+
+    /// Gets the rate limit along with the response details around it, see
+    /// `api::DrlClient::peek`
+    ///
+    /// # Errors
+    ///
+    /// Those of `api::DrlClient::peek`, and `ExitCode::Input` inside an async runtime
+    ///
+    /// # Arguments
+    ///
+    /// * `t` - `Token` from `token`
+    pub fn peek(&self, t: &Token) -> DrlResult<Probe> {
+        block_on(self.inner.peek(t))
+    }
+
+    /// Gets the rate limit with `method`, see `api::DrlClient::probe`
+    ///
+    /// # Errors
+    ///
+    /// Those of `api::DrlClient::probe`, and `ExitCode::Input` inside an async runtime
+    ///
+    /// # Arguments
+    ///
+    /// * `t` - `Token` from `token`
+    /// * `method` - `Method` of the manifest request, only `GET` uses up a pull
+    pub fn probe(&self, t: &Token, method: Method) -> DrlResult<Probe> {
+        block_on(self.inner.probe(t, method))
+    }
+}
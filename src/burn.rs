@@ -0,0 +1,132 @@
+//! Estimate of when the limit runs out at the current burn rate, for `-v`
+//!
+//! The burn rate is worked out from the samples `state` keeps of earlier checks in the window.
+//! Pulls older than the window are given back as it slides, so a rate that never uses up the
+//! whole limit within one window never runs out
+
+use super::duration::format_age;
+use super::human;
+use super::limit::Limit;
+use super::state::{Sample, State};
+use std::fmt;
+use std::time::Duration;
+
+/// Least time between the first and the last sample for a burn rate
+const MIN_SPAN: Duration = Duration::from_secs(60);
+
+/// How fast the limit was used up between the samples
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate {
+    /// Pulls used, counting only the decreases between the samples
+    pub used: u64,
+    /// Time from the first sample to the last
+    pub span: Duration,
+}
+
+impl Rate {
+    /// Burn rate of `samples`, oldest first, `None` if they span less than `MIN_SPAN`
+    ///
+    /// # Arguments
+    ///
+    /// * `samples` - `Sample`s of the checks, oldest first
+    pub fn of(samples: &[Sample]) -> Option<Rate> {
+        let (first, last) = (samples.first()?, samples.last()?);
+        let span = Duration::from_secs(last.at.saturating_sub(first.at));
+        if span < MIN_SPAN {
+            return None;
+        }
+
+        // an increase is pulls given back by the window, not negative use
+        let used = samples
+            .windows(2)
+            .map(|w| w[0].remaining.saturating_sub(w[1].remaining))
+            .sum();
+        Some(Rate { used, span })
+    }
+
+    /// Pulls used per hour
+    pub fn per_hour(&self) -> f64 {
+        self.used as f64 * 3600.0 / self.span.as_secs_f64()
+    }
+}
+
+/// The limit along with how long it lasts at the current burn rate
+#[derive(Debug, Clone)]
+pub struct Estimate {
+    /// The limit of the latest check
+    pub limit: Limit,
+    /// Burn rate over the samples, `None` without enough of them
+    pub rate: Option<Rate>,
+}
+
+impl Estimate {
+    /// Estimate for the check of `state`, from its earlier samples
+    ///
+    /// # Arguments
+    ///
+    /// * `state` - `State` of the latest check, see `State::after`
+    pub fn new(state: &State) -> Estimate {
+        let mut samples = state.samples.clone();
+        samples.push(state.sample());
+
+        Estimate {
+            limit: state.limit.clone(),
+            rate: Rate::of(&samples),
+        }
+    }
+
+    /// Time until the limit runs out, `None` if it doesn't at the current burn rate
+    pub fn runs_out_in(&self) -> Option<Duration> {
+        let rate = self.rate?;
+        if rate.used == 0 {
+            return None;
+        }
+        // the window gives pulls back as fast as they're used
+        let window = self.limit.window.as_secs_f64();
+        if window > 0.0 && rate.per_hour() * window / 3600.0 < self.limit.total as f64 {
+            return None;
+        }
+
+        let secs = self.limit.remaining as f64 * 3600.0 / rate.per_hour();
+        Some(Duration::from_secs_f64(secs))
+    }
+
+    /// Same as the `Display` output, with the counts formatted by `human::count`
+    pub fn human(&self) -> String {
+        self.format(human::count)
+    }
+
+    /// Formats the estimate with the counts formatted by `count`
+    fn format(&self, count: fn(u64) -> String) -> String {
+        let mut out = format!(
+            "{} of {} pulls remaining",
+            count(self.limit.remaining),
+            count(self.limit.total)
+        );
+        if !self.limit.window.is_zero() {
+            out.push_str(&format!(" ({} window)", format_age(self.limit.window)));
+        }
+
+        let rate = match self.rate {
+            _ if self.limit.remaining == 0 => return out + "; the limit is used up",
+            Some(r) => r,
+            None => return out + "; not enough earlier checks for a burn rate yet",
+        };
+        if rate.used == 0 {
+            return out + &format!("; none used in the last {}", format_age(rate.span));
+        }
+
+        let burn = format!("; at current burn rate (~{:.0}/h)", rate.per_hour().ceil());
+        out.push_str(&burn);
+        match self.runs_out_in() {
+            Some(d) => out + &format!(" you will exhaust the limit in ~{}", format_age(d)),
+            None => out + " the limit won't run out",
+        }
+    }
+}
+
+impl fmt::Display for Estimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.format(|n| n.to_string()))
+    }
+}
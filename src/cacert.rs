@@ -0,0 +1,128 @@
+//! Extra certificate authorities to trust, for private registries and TLS intercepting proxies
+//!
+//! Bundles are loaded and checked up front, so a bad file fails before anything is sent
+
+use super::err::{DrlErr, DrlResult, ExitCode};
+use reqwest::Certificate;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Armor around each certificate of a PEM bundle
+const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+const END: &str = "-----END CERTIFICATE-----";
+
+/// The DER of every certificate in `pem`, `None` if one isn't closed or isn't base64
+///
+/// Anything outside the armor, like the comments some bundles have, is skipped. Split by hand
+/// rather than with openssl, which the TLS backend of macOS and Windows doesn't use
+fn pem_certificates(pem: &str) -> Option<Vec<Vec<u8>>> {
+    let mut der = Vec::new();
+    let mut rest = pem;
+    while let Some(start) = rest.find(BEGIN) {
+        let body = &rest[start + BEGIN.len()..];
+        let end = body.find(END)?;
+        let encoded: String = body[..end].split_whitespace().collect();
+        der.push(base64::decode(encoded).ok()?);
+        rest = &body[end + END.len()..];
+    }
+    Some(der)
+}
+
+/// CA certificates trusted on top of the system's, kept as DER
+#[derive(Clone, Default)]
+pub struct CaBundle {
+    der: Vec<Vec<u8>>,
+}
+
+impl fmt::Debug for CaBundle {
+    /// Leaves out the certificates themselves
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CaBundle {{ certificates: {} }}", self.der.len())
+    }
+}
+
+impl CaBundle {
+    /// Loads every certificate of a PEM bundle
+    ///
+    /// # Errors
+    ///
+    /// Returns `ExitCode::Input` if the file can't be read or holds no PEM certificate
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - PEM file with one or more CA certificates
+    pub fn from_pem(path: &Path) -> DrlResult<CaBundle> {
+        let pem = match fs::read(path) {
+            Ok(p) => p,
+            Err(e) => {
+                let msg = format!("failed to read CA bundle {}: {}", path.display(), e);
+                let err = DrlErr::new(msg, ExitCode::Input);
+                return Err(err);
+            }
+        };
+
+        let der = match pem_certificates(&String::from_utf8_lossy(&pem)) {
+            Some(d) if !d.is_empty() => d,
+            _ => {
+                let msg = format!("no PEM certificate in {}", path.display());
+                let err = DrlErr::new(msg, ExitCode::Input);
+                return Err(err);
+            }
+        };
+
+        for cert in &der {
+            if let Err(e) = Certificate::from_der(cert) {
+                let msg = format!("invalid certificate in {}: {}", path.display(), e);
+                let err = DrlErr::new(msg, ExitCode::Input);
+                return Err(err);
+            }
+        }
+        Ok(CaBundle { der })
+    }
+
+    /// Number of certificates in the bundle
+    pub fn len(&self) -> usize {
+        self.der.len()
+    }
+
+    /// Whether the bundle has no certificates
+    pub fn is_empty(&self) -> bool {
+        self.der.is_empty()
+    }
+
+    /// The certificates, for a `ClientBuilder`
+    pub(crate) fn certificates(&self) -> Vec<Certificate> {
+        self.der
+            .iter()
+            // checked by the TLS backend when loaded
+            .map(|d| Certificate::from_der(d).expect("invalid CA certificate"))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split() {
+        let pem = format!(
+            "# first\n{}\nAAEC\nAw==\n{}\n{}\nBAU=\n{}\n",
+            BEGIN, END, BEGIN, END
+        );
+        let der = pem_certificates(&pem).unwrap();
+        assert_eq!(der, [vec![0, 1, 2, 3], vec![4, 5]]);
+    }
+
+    #[test]
+    fn none() {
+        assert_eq!(pem_certificates("not a certificate"), Some(Vec::new()));
+    }
+
+    #[test]
+    fn broken() {
+        assert_eq!(pem_certificates(&format!("{}\nAAEC", BEGIN)), None);
+        assert_eq!(pem_certificates(&format!("{}\n!!\n{}", BEGIN, END)), None);
+    }
+}
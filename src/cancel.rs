@@ -0,0 +1,37 @@
+//! Cooperative cancellation of in-flight requests
+//!
+//! Any future can be used as the cancellation signal, e.g. `CancellationToken::cancelled()`
+//! from `tokio-util`, or `tokio::signal::ctrl_c()`
+
+use super::err::{DrlErr, DrlResult, ExitCode};
+use std::future::Future;
+
+/// Runs `fut` until it finishes or `cancel` completes, whichever is first
+///
+/// Dropping `fut` aborts any request it has in flight
+///
+/// # Errors
+///
+/// Returns `ExitCode::Cancelled` if `cancel` completes first
+///
+/// # Arguments
+///
+/// * `fut` - the work to do, e.g. `get_limit(&token)`
+/// * `cancel` - future that completes when `fut` should be abandoned
+pub async fn cancellable<T, F, C>(fut: F, cancel: C) -> DrlResult<T>
+where
+    F: Future<Output = DrlResult<T>>,
+    C: Future,
+{
+    tokio::select! {
+        // check for cancellation first, in case both are ready
+        biased;
+
+        _ = cancel => {
+            let msg = String::from("cancelled");
+            let err = DrlErr::new(msg, ExitCode::Cancelled);
+            Err(err)
+        }
+        result = fut => result,
+    }
+}
@@ -0,0 +1,504 @@
+//! HTTP client settings shared by every request `docker-rl` makes
+//!
+//! A `ClientConfig` is turned into a client by `api::DrlClient::builder`. Without one, clients
+//! get reqwest's defaults, including the proxy from the environment
+
+use super::cacert::CaBundle;
+use super::err::{DrlErr, DrlResult, ExitCode, Kind};
+use super::identity::ClientIdentity;
+use super::retry::RetryPolicy;
+use reqwest::{Client, ClientBuilder, Proxy, RequestBuilder, Response, Url};
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Proxy variables reqwest looks at for `https` URLs, in order
+pub const PROXY_VARS: &[&str] = &["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"];
+
+/// Variables listing the hosts that bypass the proxy, in order
+pub const NO_PROXY_VARS: &[&str] = &["NO_PROXY", "no_proxy"];
+
+/// Message reqwest fails with when the proxy answers `407`
+const PROXY_AUTH_REQUIRED: &str = "proxy authentication required";
+
+/// Message reqwest fails with when the proxy answers `CONNECT` with anything else but `200`
+const PROXY_REFUSED: &str = "unsuccessful tunnel";
+
+/// Message reqwest fails with when the proxy hangs up during `CONNECT`
+const PROXY_CLOSED: &str = "unexpected eof while tunneling";
+
+/// Basic credentials, given as `user:pass`
+#[derive(Clone, PartialEq, Eq)]
+pub struct BasicAuth {
+    /// Username
+    pub user: String,
+    /// Password
+    pub pass: String,
+}
+
+impl FromStr for BasicAuth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some((user, pass)) if !user.is_empty() => Ok(BasicAuth {
+                user: user.into(),
+                pass: pass.into(),
+            }),
+            _ => Err(String::from(
+                "invalid proxy credentials: expected user:pass",
+            )),
+        }
+    }
+}
+
+impl fmt::Debug for BasicAuth {
+    /// Leaves out the password
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BasicAuth {{ user: {:?}, pass: \"****\" }}", self.user)
+    }
+}
+
+/// IP version requests are made over
+///
+/// Docker Hub keys anonymous limits to the source IP, which usually differs between the two on
+/// a dual-stack host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpStack {
+    /// IPv4 only
+    V4,
+    /// IPv6 only
+    V6,
+}
+
+impl IpStack {
+    /// Both stacks, IPv4 first
+    pub const ALL: [IpStack; 2] = [IpStack::V4, IpStack::V6];
+
+    /// The unspecified address of the stack, binding to it keeps connections on the stack
+    pub fn unspecified(self) -> IpAddr {
+        match self {
+            IpStack::V4 => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpStack::V6 => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        }
+    }
+
+    /// The stack of `addr`
+    pub fn of(addr: IpAddr) -> IpStack {
+        match addr {
+            IpAddr::V4(_) => IpStack::V4,
+            IpAddr::V6(_) => IpStack::V6,
+        }
+    }
+}
+
+impl fmt::Display for IpStack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpStack::V4 => write!(f, "ipv4"),
+            IpStack::V6 => write!(f, "ipv6"),
+        }
+    }
+}
+
+/// Settings for the clients created by `api::DrlClient::builder`
+#[derive(Debug, Clone, Default)]
+pub struct ClientConfig {
+    /// Proxy for every request, `None` to use the environment
+    pub proxy: Option<Url>,
+    /// Basic credentials sent to the proxy
+    pub proxy_auth: Option<BasicAuth>,
+    /// Client certificate offered to every server
+    pub identity: Option<ClientIdentity>,
+    /// CA certificates trusted on top of the system's
+    pub ca_bundle: Option<CaBundle>,
+    /// Accept any server certificate, for testing only
+    pub insecure: bool,
+    /// How the requests to the registry and its token service are retried
+    pub retry: RetryPolicy,
+    /// Give up on a request after this, from connecting to the end of the body
+    pub timeout: Option<Duration>,
+    /// Give up on connecting after this
+    pub connect_timeout: Option<Duration>,
+    /// Local address connections are made from, only servers of its IP version are tried
+    ///
+    /// See `IpStack::unspecified` for keeping to one IP version from any address
+    pub local_address: Option<IpAddr>,
+}
+
+impl ClientConfig {
+    /// The proxy requests go through, from `proxy` or the environment
+    ///
+    /// Credentials in the URL are kept, see `redacted_proxy` for showing it
+    pub fn proxy_url(&self) -> Option<Url> {
+        if let Some(proxy) = &self.proxy {
+            return Some(proxy.clone());
+        }
+
+        PROXY_VARS
+            .iter()
+            .find_map(|var| env::var(var).ok())
+            .and_then(|value| Url::parse(&value).ok())
+    }
+
+    /// The proxy without any credentials, and the proxy user if there is one
+    pub fn redacted_proxy(&self) -> Option<String> {
+        let mut url = self.proxy_url()?;
+        let user = match &self.proxy_auth {
+            Some(auth) => Some(auth.user.clone()),
+            None if !url.username().is_empty() => Some(url.username().to_string()),
+            None => None,
+        };
+
+        let _ = url.set_username("");
+        let _ = url.set_password(None);
+        match user {
+            Some(user) => Some(format!("{} (as {})", url, user)),
+            None => Some(url.to_string()),
+        }
+    }
+
+    /// Checks the settings can be used, before any request is made
+    ///
+    /// # Errors
+    ///
+    /// Returns `ExitCode::Input` if there are proxy credentials but no proxy
+    pub fn validate(&self) -> DrlResult<()> {
+        if self.proxy_auth.is_some() && self.proxy_url().is_none() {
+            let msg = String::from("proxy credentials need a proxy, use --proxy or HTTPS_PROXY");
+            let err = DrlErr::new(msg, ExitCode::Input);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Starts a client with these settings
+    pub(crate) fn builder(&self) -> ClientBuilder {
+        let mut builder = Client::builder();
+
+        // credentials can't be added to the proxies reqwest finds on its own
+        if self.proxy.is_some() || self.proxy_auth.is_some() {
+            if let Some(url) = self.proxy_url() {
+                // reqwest only looks at NO_PROXY for the proxies it finds on its own
+                let no_proxy = no_proxy();
+                let mut proxy = Proxy::custom(move |target| {
+                    let host = target.host_str().unwrap_or_default();
+                    if bypasses(&no_proxy, host) {
+                        None
+                    } else {
+                        Some(url.clone())
+                    }
+                });
+                if let Some(auth) = &self.proxy_auth {
+                    proxy = proxy.basic_auth(&auth.user, &auth.pass);
+                }
+                builder = builder.proxy(proxy);
+            }
+        }
+
+        if let Some(identity) = &self.identity {
+            builder = builder.identity(identity.identity());
+        }
+        if let Some(bundle) = &self.ca_bundle {
+            for cert in bundle.certificates() {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        if self.insecure {
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        // hyper leaves out the addresses of the other IP version when bound to one
+        if let Some(addr) = self.local_address {
+            builder = builder.local_address(addr);
+        }
+
+        builder
+    }
+}
+
+/// Hosts from `NO_PROXY`, lowercased and without leading dots
+fn no_proxy() -> Vec<String> {
+    let list = NO_PROXY_VARS
+        .iter()
+        .find_map(|var| env::var(var).ok())
+        .unwrap_or_default();
+    list.split(',')
+        .map(|h| h.trim().trim_start_matches('.').to_lowercase())
+        .filter(|h| !h.is_empty())
+        .collect()
+}
+
+/// Whether `host` bypasses the proxy by `NO_PROXY`
+///
+/// `*` matches every host, any other entry the host itself and its subdomains
+///
+/// # Arguments
+///
+/// * `host` - host of the request, e.g. `registry-1.docker.io`
+pub fn bypasses_proxy(host: &str) -> bool {
+    bypasses(&no_proxy(), host)
+}
+
+/// Does the work of `bypasses_proxy`, with the hosts from `no_proxy`
+fn bypasses(no_proxy: &[String], host: &str) -> bool {
+    let host = host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_lowercase();
+    no_proxy.iter().any(|entry| {
+        entry == "*"
+            || host == *entry
+            || host
+                .strip_suffix(entry.as_str())
+                .is_some_and(|sub| sub.ends_with('.'))
+    })
+}
+
+/// A `Client` along with the settings the requests sent with it need to know of
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Http {
+    /// The `Client` every request is sent with
+    pub(crate) client: Client,
+    /// How failed requests are retried
+    pub(crate) retry: RetryPolicy,
+    /// Whether the proxy is sent credentials, to tell rejected ones from missing ones
+    pub(crate) proxy_auth: bool,
+}
+
+impl Http {
+    /// Builds the client of `builder`, started from `config` with `ClientConfig::builder`
+    ///
+    /// # Errors
+    ///
+    /// Returns `ExitCode::Input` if the HTTP client can't be created
+    pub(crate) fn new(config: &ClientConfig, builder: ClientBuilder) -> DrlResult<Http> {
+        match builder.build() {
+            Ok(client) => Ok(Http {
+                client,
+                retry: config.retry,
+                proxy_auth: config.proxy_auth.is_some(),
+            }),
+            Err(e) => {
+                let msg = format!("failed to create HTTP client: {}", e);
+                let err = DrlErr::new(msg, ExitCode::Input);
+                Err(err)
+            }
+        }
+    }
+
+    /// Sends `req`, retrying it as `retry` says
+    pub(crate) async fn send(&self, req: RequestBuilder) -> reqwest::Result<Response> {
+        super::retry::send(req, &self.retry).await
+    }
+
+    /// Turns a failure to send a request into a `DrlErr`, see `connect_error`
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - who the request was for, e.g. `docker.io`
+    /// * `e` - error from sending the request
+    pub(crate) fn connect_error(&self, host: &str, e: reqwest::Error) -> DrlErr {
+        connect_error(host, e, self.proxy_auth)
+    }
+
+    /// The error for a proxy answering `407` to a plain HTTP request, which reqwest hands back
+    /// as the response rather than failing it like a `CONNECT`
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - who the request was for, e.g. `docker.io`
+    pub(crate) fn proxy_auth_error(&self, host: &str) -> DrlErr {
+        proxy_auth_error(host, self.proxy_auth)
+    }
+}
+
+/// How a proxy failed a request, from the messages of reqwest's `CONNECT` tunnel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProxyFailure {
+    /// The proxy answered `407`
+    AuthRequired,
+    /// The proxy answered anything else but `200`
+    Refused,
+    /// The proxy hung up before answering
+    Closed,
+}
+
+impl ProxyFailure {
+    /// The failure reqwest reports as `msg`, `None` for any other message
+    fn from_message(msg: &str) -> Option<ProxyFailure> {
+        match msg {
+            PROXY_AUTH_REQUIRED => Some(ProxyFailure::AuthRequired),
+            PROXY_REFUSED => Some(ProxyFailure::Refused),
+            PROXY_CLOSED => Some(ProxyFailure::Closed),
+            _ => None,
+        }
+    }
+}
+
+/// Why the proxy failed `e`, `None` if it didn't or reqwest said so in a way not known here
+///
+/// # Arguments
+///
+/// * `e` - error from sending the request
+fn proxy_failure(e: &reqwest::Error) -> Option<ProxyFailure> {
+    let mut source = e.source();
+    while let Some(err) = source {
+        if let Some(failure) = ProxyFailure::from_message(&err.to_string()) {
+            return Some(failure);
+        }
+        source = err.source();
+    }
+    None
+}
+
+/// Whether the proxy, rather than the server, failed `e`
+///
+/// # Arguments
+///
+/// * `e` - error from sending the request
+pub(crate) fn is_proxy_error(e: &reqwest::Error) -> bool {
+    proxy_failure(e).is_some()
+}
+
+/// Turns a failure to send a request into a `DrlErr`
+///
+/// A proxy rejecting the connection is reported as `ExitCode::Proxy`, rather than as the server
+/// failing, and running out of time as `ExitCode::Timeout`. Proxy failures reqwest words in a
+/// way `ProxyFailure` doesn't know are reported like any other failure to connect
+///
+/// # Arguments
+///
+/// * `host` - who the request was for, e.g. `docker.io`
+/// * `e` - error from sending the request
+/// * `proxy_auth` - whether the proxy was sent credentials
+fn connect_error(host: &str, e: reqwest::Error, proxy_auth: bool) -> DrlErr {
+    if e.is_timeout() {
+        let msg = format!("request to {} timed out", host);
+        let kind = Kind::Timeout { host: host.into() };
+        return DrlErr::new(msg, ExitCode::Timeout).with_kind(kind);
+    }
+
+    let msg = match proxy_failure(&e) {
+        Some(ProxyFailure::AuthRequired) => return proxy_auth_error(host, proxy_auth),
+        Some(ProxyFailure::Closed) => format!("proxy closed the connection to {}", host),
+        Some(ProxyFailure::Refused) => format!("proxy refused to connect to {}", host),
+        None => {
+            let msg = format!("failed to connect to {}: {}", host, e);
+            let kind = Kind::Connection {
+                host: host.into(),
+                source: e.to_string(),
+            };
+            return DrlErr::new(msg, ExitCode::Connection).with_kind(kind);
+        }
+    };
+    DrlErr::new(msg, ExitCode::Proxy).with_kind(Kind::Proxy { host: host.into() })
+}
+
+/// The error for a proxy answering `407`, as `ExitCode::Proxy`
+///
+/// # Arguments
+///
+/// * `host` - who the request was for, e.g. `docker.io`
+/// * `proxy_auth` - whether the proxy was sent credentials, which it then rejected
+fn proxy_auth_error(host: &str, proxy_auth: bool) -> DrlErr {
+    let msg = if proxy_auth {
+        format!("proxy authentication failed connecting to {}", host)
+    } else {
+        format!(
+            "proxy authentication required connecting to {}, use --proxy-auth",
+            host
+        )
+    };
+    DrlErr::new(msg, ExitCode::Proxy).with_kind(Kind::Proxy { host: host.into() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Sends a request through a proxy on localhost that answers every `CONNECT` with `reply`
+    async fn through_proxy(reply: &str) -> reqwest::Error {
+        let reply = reply.to_owned();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut conn, _)) = listener.accept().await {
+                let mut buf = [0; 1024];
+                let _ = conn.read(&mut buf).await;
+                let _ = conn.write_all(reply.as_bytes()).await;
+            }
+        });
+
+        let config = ClientConfig {
+            proxy: Some(url),
+            ..ClientConfig::default()
+        };
+        let client = config.builder().build().unwrap();
+        client
+            .get("https://registry.test/v2/")
+            .send()
+            .await
+            .unwrap_err()
+    }
+
+    /// The messages matched are the ones of the reqwest in use
+    #[tokio::test]
+    async fn failures_of_this_reqwest() {
+        let cases = [
+            (
+                "HTTP/1.1 407 Proxy Authentication Required\r\n\r\n",
+                ProxyFailure::AuthRequired,
+                "proxy authentication required connecting to registry.test, use --proxy-auth",
+            ),
+            (
+                "HTTP/1.1 403 Forbidden\r\n\r\n",
+                ProxyFailure::Refused,
+                "proxy refused to connect to registry.test",
+            ),
+            (
+                "",
+                ProxyFailure::Closed,
+                "proxy closed the connection to registry.test",
+            ),
+        ];
+        for (reply, failure, msg) in cases {
+            let e = through_proxy(reply).await;
+            assert_eq!(proxy_failure(&e), Some(failure), "{:?}", e);
+            assert!(is_proxy_error(&e));
+
+            let err = connect_error("registry.test", e, false);
+            assert_eq!(err.ret, ExitCode::Proxy);
+            assert_eq!(err.msg, msg);
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_failures_are_connection_errors() {
+        assert_eq!(ProxyFailure::from_message("proxy said no"), None);
+
+        // headers too long for reqwest fail the tunnel with a message not matched
+        let reply = format!("HTTP/1.1 200 OK\r\nX-Padding: {}\r\n", "x".repeat(8192));
+        let e = through_proxy(&reply).await;
+        assert_eq!(proxy_failure(&e), None, "{:?}", e);
+        assert!(!is_proxy_error(&e));
+
+        let err = connect_error("registry.test", e, false);
+        assert_eq!(err.ret, ExitCode::Connection);
+        assert!(
+            matches!(err.kind, Kind::Connection { ref host, .. } if host == "registry.test"),
+            "{:?}",
+            err.kind
+        );
+    }
+}
@@ -0,0 +1,481 @@
+//! The optional config file, `~/.config/docker-rl/config.toml`, with settings per profile
+//!
+//! Each `[name]` table is a profile of option names and values, e.g. `user = "ci-bot"` or
+//! `fail-below = 10`. Keys before the first table belong to the `default` profile, which every
+//! other profile starts from. Only the part of TOML these need is understood: tables, strings,
+//! numbers and booleans. Arrays, inline tables and arrays of tables are refused as such
+
+use super::err::{DrlErr, DrlResult, ExitCode};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directory of the config file, inside the config directory
+const DIR: &str = "docker-rl";
+
+/// Name of the config file
+const FILE: &str = "config.toml";
+
+/// Profile used when none is named
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Value of one setting
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+}
+
+impl fmt::Display for Value {
+    /// Formats the value as it would be passed on the command line
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::String(s) => write!(f, "{}", s),
+            Value::Integer(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Boolean(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+/// Settings of one profile, in the order of the file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    /// Name of the profile, from its table header
+    pub name: String,
+    /// Option names and their values
+    pub values: Vec<(String, Value)>,
+}
+
+impl Profile {
+    /// Value of `key`, `None` if the profile doesn't set it
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.values.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+}
+
+/// Every profile in the config file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    /// Where the config was read from
+    pub path: PathBuf,
+    /// The profiles, in the order of the file
+    pub profiles: Vec<Profile>,
+}
+
+impl Config {
+    /// The profile called `name`, with the settings of the default profile it doesn't override
+    ///
+    /// Returns `None` if there is no such profile
+    pub fn profile(&self, name: &str) -> Option<Profile> {
+        let find = |name: &str| self.profiles.iter().find(|p| p.name == name);
+        let mut profile = find(name)?.clone();
+
+        if let Some(default) = find(DEFAULT_PROFILE).filter(|_| name != DEFAULT_PROFILE) {
+            for (key, value) in &default.values {
+                if profile.get(key).is_none() {
+                    profile.values.push((key.clone(), value.clone()));
+                }
+            }
+        }
+        Some(profile)
+    }
+}
+
+/// Path of the config file, `None` if there is no config directory
+pub fn path() -> Option<PathBuf> {
+    // relative paths are invalid by the XDG spec
+    let config = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))?;
+    Some(config.join(DIR).join(FILE))
+}
+
+/// Reads the config file at `path`
+///
+/// # Errors
+///
+/// Returns `ExitCode::Input` if the file can't be read or parsed
+///
+/// # Arguments
+///
+/// * `path` - path of the config file
+pub fn load(path: &Path) -> DrlResult<Config> {
+    let text = match fs::read_to_string(path) {
+        Ok(t) => t,
+        Err(e) => {
+            let msg = format!("failed to read {}: {}", path.display(), e);
+            let err = DrlErr::new(msg, ExitCode::Input);
+            return Err(err);
+        }
+    };
+
+    match parse(&text) {
+        Ok(profiles) => Ok(Config {
+            path: path.to_path_buf(),
+            profiles,
+        }),
+        Err(e) => {
+            let msg = format!("failed to parse {}: {}", path.display(), e);
+            let err = DrlErr::new(msg, ExitCode::Input);
+            Err(err)
+        }
+    }
+}
+
+/// Parses the profiles of a config file
+///
+/// # Arguments
+///
+/// * `text` - contents of the config file
+pub fn parse(text: &str) -> Result<Vec<Profile>, String> {
+    let mut profiles = vec![Profile {
+        name: DEFAULT_PROFILE.into(),
+        values: Vec::new(),
+    }];
+
+    for (n, line) in text.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        let at = |e: String| format!("line {}: {}", n + 1, e);
+
+        if line.starts_with("[[") {
+            return Err(at(format!("arrays of tables aren't supported: {}", line)));
+        }
+        if let Some(header) = line.strip_prefix('[') {
+            let name = match header.strip_suffix(']') {
+                Some(name) => unquote(name.trim()).map_err(at)?,
+                None => return Err(at(format!("unclosed table header: {}", line))),
+            };
+            if name.is_empty() {
+                return Err(at(String::from("empty profile name")));
+            }
+            // tables can't be reopened, except the implicit default
+            let current = profiles.len() - 1;
+            match profiles.iter().position(|p| p.name == name) {
+                Some(0) if current == 0 && profiles[0].values.is_empty() => (),
+                Some(_) => return Err(at(format!("profile '{}' defined twice", name))),
+                None => profiles.push(Profile {
+                    name,
+                    values: Vec::new(),
+                }),
+            }
+            continue;
+        }
+
+        let (key, value) = match line.split_once('=') {
+            Some((k, v)) => (unquote(k.trim()).map_err(at)?, v.trim()),
+            None => return Err(at(format!("expected key = value: {}", line))),
+        };
+        if key.is_empty() {
+            return Err(at(String::from("empty key")));
+        }
+        let value = parse_value(value).map_err(at)?;
+
+        let profile = profiles.last_mut().expect("missing profile");
+        if profile.get(&key).is_some() {
+            return Err(at(format!(
+                "'{}' set twice in profile '{}'",
+                key, profile.name
+            )));
+        }
+        profile.values.push((key, value));
+    }
+
+    // the implicit default is only a profile if it sets something
+    if profiles[0].values.is_empty() {
+        profiles.remove(0);
+    }
+    Ok(profiles)
+}
+
+/// Cuts a `#` comment off `line`, leaving any `#` inside strings alone
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '#') => return &line[..i],
+            _ => (),
+        }
+        escaped = false;
+    }
+    line
+}
+
+/// Parses a bare or quoted key or table name
+fn unquote(s: &str) -> Result<String, String> {
+    if s.starts_with('"') || s.starts_with('\'') {
+        return match parse_value(s)? {
+            Value::String(s) => Ok(s),
+            _ => unreachable!("quoted value is a string"),
+        };
+    }
+    let bare = s
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if bare {
+        Ok(s.into())
+    } else {
+        Err(format!("invalid key '{}', quote it", s))
+    }
+}
+
+/// Parses a string, number or boolean
+fn parse_value(s: &str) -> Result<Value, String> {
+    if let Some(rest) = s.strip_prefix('\'') {
+        // literal strings have no escapes
+        return match rest.strip_suffix('\'') {
+            Some(body) if !body.contains('\'') => Ok(Value::String(body.into())),
+            _ => Err(format!("invalid string: {}", s)),
+        };
+    }
+    if let Some(rest) = s.strip_prefix('"') {
+        return match rest.strip_suffix('"') {
+            Some(body) => unescape(body).map(Value::String),
+            None => Err(format!("unclosed string: {}", s)),
+        };
+    }
+
+    if s.starts_with('[') || s.starts_with('{') {
+        return Err(format!("arrays and inline tables aren't supported: {}", s));
+    }
+    match s {
+        "true" => return Ok(Value::Boolean(true)),
+        "false" => return Ok(Value::Boolean(false)),
+        _ => (),
+    }
+    let number = s.replace('_', "");
+    if let Ok(i) = number.parse::<i64>() {
+        return Ok(Value::Integer(i));
+    }
+    match number.parse::<f64>() {
+        Ok(x) if number.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '+') => {
+            Ok(Value::Float(x))
+        }
+        _ => Err(format!("invalid value '{}', strings have to be quoted", s)),
+    }
+}
+
+/// Resolves the escapes of a basic string
+fn unescape(body: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(body.len());
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Err(format!("invalid string: \"{}\"", body)),
+            '\\' => match chars.next() {
+                Some('"') => out.push('"'),
+                Some('\\') => out.push('\\'),
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some(e) => return Err(format!("unsupported escape \\{}", e)),
+                None => return Err(format!("invalid string: \"{}\"", body)),
+            },
+            c => out.push(c),
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Value {
+        Value::String(s.into())
+    }
+
+    /// The values of the only profile of `text`
+    fn values(text: &str) -> Vec<(String, Value)> {
+        let profiles = parse(text).unwrap();
+        assert_eq!(profiles.len(), 1, "{:?}", profiles);
+        profiles.into_iter().next().unwrap().values
+    }
+
+    fn value(text: &str) -> Value {
+        values(&format!("key = {}", text)).remove(0).1
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(value(r#""ci-bot""#), string("ci-bot"));
+        assert_eq!(value(r#""say \"hi\"\t\\""#), string("say \"hi\"\t\\"));
+        assert_eq!(value(r#""two\nlines""#), string("two\nlines"));
+        // literal strings keep their backslashes
+        assert_eq!(value(r#"'C:\Users\ci'"#), string(r#"C:\Users\ci"#));
+        assert_eq!(value("''"), string(""));
+
+        for (bad, error) in [
+            (r#""open"#, "unclosed string"),
+            (r#"'open"#, "invalid string"),
+            (r#"'it's'"#, "invalid string"),
+            (r#""a" "b""#, "invalid string"),
+            (r#""\u00e9""#, "unsupported escape \\u"),
+            (
+                "ci-bot",
+                "invalid value 'ci-bot', strings have to be quoted",
+            ),
+        ] {
+            let err = parse(&format!("key = {}", bad)).unwrap_err();
+            assert!(err.contains(error), "{}: {}", bad, err);
+        }
+    }
+
+    #[test]
+    fn numbers_and_booleans() {
+        assert_eq!(value("10"), Value::Integer(10));
+        assert_eq!(value("-3"), Value::Integer(-3));
+        assert_eq!(value("1_000"), Value::Integer(1000));
+        assert_eq!(value("2.5"), Value::Float(2.5));
+        assert_eq!(value("true"), Value::Boolean(true));
+        assert_eq!(value("false"), Value::Boolean(false));
+        assert_eq!(value("2.5").to_string(), "2.5");
+
+        // words f64 would take aren't numbers
+        for bad in ["inf", "nan", "True", ""] {
+            assert!(parse(&format!("key = {}", bad)).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn quoted_keys_and_names() {
+        let profiles = parse("[\"ci east\"]\n'odd.key' = 1\n\"a b\" = 2\n").unwrap();
+        assert_eq!(profiles[0].name, "ci east");
+        assert_eq!(profiles[0].get("odd.key"), Some(&Value::Integer(1)));
+        assert_eq!(profiles[0].get("a b"), Some(&Value::Integer(2)));
+
+        let err = parse("odd.key = 1").unwrap_err();
+        assert_eq!(err, "line 1: invalid key 'odd.key', quote it");
+    }
+
+    #[test]
+    fn comments() {
+        let text = "# settings for CI\n\
+                    \n\
+                    [ci] # the runners\n\
+                    user = \"ci#bot\" # not part of the user\n\
+                    pass-file = '/run/#secret'\n\
+                    quote = \"say \\\"#\\\"\"\n\
+                    # check = true\n";
+        let profiles = parse(text).unwrap();
+        assert_eq!(profiles.len(), 1);
+        assert_eq!(profiles[0].name, "ci");
+        assert_eq!(
+            profiles[0].values,
+            [
+                ("user".into(), string("ci#bot")),
+                ("pass-file".into(), string("/run/#secret")),
+                ("quote".into(), string("say \"#\"")),
+            ]
+        );
+    }
+
+    #[test]
+    fn arrays_are_refused() {
+        let err = parse("user = \"ci-bot\"\nneed = [1, 2]").unwrap_err();
+        assert_eq!(
+            err,
+            "line 2: arrays and inline tables aren't supported: [1, 2]"
+        );
+        let err = parse("proxy = { url = \"http://proxy\" }").unwrap_err();
+        assert!(err.contains("inline tables aren't supported"), "{}", err);
+        let err = parse("[[ci]]\nuser = \"ci-bot\"").unwrap_err();
+        assert_eq!(err, "line 1: arrays of tables aren't supported: [[ci]]");
+    }
+
+    #[test]
+    fn profile_tables() {
+        let text = "fail-below = 10\n\
+                    format = \"text\"\n\
+                    [ci]\n\
+                    user = \"ci-bot\"\n\
+                    format = \"json\"\n\
+                    [empty]\n";
+        let config = Config {
+            path: PathBuf::from("config.toml"),
+            profiles: parse(text).unwrap(),
+        };
+        let names: Vec<&str> = config.profiles.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, [DEFAULT_PROFILE, "ci", "empty"]);
+
+        // a profile starts from the default and overrides it
+        let ci = config.profile("ci").unwrap();
+        assert_eq!(ci.get("user"), Some(&string("ci-bot")));
+        assert_eq!(ci.get("format"), Some(&string("json")));
+        assert_eq!(ci.get("fail-below"), Some(&Value::Integer(10)));
+        let empty = config.profile("empty").unwrap();
+        assert_eq!(empty.values.len(), 2);
+        assert_eq!(config.profile(DEFAULT_PROFILE).unwrap().values.len(), 2);
+        assert!(config.profile("missing").is_none());
+
+        // without keys before the first table there is no default
+        let profiles = parse("[ci]\nuser = \"ci-bot\"").unwrap();
+        assert_eq!(profiles.len(), 1);
+        // which can still be opened explicitly, first
+        let profiles = parse("[default]\ncheck = true\n[ci]\ncheck = false").unwrap();
+        assert_eq!(profiles[0].name, DEFAULT_PROFILE);
+        assert!(parse("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        for (text, error) in [
+            ("[ci]\n[ci]", "line 2: profile 'ci' defined twice"),
+            (
+                "user = 1\n\n[default]",
+                "line 3: profile 'default' defined twice",
+            ),
+            ("[ci\nuser = \"a\"", "line 1: unclosed table header: [ci"),
+            ("# first\n[]", "line 2: empty profile name"),
+            ("[ci]\nuser", "line 2: expected key = value: user"),
+            ("\n\n= 1", "line 3: empty key"),
+            (
+                "[ci]\nretries = 1\nretries = 2",
+                "line 3: 'retries' set twice in profile 'ci'",
+            ),
+            (
+                "a = 1\nb = yes",
+                "line 2: invalid value 'yes', strings have to be quoted",
+            ),
+        ] {
+            assert_eq!(parse(text).unwrap_err(), error, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn load_names_the_file() {
+        let path = env::temp_dir().join(format!("docker-rl-config-{}.toml", std::process::id()));
+        fs::write(&path, "[ci]\nuser = ci-bot\n").unwrap();
+        let err = load(&path).unwrap_err();
+        assert_eq!(err.ret, ExitCode::Input);
+        assert_eq!(
+            err.msg,
+            format!(
+                "failed to parse {}: line 2: invalid value 'ci-bot', strings have to be quoted",
+                path.display()
+            )
+        );
+
+        fs::write(&path, "[ci]\nuser = \"ci-bot\"\n").unwrap();
+        let config = load(&path).unwrap();
+        assert_eq!(config.path, path);
+        assert_eq!(config.profiles[0].name, "ci");
+        fs::remove_file(&path).unwrap();
+
+        let err = load(&path).unwrap_err();
+        assert!(err.msg.starts_with("failed to read"), "{}", err.msg);
+    }
+}
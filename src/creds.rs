@@ -0,0 +1,410 @@
+//! Docker Hub credentials from `docker login`
+//!
+//! They are read from the `auths` of the docker config, or from the credential helper it names
+
+use super::err::{DrlErr, DrlResult, ExitCode};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Keys docker uses for Docker Hub in the `auths` and `credHelpers` of its config
+pub const HUB_AUTH_KEYS: &[&str] = &[
+    "https://index.docker.io/v1/",
+    "index.docker.io",
+    "docker.io",
+];
+
+/// Server credential helpers are asked about for Docker Hub
+const HUB_SERVER: &str = "https://index.docker.io/v1/";
+
+/// Username helpers return along with an identity token instead of a password
+const IDENTITY_TOKEN_USER: &str = "<token>";
+
+/// Path of the docker config, honouring `DOCKER_CONFIG`
+pub fn docker_config_path() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("DOCKER_CONFIG") {
+        return Some(PathBuf::from(dir).join("config.json"));
+    }
+    let home = env::var_os("HOME")?;
+    Some(PathBuf::from(home).join(".docker").join("config.json"))
+}
+
+/// One entry of the `auths` of the docker config
+#[derive(Deserialize, Debug, Default)]
+struct AuthEntry {
+    #[serde(default)]
+    auth: Option<String>,
+}
+
+/// The parts of the docker config that hold credentials
+#[derive(Deserialize, Debug, Default)]
+struct DockerConfig {
+    #[serde(default)]
+    auths: HashMap<String, AuthEntry>,
+    #[serde(default, rename = "credsStore")]
+    creds_store: Option<String>,
+    #[serde(default, rename = "credHelpers")]
+    cred_helpers: HashMap<String, String>,
+}
+
+/// What a credential helper prints for `get`
+#[derive(Deserialize, Debug)]
+struct HelperCredentials {
+    #[serde(rename = "Username")]
+    username: String,
+    #[serde(rename = "Secret")]
+    secret: String,
+}
+
+/// Username and password for Docker Hub
+#[derive(Clone)]
+pub struct Credentials {
+    /// Docker Hub username
+    pub user: String,
+    /// Password or access token
+    pub pass: String,
+}
+
+impl fmt::Debug for Credentials {
+    /// Leaves out the password
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Credentials {{ user: {:?}, .. }}", self.user)
+    }
+}
+
+impl DockerConfig {
+    /// The base64 `auth` of the first `HUB_AUTH_KEYS` entry of `auths` that has one
+    fn hub_auth(&self) -> Option<&str> {
+        HUB_AUTH_KEYS
+            .iter()
+            .find_map(|key| self.auths.get(*key)?.auth.as_deref())
+    }
+
+    /// The credential helper for Docker Hub from `credHelpers`, or else `credsStore`
+    fn hub_helper(&self) -> Option<&str> {
+        HUB_AUTH_KEYS
+            .iter()
+            .find_map(|key| self.cred_helpers.get(*key))
+            .or(self.creds_store.as_ref())
+            .map(String::as_str)
+    }
+}
+
+/// Decodes the base64 `user:pass` of an `auths` entry
+fn decode_auth(auth: &str) -> Option<Credentials> {
+    let decoded = base64::decode(auth.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let mut parts = decoded.splitn(2, ':');
+    let user = parts.next().filter(|u| !u.is_empty())?;
+    let pass = parts.next()?;
+    Some(Credentials {
+        user: user.into(),
+        pass: pass.into(),
+    })
+}
+
+/// Asks the credential helper `docker-credential-<helper>` for the Docker Hub credentials
+///
+/// Returns `None` if the helper has none
+fn ask_helper(helper: &str) -> DrlResult<Option<Credentials>> {
+    run_helper(&format!("docker-credential-{}", helper))
+}
+
+/// Runs `program get` for the Docker Hub credentials, see `ask_helper`
+fn run_helper(program: &str) -> DrlResult<Option<Credentials>> {
+    let failed = |e: &dyn fmt::Display| {
+        let msg = format!("credential helper {} failed: {}", program, e);
+        DrlErr::new(msg, ExitCode::Input)
+    };
+
+    let mut child = Command::new(program)
+        .arg("get")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| failed(&e))?;
+
+    // dropped right after, so the helper sees the end of its input
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(HUB_SERVER.as_bytes())
+            .map_err(|e| failed(&e))?;
+    }
+    let output = child.wait_with_output().map_err(|e| failed(&e))?;
+
+    // helpers report missing credentials on stdout and exit non-zero
+    if !output.status.success() {
+        let said = String::from_utf8_lossy(&output.stdout);
+        if said.to_lowercase().contains("credentials not found") {
+            return Ok(None);
+        }
+        return Err(failed(&said.trim()));
+    }
+
+    let creds: HelperCredentials = match serde_json::from_slice(&output.stdout) {
+        Ok(c) => c,
+        Err(e) => return Err(failed(&e)),
+    };
+    if creds.username == IDENTITY_TOKEN_USER {
+        let msg = format!(
+            "{} has an identity token, which can't be used here",
+            program
+        );
+        let err = DrlErr::new(msg, ExitCode::Input);
+        return Err(err);
+    }
+
+    Ok(Some(Credentials {
+        user: creds.username,
+        pass: creds.secret,
+    }))
+}
+
+/// Finds the Docker Hub credentials saved by `docker login`
+///
+/// The `auths` of the docker config are tried first, then the credential helper for Docker
+/// Hub, then the default `credsStore`. Returns `None` if there are none
+///
+/// # Errors
+///
+/// Returns `ExitCode::Input` if the docker config can't be read or parsed, or the credential
+/// helper fails
+pub fn docker_credentials() -> DrlResult<Option<Credentials>> {
+    match docker_config_path() {
+        Some(path) => credentials_in(&path),
+        None => Ok(None),
+    }
+}
+
+/// Does the work of `docker_credentials` with the docker config at `path`
+fn credentials_in(path: &Path) -> DrlResult<Option<Credentials>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            let msg = format!("failed to read {}: {}", path.display(), e);
+            let err = DrlErr::new(msg, ExitCode::Input);
+            return Err(err);
+        }
+    };
+
+    let config: DockerConfig = match serde_json::from_str(&contents) {
+        Ok(c) => c,
+        Err(e) => {
+            let msg = format!("failed to parse {}: {}", path.display(), e);
+            let err = DrlErr::new(msg, ExitCode::Input);
+            return Err(err);
+        }
+    };
+
+    if let Some(auth) = config.hub_auth() {
+        return match decode_auth(auth) {
+            Some(creds) => Ok(Some(creds)),
+            None => {
+                let msg = format!("can't decode the Docker Hub auth in {}", path.display());
+                let err = DrlErr::new(msg, ExitCode::Input);
+                Err(err)
+            }
+        };
+    }
+
+    match config.hub_helper() {
+        Some(helper) => ask_helper(helper),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A docker config with Docker Hub and another registry logged in, and helpers for both
+    const LOGGED_IN: &str = r#"{
+        "auths": {
+            "ghcr.io": {"auth": "b3RoZXI6bm9wZQ=="},
+            "https://index.docker.io/v1/": {"auth": "Y2ktYm90OnMzY3I6ZXQ="}
+        },
+        "credHelpers": {"ghcr.io": "gh"},
+        "credsStore": "desktop"
+    }"#;
+
+    /// A directory of its own for `test`
+    fn dir(test: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("docker-rl-creds-{}-{}", test, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes `contents` as the config.json of a directory for `test`, returning its path
+    fn config_file(test: &str, contents: &str) -> PathBuf {
+        let path = dir(test).join("config.json");
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn config(contents: &str) -> DockerConfig {
+        serde_json::from_str(contents).unwrap()
+    }
+
+    #[test]
+    fn decodes_the_hub_auth() {
+        let creds = credentials_in(&config_file("auths", LOGGED_IN))
+            .unwrap()
+            .unwrap();
+        assert_eq!(creds.user, "ci-bot");
+        // only the first colon separates them
+        assert_eq!(creds.pass, "s3cr:et");
+        assert_eq!(
+            format!("{:?}", creds),
+            "Credentials { user: \"ci-bot\", .. }"
+        );
+    }
+
+    #[test]
+    fn any_hub_key_will_do() {
+        for key in HUB_AUTH_KEYS {
+            let contents = format!(r#"{{"auths": {{"{}": {{"auth": "dTpw"}}}}}}"#, key);
+            assert_eq!(config(&contents).hub_auth(), Some("dTpw"), "{}", key);
+        }
+        // other registries and entries without an auth aren't for Docker Hub
+        let other = r#"{"auths": {"ghcr.io": {"auth": "dTpw"}, "docker.io": {}}}"#;
+        assert_eq!(config(other).hub_auth(), None);
+    }
+
+    #[test]
+    fn undecodable_auth() {
+        assert!(decode_auth("not base64!").is_none());
+        // no user, or no colon at all
+        assert!(decode_auth(&base64::encode(":pass")).is_none());
+        assert!(decode_auth(&base64::encode("user")).is_none());
+        let padded = decode_auth(&format!(" {}\n", base64::encode("u:p"))).unwrap();
+        assert_eq!((padded.user.as_str(), padded.pass.as_str()), ("u", "p"));
+
+        let path = config_file("undecodable", r#"{"auths": {"docker.io": {"auth": "%%"}}}"#);
+        let err = credentials_in(&path).unwrap_err();
+        assert_eq!(err.ret, ExitCode::Input);
+        assert!(
+            err.msg.starts_with("can't decode the Docker Hub auth in"),
+            "{}",
+            err.msg
+        );
+    }
+
+    #[test]
+    fn cred_helpers_before_the_creds_store() {
+        let hub =
+            r#"{"credHelpers": {"docker.io": "pass", "ghcr.io": "gh"}, "credsStore": "desktop"}"#;
+        assert_eq!(config(hub).hub_helper(), Some("pass"));
+
+        // helpers for other registries leave Docker Hub to the store
+        assert_eq!(config(LOGGED_IN).hub_helper(), Some("desktop"));
+        assert_eq!(
+            config(r#"{"credHelpers": {"ghcr.io": "gh"}}"#).hub_helper(),
+            None
+        );
+        assert_eq!(config("{}").hub_helper(), None);
+    }
+
+    #[test]
+    fn missing_and_broken_configs() {
+        let missing = dir("missing").join("config.json");
+        assert!(credentials_in(&missing).unwrap().is_none());
+        // nothing for Docker Hub
+        let path = config_file("nothing", r#"{"auths": {"ghcr.io": {"auth": "dTpw"}}}"#);
+        assert!(credentials_in(&path).unwrap().is_none());
+
+        let err = credentials_in(&config_file("broken", "{\"auths\": ")).unwrap_err();
+        assert_eq!(err.ret, ExitCode::Input);
+        assert!(err.msg.starts_with("failed to parse"), "{}", err.msg);
+    }
+
+    /// Writes a credential helper running `script` after reading the server from stdin, as
+    /// helpers do, so writing it doesn't fail with a broken pipe
+    #[cfg(unix)]
+    fn helper(dir: &Path, name: &str, script: &str) -> String {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = dir.join(format!("docker-credential-{}", name));
+        let script = format!("#!/bin/sh\nserver=$(cat)\n{}\n", script);
+        fs::write(&path, script).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn helper_answers() {
+        let dir = dir("helpers");
+        let found = helper(
+            &dir,
+            "found",
+            r#"[ "$server" = "https://index.docker.io/v1/" ] || exit 3
+echo '{"ServerURL":"https://index.docker.io/v1/","Username":"ci-bot","Secret":"s3cret"}'"#,
+        );
+        let missing = helper(
+            &dir,
+            "missing",
+            "echo 'credentials not found in native keychain'; exit 1",
+        );
+        let token = helper(
+            &dir,
+            "token",
+            r#"echo '{"Username":"<token>","Secret":"eyJ..."}'"#,
+        );
+
+        let creds = run_helper(&found).unwrap().unwrap();
+        assert_eq!(
+            (creds.user.as_str(), creds.pass.as_str()),
+            ("ci-bot", "s3cret")
+        );
+        assert!(run_helper(&missing).unwrap().is_none());
+
+        let err = run_helper(&token).unwrap_err();
+        assert_eq!(err.ret, ExitCode::Input);
+        assert!(err
+            .msg
+            .ends_with("has an identity token, which can't be used here"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failing_helpers() {
+        let dir = dir("failing");
+        let failing = helper(
+            &dir,
+            "failing",
+            "echo 'error getting credentials - err: exit status 1'; exit 1",
+        );
+        let garbled = helper(&dir, "garbled", "echo 'Username: ci-bot'");
+
+        let err = run_helper(&failing).unwrap_err();
+        assert_eq!(err.ret, ExitCode::Input);
+        assert_eq!(
+            err.msg,
+            format!(
+                "credential helper {} failed: error getting credentials - err: exit status 1",
+                failing
+            )
+        );
+        let err = run_helper(&garbled).unwrap_err();
+        assert!(err
+            .msg
+            .starts_with(&format!("credential helper {} failed: ", garbled)));
+
+        let err = ask_helper("docker-rl-test-no-such-helper").unwrap_err();
+        assert_eq!(err.ret, ExitCode::Input);
+        assert!(
+            err.msg.starts_with(
+                "credential helper docker-credential-docker-rl-test-no-such-helper failed"
+            ),
+            "{}",
+            err.msg
+        );
+    }
+}
@@ -0,0 +1,436 @@
+//! Connectivity and configuration diagnostics for `docker-rl doctor`
+//!
+//! Each step is reported on its own, so a failure points at the piece that's broken
+
+use super::api::{DrlClient, DrlClientBuilder};
+use super::client::{self, ClientConfig, PROXY_VARS};
+use super::creds::{docker_config_path, HUB_AUTH_KEYS};
+use super::err::{DrlErr, DrlResult, ExitCode};
+use super::retry::RetryPolicy;
+use super::token::Token;
+use reqwest::{Client, Method, Url};
+use serde::Serialize;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::time::Duration;
+use tokio::net;
+
+/// How far the local clock may be off from the server's before it's reported
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(60);
+
+/// How long each connection check may take
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Outcome of a single diagnostic step
+#[derive(Serialize, Debug, Clone)]
+pub struct Check {
+    /// What was checked, e.g. `dns auth.docker.io`
+    pub name: String,
+    /// Whether the check passed
+    pub passed: bool,
+    /// Whether a failure stops `docker-rl` from working
+    pub critical: bool,
+    /// What was found
+    pub detail: String,
+    /// What to look at next, only for failures
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+impl Check {
+    /// Creates a passed `Check`
+    fn pass(name: String, critical: bool, detail: String) -> Check {
+        Check {
+            name,
+            passed: true,
+            critical,
+            detail,
+            hint: None,
+        }
+    }
+
+    /// Creates a failed `Check`
+    fn fail(name: String, critical: bool, detail: String, hint: &str) -> Check {
+        Check {
+            name,
+            passed: false,
+            critical,
+            detail,
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match (self.passed, self.critical) {
+            (true, _) => "ok",
+            (false, true) => "FAIL",
+            (false, false) => "warn",
+        };
+        write!(f, "{:<4} {}: {}", status, self.name, self.detail)?;
+        if let Some(hint) = &self.hint {
+            write!(f, "\n     hint: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+/// All the diagnostic steps, in the order they ran
+#[derive(Serialize, Debug, Clone)]
+pub struct Diagnosis {
+    /// Every step that ran
+    pub checks: Vec<Check>,
+    /// Whether every critical step passed
+    pub healthy: bool,
+}
+
+impl Diagnosis {
+    /// Creates a `Diagnosis`, working out whether it's healthy
+    fn new(checks: Vec<Check>) -> Diagnosis {
+        let healthy = checks.iter().all(|c| c.passed || !c.critical);
+        Diagnosis { checks, healthy }
+    }
+
+    /// Fails if any critical step failed
+    ///
+    /// # Errors
+    ///
+    /// Returns `ExitCode::Connection` naming the failed steps
+    pub fn check(&self) -> DrlResult<()> {
+        if self.healthy {
+            return Ok(());
+        }
+
+        let failed: Vec<&str> = self
+            .checks
+            .iter()
+            .filter(|c| c.critical && !c.passed)
+            .map(|c| c.name.as_str())
+            .collect();
+        let msg = format!("critical checks failed: {}", failed.join(", "));
+        let err = DrlErr::new(msg, ExitCode::Connection);
+        Err(err)
+    }
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines: Vec<String> = self.checks.iter().map(|c| c.to_string()).collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+/// Runs every diagnostic step
+///
+/// Later steps still run when earlier ones fail, unless they need what failed. The token
+/// service of the registry is discovered first, for the later steps to use
+///
+/// # Arguments
+///
+/// * `builder` - `DrlClientBuilder` for the registry and scope to check, its timeout and
+///   retries are replaced with the doctor's own
+pub async fn diagnose(builder: DrlClientBuilder) -> Diagnosis {
+    let mut checks = Vec::new();
+
+    let (proxy_check, proxied) = check_proxy(&builder.config);
+    checks.push(proxy_check);
+
+    let drl = match builder.timeout(TIMEOUT).retry(RetryPolicy::none()).build() {
+        Ok(c) => c,
+        Err(e) => {
+            let hint = "check the proxy variables, they may not be valid URLs";
+            checks.push(Check::fail("http client".into(), true, e.msg, hint));
+            return Diagnosis::new(checks);
+        }
+    };
+
+    // Docker Hub's token service is known up front
+    let drl = if drl.registry().realm.is_some() {
+        drl
+    } else {
+        let (check, discovered) = check_registry(drl).await;
+        checks.push(check);
+        match discovered {
+            Some(d) => d,
+            None => return Diagnosis::new(checks),
+        }
+    };
+    let registry = drl.registry();
+
+    // Docker Hub's token service is on another host, other registries often serve their own
+    let mut endpoints: Vec<Url> = registry.realm.iter().map(root).collect();
+    if !endpoints.contains(&root(&registry.url)) {
+        endpoints.push(root(&registry.url));
+    }
+
+    // behind a proxy the proxy resolves the hosts, not us, unless NO_PROXY skips it
+    for url in &endpoints {
+        let direct = !proxied || client::bypasses_proxy(url.host_str().unwrap_or_default());
+        checks.push(check_dns(url, direct).await);
+    }
+
+    for url in &endpoints {
+        checks.push(check_tls(drl.client(), url).await);
+    }
+
+    if registry.is_docker_hub() {
+        checks.push(check_credentials());
+    }
+
+    let name = String::from("token");
+    let token = match drl.token(None).await {
+        Ok(t) if registry.realm.is_none() => {
+            checks.push(Check::pass(
+                name,
+                false,
+                "the registry uses no tokens".into(),
+            ));
+            t
+        }
+        Ok(t) => {
+            checks.push(Check::pass(name, true, "got an anonymous token".into()));
+            t
+        }
+        Err(e) => {
+            let hint =
+                "if the connection checks passed, the token service may be down, try again later";
+            checks.push(Check::fail(name, true, e.msg, hint));
+            return Diagnosis::new(checks);
+        }
+    };
+
+    // the clock is read off the token response
+    if registry.realm.is_some() {
+        checks.push(check_clock(&token));
+    }
+    checks.push(check_limit(&drl, &token).await);
+
+    Diagnosis::new(checks)
+}
+
+/// `url` with only its scheme, host and port
+fn root(url: &Url) -> Url {
+    let mut root = url.clone();
+    root.set_path("/");
+    root.set_query(None);
+    root
+}
+
+/// Asks `registry` for its token service
+///
+/// Returns the client with the discovered `Registry`, or `None` if the later steps can't work
+/// without it
+///
+/// # Arguments
+///
+/// * `drl` - `DrlClient` for the registry to ask
+async fn check_registry(drl: DrlClient) -> (Check, Option<DrlClient>) {
+    let name = format!("registry {}", drl.registry().name);
+
+    match drl.discover().await {
+        Ok(d) => {
+            let detail = match &d.registry().realm {
+                Some(realm) => format!("token service at {}", realm),
+                None => String::from("no token service, checking without a token"),
+            };
+            (Check::pass(name, true, detail), Some(d))
+        }
+        Err(e) => {
+            let hint = "check the registry's address, and that it serves the /v2/ API";
+            (Check::fail(name, true, e.msg, hint), None)
+        }
+    }
+}
+
+/// Reports the proxy `config` uses for `https` requests, and whether there is one
+fn check_proxy(config: &ClientConfig) -> (Check, bool) {
+    let name = String::from("proxy");
+
+    // an explicit proxy was already parsed, the environment wasn't
+    if config.proxy.is_none() {
+        let invalid = PROXY_VARS
+            .iter()
+            .find(|var| env::var(var).is_ok_and(|value| Url::parse(&value).is_err()));
+        if let Some(var) = invalid {
+            let detail = format!("{} is not a valid URL", var);
+            let hint = "set it to something like http://proxy.example.com:3128";
+            return (Check::fail(name, true, detail, hint), true);
+        }
+    }
+
+    let mut detail = match config.redacted_proxy() {
+        Some(proxy) => proxy,
+        None => return (Check::pass(name, false, "none configured".into()), false),
+    };
+    if let Ok(no_proxy) = env::var("NO_PROXY").or_else(|_| env::var("no_proxy")) {
+        detail = format!("{}, NO_PROXY={}", detail, no_proxy);
+    }
+    (Check::pass(name, false, detail), true)
+}
+
+/// Resolves the host of `url`
+///
+/// # Arguments
+///
+/// * `url` - URL of the host to resolve
+/// * `critical` - whether failing to resolve it is critical
+async fn check_dns(url: &Url, critical: bool) -> Check {
+    let host = url.host_str().unwrap_or_default();
+    let port = url.port_or_known_default().unwrap_or(443);
+    let name = format!("dns {}", host);
+
+    match net::lookup_host((host, port)).await {
+        Ok(addrs) => {
+            let addrs: Vec<String> = addrs.map(|a| a.ip().to_string()).collect();
+            Check::pass(name, critical, format!("resolved to {}", addrs.join(", ")))
+        }
+        Err(e) => {
+            let hint = "check your DNS settings, or that you are online";
+            Check::fail(name, critical, format!("failed to resolve: {}", e), hint)
+        }
+    }
+}
+
+/// Connects to `url`, over TLS unless it's `http`, any HTTP response counts
+///
+/// # Arguments
+///
+/// * `client` - `Client` to send the request with
+/// * `url` - root URL of the host to connect to
+async fn check_tls(client: &Client, url: &Url) -> Check {
+    let kind = if url.scheme() == "http" {
+        "http"
+    } else {
+        "tls"
+    };
+    let name = format!("{} {}", kind, url.host_str().unwrap_or_default());
+
+    match client.head(url.clone()).send().await {
+        Ok(resp) => Check::pass(name, true, format!("connected, got {}", resp.status())),
+        Err(e) => {
+            let hint = if e.is_timeout() {
+                "the connection timed out, check firewalls and proxies"
+            } else {
+                "check firewalls and proxies, or pass the CA of a TLS intercepting proxy with --cacert"
+            };
+            Check::fail(name, true, format!("failed to connect: {}", e), hint)
+        }
+    }
+}
+
+/// Looks for Docker Hub credentials in the docker config, and who they are for
+fn check_credentials() -> Check {
+    let name = String::from("credentials");
+
+    let path = match docker_config_path() {
+        Some(p) => p,
+        None => return Check::pass(name, false, "no home directory to look in".into()),
+    };
+
+    let contents = match fs::read_to_string(&path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let detail = format!("no docker config at {}", path.display());
+            return Check::pass(name, false, detail);
+        }
+        Err(e) => {
+            let detail = format!("failed to read {}: {}", path.display(), e);
+            return Check::fail(name, false, detail, "check the file's permissions");
+        }
+    };
+
+    let config: serde_json::Value = match serde_json::from_str(&contents) {
+        Ok(c) => c,
+        Err(e) => {
+            let detail = format!("failed to parse {}: {}", path.display(), e);
+            return Check::fail(name, false, detail, "check that the file is valid JSON");
+        }
+    };
+
+    let auth = HUB_AUTH_KEYS
+        .iter()
+        .find_map(|key| config["auths"][key]["auth"].as_str());
+    if let Some(auth) = auth {
+        let decoded = base64::decode(auth).ok();
+        let user = decoded
+            .as_deref()
+            .and_then(|d| std::str::from_utf8(d).ok())
+            .and_then(|d| d.split(':').next());
+        return match user {
+            Some(user) => {
+                let detail = format!("found for {} in {}", user, path.display());
+                Check::pass(name, false, detail)
+            }
+            None => {
+                let detail = format!("can't decode the Docker Hub auth in {}", path.display());
+                Check::fail(name, false, detail, "log in again with docker login")
+            }
+        };
+    }
+
+    let helper = HUB_AUTH_KEYS
+        .iter()
+        .find_map(|key| config["credHelpers"][key].as_str())
+        .or_else(|| config["credsStore"].as_str());
+    let detail = match helper {
+        Some(helper) => format!("stored with credential helper {}", helper),
+        None => format!("none for Docker Hub in {}", path.display()),
+    };
+    Check::pass(name, false, detail)
+}
+
+/// Compares the local clock with the `Date` header of the token response
+///
+/// # Arguments
+///
+/// * `token` - `Token` from the token service
+fn check_clock(token: &Token) -> Check {
+    let name = String::from("clock");
+
+    let (server, local) = match (token.server_date, token.received_at) {
+        (Some(s), Some(l)) => (s, l),
+        _ => return Check::pass(name, false, "server sent no Date header".into()),
+    };
+
+    let (skew, direction) = match local.duration_since(server) {
+        Ok(ahead) => (ahead, "ahead of"),
+        Err(e) => (e.duration(), "behind"),
+    };
+    let detail = format!("{}s {} the server", skew.as_secs(), direction);
+    if skew > MAX_CLOCK_SKEW {
+        Check::fail(name, false, detail, "sync the clock, e.g. with NTP")
+    } else {
+        Check::pass(name, false, detail)
+    }
+}
+
+/// Checks the anonymous limit with a `HEAD` request, so none of it is used up
+///
+/// # Arguments
+///
+/// * `drl` - `DrlClient` to send the request with
+/// * `token` - `Token` from the token service
+async fn check_limit(drl: &DrlClient, token: &Token) -> Check {
+    let name = String::from("limit");
+
+    let result = drl
+        .probe(token, Method::HEAD)
+        .await
+        .and_then(|probe| probe.limited());
+    match result {
+        Ok(limit) => match &limit.source {
+            Some(source) => Check::pass(name, true, format!("{} for {}", limit, source)),
+            None => Check::pass(name, true, limit.to_string()),
+        },
+        Err(e) => {
+            let hint = match e.ret {
+                ExitCode::OverLimit => "the limit is used up, wait for it to reset",
+                _ => "if the connection checks passed, the registry may be down, try again later",
+            };
+            Check::fail(name, true, e.msg, hint)
+        }
+    }
+}
@@ -0,0 +1,50 @@
+//! Durations for time flags, e.g. `30s`, `5m`, `1h30m`, or `250ms`
+
+use std::time::Duration;
+
+/// Example shown when a duration can't be parsed
+const EXAMPLE: &str = "expected something like 30s, 5m, 1h30m, or 250ms";
+
+/// Parses a human readable duration
+///
+/// Bare numbers are seconds, and may be fractional
+///
+/// # Arguments
+///
+/// * `s` - duration to parse
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+
+    if let Ok(secs) = s.parse::<f64>() {
+        if secs.is_finite() && secs >= 0.0 {
+            return Ok(Duration::from_secs_f64(secs));
+        }
+        return Err(format!("invalid duration '{}': {}", s, EXAMPLE));
+    }
+
+    humantime::parse_duration(s).map_err(|_| format!("invalid duration '{}': {}", s, EXAMPLE))
+}
+
+/// Formats `d` the same way `parse_duration` reads it, e.g. `1h30m`
+///
+/// # Arguments
+///
+/// * `d` - duration to format
+pub fn format_duration(d: Duration) -> String {
+    humantime::format_duration(d).to_string().replace(' ', "")
+}
+
+/// Formats `d` as an age in its largest unit, rounded down, e.g. `12m` or `3d`
+///
+/// # Arguments
+///
+/// * `d` - age to format
+pub fn format_age(d: Duration) -> String {
+    let secs = d.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86399 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
@@ -1,12 +1,20 @@
 //! Exit Codes for libdocker-rl
+//!
+//! Every failure is a `DrlErr`, with an `ExitCode` and, where the caller can act on them, the
+//! details of what went wrong in its `Kind`. Nothing here prints, `DrlErr::exit` is for the CLI
 
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use std::error::Error;
 use std::fmt;
 use std::process;
+use std::sync::OnceLock;
+use std::time::Duration;
 
 /// Exit codes
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub enum ExitCode {
     /// Exit code for successful programs
+    #[default]
     Ok = 0,
     /// Exit code when limit has been exceeded
     OverLimit,
@@ -18,14 +26,151 @@ pub enum ExitCode {
     Body,
     /// Error parsing rate limit
     Parsing,
+    /// Error reading input
+    Input,
+    /// Exit code when the remaining limit is below a threshold
+    BelowThreshold,
+    /// Exit code when the check was cancelled before finishing
+    Cancelled,
+    /// Exit code when the limit belongs to another identity than the expected one
+    IdentityMismatch,
+    /// Exit code when the cached result is missing or too old
+    Stale,
+    /// Exit code when the remaining limit is below the warning threshold
+    Warning,
+    /// Exit code when the proxy refused the connection
+    Proxy,
+    /// Exit code when a request took longer than `--timeout` or `--connect-timeout`
+    Timeout,
 }
 
-impl Default for ExitCode {
-    fn default() -> Self {
-        ExitCode::Ok
+impl ExitCode {
+    /// Name of the code in JSON, e.g. `over_limit`
+    pub fn name(self) -> &'static str {
+        match self {
+            ExitCode::Ok => "ok",
+            ExitCode::OverLimit => "over_limit",
+            ExitCode::Unauthorized => "unauthorized",
+            ExitCode::Connection => "connection",
+            ExitCode::Body => "body",
+            ExitCode::Parsing => "parsing",
+            ExitCode::Input => "input",
+            ExitCode::BelowThreshold => "below_threshold",
+            ExitCode::Cancelled => "cancelled",
+            ExitCode::IdentityMismatch => "identity_mismatch",
+            ExitCode::Stale => "stale",
+            ExitCode::Warning => "warning",
+            ExitCode::Proxy => "proxy",
+            ExitCode::Timeout => "timeout",
+        }
     }
 }
 
+/// What went wrong, beyond the message
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Kind {
+    /// Nothing more than the message and the `ExitCode`
+    #[default]
+    Other,
+    /// The token service or the registry rejected the credentials or the token
+    Auth {
+        /// Status of the response
+        status: u16,
+    },
+    /// The registry answered `429`, the limit is used up
+    RateLimited {
+        /// How long to wait before trying again, from `Retry-After`
+        retry_after: Option<Duration>,
+    },
+    /// A rate limit header was missing or couldn't be parsed
+    Parse {
+        /// Name of the header
+        header: String,
+        /// Value of the header, `None` if it was missing or not text
+        raw_value: Option<String>,
+    },
+    /// The host couldn't be reached
+    Connection {
+        /// Host that was connected to
+        host: String,
+        /// What the connection failed with
+        source: String,
+    },
+    /// The proxy refused to connect to the host
+    Proxy {
+        /// Host the proxy was asked to connect to
+        host: String,
+    },
+    /// Connecting to the host or getting its answer took too long
+    Timeout {
+        /// Host that was connected to
+        host: String,
+    },
+}
+
+impl Kind {
+    /// Name of the kind in JSON, the name of `code` for `Kind::Other`
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - `ExitCode` of the error
+    pub fn name(&self, code: ExitCode) -> &'static str {
+        match self {
+            Kind::Other => code.name(),
+            Kind::Auth { .. } => "auth",
+            Kind::RateLimited { .. } => "rate_limited",
+            Kind::Parse { .. } => "parse",
+            Kind::Connection { .. } => "connection",
+            Kind::Proxy { .. } => "proxy",
+            Kind::Timeout { .. } => "timeout",
+        }
+    }
+}
+
+/// The installed exit style, if any
+static STYLE: OnceLock<ExitStyle> = OnceLock::new();
+
+/// How `ExitCode`s are turned into exit statuses
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExitStyle {
+    /// Every `ExitCode` exits with its own status
+    #[default]
+    Codes,
+    /// Monitoring plugin statuses: 0 OK, 1 WARNING, 2 CRITICAL, 3 UNKNOWN
+    Plugin,
+}
+
+impl ExitStyle {
+    /// Exit status for `code`
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - `ExitCode` to exit with
+    pub fn status(self, code: ExitCode) -> i32 {
+        match self {
+            ExitStyle::Codes => code as i32,
+            ExitStyle::Plugin => match code {
+                ExitCode::Ok => 0,
+                ExitCode::Warning => 1,
+                ExitCode::OverLimit | ExitCode::BelowThreshold | ExitCode::IdentityMismatch => 2,
+                _ => 3,
+            },
+        }
+    }
+
+    /// Makes this the style every exit uses
+    ///
+    /// Can only be done once, returns `false` if a style was already installed
+    pub fn install(self) -> bool {
+        STYLE.set(self).is_ok()
+    }
+}
+
+/// The installed `ExitStyle`, or `ExitStyle::Codes`
+pub fn installed() -> ExitStyle {
+    STYLE.get().copied().unwrap_or_default()
+}
+
 /// Wrapper around result to keep track of `ExitCode`s
 pub type DrlResult<T> = std::result::Result<T, DrlErr>;
 
@@ -36,24 +181,88 @@ impl fmt::Display for DrlErr {
     }
 }
 
+impl Error for DrlErr {}
+
 /// Wrapper around exit code
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct DrlErr {
     /// Message to print on exit
     pub msg: String,
     // ExitCode to use
     pub ret: ExitCode,
+    /// Details of what went wrong
+    pub kind: Kind,
 }
 
 impl DrlErr {
     /// Implements constructor
     pub fn new(msg: String, ret: ExitCode) -> DrlErr {
-        DrlErr { msg, ret }
+        DrlErr {
+            msg,
+            ret,
+            kind: Kind::Other,
+        }
+    }
+
+    /// The same error with the details in `kind`
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - `Kind` of the error
+    pub fn with_kind(mut self, kind: Kind) -> DrlErr {
+        self.kind = kind;
+        self
+    }
+
+    /// Exits with code, without printing anything
+    ///
+    /// Only for the CLI, the status depends on the installed `ExitStyle`
+    pub fn exit(&self) -> ! {
+        process::exit(installed().status(self.ret));
     }
 
     /// Prints message and exits with code
+    ///
+    /// Kept for code written against earlier versions, the CLI prints errors as `--format`
+    /// says and then calls `exit`
+    #[deprecated(note = "print the message as needed and call `exit`")]
     pub fn err_out(&self) -> ! {
         eprintln!("{}", &self.msg);
-        process::exit(self.ret as i32);
+        self.exit()
+    }
+}
+
+impl Serialize for DrlErr {
+    /// Serializes as an object of `kind`, `message`, `exit_code` and the fields of the `Kind`
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("kind", self.kind.name(self.ret))?;
+        map.serialize_entry("message", &self.msg)?;
+        map.serialize_entry("exit_code", &installed().status(self.ret))?;
+        match &self.kind {
+            Kind::Other => (),
+            Kind::Auth { status } => map.serialize_entry("status", status)?,
+            Kind::RateLimited { retry_after } => {
+                let secs = retry_after.map(|d| d.as_secs());
+                map.serialize_entry("retry_after_seconds", &secs)?;
+            }
+            Kind::Parse { header, raw_value } => {
+                map.serialize_entry("header", header)?;
+                map.serialize_entry("raw_value", raw_value)?;
+            }
+            Kind::Connection { host, source } => {
+                map.serialize_entry("host", host)?;
+                map.serialize_entry("source", source)?;
+            }
+            Kind::Proxy { host } | Kind::Timeout { host } => map.serialize_entry("host", host)?,
+        }
+        map.end()
     }
 }
+
+/// A failed run, as printed to stderr with `--format json`
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct Failure<'a> {
+    /// What went wrong
+    pub error: &'a DrlErr,
+}
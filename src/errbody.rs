@@ -0,0 +1,206 @@
+//! Error bodies sent by the registry and the token service
+//!
+//! The registry sends `{"errors":[{"code":"DENIED","message":"..."}]}`, the token service
+//! `{"details":"..."}`. Anything else is ignored
+
+use reqwest::Response;
+use serde::Deserialize;
+use std::fmt;
+
+/// Most of a body that is read, the rest is dropped
+const MAX_BODY: usize = 64 * 1024;
+
+/// Most of a message that is shown, in characters
+const MAX_MESSAGE: usize = 200;
+
+/// One entry of a registry error body
+#[derive(Deserialize, Debug)]
+struct RegistryError {
+    #[serde(default)]
+    code: Option<String>,
+    #[serde(default)]
+    message: Option<String>,
+}
+
+/// Either kind of error body
+#[derive(Deserialize, Debug)]
+struct Body {
+    #[serde(default)]
+    errors: Vec<RegistryError>,
+    #[serde(default)]
+    details: Option<String>,
+}
+
+/// What an error body said went wrong
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorBody {
+    /// Error code from the registry, e.g. `DENIED`
+    pub code: Option<String>,
+    /// Message for people, cut to a sensible length
+    pub message: String,
+}
+
+impl fmt::Display for ErrorBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.code {
+            Some(code) => write!(f, "({}): {}", code, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Cuts `message` down to `MAX_MESSAGE` characters, on one line
+fn shorten(message: &str) -> String {
+    let message = message.split_whitespace().collect::<Vec<_>>().join(" ");
+    if message.chars().count() <= MAX_MESSAGE {
+        return message;
+    }
+    let cut: String = message.chars().take(MAX_MESSAGE).collect();
+    format!("{}…", cut)
+}
+
+/// Parses an error body, `None` if it isn't one of the known kinds
+///
+/// Only the first registry error is kept
+///
+/// # Arguments
+///
+/// * `body` - raw response body
+pub fn parse(body: &[u8]) -> Option<ErrorBody> {
+    let body: Body = serde_json::from_slice(body).ok()?;
+
+    if let Some(first) = body.errors.into_iter().next() {
+        let message = first.message.filter(|m| !m.trim().is_empty());
+        return match (first.code, message) {
+            (code, Some(message)) => Some(ErrorBody {
+                code,
+                message: shorten(&message),
+            }),
+            (Some(code), None) => Some(ErrorBody {
+                code: None,
+                message: shorten(&code),
+            }),
+            (None, None) => None,
+        };
+    }
+
+    body.details
+        .filter(|d| !d.trim().is_empty())
+        .map(|d| ErrorBody {
+            code: None,
+            message: shorten(&d),
+        })
+}
+
+/// Reads and parses the error body of `resp`, dropping anything past `MAX_BODY`
+///
+/// # Arguments
+///
+/// * `resp` - response with an error status
+pub(crate) async fn read(mut resp: Response) -> Option<ErrorBody> {
+    let mut body = Vec::new();
+    while let Ok(Some(chunk)) = resp.chunk().await {
+        body.extend_from_slice(&chunk);
+        if body.len() > MAX_BODY {
+            // a cut off body isn't valid JSON anyway
+            return None;
+        }
+    }
+    parse(&body)
+}
+
+/// Appends what `detail` says to `msg`, e.g. `over limit (TOOMANYREQUESTS): ...`
+///
+/// # Arguments
+///
+/// * `msg` - message describing the failure
+/// * `detail` - parsed error body, if there was one
+pub(crate) fn describe(msg: String, detail: Option<ErrorBody>) -> String {
+    match detail {
+        Some(d @ ErrorBody { code: Some(_), .. }) => format!("{} {}", msg, d),
+        Some(d) => format!("{}: {}", msg, d),
+        None => msg,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry_error() {
+        let body = br#"{"errors":[{"code":"DENIED","message":"requested access to the resource is denied"},{"code":"UNAUTHORIZED","message":"second"}]}"#;
+        let parsed = parse(body).unwrap();
+        assert_eq!(parsed.code.as_deref(), Some("DENIED"));
+        assert_eq!(parsed.message, "requested access to the resource is denied");
+        assert_eq!(
+            parsed.to_string(),
+            "(DENIED): requested access to the resource is denied"
+        );
+    }
+
+    #[test]
+    fn registry_error_without_message() {
+        let parsed = parse(br#"{"errors":[{"code":"TOOMANYREQUESTS","message":" "}]}"#).unwrap();
+        assert_eq!(parsed.code, None);
+        assert_eq!(parsed.message, "TOOMANYREQUESTS");
+
+        assert_eq!(parse(br#"{"errors":[{}]}"#), None);
+    }
+
+    #[test]
+    fn token_service_details() {
+        let parsed = parse(br#"{"details":"incorrect username or password"}"#).unwrap();
+        assert_eq!(parsed.code, None);
+        assert_eq!(parsed.to_string(), "incorrect username or password");
+
+        assert_eq!(parse(br#"{"details":""}"#), None);
+    }
+
+    #[test]
+    fn unknown_bodies() {
+        for body in [
+            &b""[..],
+            b"<html>502 Bad Gateway</html>",
+            b"{}",
+            b"[]",
+            b"null",
+        ] {
+            assert_eq!(parse(body), None, "{:?}", String::from_utf8_lossy(body));
+        }
+    }
+
+    #[test]
+    fn messages_on_one_line() {
+        let parsed = parse(br#"{"details":"  first\n\tsecond  "}"#).unwrap();
+        assert_eq!(parsed.message, "first second");
+    }
+
+    #[test]
+    fn long_messages_cut() {
+        assert_eq!(shorten(&"x".repeat(MAX_MESSAGE)), "x".repeat(MAX_MESSAGE));
+
+        let cut = shorten(&"é".repeat(MAX_MESSAGE + 1));
+        assert_eq!(cut.chars().count(), MAX_MESSAGE + 1);
+        assert!(cut.ends_with("é…"));
+    }
+
+    #[test]
+    fn described() {
+        let coded = ErrorBody {
+            code: Some("DENIED".into()),
+            message: "no".into(),
+        };
+        let plain = ErrorBody {
+            code: None,
+            message: "no".into(),
+        };
+
+        assert_eq!(
+            describe("failed".into(), Some(coded)),
+            "failed (DENIED): no"
+        );
+        assert_eq!(describe("failed".into(), Some(plain)), "failed: no");
+        assert_eq!(describe("failed".into(), None), "failed");
+    }
+}
@@ -0,0 +1,89 @@
+//! Checks that a limit belongs to the identity it was meant for
+//!
+//! A typo'd or missing credential can quietly fall back to an anonymous token, and the limit
+//! shown is then the one of the IP
+
+use super::err::{DrlErr, DrlResult, ExitCode};
+use super::token::Token;
+use std::fmt;
+use std::net::IpAddr;
+
+/// Who a check was made as
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Identity {
+    /// No account, the limit is keyed to the IP
+    Anonymous,
+    /// The named account, or its id when the registry only gave that
+    User(String),
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Identity::Anonymous => write!(f, "anonymous"),
+            Identity::User(user) => write!(f, "{}", user),
+        }
+    }
+}
+
+impl Identity {
+    /// Works out who a check was made as, from the token or else what the limit is keyed to
+    ///
+    /// Returns `None` if neither says
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - `Token` the check was made with
+    /// * `source` - what the limit is keyed to, from `SOURCE_HEADER`
+    pub fn of(token: &Token, source: Option<&str>) -> Option<Identity> {
+        if let Some(user) = token.user() {
+            return Some(user.map_or(Identity::Anonymous, Identity::User));
+        }
+
+        // anonymous limits are keyed to the IP, authenticated ones to the account
+        let source = source?;
+        if source.parse::<IpAddr>().is_ok() {
+            Some(Identity::Anonymous)
+        } else {
+            Some(Identity::User(source.into()))
+        }
+    }
+
+    /// Whether this is the user `name`, ignoring case like Docker Hub does
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - username to compare against
+    pub fn is_user(&self, name: &str) -> bool {
+        match self {
+            Identity::Anonymous => false,
+            Identity::User(user) => user.eq_ignore_ascii_case(name),
+        }
+    }
+}
+
+/// Fails unless the check was made as `expected`
+///
+/// # Errors
+///
+/// Returns `ExitCode::IdentityMismatch` if `actual` is anonymous, another user, or unknown
+///
+/// # Arguments
+///
+/// * `expected` - username the limit should belong to
+/// * `actual` - `Identity` the check was made as, `None` if it's unknown
+pub fn expect_user(expected: &str, actual: Option<&Identity>) -> DrlResult<()> {
+    match actual {
+        Some(identity) if identity.is_user(expected) => Ok(()),
+        Some(identity) => {
+            let msg = format!("identity mismatch: expected {}, got {}", expected, identity);
+            let err = DrlErr::new(msg, ExitCode::IdentityMismatch);
+            Err(err)
+        }
+        None => {
+            let msg = format!("identity mismatch: expected {}, got unknown", expected);
+            let err = DrlErr::new(msg, ExitCode::IdentityMismatch);
+            Err(err)
+        }
+    }
+}
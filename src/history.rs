@@ -0,0 +1,156 @@
+//! History of checks for `--log-file`, one record appended per check
+//!
+//! Files ending in `.jsonl` or `.ndjson` get a JSON object per line, anything else CSV with a
+//! header. The file is opened again for every record, so each one is on disk right away and a
+//! file that was truncated or rotated away is started over, header included
+
+use super::err::{DrlErr, DrlResult, ExitCode};
+use super::limit::Limit;
+use super::timestamp::{Timestamp, Timestamps};
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// First line of a CSV history
+pub const CSV_HEADER: &str = "timestamp,profile,user,remaining,total,source";
+
+/// How a history file is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryFormat {
+    /// Comma separated values, with `CSV_HEADER` first
+    Csv,
+    /// One JSON object per line
+    JsonLines,
+}
+
+impl HistoryFormat {
+    /// Format of the history at `path`, from its extension
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - path of the history file
+    pub fn of(path: &Path) -> HistoryFormat {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("jsonl") | Some("ndjson") => HistoryFormat::JsonLines,
+            _ => HistoryFormat::Csv,
+        }
+    }
+}
+
+/// One check in the history
+#[derive(Serialize, Debug, Clone)]
+pub struct Entry {
+    /// When the check finished
+    pub timestamp: Timestamp,
+    /// Profile of the config file the check was for, if any
+    pub profile: Option<String>,
+    /// User the limit was checked for, `None` for anonymous
+    pub user: Option<String>,
+    /// Remaining pulls
+    pub remaining: u64,
+    /// Pulls in the window
+    pub total: u64,
+    /// What the limit is keyed to
+    pub source: Option<String>,
+}
+
+impl Entry {
+    /// Entry for `limit`, checked at `now`
+    ///
+    /// # Arguments
+    ///
+    /// * `profile` - profile the check was for, `None` without a config file
+    /// * `user` - user the limit was checked for, `None` for anonymous
+    /// * `limit` - the limit
+    /// * `now` - when the check finished
+    /// * `timestamps` - how to write `now`
+    pub fn new(
+        profile: Option<String>,
+        user: Option<String>,
+        limit: &Limit,
+        now: SystemTime,
+        timestamps: &Timestamps,
+    ) -> Entry {
+        Entry {
+            timestamp: timestamps.render(now),
+            profile,
+            user,
+            remaining: limit.remaining,
+            total: limit.total,
+            source: limit.source.clone(),
+        }
+    }
+
+    /// The entry as a CSV row, in the order of `CSV_HEADER`
+    pub fn csv(&self) -> String {
+        let fields = [
+            self.timestamp.to_string(),
+            self.profile.clone().unwrap_or_default(),
+            self.user.clone().unwrap_or_default(),
+            self.remaining.to_string(),
+            self.total.to_string(),
+            self.source.clone().unwrap_or_default(),
+        ];
+        fields
+            .iter()
+            .map(|f| csv_field(f))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+/// Quotes `field` if it has a comma, quote or line break in it
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.into()
+    }
+}
+
+/// Appends `entry` to the history at `path`, creating it if needed
+///
+/// A CSV history that is new or empty gets `CSV_HEADER` first
+///
+/// # Errors
+///
+/// Returns `ExitCode::Input` if the file can't be opened or written
+///
+/// # Arguments
+///
+/// * `path` - path of the history file
+/// * `entry` - `Entry` to append
+pub fn append(path: &Path, entry: &Entry) -> DrlResult<()> {
+    let failed = |e: &dyn std::fmt::Display| {
+        let msg = format!("failed to write history {}: {}", path.display(), e);
+        DrlErr::new(msg, ExitCode::Input)
+    };
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| failed(&e))?;
+
+    let mut lines = String::new();
+    match HistoryFormat::of(path) {
+        HistoryFormat::Csv => {
+            let empty = file.metadata().map(|m| m.len() == 0).unwrap_or(false);
+            if empty {
+                lines.push_str(CSV_HEADER);
+                lines.push('\n');
+            }
+            lines.push_str(&entry.csv());
+        }
+        // only derived impls get here, which can't fail
+        HistoryFormat::JsonLines => {
+            lines.push_str(&serde_json::to_string(entry).expect("failed to serialize entry"))
+        }
+    }
+    lines.push('\n');
+
+    // one write, so a record is never split by another process appending
+    file.write_all(lines.as_bytes()).map_err(|e| failed(&e))
+}
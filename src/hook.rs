@@ -0,0 +1,291 @@
+//! Commands run by `--on-breach` and `--on-recover`, when a polled limit crosses `--notify-below`
+//!
+//! Like the command of an alert, a hook runs through the shell, with the limit in `HOOK_VARS`.
+//! `Crossings` tells when to run them: the breach hook once when the remaining pulls go below
+//! the threshold, the recover hook once when they are back up to it. Hooks run in the
+//! background, and a failing one is only logged, the polling goes on
+
+use super::alert::shell;
+use super::err::{DrlErr, DrlResult, ExitCode};
+use super::limit::Limit;
+use super::logtarget::LogSink;
+use std::fmt;
+use std::process::Stdio;
+
+/// Environment variables the hooks get: remaining, total, user, source and threshold
+///
+/// The user is empty for anonymous checks, and so is the source if the registry didn't say
+pub const HOOK_VARS: &[&str] = &[
+    "DRL_REMAINING",
+    "DRL_TOTAL",
+    "DRL_USER",
+    "DRL_SOURCE",
+    "DRL_THRESHOLD",
+];
+
+/// Which way a limit crossed the threshold
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Crossing {
+    /// The remaining pulls went below the threshold
+    Breach,
+    /// The remaining pulls are back up to the threshold or above
+    Recover,
+}
+
+impl fmt::Display for Crossing {
+    /// The option of the hook, `on-breach` or `on-recover`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Crossing::Breach => write!(f, "on-breach"),
+            Crossing::Recover => write!(f, "on-recover"),
+        }
+    }
+}
+
+/// Tracks the checks of one identity, to tell when they cross the threshold
+///
+/// A limit that starts out below the threshold is a breach, one that starts out above it isn't
+/// a recovery
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crossings {
+    /// Remaining pulls below which the limit is breached
+    below: u64,
+    /// Whether the last check was below the threshold
+    breached: bool,
+}
+
+impl Crossings {
+    /// Crossings of `below`, starting out above it
+    ///
+    /// # Arguments
+    ///
+    /// * `below` - remaining pulls below which the limit is breached
+    pub fn new(below: u64) -> Crossings {
+        Crossings {
+            below,
+            breached: false,
+        }
+    }
+
+    /// Counts a check, returning which way it crossed the threshold, if it did
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - `Limit` from the check
+    pub fn check(&mut self, limit: &Limit) -> Option<Crossing> {
+        let was = self.breached;
+        self.breached = limit.remaining < self.below;
+        match (was, self.breached) {
+            (false, true) => Some(Crossing::Breach),
+            (true, false) => Some(Crossing::Recover),
+            _ => None,
+        }
+    }
+}
+
+/// The hooks of `--on-breach` and `--on-recover`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hooks {
+    /// Remaining pulls below which the limit is breached, `--notify-below`
+    pub below: u64,
+    /// Shell command to run when the limit goes below `below`
+    pub on_breach: Option<String>,
+    /// Shell command to run when the limit is back up to `below`
+    pub on_recover: Option<String>,
+}
+
+impl Hooks {
+    /// `Crossings` of the threshold, one for each identity polled
+    pub fn crossings(&self) -> Crossings {
+        Crossings::new(self.below)
+    }
+
+    /// Values of `HOOK_VARS` for `limit`, in the same order
+    ///
+    /// # Arguments
+    ///
+    /// * `user` - user the limit was checked for, `None` for anonymous
+    /// * `limit` - `Limit` that crossed the threshold
+    pub fn vars(&self, user: Option<&str>, limit: &Limit) -> Vec<(&'static str, String)> {
+        let values = vec![
+            limit.remaining.to_string(),
+            limit.total.to_string(),
+            user.unwrap_or_default().to_string(),
+            limit.source.clone().unwrap_or_default(),
+            self.below.to_string(),
+        ];
+        HOOK_VARS.iter().copied().zip(values).collect()
+    }
+
+    /// Runs the hook for `crossing`, if there is one
+    ///
+    /// # Errors
+    ///
+    /// Returns `ExitCode::Warning` if the command can't be run or exits with anything but 0,
+    /// with what it wrote to stderr
+    ///
+    /// # Arguments
+    ///
+    /// * `crossing` - which way the limit crossed the threshold
+    /// * `user` - user the limit was checked for, `None` for anonymous
+    /// * `limit` - `Limit` that crossed the threshold
+    pub async fn run(
+        &self,
+        crossing: Crossing,
+        user: Option<&str>,
+        limit: &Limit,
+    ) -> DrlResult<()> {
+        let cmd = match crossing {
+            Crossing::Breach => &self.on_breach,
+            Crossing::Recover => &self.on_recover,
+        };
+        let cmd = match cmd {
+            Some(c) => c,
+            None => return Ok(()),
+        };
+
+        let output = shell(cmd)
+            .envs(self.vars(user, limit))
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output()
+            .await;
+        let failure = match output {
+            Ok(o) if o.status.success() => return Ok(()),
+            Ok(o) => {
+                let said = String::from_utf8_lossy(&o.stderr);
+                match said.trim() {
+                    "" => format!("--{} hook failed: {}", crossing, o.status),
+                    said => format!("--{} hook failed: {}: {}", crossing, o.status, said),
+                }
+            }
+            Err(e) => format!("failed to run --{} hook: {}", crossing, e),
+        };
+
+        let err = DrlErr::new(failure, ExitCode::Warning);
+        Err(err)
+    }
+
+    /// Runs the hook for `crossing` in the background, logging a failure to `log`
+    ///
+    /// Polling goes on while the hook runs, however long it takes
+    ///
+    /// # Arguments
+    ///
+    /// * `crossing` - which way the limit crossed the threshold
+    /// * `user` - user the limit was checked for, `None` for anonymous
+    /// * `limit` - `Limit` that crossed the threshold
+    /// * `log` - `LogSink` to warn on
+    pub fn spawn(&self, crossing: Crossing, user: Option<&str>, limit: &Limit, log: &LogSink) {
+        let (hooks, user, limit, log) = (
+            self.clone(),
+            user.map(String::from),
+            limit.clone(),
+            log.clone(),
+        );
+        tokio::spawn(async move {
+            if let Err(e) = hooks.run(crossing, user.as_deref(), &limit).await {
+                log.warn(&e.msg);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn limit(remaining: u64) -> Limit {
+        Limit {
+            remaining,
+            total: 100,
+            window: Duration::from_secs(21600),
+            source: Some(String::from("203.0.113.7")),
+        }
+    }
+
+    fn hooks(on_breach: &str, on_recover: &str) -> Hooks {
+        Hooks {
+            below: 10,
+            on_breach: Some(on_breach.into()),
+            on_recover: Some(on_recover.into()),
+        }
+    }
+
+    #[test]
+    fn once_per_crossing() {
+        let mut crossings = Crossings::new(10);
+        let checks = [50, 12, 8, 5, 3, 10, 20, 9, 9, 10];
+        let seen: Vec<Option<Crossing>> =
+            checks.iter().map(|r| crossings.check(&limit(*r))).collect();
+        let (b, r) = (Some(Crossing::Breach), Some(Crossing::Recover));
+        assert_eq!(seen, [None, None, b, None, None, r, None, b, None, r]);
+    }
+
+    #[test]
+    fn starting_below_is_a_breach() {
+        let mut crossings = Crossings::new(10);
+        assert_eq!(crossings.check(&limit(0)), Some(Crossing::Breach));
+    }
+
+    #[test]
+    fn vars() {
+        let vars = hooks("", "").vars(Some("ci-bot"), &limit(8));
+        assert_eq!(
+            vars,
+            [
+                ("DRL_REMAINING", String::from("8")),
+                ("DRL_TOTAL", String::from("100")),
+                ("DRL_USER", String::from("ci-bot")),
+                ("DRL_SOURCE", String::from("203.0.113.7")),
+                ("DRL_THRESHOLD", String::from("10")),
+            ]
+        );
+
+        let vars = hooks("", "").vars(None, &Limit::default());
+        assert_eq!(vars[2], ("DRL_USER", String::new()));
+        assert_eq!(vars[3], ("DRL_SOURCE", String::new()));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn runs_the_hook_of_the_crossing() {
+        let hooks = hooks(
+            "test \"$DRL_REMAINING/$DRL_TOTAL<$DRL_THRESHOLD\" = 8/100\\<10",
+            "exit 3",
+        );
+        assert!(hooks.run(Crossing::Breach, None, &limit(8)).await.is_ok());
+
+        let err = hooks
+            .run(Crossing::Recover, None, &limit(12))
+            .await
+            .unwrap_err();
+        assert_eq!(err.ret, ExitCode::Warning);
+        assert_eq!(err.msg, "--on-recover hook failed: exit status: 3");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn failures_come_with_stderr() {
+        let hooks = hooks("echo 'pager is down' >&2; echo ignored; exit 1", "");
+        let err = hooks
+            .run(Crossing::Breach, None, &limit(8))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.msg,
+            "--on-breach hook failed: exit status: 1: pager is down"
+        );
+    }
+
+    #[tokio::test]
+    async fn no_hook_for_the_crossing() {
+        let hooks = Hooks {
+            below: 10,
+            on_breach: None,
+            on_recover: None,
+        };
+        assert!(hooks.run(Crossing::Breach, None, &limit(8)).await.is_ok());
+    }
+}
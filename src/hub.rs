@@ -0,0 +1,144 @@
+//! Small client for the Docker Hub API on `hub.docker.com`
+//!
+//! Only used to look up the plan of an account, the registry doesn't report it
+
+use super::api::DrlClient;
+use super::client::Http;
+use super::err::{DrlErr, DrlResult, ExitCode, Kind};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+/// Base URL of the Docker Hub API, unless `DrlClientBuilder::hub_url` says otherwise
+pub const HUB_URL: &str = "https://hub.docker.com/v2";
+
+/// Body of the login request
+#[derive(Serialize, Debug)]
+struct Login<'a> {
+    username: &'a str,
+    password: &'a str,
+}
+
+/// Response to the login request
+#[derive(Deserialize, Debug)]
+struct LoginResponse {
+    token: String,
+}
+
+/// The parts of the logged in user `docker-rl` cares about
+#[derive(Deserialize, Debug, Default)]
+pub struct HubUser {
+    /// Username of the account
+    #[serde(default)]
+    pub username: String,
+    /// Plan of the account, e.g. `free` or `pro`
+    #[serde(default, alias = "plan_name")]
+    pub plan: Option<String>,
+}
+
+/// Logs in to the Docker Hub API, returning a JWT for it
+///
+/// This is not the same token as the registry uses
+///
+/// # Arguments
+///
+/// * `http` - `Http` to send the request with
+/// * `hub_url` - base URL of the API
+/// * `user` - username
+/// * `pass` - password or personal access token
+async fn login(http: &Http, hub_url: &str, user: &str, pass: &str) -> DrlResult<String> {
+    let url = format!("{}/users/login", hub_url);
+    let body = Login {
+        username: user,
+        password: pass,
+    };
+    let req = http.client.post(&url).json(&body);
+
+    let resp = match req.send().await {
+        Ok(r) => r,
+        Err(e) => return Err(http.connect_error("hub.docker.com", e)),
+    };
+
+    match resp.status() {
+        StatusCode::OK => (),
+        StatusCode::UNAUTHORIZED => {
+            let msg = format!("hub.docker.com login failed for {}", user);
+            let err =
+                DrlErr::new(msg, ExitCode::Unauthorized).with_kind(Kind::Auth { status: 401 });
+            return Err(err);
+        }
+        _ => {
+            let msg = format!("unknown response from hub.docker.com {:?}", resp.status());
+            let err = DrlErr::new(msg, ExitCode::Connection);
+            return Err(err);
+        }
+    };
+
+    match resp.json::<LoginResponse>().await {
+        Ok(l) => Ok(l.token),
+        Err(e) => {
+            let msg = format!("failed to parse hub.docker.com response: {}", e);
+            let err = DrlErr::new(msg, ExitCode::Body);
+            Err(err)
+        }
+    }
+}
+
+/// Gets the logged in user from the Docker Hub API, including its plan
+///
+/// # Errors
+///
+/// Returns `ExitCode::Unauthorized` if the login is rejected, `ExitCode::Connection` if the API
+/// can't be reached or answers anything else, and `ExitCode::Body` if it can't be parsed
+///
+/// # Arguments
+///
+/// * `client` - `DrlClient` to send the requests with
+/// * `user` - username
+/// * `pass` - password or personal access token
+pub async fn get_hub_user(client: &DrlClient, user: &str, pass: &str) -> DrlResult<HubUser> {
+    let http = client.http();
+    let jwt = login(http, client.hub_url(), user, pass).await?;
+
+    let url = format!("{}/user/", client.hub_url());
+    let req = http.client.get(&url).bearer_auth(jwt);
+
+    let resp = match req.send().await {
+        Ok(r) => r,
+        Err(e) => return Err(http.connect_error("hub.docker.com", e)),
+    };
+
+    if resp.status() != StatusCode::OK {
+        let msg = format!("unknown response from hub.docker.com {:?}", resp.status());
+        let err = DrlErr::new(msg, ExitCode::Connection);
+        return Err(err);
+    }
+
+    match resp.json::<HubUser>().await {
+        Ok(u) => Ok(u),
+        Err(e) => {
+            let msg = format!("failed to parse hub.docker.com response: {}", e);
+            let err = DrlErr::new(msg, ExitCode::Body);
+            Err(err)
+        }
+    }
+}
+
+/// Gets the plan name of the account
+///
+/// # Errors
+///
+/// An error is returned if the API can't be reached, or doesn't report a plan
+///
+/// # Arguments
+///
+/// * `client` - `DrlClient` to send the requests with
+/// * `user` - username
+/// * `pass` - password or personal access token
+pub async fn get_plan(client: &DrlClient, user: &str, pass: &str) -> DrlResult<String> {
+    let hub_user = get_hub_user(client, user, pass).await?;
+
+    hub_user.plan.ok_or_else(|| {
+        let msg = format!("hub.docker.com reported no plan for {}", user);
+        DrlErr::new(msg, ExitCode::Body)
+    })
+}
@@ -0,0 +1,92 @@
+//! Friendlier formatting of counts for `--human`
+//!
+//! Separators are always `,`, whatever the locale, so the output is the same everywhere
+
+use super::limit::Limit;
+
+/// Formats `n` with thousands separators, e.g. `49,987`
+///
+/// # Arguments
+///
+/// * `n` - count to format
+pub fn count(n: u64) -> String {
+    let digits = n.to_string();
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(c);
+    }
+
+    out
+}
+
+/// Formats a percentage rounded to a whole number, or `-` if there is none
+///
+/// # Arguments
+///
+/// * `percent` - percentage to format, see `Limit::percent`
+pub fn percent(percent: Option<f64>) -> String {
+    match percent {
+        Some(p) => format!("{:.0}%", p),
+        None => String::from("-"),
+    }
+}
+
+/// Formats `limit` with separators and the percentage left, e.g. `49,987/50,000 (100%)`
+///
+/// # Arguments
+///
+/// * `limit` - `Limit` to format
+pub fn limit(limit: &Limit) -> String {
+    format!(
+        "{}/{} ({})",
+        count(limit.remaining),
+        count(limit.total),
+        percent(limit.percent())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn separators() {
+        let cases = [
+            (0, "0"),
+            (999, "999"),
+            (1000, "1,000"),
+            (49987, "49,987"),
+            (100000, "100,000"),
+            (1234567, "1,234,567"),
+            (u64::MAX, "18,446,744,073,709,551,615"),
+        ];
+        for (n, expected) in cases {
+            assert_eq!(count(n), expected);
+        }
+    }
+
+    #[test]
+    fn percent_rounded() {
+        assert_eq!(percent(Some(99.97)), "100%");
+        assert_eq!(percent(Some(42.4)), "42%");
+        assert_eq!(percent(Some(0.0)), "0%");
+        assert_eq!(percent(None), "-");
+    }
+
+    #[test]
+    fn limits() {
+        let limit = Limit {
+            remaining: 49987,
+            total: 50000,
+            ..Limit::default()
+        };
+        assert_eq!(super::limit(&limit), "49,987/50,000 (100%)");
+
+        let unknown = Limit::default();
+        assert_eq!(super::limit(&unknown), "0/0 (-)");
+    }
+}
@@ -0,0 +1,119 @@
+//! Whether pulling one image counts against the limit, for `--image`
+//!
+//! The manifest of the image is asked for with `HEAD`, which doesn't use anything up, with a
+//! token scoped to its repository. A pull is the `GET` of that manifest, and of the platform's
+//! one after it for a manifest list, which Docker Hub counts as one pull
+
+use super::client::Http;
+use super::err::DrlResult;
+use super::limit::{fetch_limit, Limit, Probe, MANIFEST_LIST_TYPES};
+use super::registry::Registry;
+use super::token::{fetch_anon_token, Scope, Token};
+use reqwest::Method;
+use serde::Serialize;
+use std::fmt;
+
+/// What a pull of one image would do to the limit
+#[derive(Serialize, Debug, Clone)]
+pub struct ImageCheck {
+    /// The image, e.g. `library/ubuntu:22.04`
+    pub image: String,
+    /// Whether a pull of it counts against the limit, `false` if the registry reports none
+    pub counts: bool,
+    /// The limit it counts against, if it does
+    #[serde(flatten)]
+    pub limit: Option<Limit>,
+    /// Whether the image is a manifest list, with a manifest per platform
+    pub manifest_list: bool,
+    /// Media type of its manifest
+    pub media_type: Option<String>,
+    /// Digest of its manifest
+    pub digest: Option<String>,
+}
+
+impl ImageCheck {
+    /// Check of the manifest of `registry` from `probe`
+    ///
+    /// # Arguments
+    ///
+    /// * `registry` - `Registry` with the repository and reference of the image
+    /// * `probe` - `Probe` of its manifest
+    pub fn new(registry: &Registry, probe: &Probe) -> ImageCheck {
+        let media_type = probe
+            .media_type
+            .as_deref()
+            .map(|t| t.split(';').next().unwrap_or_default().trim().to_string());
+        let manifest_list = media_type
+            .as_deref()
+            .is_some_and(|t| MANIFEST_LIST_TYPES.contains(&t));
+
+        ImageCheck {
+            image: image_name(registry),
+            counts: !probe.unlimited,
+            limit: probe.limited().ok(),
+            manifest_list,
+            media_type,
+            digest: probe.digest.clone(),
+        }
+    }
+}
+
+impl fmt::Display for ImageCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.limit {
+            Some(limit) if self.counts => write!(
+                f,
+                "{}: a pull counts against the limit, {} remaining",
+                self.image, limit
+            )?,
+            _ => write!(f, "{}: a pull doesn't count against any limit", self.image)?,
+        }
+        if self.manifest_list {
+            write!(f, " (manifest list, counted once)")?;
+        }
+        Ok(())
+    }
+}
+
+/// `repository:tag` or `repository@digest` of the manifest of `registry`
+fn image_name(registry: &Registry) -> String {
+    let separator = if registry.reference.contains(':') {
+        '@'
+    } else {
+        ':'
+    };
+    format!("{}{}{}", registry.repository, separator, registry.reference)
+}
+
+/// Checks the image `repo` at `reference` on Docker Hub, with an anonymous token
+///
+/// See `api::DrlClient::image` for other registries and settings
+///
+/// # Errors
+///
+/// The same as `token::get_anon_token` and `limit::probe_limit`, a registry that doesn't answer
+/// `HEAD` fails rather than using up a pull
+///
+/// # Arguments
+///
+/// * `repo` - repository of the image, e.g. `library/ubuntu`
+/// * `reference` - tag or digest, e.g. `22.04`
+pub async fn get_limit_for(repo: &str, reference: &str) -> DrlResult<ImageCheck> {
+    let mut registry = Registry::default();
+    registry.repository = registry.full_name(repo);
+    registry.reference = reference.into();
+
+    let http = Http::default();
+    let token = fetch_anon_token(&http, &registry, &Scope::Pull).await?;
+    fetch_image(&http, &registry, &token).await
+}
+
+/// Checks the image of `registry` with `t`, which has to be scoped to it, using `http`
+pub(crate) async fn fetch_image(
+    http: &Http,
+    registry: &Registry,
+    t: &Token,
+) -> DrlResult<ImageCheck> {
+    let probe = fetch_limit(http, registry, t, Method::HEAD).await?;
+    Ok(ImageCheck::new(registry, &probe))
+}
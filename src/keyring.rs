@@ -0,0 +1,321 @@
+//! Docker Hub credentials saved in the OS keyring by `docker-rl login`
+//!
+//! The keyring is reached through the tool each platform ships: `security` for the macOS
+//! Keychain, `secret-tool` for the Secret Service, and PowerShell for the Windows Credential
+//! Locker. One login is kept, as a single entry holding the user and the secret, so checks
+//! without `-u` can use it too
+//!
+//! Without the `keyring` feature, on by default, there is no keyring: nothing is loaded, and
+//! `store` and `delete` fail
+
+use super::err::{DrlErr, DrlResult, ExitCode};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+#[cfg(feature = "keyring")]
+use {
+    std::io::{self, Write},
+    std::process::{Command, Stdio},
+};
+
+/// Service the entry is saved under
+pub const SERVICE: &str = "docker-rl";
+
+/// Account the entry is saved under, the user is kept in the entry itself
+#[cfg(feature = "keyring")]
+const ACCOUNT: &str = "docker-hub";
+
+/// Exit code of the keyring tool when there is no entry
+#[cfg(all(feature = "keyring", any(target_os = "macos", windows)))]
+const NOT_FOUND: i32 = 44;
+#[cfg(all(feature = "keyring", not(any(target_os = "macos", windows))))]
+const NOT_FOUND: i32 = 1;
+
+/// Credentials saved in the keyring
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Login {
+    /// Docker Hub username
+    pub user: String,
+    /// Password or access token
+    pub secret: String,
+    /// Whether `secret` is an access token
+    #[serde(default)]
+    pub token: bool,
+}
+
+impl fmt::Debug for Login {
+    /// Leaves out the secret
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Login {{ user: {:?}, token: {:?}, .. }}",
+            self.user, self.token
+        )
+    }
+}
+
+/// A run of the keyring tool
+#[cfg(feature = "keyring")]
+struct Call {
+    program: &'static str,
+    args: Vec<String>,
+    /// Written to its stdin, so secrets stay out of the process list
+    input: Option<String>,
+}
+
+#[cfg(feature = "keyring")]
+impl Call {
+    /// Runs the tool, returning its stdout, or `None` if the entry isn't there
+    ///
+    /// With `missing_ok`, a tool that isn't installed is the same as no entry
+    fn run(self, missing_ok: bool) -> DrlResult<Option<String>> {
+        let program = self.program;
+        let failed = |e: &dyn fmt::Display| {
+            let msg = format!("keyring ({}) failed: {}", program, e);
+            DrlErr::new(msg, ExitCode::Input)
+        };
+
+        let child = Command::new(program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn();
+        let mut child = match child {
+            Ok(c) => c,
+            Err(e) if missing_ok && e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                let msg = format!("can't reach the keyring, failed to run {}: {}", program, e);
+                let err = DrlErr::new(msg, ExitCode::Input);
+                return Err(err);
+            }
+        };
+
+        // dropped right after, so the tool sees the end of its input
+        if let Some(mut stdin) = child.stdin.take() {
+            let input = self.input.unwrap_or_default();
+            stdin.write_all(input.as_bytes()).map_err(|e| failed(&e))?;
+        }
+        let output = child.wait_with_output().map_err(|e| failed(&e))?;
+
+        // secret-tool exits 1 for any failure, but only says something for the others
+        let said = String::from_utf8_lossy(&output.stderr);
+        let quiet = said.trim().is_empty() || cfg!(any(target_os = "macos", windows));
+        match output.status.code() {
+            Some(0) => Ok(Some(String::from_utf8_lossy(&output.stdout).into_owned())),
+            Some(NOT_FOUND) if quiet => Ok(None),
+            _ => Err(failed(&said.trim())),
+        }
+    }
+}
+
+/// Encodes `login` for the entry, base64 keeps it to one word for every tool
+#[cfg(feature = "keyring")]
+fn encode(login: &Login) -> String {
+    // only derived impls get here, which can't fail
+    let json = serde_json::to_vec(login).expect("failed to serialize login");
+    base64::encode(json)
+}
+
+/// Decodes the entry written by `encode`
+#[cfg(feature = "keyring")]
+fn decode(entry: &str) -> DrlResult<Login> {
+    let login = base64::decode(entry.trim())
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok());
+    match login {
+        Some(l) => Ok(l),
+        None => {
+            let msg = format!("can't decode the {} keyring entry, log in again", SERVICE);
+            let err = DrlErr::new(msg, ExitCode::Input);
+            Err(err)
+        }
+    }
+}
+
+#[cfg(all(feature = "keyring", target_os = "macos"))]
+fn store_call(entry: String) -> Call {
+    // -i reads the command from stdin instead of taking the secret as an argument
+    let command = format!(
+        "add-generic-password -U -s {} -a {} -w {}\n",
+        SERVICE, ACCOUNT, entry
+    );
+    Call {
+        program: "security",
+        args: vec!["-i".into()],
+        input: Some(command),
+    }
+}
+
+#[cfg(all(feature = "keyring", target_os = "macos"))]
+fn lookup_call() -> Call {
+    Call {
+        program: "security",
+        args: ["find-generic-password", "-s", SERVICE, "-a", ACCOUNT, "-w"]
+            .iter()
+            .map(|a| a.to_string())
+            .collect(),
+        input: None,
+    }
+}
+
+#[cfg(all(feature = "keyring", target_os = "macos"))]
+fn delete_call() -> Call {
+    Call {
+        program: "security",
+        args: ["delete-generic-password", "-s", SERVICE, "-a", ACCOUNT]
+            .iter()
+            .map(|a| a.to_string())
+            .collect(),
+        input: None,
+    }
+}
+
+#[cfg(all(feature = "keyring", windows))]
+fn powershell(script: String, input: Option<String>) -> Call {
+    let vault = "[void][Windows.Security.Credentials.PasswordVault,Windows.Security.Credentials,\
+                 ContentType=WindowsRuntime]; $v = New-Object Windows.Security.Credentials.PasswordVault";
+    Call {
+        program: "powershell",
+        args: vec![
+            "-NoProfile".into(),
+            "-NonInteractive".into(),
+            "-Command".into(),
+            format!("{}; {}", vault, script),
+        ],
+        input,
+    }
+}
+
+#[cfg(all(feature = "keyring", windows))]
+fn find() -> String {
+    format!(
+        "try {{ $c = $v.Retrieve('{}', '{}') }} catch {{ exit {} }}",
+        SERVICE, ACCOUNT, NOT_FOUND
+    )
+}
+
+#[cfg(all(feature = "keyring", windows))]
+fn store_call(entry: String) -> Call {
+    let script = format!(
+        "$c = New-Object Windows.Security.Credentials.PasswordCredential('{}', '{}', \
+         [Console]::In.ReadToEnd().Trim()); $v.Add($c)",
+        SERVICE, ACCOUNT
+    );
+    powershell(script, Some(entry))
+}
+
+#[cfg(all(feature = "keyring", windows))]
+fn lookup_call() -> Call {
+    let script = format!("{}; $c.RetrievePassword(); $c.Password", find());
+    powershell(script, None)
+}
+
+#[cfg(all(feature = "keyring", windows))]
+fn delete_call() -> Call {
+    let script = format!("{}; $v.Remove($c)", find());
+    powershell(script, None)
+}
+
+#[cfg(all(feature = "keyring", not(any(target_os = "macos", windows))))]
+fn attributes() -> Vec<String> {
+    ["service", SERVICE, "account", ACCOUNT]
+        .iter()
+        .map(|a| a.to_string())
+        .collect()
+}
+
+#[cfg(all(feature = "keyring", not(any(target_os = "macos", windows))))]
+fn store_call(entry: String) -> Call {
+    let mut args = vec!["store".into(), "--label=docker-rl Docker Hub login".into()];
+    args.extend(attributes());
+    Call {
+        program: "secret-tool",
+        args,
+        input: Some(entry),
+    }
+}
+
+#[cfg(all(feature = "keyring", not(any(target_os = "macos", windows))))]
+fn lookup_call() -> Call {
+    let mut args = vec!["lookup".to_string()];
+    args.extend(attributes());
+    Call {
+        program: "secret-tool",
+        args,
+        input: None,
+    }
+}
+
+#[cfg(all(feature = "keyring", not(any(target_os = "macos", windows))))]
+fn delete_call() -> Call {
+    let mut args = vec!["clear".to_string()];
+    args.extend(attributes());
+    Call {
+        program: "secret-tool",
+        args,
+        input: None,
+    }
+}
+
+/// Error of `store` and `delete` without a keyring
+#[cfg(not(feature = "keyring"))]
+fn unsupported() -> DrlErr {
+    let msg = "built without the keyring feature, so credentials can't be saved";
+    DrlErr::new(msg.to_string(), ExitCode::Input)
+}
+
+/// Saves `login` in the keyring, replacing the one there
+///
+/// # Errors
+///
+/// Returns `ExitCode::Input` if the keyring can't be reached or refuses the entry
+///
+/// # Arguments
+///
+/// * `login` - `Login` to save
+#[cfg(feature = "keyring")]
+pub fn store(login: &Login) -> DrlResult<()> {
+    store_call(encode(login)).run(false).map(|_| ())
+}
+
+#[cfg(not(feature = "keyring"))]
+pub fn store(_login: &Login) -> DrlResult<()> {
+    Err(unsupported())
+}
+
+/// The `Login` saved in the keyring, `None` if there is none or no keyring tool is installed
+///
+/// # Errors
+///
+/// Returns `ExitCode::Input` if the keyring fails or the entry can't be decoded
+#[cfg(feature = "keyring")]
+pub fn load() -> DrlResult<Option<Login>> {
+    match lookup_call().run(true)? {
+        Some(entry) if !entry.trim().is_empty() => decode(&entry).map(Some),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(not(feature = "keyring"))]
+pub fn load() -> DrlResult<Option<Login>> {
+    Ok(None)
+}
+
+/// Removes the `Login` from the keyring, returning it, or `None` if there was none
+///
+/// # Errors
+///
+/// Returns `ExitCode::Input` if the keyring can't be reached or refuses to remove the entry
+#[cfg(feature = "keyring")]
+pub fn delete() -> DrlResult<Option<Login>> {
+    let login = load()?;
+    if login.is_some() {
+        delete_call().run(false)?;
+    }
+    Ok(login)
+}
+
+#[cfg(not(feature = "keyring"))]
+pub fn delete() -> DrlResult<Option<Login>> {
+    Err(unsupported())
+}
@@ -1,8 +1,68 @@
 //! Library for `docker-rl
 //!
 //! Can be used to get rate limit for Docker Hub
+//!
+//! `api::DrlClient` is the entry point, the free functions in `token` and `limit` create a new
+//! client for every call. With the `blocking` feature, `blocking` has the same without async
+//!
+//! # Runtime
+//!
+//! The async functions need a tokio 1 runtime with its IO and time drivers, e.g. from
+//! `#[tokio::main]` or `runtime::Builder::enable_all`, as reqwest and the polling timers are
+//! built on them. Polled on another executor they panic. The `blocking` functions bring a
+//! runtime of their own, one worker thread shared by every call, and refuse to run inside a
+//! tokio runtime, returning `ExitCode::Input` instead of blocking it, see `blocking`
 
+pub mod accounts;
+pub mod alert;
+pub mod api;
+pub mod backoff;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod burn;
+pub mod cacert;
+pub mod cancel;
+pub mod client;
+pub mod configfile;
+pub mod creds;
+pub mod doctor;
+pub mod duration;
 pub mod err;
+pub mod errbody;
+pub mod expect;
+pub mod history;
+pub mod hook;
+pub mod hub;
+pub mod human;
+pub mod identity;
+pub mod image;
+pub mod keyring;
 pub mod limit;
+#[cfg(feature = "tracing")]
+pub mod logging;
+pub mod logtarget;
+pub mod metrics;
+pub mod nagios;
+pub mod need;
+pub mod notify;
 pub mod options;
+pub mod pace;
+pub mod plan;
+pub mod progress;
+pub mod quota;
+pub mod registry;
+pub mod report;
+pub mod retry;
+pub mod schema;
+pub mod serve;
+pub mod settings;
+pub mod state;
+pub mod table;
+pub mod template;
+pub mod threshold;
+pub mod timestamp;
 pub mod token;
+pub mod tokencache;
+mod trace;
+pub mod verify;
+pub mod watch;
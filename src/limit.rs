@@ -1,19 +1,73 @@
-//! Gets limit from `docker.io`'s ratelimitpreview manifest
+//! Gets limit from `docker.io`'s ratelimitpreview manifest, or another registry's
 
-use super::err::{DrlErr, DrlResult, ExitCode};
-use super::token::Token;
-use reqwest::header::HeaderMap;
-use reqwest::{Client, StatusCode};
+use super::backoff::{Backoff, BackoffPolicy};
+use super::client::Http;
+use super::duration::format_age;
+use super::err::{DrlErr, DrlResult, ExitCode, Kind};
+use super::errbody;
+use super::pace::{Pace, Paced, Pacing};
+use super::registry::Registry;
+use super::token::{Token, TokenProvider};
+use super::trace;
+use futures::stream::{self, Stream, StreamExt};
+use reqwest::header::{HeaderMap, ACCEPT, CONTENT_TYPE, RETRY_AFTER};
+use reqwest::{Method, StatusCode};
+use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::net::IpAddr;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::Semaphore;
+use tokio::time::{self, Interval, MissedTickBehavior};
 
 /// The current state of the rate limit
-#[derive(Debug, Default, Copy, Clone)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Limit {
     /// Number of remaining requests of the rate limit, out of `total`
     pub remaining: u64,
     /// Total number of possible requests for the rate limit
     pub total: u64,
+    /// Length of the rate limit window, zero if the registry didn't say
+    #[serde(rename = "window_seconds", with = "seconds", default)]
+    pub window: Duration,
+    /// What the limit is keyed to, from `SOURCE_HEADER`
+    ///
+    /// An IP for anonymous checks, which everyone behind the same NAT shares, or an account
+    #[serde(default)]
+    pub source: Option<String>,
+}
+
+/// (De)serializes a `Duration` as whole seconds
+mod seconds {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(d: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(d.as_secs())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_secs)
+    }
+}
+
+impl Limit {
+    /// Whether the limit is keyed to an IP rather than an account
+    pub fn keyed_to_ip(&self) -> bool {
+        self.source
+            .as_deref()
+            .is_some_and(|s| s.parse::<IpAddr>().is_ok())
+    }
+
+    /// Percentage of the limit remaining, `None` if `total` is 0
+    pub fn percent(&self) -> Option<f64> {
+        if self.total == 0 {
+            None
+        } else {
+            Some(self.remaining as f64 * 100.0 / self.total as f64)
+        }
+    }
 }
 
 impl fmt::Display for Limit {
@@ -22,6 +76,67 @@ impl fmt::Display for Limit {
     }
 }
 
+/// Manifest used to check the rate limit on `docker.io`, see `Registry::manifest_url`
+pub const LIMIT_URL: &str =
+    "https://registry-1.docker.io/v2/ratelimitpreview/test/manifests/latest";
+
+/// Response headers that show a cache sits between us and the registry
+pub const CACHE_HEADERS: &[&str] = &["age", "x-cache", "via"];
+
+/// Header naming what the limit is keyed to, an IP or an account
+pub const SOURCE_HEADER: &str = "docker-ratelimit-source";
+
+/// Header with the digest of the manifest
+pub const DIGEST_HEADER: &str = "docker-content-digest";
+
+/// Media types of manifests pointing to one per platform
+pub const MANIFEST_LIST_TYPES: &[&str] = &[
+    "application/vnd.docker.distribution.manifest.list.v2+json",
+    "application/vnd.oci.image.index.v1+json",
+];
+
+/// Media types of manifests of a single image
+pub const MANIFEST_TYPES: &[&str] = &[
+    "application/vnd.docker.distribution.manifest.v2+json",
+    "application/vnd.oci.image.manifest.v1+json",
+];
+
+/// The limit along with the response details around it
+#[derive(Debug, Clone, Default)]
+pub struct Probe {
+    /// The rate limit
+    pub limit: Limit,
+    /// Any `CACHE_HEADERS` in the response, with their values
+    pub cache_headers: Vec<(String, String)>,
+    /// Whether the response had no limit at all, as for accounts without one
+    ///
+    /// `limit` is zeroed when this is set, except for its `source`
+    pub unlimited: bool,
+    /// Method the limit was read with, only `GET` uses up a pull
+    pub method: Method,
+    /// `Content-Type` of the manifest, e.g. `MANIFEST_LIST_TYPES` for a multi-platform image
+    pub media_type: Option<String>,
+    /// `Docker-Content-Digest` of the manifest
+    pub digest: Option<String>,
+    /// Name of the registry that was checked, e.g. `docker.io`
+    pub registry: String,
+}
+
+impl Probe {
+    /// The limit, or an error if the registry didn't report one
+    pub fn limited(&self) -> DrlResult<Limit> {
+        if self.unlimited {
+            let msg = format!(
+                "error parsing rate limit: {} reported no limit",
+                self.registry
+            );
+            let err = DrlErr::new(msg, ExitCode::Parsing);
+            return Err(err);
+        }
+        Ok(self.limit.clone())
+    }
+}
+
 /// Parse the named header `key` from `headers`.
 ///
 /// # Errors
@@ -32,60 +147,317 @@ fn parse_header<T: FromStr>(headers: &HeaderMap, key: &str) -> DrlResult<T>
 where
     T::Err: fmt::Display,
 {
-    let header = headers
-        .get(key)
-        .ok_or_else(|| DrlErr::new("error parsing rate limit".into(), ExitCode::Parsing))?;
+    let kind = |raw_value: Option<&str>| Kind::Parse {
+        header: key.into(),
+        raw_value: raw_value.map(String::from),
+    };
+    let header = headers.get(key).ok_or_else(|| {
+        let msg = format!("error parsing rate limit: no {} header", key);
+        DrlErr::new(msg, ExitCode::Parsing).with_kind(kind(None))
+    })?;
 
     let value = header.to_str().map_err(|e| {
-        DrlErr::new(
-            format!("error parsing rate limit: {}", e),
-            ExitCode::Parsing,
-        )
+        let msg = format!("error parsing rate limit: {}", e);
+        DrlErr::new(msg, ExitCode::Parsing).with_kind(kind(None))
     })?;
 
     // Take up to the first semicolon, or the end
     let end = value.find(';').unwrap_or(value.len());
-    let value = &value[..end];
 
-    T::from_str(value).map_err(|e| {
-        DrlErr::new(
-            format!("error parsing rate limit: {}", e),
-            ExitCode::Parsing,
-        )
+    T::from_str(&value[..end]).map_err(|e| {
+        let msg = format!("error parsing rate limit: {}", e);
+        DrlErr::new(msg, ExitCode::Parsing).with_kind(kind(Some(value)))
     })
 }
 
-/// Gets rate limit from `docker.io`
+/// Longest wait taken from `Retry-After`, longer ones are cut down to it
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Parses `Retry-After`, either seconds or an HTTP date, as the time left to wait from `now`
+///
+/// Returns `None` if there is none, or it can't be parsed. The wait is at most a week, whatever
+/// the registry said
+///
+/// # Arguments
+///
+/// * `headers` - headers of the response
+/// * `now` - current time on the local clock
+pub fn parse_retry_after(headers: &HeaderMap, now: SystemTime) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    let wait = match value.parse::<u64>() {
+        Ok(secs) => Duration::from_secs(secs),
+        // a date in the past means now
+        Err(_) => {
+            let at = httpdate::parse_http_date(value).ok()?;
+            at.duration_since(now).unwrap_or_default()
+        }
+    };
+    Some(wait.min(MAX_RETRY_AFTER))
+}
+
+/// Parses the window from the `w` parameter of `ratelimit-limit`, e.g. `100;w=21600`
+///
+/// Returns a zero `Duration` if there is none
+fn parse_window(headers: &HeaderMap) -> Duration {
+    headers
+        .get("ratelimit-limit")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| {
+            v.split(';')
+                .skip(1)
+                .find_map(|param| param.trim().strip_prefix("w=")?.parse().ok())
+        })
+        .map(Duration::from_secs)
+        .unwrap_or_default()
+}
+
+/// Message for a `429`, with when the limit resets if the registry said
+///
+/// e.g. `over limit, resets in ~42m (at 14:32 UTC)`, or `over limit, resets within 6h` with only
+/// the window to go by
+///
+/// # Arguments
+///
+/// * `retry_after` - time until the limit resets, from `Retry-After`
+/// * `window` - length of the rate limit window, zero if the registry didn't say
+/// * `now` - current time on the local clock
+fn over_limit(retry_after: Option<Duration>, window: Duration, now: SystemTime) -> String {
+    match retry_after {
+        Some(d) => match now.checked_add(d).and_then(|at| reset_at(at, d)) {
+            Some(at) => format!("over limit, resets in ~{} (at {} UTC)", format_age(d), at),
+            None => format!("over limit, resets in ~{}", format_age(d)),
+        },
+        None if !window.is_zero() => {
+            format!("over limit, resets within {}", format_age(window))
+        }
+        None => String::from("over limit"),
+    }
+}
+
+/// When the limit resets, as `14:32`, or `2021-08-07 14:32` unless it's within a day
+///
+/// Returns `None` if `at` is before the Unix epoch
+///
+/// # Arguments
+///
+/// * `at` - time the limit resets
+/// * `wait` - time until then
+fn reset_at(at: SystemTime, wait: Duration) -> Option<String> {
+    let secs = at.duration_since(UNIX_EPOCH).ok()?.as_secs();
+    let time = format!("{:02}:{:02}", secs / 3600 % 24, secs / 60 % 60);
+    if wait < Duration::from_secs(24 * 60 * 60) {
+        return Some(time);
+    }
+    let (year, month, day) = civil_date(secs / (24 * 60 * 60));
+    Some(format!("{}-{:02}-{:02} {}", year, month, day, time))
+}
+
+/// Year, month and day of the `days`th day since the Unix epoch, in the proleptic Gregorian
+/// calendar
+///
+/// Unlike `humantime`, works for years past 9999
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    // from 0000-03-01, so leap days come last in the 400 year eras
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    (year, month, day)
+}
+
+/// How long to wait before polling again after `result`, `None` to keep to the interval
+///
+/// Only a `429` whose `Retry-After` is further away than the next poll waits longer
+fn backoff(result: &DrlResult<Limit>, interval: Duration) -> Option<Duration> {
+    match result {
+        Err(DrlErr {
+            kind: Kind::RateLimited {
+                retry_after: Some(d),
+            },
+            ..
+        }) if *d > interval => Some(*d),
+        _ => None,
+    }
+}
+
+/// Gets rate limit from `docker.io`, without using any of it up
+///
+/// See `peek_limit`
 ///
 /// # Arguments
 ///
 /// `t` - `Token` JWT token from `docker.io`
+///
+/// # Errors
+///
+/// A `401` from the registry is returned as `ExitCode::Unauthorized`, meaning the token has
+/// to be replaced even if `Token::is_expired` says otherwise
 pub async fn get_limit(t: &Token) -> DrlResult<Limit> {
-    let client = Client::new();
-    let url = "https://registry-1.docker.io/v2/ratelimitpreview/test/manifests/latest";
-    let req = client.get(url);
-    let req = req.bearer_auth(t.token.as_str());
+    let probe = peek_limit(t).await?;
+    probe.limited()
+}
 
-    // send request
-    let resp = match req.send().await {
-        Ok(r) => r,
-        Err(e) => {
-            let msg = format!("failed to connect to docker.io: {}", e);
+/// Gets rate limit from `docker.io` with a `HEAD` request, so none of it is used up
+///
+/// Registries that don't answer `HEAD`, or leave the limit off it, are asked with `GET`
+/// instead, which does use up a pull
+///
+/// # Arguments
+///
+/// * `t` - `Token` JWT token from `docker.io`
+///
+/// # Errors
+///
+/// See `get_limit`
+pub async fn peek_limit(t: &Token) -> DrlResult<Probe> {
+    fetch_limit_peek(&Http::default(), &Registry::default(), t).await
+}
+
+/// Gets rate limit from `docker.io`, along with the response details around it
+///
+/// `HEAD` requests don't count against the limit, `GET` requests do
+///
+/// # Arguments
+///
+/// * `t` - `Token` JWT token from `docker.io`
+/// * `method` - `Method` of the manifest request
+///
+/// # Errors
+///
+/// See `get_limit`
+pub async fn probe_limit(t: &Token, method: Method) -> DrlResult<Probe> {
+    fetch_limit(&Http::default(), &Registry::default(), t, method).await
+}
+
+/// Gets rate limit from `registry` using `http`, see `probe_limit`
+///
+/// # Arguments
+///
+/// * `http` - `Http` to send the request with
+/// * `registry` - `Registry` to check
+/// * `t` - `Token` JWT token from `docker.io`
+/// * `method` - `Method` of the manifest request
+pub(crate) async fn fetch_limit(
+    http: &Http,
+    registry: &Registry,
+    t: &Token,
+    method: Method,
+) -> DrlResult<Probe> {
+    match fetch_probe(http, registry, t, method.clone()).await? {
+        Some(probe) => Ok(probe),
+        None => {
+            let name = &registry.name;
+            let msg = format!("error connecting to {}: {} not supported", name, method);
             let err = DrlErr::new(msg, ExitCode::Connection);
-            return Err(err);
+            Err(err)
+        }
+    }
+}
+
+/// Gets rate limit from `registry` using `http`, see `peek_limit`
+///
+/// # Arguments
+///
+/// * `http` - `Http` to send the request with
+/// * `registry` - `Registry` to check
+/// * `t` - `Token` JWT token from `docker.io`
+pub(crate) async fn fetch_limit_peek(
+    http: &Http,
+    registry: &Registry,
+    t: &Token,
+) -> DrlResult<Probe> {
+    match fetch_probe(http, registry, t, Method::HEAD).await? {
+        Some(probe) if !probe.unlimited => Ok(probe),
+        // accounts without a limit get no headers on `GET` either, which doesn't use anything up
+        _ => {
+            trace::retry("no limit in HEAD response");
+            fetch_limit(http, registry, t, Method::GET).await
         }
+    }
+}
+
+/// Does the work of `fetch_limit`
+///
+/// Returns `None` if the registry doesn't support `method` for the manifest
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        name = "get_limit",
+        skip_all,
+        fields(registry = %registry.host(), %method, status = tracing::field::Empty, elapsed_ms = tracing::field::Empty)
+    )
+)]
+async fn fetch_probe(
+    http: &Http,
+    registry: &Registry,
+    t: &Token,
+    method: Method,
+) -> DrlResult<Option<Probe>> {
+    // without these, registries may answer with an older manifest than a pull would get
+    let accept = MANIFEST_LIST_TYPES
+        .iter()
+        .chain(MANIFEST_TYPES)
+        .copied()
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut req = http
+        .client
+        .request(method.clone(), registry.manifest_url())
+        .header(ACCEPT, accept);
+    // registries without a token service get no token
+    if !t.token.is_empty() {
+        req = req.bearer_auth(t.token.as_str());
+    }
+
+    // send request
+    let started = Instant::now();
+    let resp = match http.send(req).await {
+        Ok(r) => r,
+        Err(e) => return Err(http.connect_error(&registry.name, e)),
     };
 
+    trace::response(resp.status(), started.elapsed());
+
     // check for over limit status code
     match resp.status() {
         StatusCode::OK => (),
+        StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED => return Ok(None),
+        StatusCode::PROXY_AUTHENTICATION_REQUIRED => {
+            return Err(http.proxy_auth_error(&registry.name));
+        }
+        StatusCode::UNAUTHORIZED => {
+            // the registry has the final say, whatever the token's expiry looks like locally
+            let msg = format!("token rejected by {}", registry.name);
+            let msg = errbody::describe(msg, errbody::read(resp).await);
+            let err =
+                DrlErr::new(msg, ExitCode::Unauthorized).with_kind(Kind::Auth { status: 401 });
+            return Err(err);
+        }
+        StatusCode::FORBIDDEN => {
+            let msg = String::from("registry denied the request");
+            let msg = errbody::describe(msg, errbody::read(resp).await);
+            let err =
+                DrlErr::new(msg, ExitCode::Unauthorized).with_kind(Kind::Auth { status: 403 });
+            return Err(err);
+        }
         StatusCode::TOO_MANY_REQUESTS => {
-            let msg = String::from("over limit");
-            let err = DrlErr::new(msg, ExitCode::OverLimit);
+            let now = SystemTime::now();
+            let retry_after = parse_retry_after(resp.headers(), now);
+            let msg = over_limit(retry_after, parse_window(resp.headers()), now);
+            let msg = errbody::describe(msg, errbody::read(resp).await);
+            let err =
+                DrlErr::new(msg, ExitCode::OverLimit).with_kind(Kind::RateLimited { retry_after });
             return Err(err);
         }
-        _ => {
-            let msg = format!("error connecting to docker.io: {}", resp.status());
+        status => {
+            let msg = format!("error connecting to {}: {}", registry.name, status);
+            let msg = errbody::describe(msg, errbody::read(resp).await);
             let err = DrlErr::new(msg, ExitCode::Connection);
             return Err(err);
         }
@@ -94,9 +466,346 @@ pub async fn get_limit(t: &Token) -> DrlResult<Limit> {
     // limits stored in the headers
     let headers = resp.headers();
 
+    let source = headers
+        .get(SOURCE_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from);
+
+    let text = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from)
+    };
+    let media_type = text(CONTENT_TYPE.as_str());
+    let digest = text(DIGEST_HEADER);
+
+    let cache_headers = CACHE_HEADERS
+        .iter()
+        .filter_map(|name| {
+            let value = headers.get(*name)?.to_str().ok()?;
+            Some((name.to_string(), value.to_string()))
+        })
+        .collect();
+
+    // accounts without a limit get neither header
+    if !headers.contains_key("ratelimit-limit") && !headers.contains_key("ratelimit-remaining") {
+        return Ok(Some(Probe {
+            limit: Limit {
+                source,
+                ..Limit::default()
+            },
+            cache_headers,
+            unlimited: true,
+            method,
+            media_type,
+            digest,
+            registry: registry.name.clone(),
+        }));
+    }
+
     // get rate limit
     let total: u64 = parse_header(headers, "ratelimit-limit")?;
     let remaining: u64 = parse_header(headers, "ratelimit-remaining")?;
+    let window = parse_window(headers);
+
+    Ok(Some(Probe {
+        limit: Limit {
+            remaining,
+            total,
+            window,
+            source,
+        },
+        cache_headers,
+        unlimited: false,
+        method,
+        media_type,
+        digest,
+        registry: registry.name.clone(),
+    }))
+}
+
+/// Gets the limit with a token from `provider`
+///
+/// A token rejected by the registry is replaced and the check retried once
+async fn poll_limit(provider: &mut TokenProvider) -> DrlResult<Limit> {
+    poll_probe(provider).await.and_then(|p| p.limited())
+}
+
+/// Does the work of `poll_limit`, keeping the response details around the limit
+pub(crate) async fn poll_probe(provider: &mut TokenProvider) -> DrlResult<Probe> {
+    let http = provider.http().clone();
+    let registry = provider.registry().clone();
+
+    let token = provider.token().await?;
+    match fetch_limit_peek(&http, &registry, token).await {
+        Err(e) if e.ret == ExitCode::Unauthorized => {
+            trace::retry("token rejected by the registry");
+            provider.invalidate();
+            let token = provider.token().await?;
+            fetch_limit_peek(&http, &registry, token).await
+        }
+        result => result,
+    }
+}
 
-    Ok(Limit { remaining, total })
+/// Yields a fresh `Limit` every `interval`, starting right away
+///
+/// Tokens come from `provider`, which replaces them as they expire. Failed checks are yielded
+/// as `Err` without ending the stream, and polling stops when the stream is dropped. Polls that
+/// run late push back the following ones rather than bursting to catch up. Once the limit is
+/// used up, there is no poll before the `Retry-After` of the registry, if that is later than
+/// the next one. Checks that keep failing back off as `BackoffPolicy::default` says, see
+/// `poll_limits_with`
+///
+/// # Panics
+///
+/// Panics if `interval` is zero
+///
+/// # Arguments
+///
+/// * `provider` - `TokenProvider` for the identity to check
+/// * `interval` - time between checks
+pub fn poll_limits(
+    provider: TokenProvider,
+    interval: Duration,
+) -> impl Stream<Item = DrlResult<Limit>> {
+    poll_limits_with(provider, interval, BackoffPolicy::default()).map(|(result, _)| result)
+}
+
+/// Does the work of `poll_limits`, backing off as `policy` says
+///
+/// Each check is yielded along with the `Backoff` after it, which has the failures in a row and
+/// the time until the next check
+///
+/// # Panics
+///
+/// Panics if `interval` is zero
+///
+/// # Arguments
+///
+/// * `provider` - `TokenProvider` for the identity to check
+/// * `interval` - time between checks while they succeed
+/// * `policy` - `BackoffPolicy` for checks that keep failing
+pub fn poll_limits_with(
+    provider: TokenProvider,
+    interval: Duration,
+    policy: BackoffPolicy,
+) -> impl Stream<Item = (DrlResult<Limit>, Backoff)> {
+    poll_limits_bounded(provider, interval, policy, None)
+}
+
+/// Does the work of `poll_limits_with`, with a permit from `permits` for every check
+///
+/// Streams sharing `permits` have no more checks in flight between them than it has permits,
+/// a check waiting for one pushes back the following ones
+///
+/// # Panics
+///
+/// Panics if `interval` is zero
+pub(crate) fn poll_limits_bounded(
+    provider: TokenProvider,
+    interval: Duration,
+    policy: BackoffPolicy,
+    permits: Option<Arc<Semaphore>>,
+) -> impl Stream<Item = (DrlResult<Limit>, Backoff)> {
+    assert!(interval > Duration::default(), "interval must be non-zero");
+
+    let new_ticker = move || {
+        let mut ticker = time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        ticker
+    };
+
+    // the interval needs a runtime, so it's only created on the first poll
+    let failures = Backoff::new(interval, policy);
+    let state: (TokenProvider, Option<Interval>, Option<Duration>, Backoff) =
+        (provider, None, None, failures);
+
+    stream::unfold(state, move |(mut provider, ticker, wait, mut failures)| {
+        let permits = permits.clone();
+        async move {
+            let mut ticker = match (ticker, wait) {
+                (Some(ticker), None) => ticker,
+                // the polls after the wait keep to the interval from there
+                (_, Some(wait)) => {
+                    time::sleep(wait).await;
+                    new_ticker()
+                }
+                (None, None) => new_ticker(),
+            };
+
+            ticker.tick().await;
+            // the semaphore is never closed, so there is always a permit eventually
+            let permit = match &permits {
+                Some(p) => p.acquire().await.ok(),
+                None => None,
+            };
+            let result = poll_limit(&mut provider).await;
+            drop(permit);
+            let delay = failures.record(result.is_err());
+            let wait = match backoff(&result, interval) {
+                Some(reset) if reset >= delay => {
+                    trace::backoff(reset);
+                    Some(reset)
+                }
+                _ if failures.backing_off() => {
+                    trace::failing(failures.failures(), delay);
+                    Some(delay)
+                }
+                _ => None,
+            };
+            Some(((result, failures), (provider, Some(ticker), wait, failures)))
+        }
+    })
+}
+
+/// Does the work of `poll_limits_with`, choosing the time between checks as `pacing` says
+///
+/// Each check is yielded along with the `Backoff` and the `Pace` after it. Successful checks
+/// are followed by the interval `pace::choose` picks from the recent ones, failed checks by
+/// the backoff from `Pacing::min`, or the `Retry-After` of the registry if that is later
+///
+/// # Panics
+///
+/// Panics if `Pacing::min` is zero
+///
+/// # Arguments
+///
+/// * `provider` - `TokenProvider` for the identity to check
+/// * `pacing` - `Pacing` for the time between checks
+/// * `policy` - `BackoffPolicy` for checks that keep failing
+pub fn poll_limits_adaptive(
+    provider: TokenProvider,
+    pacing: Pacing,
+    policy: BackoffPolicy,
+) -> impl Stream<Item = (DrlResult<Limit>, Backoff, Pace)> {
+    assert!(
+        pacing.min > Duration::default(),
+        "interval must be non-zero"
+    );
+
+    let failures = Backoff::new(pacing.min, policy);
+    let state: (TokenProvider, Option<Duration>, Paced, Backoff) =
+        (provider, None, Paced::new(pacing), failures);
+
+    stream::unfold(
+        state,
+        |(mut provider, wait, mut paced, mut failures)| async move {
+            if let Some(wait) = wait {
+                time::sleep(wait).await;
+            }
+
+            let result = poll_limit(&mut provider).await;
+            let delay = failures.record(result.is_err());
+            let wait = match &result {
+                Ok(limit) => paced.record(limit, SystemTime::now()).interval,
+                Err(_) => match backoff(&result, delay) {
+                    Some(reset) => {
+                        trace::backoff(reset);
+                        reset
+                    }
+                    None => {
+                        if failures.backing_off() {
+                            trace::failing(failures.failures(), delay);
+                        }
+                        delay
+                    }
+                },
+            };
+            let pace = paced.pace();
+            Some((
+                (result, failures, pace),
+                (provider, Some(wait), paced, failures),
+            ))
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    /// 2021-08-06T17:04:05Z
+    fn now() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_628_269_445)
+    }
+
+    fn retry_after(value: &str) -> Option<Duration> {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(value).unwrap());
+        parse_retry_after(&headers, now())
+    }
+
+    #[test]
+    fn retry_after_seconds_and_dates() {
+        assert_eq!(retry_after("120"), Some(Duration::from_secs(120)));
+        let date = "Fri, 06 Aug 2021 17:14:05 GMT";
+        assert_eq!(retry_after(date), Some(Duration::from_secs(600)));
+        assert_eq!(retry_after("soon"), None);
+        assert_eq!(parse_retry_after(&HeaderMap::new(), now()), None);
+    }
+
+    #[test]
+    fn retry_after_in_the_past_is_now() {
+        let date = "Thu, 05 Aug 2021 17:04:05 GMT";
+        assert_eq!(retry_after(date), Some(Duration::ZERO));
+        assert_eq!(
+            over_limit(retry_after(date), Duration::ZERO, now()),
+            "over limit, resets in ~0s (at 17:04 UTC)"
+        );
+    }
+
+    #[test]
+    fn huge_retry_after_is_capped() {
+        let wait = retry_after(&u64::MAX.to_string());
+        assert_eq!(wait, Some(MAX_RETRY_AFTER));
+        assert_eq!(
+            over_limit(wait, Duration::ZERO, now()),
+            "over limit, resets in ~7d (at 2021-08-13 17:04 UTC)"
+        );
+    }
+
+    #[test]
+    fn over_limit_messages() {
+        let wait = Some(Duration::from_secs(42 * 60));
+        assert_eq!(
+            over_limit(wait, Duration::ZERO, now()),
+            "over limit, resets in ~42m (at 17:46 UTC)"
+        );
+        let window = Duration::from_secs(21600);
+        assert_eq!(
+            over_limit(None, window, now()),
+            "over limit, resets within 6h"
+        );
+        assert_eq!(over_limit(None, Duration::ZERO, now()), "over limit");
+    }
+
+    #[test]
+    fn over_limit_past_the_end_of_time() {
+        // no panic and no time of day when it can't be told
+        let wait = Duration::from_secs(u64::MAX);
+        assert_eq!(
+            over_limit(Some(wait), Duration::ZERO, now()),
+            format!("over limit, resets in ~{}", format_age(wait))
+        );
+    }
+
+    #[test]
+    fn reset_at_years_past_9999() {
+        let at = UNIX_EPOCH + Duration::from_secs(253_402_300_800 + 3600 + 120);
+        let wait = Duration::from_secs(48 * 3600);
+        assert_eq!(reset_at(at, wait).as_deref(), Some("10000-01-01 01:02"));
+    }
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        // 2021-08-06, and the leap days of 2000 and 2024
+        assert_eq!(civil_date(18_845), (2021, 8, 6));
+        assert_eq!(civil_date(11_016), (2000, 2, 29));
+        assert_eq!(civil_date(19_782), (2024, 2, 29));
+        assert_eq!(civil_date(19_783), (2024, 3, 1));
+    }
 }
@@ -0,0 +1,344 @@
+//! A small `tracing` subscriber printing to stderr, or the `--log-target`, for `-vv` and
+//! `RUST_LOG`
+//!
+//! `RUST_LOG` takes a level, e.g. `debug`, and `target=level` pairs separated by commas, e.g.
+//! `libdocker_rl=trace,hyper=debug`. The longest matching target wins. Only built with the
+//! `tracing` feature
+
+use super::err::{DrlErr, DrlResult, ExitCode};
+use super::logtarget::{LogSink, Priority};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::fmt::{self, Write as _};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Metadata, Subscriber};
+use tracing_core::span::Current;
+
+/// Environment variable with the filter
+pub const ENV: &str = "RUST_LOG";
+
+/// Target of the library's own events
+const TARGET: &str = "libdocker_rl";
+
+/// Which events are printed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    /// Level of targets without a directive of their own
+    default: LevelFilter,
+    /// Levels of targets and the modules inside them
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    /// Filter for `-v` passed `verbose` times, `None` if nothing is logged
+    ///
+    /// `-v` only prints the usual details, `-vv` the debug events of the library and `-vvv`
+    /// its trace events as well
+    ///
+    /// # Arguments
+    ///
+    /// * `verbose` - number of times `-v` was passed
+    pub fn verbosity(verbose: u8) -> Option<Filter> {
+        let level = match verbose {
+            0 | 1 => return None,
+            2 => LevelFilter::DEBUG,
+            _ => LevelFilter::TRACE,
+        };
+        Some(Filter {
+            default: LevelFilter::WARN,
+            targets: vec![(TARGET.into(), level)],
+        })
+    }
+
+    /// Parses a filter like `RUST_LOG`'s
+    ///
+    /// # Arguments
+    ///
+    /// * `s` - filter to parse, e.g. `debug` or `libdocker_rl=trace,hyper=debug`
+    pub fn parse(s: &str) -> Result<Filter, String> {
+        let mut filter = Filter {
+            default: LevelFilter::ERROR,
+            targets: Vec::new(),
+        };
+
+        for directive in s.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let level = |l: &str| {
+                l.parse::<LevelFilter>()
+                    .map_err(|_| format!("invalid level '{}' in {}", l, ENV))
+            };
+            match directive.split_once('=') {
+                Some((target, l)) => filter.targets.push((target.into(), level(l)?)),
+                // a bare target means everything of it
+                None if directive.parse::<LevelFilter>().is_err() => {
+                    filter.targets.push((directive.into(), LevelFilter::TRACE))
+                }
+                None => filter.default = level(directive)?,
+            }
+        }
+        Ok(filter)
+    }
+
+    /// Level of `target`
+    fn level(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .filter(|(t, _)| {
+                target == t
+                    || target
+                        .strip_prefix(t.as_str())
+                        .is_some_and(|r| r.starts_with("::"))
+            })
+            .max_by_key(|(t, _)| t.len())
+            .map_or(self.default, |(_, l)| *l)
+    }
+
+    /// The most verbose level of any target
+    fn max_level(&self) -> LevelFilter {
+        self.targets
+            .iter()
+            .map(|(_, l)| *l)
+            .fold(self.default, LevelFilter::max)
+    }
+}
+
+/// Fields of a span or event, formatted as `key=value`
+#[derive(Default)]
+struct Fields {
+    /// The `message` field
+    message: String,
+    /// The other fields
+    rest: String,
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            self.record_debug(field, &value)
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+            return;
+        }
+        if !self.rest.is_empty() {
+            self.rest.push(' ');
+        }
+        let _ = write!(self.rest, "{}={:?}", field.name(), value);
+    }
+}
+
+/// What is kept of an open span
+struct SpanData {
+    metadata: &'static Metadata<'static>,
+    fields: Fields,
+    /// Handles to the span, it's dropped once there are none
+    refs: usize,
+}
+
+thread_local! {
+    /// Spans entered on this thread, innermost last
+    static STACK: RefCell<Vec<Id>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Subscriber printing events with the spans they happened in
+pub struct Logger {
+    filter: Filter,
+    sink: LogSink,
+    next_id: AtomicU64,
+    spans: Mutex<HashMap<u64, SpanData>>,
+}
+
+impl Logger {
+    /// Logger printing what `filter` lets through
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - `Filter` of the events to print
+    /// * `sink` - `LogSink` the events are written to
+    pub fn new(filter: Filter, sink: LogSink) -> Logger {
+        Logger {
+            filter,
+            sink,
+            next_id: AtomicU64::new(1),
+            spans: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The spans of this thread, formatted as `name{fields}:` from the outermost
+    fn scope(&self) -> String {
+        let spans = self.spans.lock().expect("poisoned span lock");
+        STACK.with(|stack| {
+            let mut out = String::new();
+            for id in stack.borrow().iter() {
+                if let Some(span) = spans.get(&id.into_u64()) {
+                    let _ = match span.fields.rest.as_str() {
+                        "" => write!(out, "{}: ", span.metadata.name()),
+                        fields => write!(out, "{}{{{}}}: ", span.metadata.name(), fields),
+                    };
+                }
+            }
+            out
+        })
+    }
+}
+
+impl Subscriber for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        *metadata.level() <= self.filter.level(metadata.target())
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        Some(self.filter.max_level())
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut fields = Fields::default();
+        span.record(&mut fields);
+
+        let data = SpanData {
+            metadata: span.metadata(),
+            fields,
+            refs: 1,
+        };
+        self.spans
+            .lock()
+            .expect("poisoned span lock")
+            .insert(id, data);
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        if let Some(data) = self
+            .spans
+            .lock()
+            .expect("poisoned span lock")
+            .get_mut(&span.into_u64())
+        {
+            values.record(&mut data.fields);
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+
+        let meta = event.metadata();
+        let mut line = self.scope();
+        if meta.target() != TARGET && !meta.target().starts_with("libdocker_rl::") {
+            let _ = write!(line, "{}: ", meta.target());
+        }
+        line.push_str(&fields.message);
+        if !fields.rest.is_empty() {
+            let _ = write!(line, " {}", fields.rest);
+        }
+
+        // the journal and syslog have priorities instead
+        if self.sink.is_stderr() {
+            line = format!("{:>5} {}", meta.level(), line);
+        }
+        self.sink.log(priority(meta.level()), &line);
+    }
+
+    fn enter(&self, span: &Id) {
+        STACK.with(|stack| stack.borrow_mut().push(span.clone()));
+    }
+
+    fn exit(&self, span: &Id) {
+        STACK.with(|stack| {
+            let mut stack = stack.borrow_mut();
+            if let Some(at) = stack.iter().rposition(|id| id == span) {
+                stack.remove(at);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(data) = self
+            .spans
+            .lock()
+            .expect("poisoned span lock")
+            .get_mut(&span.into_u64())
+        {
+            data.refs += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let mut spans = self.spans.lock().expect("poisoned span lock");
+        let closed = match spans.get_mut(&span.into_u64()) {
+            Some(data) => {
+                data.refs -= 1;
+                data.refs == 0
+            }
+            None => false,
+        };
+        if closed {
+            spans.remove(&span.into_u64());
+        }
+        closed
+    }
+
+    fn current_span(&self) -> Current {
+        let current = STACK.with(|stack| stack.borrow().last().cloned());
+        let spans = self.spans.lock().expect("poisoned span lock");
+        match current.and_then(|id| spans.get(&id.into_u64()).map(|d| (id, d.metadata))) {
+            Some((id, metadata)) => Current::new(id, metadata),
+            None => Current::none(),
+        }
+    }
+}
+
+/// Syslog priority of events at `level`
+fn priority(level: &Level) -> Priority {
+    match *level {
+        Level::ERROR => Priority::Error,
+        Level::WARN => Priority::Warning,
+        Level::INFO => Priority::Info,
+        _ => Priority::Debug,
+    }
+}
+
+/// Installs a `Logger` for `RUST_LOG`, or for `-vv` if it isn't set
+///
+/// Nothing is installed if neither asks for any events
+///
+/// # Errors
+///
+/// Returns `ExitCode::Input` if `RUST_LOG` can't be parsed
+///
+/// # Arguments
+///
+/// * `verbose` - number of times `-v` was passed
+/// * `sink` - `LogSink` of `--log-target`
+pub fn init(verbose: u8, sink: LogSink) -> DrlResult<()> {
+    let filter = match env::var(ENV) {
+        Ok(s) if !s.trim().is_empty() => match Filter::parse(&s) {
+            Ok(f) => f,
+            Err(msg) => {
+                let err = DrlErr::new(msg, ExitCode::Input);
+                return Err(err);
+            }
+        },
+        _ => match Filter::verbosity(verbose) {
+            Some(f) => f,
+            None => return Ok(()),
+        },
+    };
+
+    // only fails if one was installed already, which is as good
+    let _ = tracing::subscriber::set_global_default(Logger::new(filter, sink));
+    Ok(())
+}
@@ -0,0 +1,402 @@
+//! Where `--watch` and `--serve` write their messages, for `--log-target`
+//!
+//! stderr by default. Under systemd, `journald` sends every message to the journal with its
+//! priority, and `syslog` to the local syslog daemon, so nothing has to parse stderr. The
+//! `tracing` events of `-vv` and `RUST_LOG` go to the same place. journald is only built on
+//! Linux with the `journald` feature
+
+use super::err::{DrlErr, DrlResult, ExitCode};
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+#[cfg(unix)]
+use {std::os::unix::net::UnixDatagram, std::sync::Arc};
+
+/// Name the messages are logged under
+pub const IDENTIFIER: &str = "docker-rl";
+
+/// Socket of the native journald protocol
+#[cfg(all(feature = "journald", target_os = "linux"))]
+pub const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Sockets of the local syslog daemon, the first one there is used
+#[cfg(unix)]
+pub const SYSLOG_SOCKETS: &[&str] = &["/dev/log", "/var/run/syslog", "/var/run/log"];
+
+/// Syslog facility of the messages, `daemon`
+#[cfg(unix)]
+const FACILITY: u8 = 3;
+
+/// Where messages are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogTarget {
+    /// Standard error, as plain lines
+    #[default]
+    Stderr,
+    /// The systemd journal
+    Journald,
+    /// The local syslog daemon
+    Syslog,
+}
+
+impl LogTarget {
+    /// Names accepted on the command line
+    pub const VARIANTS: &'static [&'static str] = &["stderr", "journald", "syslog"];
+}
+
+impl FromStr for LogTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stderr" => Ok(LogTarget::Stderr),
+            "journald" => Ok(LogTarget::Journald),
+            "syslog" => Ok(LogTarget::Syslog),
+            _ => Err(format!("unknown log target: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for LogTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LogTarget::Stderr => "stderr",
+            LogTarget::Journald => "journald",
+            LogTarget::Syslog => "syslog",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Syslog severity of a message, journald uses the same numbers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Something failed
+    Error = 3,
+    /// Something failed, but the watch or exporter keeps going
+    Warning = 4,
+    /// What the watch or exporter is doing
+    Info = 6,
+    /// Details for debugging
+    Debug = 7,
+}
+
+/// Socket messages are sent to, with the format it wants
+#[derive(Debug, Clone)]
+enum Sink {
+    Stderr,
+    #[cfg(all(feature = "journald", target_os = "linux"))]
+    Journald(Arc<UnixDatagram>),
+    #[cfg(unix)]
+    Syslog(Arc<UnixDatagram>),
+}
+
+/// Destination of the messages of one run, cheap to clone
+#[derive(Debug, Clone)]
+pub struct LogSink {
+    sink: Sink,
+}
+
+impl Default for LogSink {
+    fn default() -> LogSink {
+        LogSink::stderr()
+    }
+}
+
+impl LogSink {
+    /// Writes to stderr
+    pub fn stderr() -> LogSink {
+        LogSink { sink: Sink::Stderr }
+    }
+
+    /// Connects to `target`
+    ///
+    /// # Errors
+    ///
+    /// Returns `ExitCode::Input` if this build or platform can't log to `target`, or its socket
+    /// can't be reached, e.g. journald outside of systemd
+    ///
+    /// # Arguments
+    ///
+    /// * `target` - `LogTarget` to write to
+    pub fn open(target: LogTarget) -> DrlResult<LogSink> {
+        match target {
+            LogTarget::Stderr => Ok(LogSink::stderr()),
+            LogTarget::Journald => LogSink::journald(),
+            LogTarget::Syslog => LogSink::syslog(),
+        }
+    }
+
+    /// Connects to journald on `JOURNALD_SOCKET`
+    #[cfg(all(feature = "journald", target_os = "linux"))]
+    fn journald() -> DrlResult<LogSink> {
+        LogSink::journald_at(JOURNALD_SOCKET)
+    }
+
+    /// Fails, this build can't log to journald
+    #[cfg(not(all(feature = "journald", target_os = "linux")))]
+    fn journald() -> DrlResult<LogSink> {
+        let msg = format!(
+            "can't log to journald, {} was built without the journald feature or not for Linux",
+            IDENTIFIER
+        );
+        let err = DrlErr::new(msg, ExitCode::Input);
+        Err(err)
+    }
+
+    /// Connects to journald on `path`
+    #[cfg(all(feature = "journald", target_os = "linux"))]
+    pub(crate) fn journald_at(path: &str) -> DrlResult<LogSink> {
+        match connect(path) {
+            Ok(socket) => Ok(LogSink {
+                sink: Sink::Journald(Arc::new(socket)),
+            }),
+            Err(e) => {
+                let msg = format!("journald isn't available at {}: {}", path, e);
+                let err = DrlErr::new(msg, ExitCode::Input);
+                Err(err)
+            }
+        }
+    }
+
+    /// Connects to the first of `SYSLOG_SOCKETS` that is there
+    #[cfg(unix)]
+    fn syslog() -> DrlResult<LogSink> {
+        LogSink::syslog_at(SYSLOG_SOCKETS)
+    }
+
+    /// Fails, there is no local syslog daemon to connect to
+    #[cfg(not(unix))]
+    fn syslog() -> DrlResult<LogSink> {
+        let msg = String::from("can't log to syslog, it's only there on unix");
+        let err = DrlErr::new(msg, ExitCode::Input);
+        Err(err)
+    }
+
+    /// Connects to the first of `paths` that is there
+    #[cfg(unix)]
+    pub(crate) fn syslog_at(paths: &[&str]) -> DrlResult<LogSink> {
+        let mut errors = Vec::new();
+        for path in paths {
+            match connect(path) {
+                Ok(socket) => {
+                    return Ok(LogSink {
+                        sink: Sink::Syslog(Arc::new(socket)),
+                    })
+                }
+                Err(e) => errors.push(format!("{}: {}", path, e)),
+            }
+        }
+
+        let msg = format!("syslog isn't available, {}", errors.join(", "));
+        let err = DrlErr::new(msg, ExitCode::Input);
+        Err(err)
+    }
+
+    /// Whether messages go to stderr, where they are written as plain lines
+    pub fn is_stderr(&self) -> bool {
+        matches!(self.sink, Sink::Stderr)
+    }
+
+    /// Writes `msg` with `priority`
+    ///
+    /// On stderr `msg` is written as it is. Failing to write is ignored, like a closed stderr
+    ///
+    /// # Arguments
+    ///
+    /// * `priority` - `Priority` of the message, not written on stderr
+    /// * `msg` - the message
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    pub fn log(&self, priority: Priority, msg: &str) {
+        match &self.sink {
+            Sink::Stderr => {
+                let _ = writeln!(io::stderr().lock(), "{}", msg);
+            }
+            #[cfg(all(feature = "journald", target_os = "linux"))]
+            Sink::Journald(socket) => {
+                let _ = socket.send(&journald_entry(priority, msg));
+            }
+            #[cfg(unix)]
+            Sink::Syslog(socket) => {
+                let _ = socket.send(syslog_line(priority, msg).as_bytes());
+            }
+        }
+    }
+
+    /// Writes `msg` as a warning, after `warning: ` on stderr
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - the message
+    pub fn warn(&self, msg: &str) {
+        if self.is_stderr() {
+            self.log(Priority::Warning, &format!("warning: {}", msg));
+        } else {
+            self.log(Priority::Warning, msg);
+        }
+    }
+
+    /// Writes `msg` as information
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - the message
+    pub fn info(&self, msg: &str) {
+        self.log(Priority::Info, msg);
+    }
+}
+
+/// Unbound datagram socket connected to `path`
+#[cfg(unix)]
+fn connect(path: &str) -> io::Result<UnixDatagram> {
+    let socket = UnixDatagram::unbound()?;
+    socket.connect(path)?;
+    Ok(socket)
+}
+
+/// One entry of the native journald protocol, as `FIELD=value` lines
+///
+/// Values with a newline are written with their length instead, as the protocol wants
+#[cfg(all(feature = "journald", target_os = "linux"))]
+fn journald_entry(priority: Priority, msg: &str) -> Vec<u8> {
+    let mut entry = Vec::new();
+    let priority = (priority as u8).to_string();
+    let fields = [
+        ("PRIORITY", priority.as_str()),
+        ("SYSLOG_IDENTIFIER", IDENTIFIER),
+        ("MESSAGE", msg),
+    ];
+    for (name, value) in fields {
+        entry.extend_from_slice(name.as_bytes());
+        if value.contains('\n') {
+            entry.push(b'\n');
+            entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            entry.push(b'=');
+        }
+        entry.extend_from_slice(value.as_bytes());
+        entry.push(b'\n');
+    }
+    entry
+}
+
+/// One message for a local syslog daemon, `<PRI>docker-rl[pid]: msg`
+///
+/// The daemon fills in the time and host itself
+#[cfg(unix)]
+fn syslog_line(priority: Priority, msg: &str) -> String {
+    let pri = FACILITY * 8 + priority as u8;
+    format!("<{}>{}[{}]: {}", pri, IDENTIFIER, std::process::id(), msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_targets() {
+        for name in LogTarget::VARIANTS {
+            let target: LogTarget = name.parse().unwrap();
+            assert_eq!(target.to_string(), *name);
+        }
+        assert!("journal".parse::<LogTarget>().is_err());
+    }
+
+    #[cfg(unix)]
+    mod sockets {
+        use super::*;
+        use std::path::PathBuf;
+        use std::time::Duration;
+
+        /// Socket at a fresh path in the temporary directory, to receive what is logged
+        struct Listener {
+            path: PathBuf,
+            socket: UnixDatagram,
+        }
+
+        impl Listener {
+            fn bind(name: &str) -> Listener {
+                let file = format!("docker-rl-{}-{}.sock", name, std::process::id());
+                let path = std::env::temp_dir().join(file);
+                let _ = std::fs::remove_file(&path);
+                let socket = UnixDatagram::bind(&path).unwrap();
+                socket
+                    .set_read_timeout(Some(Duration::from_secs(5)))
+                    .unwrap();
+                Listener { path, socket }
+            }
+
+            fn path(&self) -> &str {
+                self.path.to_str().unwrap()
+            }
+
+            fn recv(&self) -> Vec<u8> {
+                let mut buf = vec![0; 4096];
+                let n = self.socket.recv(&mut buf).unwrap();
+                buf.truncate(n);
+                buf
+            }
+        }
+
+        impl Drop for Listener {
+            fn drop(&mut self) {
+                let _ = std::fs::remove_file(&self.path);
+            }
+        }
+
+        #[test]
+        fn syslog_priorities() {
+            let listener = Listener::bind("syslog");
+            let missing = "/nonexistent/docker-rl.sock";
+            let log = LogSink::syslog_at(&[missing, listener.path()]).unwrap();
+            assert!(!log.is_stderr());
+
+            log.warn("check failed: registry down");
+            let line = String::from_utf8(listener.recv()).unwrap();
+            let pid = std::process::id();
+            let expected = format!("<28>docker-rl[{}]: check failed: registry down", pid);
+            assert_eq!(line, expected);
+
+            log.info("serving metrics");
+            let line = String::from_utf8(listener.recv()).unwrap();
+            assert!(line.starts_with("<30>docker-rl["), "{}", line);
+        }
+
+        #[test]
+        fn syslog_unavailable() {
+            let err = LogSink::syslog_at(&["/nonexistent/docker-rl.sock"]).unwrap_err();
+            assert_eq!(err.ret, ExitCode::Input);
+            assert!(err.msg.contains("syslog isn't available"), "{}", err.msg);
+        }
+
+        #[cfg(all(feature = "journald", target_os = "linux"))]
+        #[test]
+        fn journald_fields() {
+            let listener = Listener::bind("journald");
+            let log = LogSink::journald_at(listener.path()).unwrap();
+
+            log.warn("check failed");
+            let entry = String::from_utf8(listener.recv()).unwrap();
+            assert_eq!(
+                entry,
+                "PRIORITY=4\nSYSLOG_IDENTIFIER=docker-rl\nMESSAGE=check failed\n"
+            );
+
+            log.log(Priority::Error, "two\nlines");
+            let entry = listener.recv();
+            let mut expected = b"PRIORITY=3\nSYSLOG_IDENTIFIER=docker-rl\nMESSAGE\n".to_vec();
+            expected.extend_from_slice(&9u64.to_le_bytes());
+            expected.extend_from_slice(b"two\nlines\n");
+            assert_eq!(entry, expected);
+        }
+
+        #[cfg(all(feature = "journald", target_os = "linux"))]
+        #[test]
+        fn journald_unavailable() {
+            let err = LogSink::journald_at("/nonexistent/journal.sock").unwrap_err();
+            assert_eq!(err.ret, ExitCode::Input);
+            let expected = "journald isn't available at /nonexistent/journal.sock";
+            assert!(err.msg.starts_with(expected), "{}", err.msg);
+        }
+    }
+}
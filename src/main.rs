@@ -2,10 +2,8 @@
 //!
 //! Command line utility to check docker rate limit
 //!
-//! **Note:** check the rate limit lowers the limit
-//!
-//! **Note:** docker reports the limit before decrementing it
-//! so it's 1 less.
+//! **Note:** the limit is checked with `HEAD`, which doesn't lower it. Registries that
+//! don't answer `HEAD` with the limit are checked with `GET`, which lowers it by 1
 //!
 //! # Examples
 //!
@@ -28,52 +26,1694 @@
 //!  > docker-rl -u someuser -p somepass
 //!  > 97/200
 //! ```
+//!
+//! # Users From Stdin
+//! ```sh
+//!  > export HUB_PASS_CI_BOT=somepass HUB_PASS_RELEASE=otherpass
+//!  > printf 'ci-bot\nrelease\n' | docker-rl --users-from-stdin --password-env-prefix HUB_PASS_
+//!  > ci-bot: 97/200
+//!  > release: authentication failed for release, wrong user or password
+//! ```
+//!
+//! # Dry Run
+//! ```sh
+//!  > docker-rl -u someuser --dry-run
+//!  > token: GET https://auth.docker.io/token
+//!  >   service=registry.docker.io
+//!  >   scope=repository:ratelimitpreview/test:pull
+//!  > manifest: HEAD https://registry-1.docker.io/v2/ratelimitpreview/test/manifests/latest
+//!  > identity: someuser
+//! ```
 
-use libdocker_rl::err::DrlResult;
-use libdocker_rl::limit::get_limit;
-use libdocker_rl::options::Opts;
-use libdocker_rl::token::{get_anon_token, get_userpass_token, Token};
+use futures::future;
+use futures::join;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use libdocker_rl::accounts::{env_password, read_users, run_bounded};
+use libdocker_rl::alert::Alert;
+use libdocker_rl::api::{DrlClient, DrlClientBuilder};
+use libdocker_rl::backoff::Backoff;
+use libdocker_rl::burn::Estimate;
+use libdocker_rl::cancel::cancellable;
+use libdocker_rl::client::IpStack;
+use libdocker_rl::creds::docker_credentials;
+use libdocker_rl::doctor::diagnose;
+use libdocker_rl::duration::format_duration;
+use libdocker_rl::err::{DrlErr, DrlResult, ExitCode, ExitStyle, Failure};
+use libdocker_rl::expect::{expect_user, Identity};
+use libdocker_rl::history;
+use libdocker_rl::hook::Hooks;
+use libdocker_rl::hub::get_plan;
+use libdocker_rl::human;
+use libdocker_rl::keyring;
+use libdocker_rl::limit::{poll_limits_adaptive, poll_limits_with, Limit};
+#[cfg(feature = "tracing")]
+use libdocker_rl::logging;
+use libdocker_rl::logtarget::LogSink;
+use libdocker_rl::nagios;
+use libdocker_rl::need::Need;
+use libdocker_rl::notify;
+use libdocker_rl::options::{self, CacheCommand, Command, Format, Opts};
+use libdocker_rl::pace::Pace;
+use libdocker_rl::plan::{BatchPlan, Plan};
+use libdocker_rl::progress::Progress;
+use libdocker_rl::report::{self, Comparison, Report};
+use libdocker_rl::schema::{Versioned, SCHEMA};
+use libdocker_rl::serve::Exporter;
+use libdocker_rl::state::{self, Cached, State};
+use libdocker_rl::table::Style;
+use libdocker_rl::template;
+use libdocker_rl::token::{Token, TokenProvider, DEFAULT_SKEW};
+use libdocker_rl::tokencache;
+use libdocker_rl::verify::verify;
+use libdocker_rl::watch::{Record, Summary, SummaryLine};
 use rpassword::read_password_from_tty;
+use serde::Serialize;
+use std::fmt::Display;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::process;
+use std::time::SystemTime;
+use tokio::signal;
+#[cfg(unix)]
+use tokio::signal::unix::SignalKind;
+
+/// Gets the password for `user` from the options, or prompts for it
+///
+/// An access token from `--token` is used as the password, and `--pass-file` is read
+///
+/// # Arguments
+///
+/// * `user` - user for basic authentication
+/// * `opts` - `Opts` struct with parsed options
+fn get_password(user: &str, opts: &Opts) -> String {
+    if let Some(pass) = opts.pass.clone().or_else(|| opts.token.clone()) {
+        return pass;
+    }
+    if let Some(path) = &opts.pass_file {
+        return match read_pass_file(path) {
+            Ok(pass) => pass,
+            Err(e) => fail(&e, opts),
+        };
+    }
+
+    // rpassword docs say:
+    //   Prompt for a password on TTY (safest but not always most practical
+    //   when integrating with other tools or unit testing)
+    //
+    // should this have error handling?
+
+    let prompt = format!("Password for {}: ", user);
+    read_password_from_tty(Some(&prompt)).unwrap()
+}
+
+/// Reads the password from `path`, without the line break at the end
+///
+/// # Arguments
+///
+/// * `path` - file holding the password, e.g. a mounted CI secret
+fn read_pass_file(path: &Path) -> DrlResult<String> {
+    match fs::read_to_string(path) {
+        Ok(pass) => Ok(pass.trim_end_matches(&['\r', '\n'][..]).to_string()),
+        Err(e) => {
+            let msg = format!("failed to read {}: {}", path.display(), e);
+            let err = DrlErr::new(msg, ExitCode::Input);
+            Err(err)
+        }
+    }
+}
+
+/// Resolves the user and password to check, prompting for the password if needed
+///
+/// Returns `None` for anonymous checks
+///
+/// # Arguments
+///
+/// * `opts` - `Opts` struct with parsed options
+fn get_credentials(opts: &Opts) -> Option<(String, String)> {
+    // skip every credential source
+    if opts.anonymous {
+        return None;
+    }
+
+    let user = opts.user.clone()?;
+    let pass = get_password(&user, opts);
+    Some((user, pass))
+}
+
+/// Fills in the user and password from `docker login`, when they weren't passed
+///
+/// With `-u` but no `-p`, the saved credentials are only used if they are for that user.
+/// Failures only warn, the check goes on without them
+///
+/// # Arguments
+///
+/// * `opts` - `Opts` struct with parsed options
+fn use_docker_credentials(opts: &mut Opts) {
+    let passed = opts.pass.is_some() || opts.token.is_some() || opts.pass_file.is_some();
+    if opts.anonymous || opts.no_docker_config || passed {
+        return;
+    }
+    // they are Docker Hub credentials, never send them anywhere else
+    if !opts.registry().is_docker_hub() {
+        return;
+    }
+
+    let creds = match docker_credentials() {
+        Ok(Some(c)) => c,
+        Ok(None) => return,
+        Err(e) => {
+            if !opts.quiet {
+                eprintln!("warning: couldn't use docker login credentials: {}", e);
+            }
+            return;
+        }
+    };
+
+    match &opts.user {
+        Some(user) if *user != creds.user => (),
+        _ => {
+            if opts.verbose > 0 {
+                eprintln!("using docker login credentials for {}", creds.user);
+            }
+            opts.user = Some(creds.user);
+            opts.pass = Some(creds.pass);
+        }
+    }
+}
+
+/// Fills in the user and password from `docker-rl login`, when they weren't passed
+///
+/// Like the `docker login` credentials, they are only used with `-u` if they are for that
+/// user. Failures only warn, the check goes on without them
+///
+/// # Arguments
+///
+/// * `opts` - `Opts` struct with parsed options
+fn use_keyring_credentials(opts: &mut Opts) {
+    let passed = opts.pass.is_some() || opts.token.is_some() || opts.pass_file.is_some();
+    if opts.anonymous || opts.no_keyring || passed {
+        return;
+    }
+    // they are Docker Hub credentials, never send them anywhere else
+    if !opts.registry().is_docker_hub() {
+        return;
+    }
+
+    let login = match keyring::load() {
+        Ok(Some(l)) => l,
+        Ok(None) => return,
+        Err(e) => {
+            if !opts.quiet {
+                eprintln!("warning: couldn't use the keyring credentials: {}", e);
+            }
+            return;
+        }
+    };
+
+    match &opts.user {
+        Some(user) if *user != login.user => (),
+        _ => {
+            if opts.verbose > 0 {
+                eprintln!("using keyring credentials for {}", login.user);
+            }
+            opts.user = Some(login.user);
+            if login.token {
+                opts.token = Some(login.secret);
+            } else {
+                opts.pass = Some(login.secret);
+            }
+        }
+    }
+}
+
+/// Checks the credentials with Docker Hub and saves them in the keyring, for `login`
+///
+/// The user is asked for when `-u` wasn't passed, and the password the same as for a check
+///
+/// # Errors
+///
+/// Returns `ExitCode::Input` if the registry isn't Docker Hub or the keyring can't save them,
+/// and the token errors if Docker Hub rejects them
+///
+/// # Arguments
+///
+/// * `client` - `DrlClient` to check the credentials with
+/// * `opts` - `Opts` struct with parsed options
+async fn login(client: &DrlClient, opts: &Opts) -> DrlResult<()> {
+    if !client.registry().is_docker_hub() {
+        let msg = "login only saves Docker Hub credentials".to_string();
+        let err = DrlErr::new(msg, ExitCode::Input);
+        return Err(err);
+    }
+
+    let user = match &opts.user {
+        Some(u) => u.clone(),
+        None => read_user()?,
+    };
+    let secret = get_password(&user, opts);
+    let token = opts.token.is_some();
+
+    // like docker login, they are only saved once they work
+    if token {
+        client.pat_token(&user, &secret).await?;
+    } else {
+        client.token(Some((&user, &secret))).await?;
+    }
+
+    let login = keyring::Login {
+        user,
+        secret,
+        token,
+    };
+    keyring::store(&login)?;
+    if !opts.quiet {
+        println!(
+            "logged in as {}, credentials saved in the keyring",
+            login.user
+        );
+    }
+    Ok(())
+}
+
+/// Removes the credentials saved by `login` from the keyring, for `logout`
+///
+/// # Errors
+///
+/// Returns `ExitCode::Input` if the keyring can't remove them
+///
+/// # Arguments
+///
+/// * `opts` - `Opts` struct with parsed options
+fn logout(opts: &Opts) -> DrlResult<()> {
+    let login = keyring::delete()?;
+    if !opts.quiet {
+        match login {
+            Some(l) => println!("removed the credentials of {} from the keyring", l.user),
+            None => println!("not logged in"),
+        }
+    }
+    Ok(())
+}
+
+/// Lists or clears the token cache, or prints its path, for `cache`
+///
+/// # Errors
+///
+/// Returns `ExitCode::Input` if there is no cache directory or the file can't be changed, and
+/// `ExitCode::Parsing` if it's corrupt
+///
+/// # Arguments
+///
+/// * `command` - what `cache` was asked to do
+/// * `opts` - `Opts` struct with parsed options
+fn cache(command: &CacheCommand, opts: &Opts) -> DrlResult<()> {
+    match command {
+        CacheCommand::List => {
+            let listing = tokencache::list(&opts.timestamps())?;
+            print_value(&listing, opts.format);
+        }
+        CacheCommand::Clear { user } => {
+            let removed = tokencache::clear(user.as_deref())?;
+            let plural = if removed == 1 { "" } else { "s" };
+            match user {
+                Some(user) => println!("removed {} cached token{} of {}", removed, plural, user),
+                None => println!("removed {} cached token{}", removed, plural),
+            }
+        }
+        CacheCommand::Path => println!("{}", tokencache::required_path()?.display()),
+    }
+    Ok(())
+}
+
+/// Asks for the Docker Hub user on stdin
+///
+/// # Errors
+///
+/// Returns `ExitCode::Input` if stdin can't be read or the user is empty
+fn read_user() -> DrlResult<String> {
+    eprint!("Username: ");
+    let mut user = String::new();
+    if let Err(e) = io::stdin().read_line(&mut user) {
+        let msg = format!("failed to read the user: {}", e);
+        let err = DrlErr::new(msg, ExitCode::Input);
+        return Err(err);
+    }
+
+    match user.trim() {
+        "" => {
+            let msg = "no user given".to_string();
+            let err = DrlErr::new(msg, ExitCode::Input);
+            Err(err)
+        }
+        user => Ok(user.into()),
+    }
+}
+
+/// Gets jwt token
+///
+/// With `--token-cache`, a cached token is used if there is one, and a new one is cached
+///
+/// # Arguments
+///
+/// * `client` - `DrlClient` to request the token with
+/// * `creds` - user and password, `None` for an anonymous token
+/// * `opts` - `Opts` struct with parsed options
+async fn get_token(
+    client: &DrlClient,
+    creds: Option<(String, String)>,
+    opts: &Opts,
+) -> DrlResult<Token> {
+    let key = token_key(client, opts);
+    if let Some(token) = key.as_ref().and_then(|k| tokencache::load(k, DEFAULT_SKEW)) {
+        if opts.verbose > 0 {
+            eprintln!("using a cached token");
+        }
+        return Ok(token);
+    }
+
+    let token = match &creds {
+        Some((user, pass)) if opts.token.is_some() => client.pat_token(user, pass).await?,
+        Some((user, pass)) => client.token(Some((user, pass))).await?,
+        None => client.token(None).await?,
+    };
+
+    // failing to cache it only costs a token request next time
+    if let Some(key) = &key {
+        if let Err(e) = tokencache::store(key, &token) {
+            if !opts.quiet {
+                eprintln!("warning: couldn't cache the token: {}", e);
+            }
+        }
+    }
+    Ok(token)
+}
+
+/// Key of the token for `--token-cache`, `None` if it wasn't passed
+///
+/// # Arguments
+///
+/// * `client` - `DrlClient` the token is for
+/// * `opts` - `Opts` struct with parsed options
+fn token_key(client: &DrlClient, opts: &Opts) -> Option<tokencache::Key> {
+    if !opts.token_cache {
+        return None;
+    }
+    Some(tokencache::Key::new(
+        &client.registry().name,
+        opts.user.as_deref(),
+        &opts.scope,
+    ))
+}
+
+/// Looks up the plan of the account when `--show-plan` was passed
+///
+/// Failures only warn, the limit is still worth showing without the plan
+///
+/// # Arguments
+///
+/// * `client` - `DrlClient` to look it up with
+/// * `creds` - user and password, `None` for anonymous checks
+/// * `opts` - `Opts` struct with parsed options
+async fn lookup_plan(
+    client: &DrlClient,
+    creds: Option<(String, String)>,
+    opts: &Opts,
+) -> Option<String> {
+    let (user, pass) = match creds {
+        Some(c) if opts.show_plan => c,
+        _ => return None,
+    };
+
+    match get_plan(client, &user, &pass).await {
+        Ok(plan) => Some(plan),
+        Err(e) => {
+            if !opts.quiet {
+                eprintln!("warning: couldn't get plan: {}", e);
+            }
+            None
+        }
+    }
+}
+
+/// Prints the requested and granted scopes to stderr
+///
+/// # Arguments
+///
+/// * `identity` - who the token is for
+/// * `client` - `DrlClient` that requested it
+/// * `token` - `Token` that was granted
+fn print_scopes(identity: &str, client: &DrlClient, token: &Token) {
+    let requested = client.scope().for_repository(&client.registry().repository);
+    eprintln!("{}: requested scope {}", identity, requested);
+    match token.granted_scopes() {
+        Some(granted) if granted.is_empty() => eprintln!("{}: granted no scopes", identity),
+        Some(granted) => eprintln!("{}: granted scope {}", identity, granted.join(" ")),
+        None => eprintln!(
+            "{}: granted scope unknown, token can't be decoded",
+            identity
+        ),
+    }
+}
+
+/// Prints what the limit is keyed to and its window to stderr
+///
+/// # Arguments
+///
+/// * `identity` - who the check was for
+/// * `limit` - `Limit` from the check
+fn print_keying(identity: &str, limit: &Limit) {
+    match &limit.source {
+        Some(source) if limit.keyed_to_ip() => {
+            eprintln!("{}: limit keyed to IP {}", identity, source)
+        }
+        Some(source) => eprintln!("{}: limit keyed to account {}", identity, source),
+        None => eprintln!("{}: limit keyed to unknown", identity),
+    }
+    if !limit.window.is_zero() {
+        eprintln!("{}: window {}", identity, format_duration(limit.window));
+    }
+}
+
+/// Gets the limit for `user`, without prompting for anything
+///
+/// # Arguments
+///
+/// * `client` - `DrlClient` to check with
+/// * `user` - user for basic authentication
+/// * `opts` - `Opts` struct with parsed options
+async fn check_env_user(client: &DrlClient, user: String, opts: &Opts) -> Report {
+    // structopt makes sure the prefix is there
+    let prefix = opts.password_env_prefix.as_deref().unwrap_or_default();
+
+    let result = match env_password(prefix, &user) {
+        Ok(pass) => check_userpass(client, user.clone(), pass, opts).await,
+        Err(e) => Err(e),
+    };
+
+    Report::new(Some(user), result)
+}
+
+/// Gets the limit with user/pass
+///
+/// # Arguments
+///
+/// * `client` - `DrlClient` to check with
+/// * `user` - user for basic authentication
+/// * `pass` - password for basic authentication
+/// * `opts` - `Opts` struct with parsed options
+async fn check_userpass(
+    client: &DrlClient,
+    user: String,
+    pass: String,
+    opts: &Opts,
+) -> DrlResult<Limit> {
+    let token = client.token(Some((&user, &pass))).await?;
+    if opts.verbose > 0 {
+        print_scopes(&user, client, &token);
+    }
+    let limit = client.limit(&token).await?;
+    if opts.verbose > 0 {
+        print_keying(&user, &limit);
+    }
+    Ok(limit)
+}
+
+/// Gets the anonymous limit
+///
+/// # Arguments
+///
+/// * `client` - `DrlClient` to check with
+/// * `opts` - `Opts` struct with parsed options
+async fn check_anon(client: &DrlClient, opts: &Opts) -> DrlResult<Limit> {
+    let token = client.token(None).await?;
+    if opts.verbose > 0 {
+        print_scopes("anonymous", client, &token);
+    }
+    let limit = client.limit(&token).await?;
+    if opts.verbose > 0 {
+        print_keying("anonymous", &limit);
+    }
+    Ok(limit)
+}
+
+/// Checks the anonymous and the user's limit concurrently, and prints both
+///
+/// Exits with the code of the first failure, after everything is printed
+///
+/// # Arguments
+///
+/// * `client` - `DrlClient` to check with
+/// * `user` - user for basic authentication
+/// * `opts` - `Opts` struct with parsed options
+async fn check_compare(client: &DrlClient, user: String, opts: &Opts) {
+    // prompt before anything is in flight
+    let pass = get_password(&user, opts);
+
+    let checks = async {
+        Ok(join!(
+            check_anon(client, opts),
+            check_userpass(client, user.clone(), pass, opts)
+        ))
+    };
+    let result = cancellable(checks, interrupted()).await;
+    let (anon, auth) = result.unwrap_or_else(|e| fail(&e, opts));
+    let comparison = Comparison::new(Report::new(None, anon), Report::new(Some(user), auth));
+
+    match opts.format {
+        Format::Table => print_reports(&comparison.reports(), opts),
+        Format::Plain if opts.human => println!(
+            "{}    {}",
+            comparison.anonymous.human(),
+            comparison.authenticated.human()
+        ),
+        _ => print_value(&comparison, opts.format),
+    }
+
+    let failure = comparison
+        .reports()
+        .iter()
+        .find_map(|r| r.result.as_ref().err().cloned());
+    if let Some(err) = failure {
+        process::exit(err.ret as i32);
+    }
+}
+
+/// Reads users from stdin
+fn stdin_users() -> DrlResult<Vec<String>> {
+    read_users(io::stdin().lock())
+}
+
+/// Checks every user read from stdin concurrently, and prints a line for each
+///
+/// Exits with the code of the first failure, after everything is printed
+///
+/// # Arguments
+///
+/// * `client` - `DrlClient` to check with
+/// * `opts` - `Opts` struct with parsed options
+async fn check_stdin_users(client: &DrlClient, opts: &Opts) {
+    let users = stdin_users().unwrap_or_else(|e| fail(&e, opts));
+
+    let progress = Progress::start(opts.show_progress(), "checking accounts…");
+    let checks = run_bounded(users, opts.concurrency, opts.stagger, |user| {
+        check_env_user(client, user, opts)
+    });
+    let result = cancellable(async { Ok(checks.await) }, interrupted()).await;
+    progress.finish();
+    let reports = result.unwrap_or_else(|e| fail(&e, opts));
+
+    print_reports(&reports, opts);
+
+    let failure = reports.iter().find_map(|r| r.result.as_ref().err());
+    if let Some(err) = failure {
+        process::exit(err.ret as i32);
+    }
+}
+
+/// Options that only make sense for one check, by name
+///
+/// # Arguments
+///
+/// * `opts` - `Opts` struct with parsed options
+fn single_only(opts: &Opts) -> Option<&'static str> {
+    let single = [
+        (opts.users_from_stdin, "--users-from-stdin"),
+        (opts.compare, "--compare"),
+        (opts.verify, "--verify"),
+        (opts.need.is_some(), "--need"),
+        (opts.expect_user.is_some(), "--expect-user"),
+        (opts.show_plan, "--show-plan"),
+        (opts.nagios, "--nagios"),
+        (opts.watch, "--watch"),
+        (opts.serve.is_some(), "--serve"),
+        (opts.cached, "--cached"),
+        (opts.dry_run, "--dry-run"),
+        (opts.pushgateway.is_some(), "--pushgateway"),
+        (opts.alerter().is_some(), "--alert-below"),
+        (opts.image.is_some(), "--image"),
+        (opts.compare_stacks, "--compare-stacks"),
+    ];
+    single.iter().find(|(set, _)| *set).map(|(_, name)| *name)
+}
+
+/// Checks the limit of one profile, with its own client, registry and thresholds
+///
+/// # Arguments
+///
+/// * `creds` - user and password, `None` for an anonymous check
+/// * `stack` - IP version to check over, `None` for the one of the options
+/// * `opts` - `Opts` of the profile
+async fn check_profile(
+    creds: Option<(String, String)>,
+    stack: Option<IpStack>,
+    opts: &Opts,
+) -> DrlResult<Limit> {
+    let mut config = opts.client_config()?;
+    if let Some(stack) = stack {
+        config.local_address = Some(stack.unspecified());
+    }
+
+    let client = DrlClient::builder()
+        .config(config)
+        .registry(opts.registry())
+        .scope(opts.scope.clone())
+        .build()?
+        .discover()
+        .await?;
+
+    let creds = creds.as_ref().map(|(u, p)| (u.as_str(), p.as_str()));
+    let token = client.token(creds).await?;
+    let limit = client.limit(&token).await?;
+    opts.threshold().check(&limit)?;
+    Ok(limit)
+}
+
+/// Checks every profile concurrently, and prints a report for each
+///
+/// Output follows the options of the first profile. Exits with the code of the first failure,
+/// after everything is printed
+///
+/// # Arguments
+///
+/// * `profiles` - `Opts` of every profile
+async fn check_profiles(mut profiles: Vec<Opts>) {
+    for opts in &profiles {
+        if let Some(name) = single_only(opts) {
+            let profile = opts.profile.join(",");
+            let msg = format!("{} can't be used when checking several profiles", name);
+            let msg = format!("{}, it's set for profile '{}'", msg, profile);
+            fail(&DrlErr::new(msg, ExitCode::Input), opts);
+        }
+    }
+
+    // prompts can't overlap, so every password is asked for before the checks start
+    let creds: Vec<_> = profiles
+        .iter_mut()
+        .map(|opts| {
+            use_keyring_credentials(opts);
+            use_docker_credentials(opts);
+            get_credentials(opts)
+        })
+        .collect();
+
+    let opts = &profiles[0];
+    let progress = Progress::start(opts.show_progress(), "checking profiles…");
+    let checks = profiles.iter().zip(creds).map(|(opts, creds)| async move {
+        let mut report = Report::new(opts.user.clone(), check_profile(creds, None, opts).await);
+        report.profile = opts.profile.first().cloned();
+        report
+    });
+    let result = cancellable(async { Ok(future::join_all(checks).await) }, interrupted()).await;
+    progress.finish();
+    let reports = result.unwrap_or_else(|e| fail(&e, opts));
+
+    if !opts.check {
+        print_reports(&reports, opts);
+    }
+
+    let failure = reports.iter().find_map(|r| r.result.as_ref().err());
+    if let Some(err) = failure {
+        process::exit(err.ret as i32);
+    }
+}
+
+/// Checks the limit over IPv4 and IPv6 concurrently, and prints a report for each
+///
+/// Exits with the code of the first failure, after everything is printed
+///
+/// # Arguments
+///
+/// * `opts` - `Opts` struct with parsed options
+async fn check_stacks(opts: &Opts) {
+    let creds = get_credentials(opts);
+
+    let progress = Progress::start(opts.show_progress(), "checking both stacks…");
+    let checks = IpStack::ALL.iter().map(|&stack| {
+        let creds = creds.clone();
+        async move {
+            let mut report = Report::new(
+                opts.user.clone(),
+                check_profile(creds, Some(stack), opts).await,
+            );
+            report.stack = Some(stack);
+            report
+        }
+    });
+    let result = cancellable(async { Ok(future::join_all(checks).await) }, interrupted()).await;
+    progress.finish();
+    let reports = result.unwrap_or_else(|e| fail(&e, opts));
+
+    if !opts.check {
+        print_reports(&reports, opts);
+    }
+
+    let failure = reports.iter().find_map(|r| r.result.as_ref().err());
+    if let Some(err) = failure {
+        process::exit(err.ret as i32);
+    }
+}
+
+/// Prints `reports` to stdout in the requested format
+///
+/// Tables use box drawing characters when stdout is a terminal, and a single report is
+/// printed the same as plain output
+///
+/// # Arguments
+///
+/// * `reports` - reports to print
+/// * `opts` - `Opts` struct with parsed options
+fn print_reports(reports: &[Report], opts: &Opts) {
+    let format = opts.format;
+
+    if format == Format::Table && reports.len() > 1 {
+        let style = if io::stdout().is_terminal() {
+            Style::Unicode
+        } else {
+            Style::Ascii
+        };
+        let table = report::table(reports, opts.human);
+        print!("{}", table.render(style, opts.wide));
+    } else if format == Format::Plain && opts.human {
+        for report in reports {
+            println!("{}", report.human());
+        }
+    } else {
+        print_values(reports, format);
+    }
+}
+
+/// Prints `values` to stdout in the requested format
+///
+/// Plain and table output print one line per value, JSON prints an array, with a
+/// `schema_version` in each element
+///
+/// # Arguments
+///
+/// * `values` - values to print
+/// * `format` - `Format` to print them in
+fn print_values<T: Display + Serialize>(values: &[T], format: Format) {
+    match format {
+        Format::Plain | Format::Table => {
+            for value in values {
+                println!("{}", value);
+            }
+        }
+        Format::Template => {
+            for value in values {
+                print_value(value, format);
+            }
+        }
+        Format::Json => {
+            let versioned: Vec<_> = values.iter().map(Versioned::new).collect();
+            println!("{}", to_json(&versioned));
+        }
+    }
+}
+
+/// Prints `value` to stdout in the requested format
+///
+/// JSON gets a `schema_version` field added, and templates get the same fields
+///
+/// # Arguments
+///
+/// * `value` - value to print
+/// * `format` - `Format` to print it in
+fn print_value<T: Display + Serialize>(value: &T, format: Format) {
+    match (format, template::installed()) {
+        (Format::Json, _) => println!("{}", to_json(&Versioned::new(value))),
+        (Format::Template, Some(t)) => println!("{}", t.render(&Versioned::new(value))),
+        _ => println!("{}", value),
+    }
+}
+
+/// Serializes `value` as pretty printed JSON
+fn to_json<T: Serialize + ?Sized>(value: &T) -> String {
+    // only derived impls get here, which can't fail
+    serde_json::to_string_pretty(value).expect("failed to serialize output")
+}
+
+/// Serializes `value` as JSON on a single line
+fn to_json_line<T: Serialize + ?Sized>(value: &T) -> String {
+    // only derived impls get here, which can't fail
+    serde_json::to_string(value).expect("failed to serialize output")
+}
+
+/// Completes on Ctrl-C, or SIGTERM on unix
+///
+/// Never completes if the handlers can't be installed
+async fn interrupted() {
+    let ctrl_c = async {
+        if signal::ctrl_c().await.is_err() {
+            future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminated = async {
+        match signal::unix::signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(_) => future::pending::<()>().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminated = future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => (),
+        _ = terminated => (),
+    }
+}
+
+/// SIGHUPs, which never arrive on Windows or if the handler can't be installed
+struct Hangups {
+    #[cfg(unix)]
+    signal: Option<signal::unix::Signal>,
+}
+
+impl Hangups {
+    /// Starts listening for SIGHUP, which no longer ends the process
+    fn new() -> Hangups {
+        Hangups {
+            #[cfg(unix)]
+            signal: signal::unix::signal(SignalKind::hangup()).ok(),
+        }
+    }
+
+    /// Completes on the next SIGHUP
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(signal) = &mut self.signal {
+            if signal.recv().await.is_some() {
+                return;
+            }
+        }
+        future::pending::<()>().await
+    }
+}
+
+/// Fails if `--expect-user` was passed and the check wasn't made as that user
+///
+/// # Arguments
+///
+/// * `token` - `Token` the check was made with
+/// * `source` - what the limit is keyed to, if the registry said
+/// * `opts` - `Opts` struct with parsed options
+fn expect_identity(token: &Token, source: Option<&str>, opts: &Opts) -> DrlResult<()> {
+    match &opts.expect_user {
+        Some(expected) => expect_user(expected, Identity::of(token, source).as_ref()),
+        None => Ok(()),
+    }
+}
+
+/// Checks the limit for a single identity and prints it, exiting on failure
+///
+/// Ctrl-C cancels the check, but only once any password prompt is done
+///
+/// # Arguments
+///
+/// * `client` - `DrlClient` to check with
+/// * `opts` - `Opts` struct with parsed options
+async fn check_single(client: &DrlClient, opts: &Opts) {
+    if opts.verbose > 0 {
+        let identity = opts.user.as_deref().unwrap_or("anonymous");
+        eprintln!("checking the limit for {}", identity);
+    }
+
+    let creds = get_credentials(opts);
+    let key = token_key(client, opts);
+    let cached = key
+        .as_ref()
+        .is_some_and(|k| tokencache::load(k, DEFAULT_SKEW).is_some());
+
+    let result = cancellable(run_single(client, creds.clone(), opts), interrupted()).await;
+    let result = match (result, key) {
+        // the registry has the final say on a cached token, check again with a new one
+        (Err(e), Some(key)) if e.ret == ExitCode::Unauthorized && cached => {
+            if tokencache::forget(&key).is_err() {
+                fail(&e, opts);
+            }
+            if opts.verbose > 0 {
+                eprintln!("cached token rejected, requesting a new one");
+            }
+            cancellable(run_single(client, creds, opts), interrupted()).await
+        }
+        (result, _) => result,
+    };
+    result.unwrap_or_else(|e| fail(&e, opts));
+}
+
+/// Keeps `limit` from a check made with `token`, the same way whatever the check was
+///
+/// Fails if it's the limit of the wrong identity, which isn't kept, else saves the state, logs
+/// the history, pushes the metrics and sends an alert
+///
+/// # Arguments
+///
+/// * `client` - `DrlClient` the check was made with
+/// * `token` - `Token` the check was made with
+/// * `limit` - `Limit` it found
+/// * `opts` - `Opts` struct with parsed options
+async fn record_limit(
+    client: &DrlClient,
+    token: &Token,
+    limit: &Limit,
+    opts: &Opts,
+) -> DrlResult<()> {
+    expect_identity(token, limit.source.as_deref(), opts)?;
+    save_state(limit, opts);
+    log_history(limit, opts);
+    push_metrics(client, limit, opts).await?;
+    send_alert(client, limit, true, opts, &LogSink::stderr()).await;
+    Ok(())
+}
+
+/// Does the work of `check_single`
+///
+/// # Arguments
+///
+/// * `client` - `DrlClient` to check with
+/// * `creds` - user and password, `None` for an anonymous check
+/// * `opts` - `Opts` struct with parsed options
+async fn run_single(
+    client: &DrlClient,
+    creds: Option<(String, String)>,
+    opts: &Opts,
+) -> DrlResult<()> {
+    let format = opts.format;
+
+    // get auth token for docker hub
+    let identity = opts.user.as_deref().unwrap_or("anonymous");
+    let progress = Progress::start(opts.show_progress(), "authenticating…");
+    let token = get_token(client, creds.clone(), opts).await?;
+    progress.phase("querying registry…");
+
+    if opts.verbose > 0 {
+        print_scopes(identity, client, &token);
+    }
+
+    if opts.verify {
+        let user = opts.user.clone();
+        let verification = verify(client, &token, user, opts.verify_delay).await?;
+        progress.finish();
+
+        if !opts.check {
+            if format != Format::Json && !opts.quiet {
+                for reason in &verification.reasons {
+                    eprintln!("warning: cache suspected, {}", reason);
+                }
+            }
+            print_value(&verification, format);
+        }
+        if opts.verbose > 0 {
+            print_keying(identity, &verification.limit);
+        }
+
+        record_limit(client, &token, &verification.limit, opts).await?;
+        return opts.threshold().check(&verification.limit);
+    }
+
+    if let Some(needed) = opts.need {
+        let probe = client.peek(&token).await?;
+        progress.finish();
+
+        let need = Need::new(opts.user.clone(), needed, &probe);
+        if !opts.check {
+            print_value(&need, format);
+        }
+        if opts.verbose > 0 && !probe.unlimited {
+            print_keying(identity, &probe.limit);
+        }
+
+        if probe.unlimited {
+            expect_identity(&token, probe.limit.source.as_deref(), opts)?;
+            return need.check();
+        }
+        record_limit(client, &token, &probe.limit, opts).await?;
+        need.check()?;
+        return opts.threshold().check(&probe.limit);
+    }
+
+    if opts.image.is_some() {
+        let image = client.image(&token).await?;
+        progress.finish();
+        if !opts.check {
+            print_value(&image, format);
+        }
+
+        return match &image.limit {
+            Some(limit) => {
+                record_limit(client, &token, limit, opts).await?;
+                opts.threshold().check(limit)
+            }
+            None => Ok(()),
+        };
+    }
+
+    // get limit from token
+    let (result, plan) = join!(client.peek(&token), lookup_plan(client, creds, opts));
+    progress.finish();
+    let probe = result?;
+    let limit = probe.limited()?;
+    if opts.verbose > 0 {
+        print_keying(identity, &limit);
+    }
 
-/// Parses options stuct and gets jwt token
+    // json and templates are labelled with the identity, plain stays just the limit
+    if !opts.check && !opts.nagios {
+        match (format, plan) {
+            (Format::Json | Format::Template, plan) => {
+                let mut report = Report::new(opts.user.clone(), Ok(limit.clone()));
+                report.plan = plan;
+                print_value(&report, format);
+            }
+            (_, Some(plan)) if opts.human => println!("{} ({})", human::limit(&limit), plan),
+            (_, None) if opts.human => println!("{}", human::limit(&limit)),
+            (_, Some(plan)) => println!("{} ({})", limit, plan),
+            (_, None) => print_value(&limit, format),
+        }
+    }
+
+    record_limit(client, &token, &limit, opts).await?;
+    if opts.nagios {
+        opts.nagios_thresholds().check(&limit).exit();
+    }
+    opts.threshold().check(&limit)
+}
+
+/// Provider of tokens for the identity to check, for the long running modes
 ///
 /// # Arguments
 ///
+/// * `client` - `DrlClient` the tokens are requested with
 /// * `opts` - `Opts` struct with parsed options
-async fn get_token(opts: Opts) -> DrlResult<Token> {
-    let Opts { user, pass } = opts;
+fn token_provider(client: &DrlClient, opts: &Opts) -> TokenProvider {
+    client.provider(get_credentials(opts))
+}
 
-    if let Some(user) = user {
-        let pass = pass.unwrap_or_else(|| {
-            // rpassword docs say:
-            //   Prompt for a password on TTY (safest but not always most practical
-            //   when integrating with other tools or unit testing)
-            //
-            // should this have error handling?
+/// `opts` with the user and password filled in from `docker-rl login` and `docker login`
+///
+/// # Arguments
+///
+/// * `opts` - `Opts` struct with parsed options
+fn with_saved_credentials(opts: &Opts) -> Opts {
+    let mut opts = opts.clone();
+    use_keyring_credentials(&mut opts);
+    use_docker_credentials(&mut opts);
+    opts
+}
 
-            let prompt = format!("Password for {}: ", user);
-            read_password_from_tty(Some(&prompt)).unwrap()
-        });
+/// `opts` with the credentials read again, for `--watch` on SIGHUP
+///
+/// Returns `None`, after a warning, if the password would be prompted for or the password
+/// file can't be read, so the watch keeps the credentials it has
+///
+/// # Arguments
+///
+/// * `opts` - `Opts` struct with parsed options, without the saved credentials filled in
+/// * `log` - `LogSink` of `--log-target`
+fn reload_credentials(opts: &Opts, log: &LogSink) -> Option<Opts> {
+    let reloaded = with_saved_credentials(opts);
+    let passed = reloaded.pass.is_some() || reloaded.token.is_some();
+    let problem = match &reloaded.pass_file {
+        Some(path) => read_pass_file(path).err().map(|e| e.msg),
+        None if !reloaded.anonymous && reloaded.user.is_some() && !passed => {
+            Some(String::from("the password was typed in"))
+        }
+        None => None,
+    };
+
+    match problem {
+        Some(problem) => {
+            if !opts.quiet {
+                log.warn(&format!("not reloading credentials, {}", problem));
+            }
+            None
+        }
+        None => Some(reloaded),
+    }
+}
+
+/// The checks of `--watch` for `provider`, every `--interval` or as `--adaptive` chooses
+///
+/// Each check comes with the `Backoff` after it, and the `Pace` with `--adaptive`
+///
+/// # Arguments
+///
+/// * `provider` - `TokenProvider` for the identity to check
+/// * `opts` - `Opts` struct with parsed options
+fn watch_polls(
+    provider: TokenProvider,
+    opts: &Opts,
+) -> BoxStream<'static, (DrlResult<Limit>, Backoff, Option<Pace>)> {
+    match opts.pacing() {
+        Some(pacing) => poll_limits_adaptive(provider, pacing, opts.backoff())
+            .map(|(result, backoff, pace)| (result, backoff, Some(pace)))
+            .boxed(),
+        None => poll_limits_with(provider, opts.interval, opts.backoff())
+            .map(|(result, backoff)| (result, backoff, None))
+            .boxed(),
+    }
+}
+
+/// Checks the limit every `--interval`, or as `--adaptive` chooses, for `--watch` and prints a
+/// line each time
+///
+/// JSON is printed as one object per line. Failed checks don't end the watch. Ctrl-C,
+/// SIGTERM or the end of `--duration` abandons the check in flight and ends it with a summary
+/// on stderr, and as the last line of JSON with `--format json`. SIGHUP reads
+/// `--pass-file`, the keyring and the docker config again and checks right away, keeping the
+/// credentials if they were typed in
+///
+/// # Arguments
+///
+/// * `client` - `DrlClient` to check with
+/// * `opts` - `Opts` struct with parsed options, without the saved credentials filled in
+/// * `log` - `LogSink` of `--log-target`, for everything but the lines of the checks
+async fn watch(client: &DrlClient, opts: &Opts, log: &LogSink) {
+    let mut current = with_saved_credentials(opts);
+    let provider = token_provider(client, &current);
+    let mut user = provider.user().map(String::from);
+    let timestamps = opts.timestamps();
+
+    let mut polls = watch_polls(provider, opts);
+    let mut summary = Summary::default();
+    let mut hangups = Hangups::new();
+    let stopped = interrupted();
+    futures::pin_mut!(stopped);
+    let elapsed = async {
+        match opts.duration {
+            Some(d) => tokio::time::sleep(d).await,
+            None => future::pending().await,
+        }
+    };
+    futures::pin_mut!(elapsed);
+    // alerts are only sent when the limit goes below the threshold, not every check after
+    let mut below = false;
+    let mut notifier = opts.notifier().unwrap_or_else(|e| fail(&e, opts));
+    let hooks = opts.hooks();
+    let mut crossings = hooks.as_ref().map(Hooks::crossings);
+
+    loop {
+        let (result, backoff, pace) = tokio::select! {
+            _ = &mut stopped => break,
+            _ = &mut elapsed => break,
+            _ = hangups.recv() => {
+                current = match reload_credentials(opts, log) {
+                    Some(reloaded) => reloaded,
+                    None => continue,
+                };
+                let provider = token_provider(client, &current);
+                user = provider.user().map(String::from);
+                if !opts.quiet {
+                    let who = user.as_deref().unwrap_or("anonymous");
+                    log.info(&format!("reloaded credentials, checking as {}", who));
+                }
+                polls = watch_polls(provider, opts);
+                continue;
+            }
+            result = polls.next() => match result {
+                Some(r) => r,
+                None => break,
+            },
+        };
+
+        let now = SystemTime::now();
+        summary.add(&result, now);
+        if opts.verbose > 0 {
+            match (&result, pace) {
+                (Err(_), _) => log.info(&backoff.to_string()),
+                (Ok(_), Some(pace)) => log.info(&pace.to_string()),
+                _ => (),
+            }
+        }
+        let record = Record::new(user.clone(), &result, now, &timestamps);
+        match opts.format {
+            Format::Json => println!("{}", to_json_line(&Versioned::new(&record))),
+            Format::Template => print_value(&record, opts.format),
+            _ if record.error.is_some() && opts.quiet => (),
+            _ if opts.human => println!("{}", record.human()),
+            _ => println!("{}", record),
+        }
+
+        if let Ok(limit) = &result {
+            save_state(limit, &current);
+            log_history(limit, &current);
+            if let Err(e) = push_metrics(client, limit, &current).await {
+                fail(&e, &current);
+            }
+            below = send_alert(client, limit, !below, &current, log).await;
+            let shown = notifier
+                .as_mut()
+                .and_then(|n| n.check(user.as_deref(), limit));
+            if let Some(notification) = shown {
+                if let Err(e) = notify::show(&notification).await {
+                    if !opts.quiet {
+                        log.warn(&e.msg);
+                    }
+                }
+            }
+            let crossed = crossings.as_mut().and_then(|c| c.check(limit));
+            if let (Some(hooks), Some(crossing)) = (&hooks, crossed) {
+                hooks.spawn(crossing, user.as_deref(), limit, log);
+            }
+        }
+    }
+
+    if opts.format == Format::Json {
+        let line = SummaryLine { summary: &summary };
+        println!("{}", to_json_line(&Versioned::new(&line)));
+    }
+    if !opts.quiet {
+        log.info(&format!("stopped after {}", summary));
+    }
+}
 
-        get_userpass_token(user, pass).await
+/// Serves Prometheus metrics on `addr` for `--serve`, until Ctrl-C
+///
+/// With `--users-from-stdin`, every user read is checked, and each password has to be there
+/// before anything is served
+///
+/// # Arguments
+///
+/// * `client` - `DrlClient` to check with
+/// * `addr` - address to listen on
+/// * `opts` - `Opts` struct with parsed options
+/// * `log` - `LogSink` of `--log-target`
+async fn serve(client: &DrlClient, addr: SocketAddr, opts: &Opts, log: LogSink) {
+    let providers = if opts.users_from_stdin {
+        // structopt makes sure the prefix is there
+        let prefix = opts.password_env_prefix.as_deref().unwrap_or_default();
+        let users = stdin_users().unwrap_or_else(|e| fail(&e, opts));
+        users
+            .into_iter()
+            .map(|user| {
+                let pass = env_password(prefix, &user).unwrap_or_else(|e| fail(&e, opts));
+                client.provider(Some((user, pass)))
+            })
+            .collect()
     } else {
-        get_anon_token().await
+        vec![token_provider(client, opts)]
+    };
+    let auth = opts.metrics_auth().unwrap_or_else(|e| fail(&e, opts));
+    // notifications are for the desktop of --watch, not the server of an exporter
+    let hooks = opts.hooks();
+    if opts.notify_below.is_some() && hooks.is_none() {
+        let msg = String::from("--notify-below needs --on-breach or --on-recover with --serve");
+        fail(&DrlErr::new(msg, ExitCode::Input), opts);
+    }
+
+    let exporter = Exporter {
+        addr,
+        interval: opts.serve_interval,
+        backoff: opts.backoff(),
+        quiet: opts.quiet,
+        verbose: opts.verbose > 0,
+        auth,
+        concurrency: opts.concurrency,
+        ready_failures: opts.ready_failures,
+        health_exempt: opts.metrics_auth_exempt_health,
+        hooks,
+        log,
+    };
+    let result = exporter.run_all(providers, interrupted()).await;
+    result.unwrap_or_else(|e| fail(&e, opts));
+}
+
+/// Asks the registry of `client` for its token service, exiting if it can't
+///
+/// # Arguments
+///
+/// * `client` - `DrlClient` for the registry from the options
+/// * `opts` - `Opts` struct with parsed options
+async fn discover_registry(client: DrlClient, opts: &Opts) -> DrlClient {
+    let progress = Progress::start(opts.show_progress(), "contacting registry…");
+    let result = cancellable(client.discover(), interrupted()).await;
+    progress.finish();
+
+    result.unwrap_or_else(|e| fail(&e, opts))
+}
+
+/// Runs `docker-rl doctor` and prints every step, exiting non-zero if a critical one failed
+///
+/// # Arguments
+///
+/// * `builder` - `DrlClientBuilder` for the registry to check
+/// * `opts` - `Opts` struct with parsed options
+async fn doctor(builder: DrlClientBuilder, opts: &Opts) {
+    let progress = Progress::start(opts.show_progress(), "running checks…");
+    let diagnosis = diagnose(builder).await;
+    progress.finish();
+
+    print_value(&diagnosis, opts.format);
+    diagnosis.check().unwrap_or_else(|e| fail(&e, opts));
+}
+
+/// Pushes `limit` to the Pushgateway, if `--pushgateway` was passed
+///
+/// Failures only warn, unless `--push-strict` was passed
+///
+/// # Arguments
+///
+/// * `client` - `DrlClient` to push with
+/// * `limit` - `Limit` to push
+/// * `opts` - `Opts` struct with parsed options
+async fn push_metrics(client: &DrlClient, limit: &Limit, opts: &Opts) -> DrlResult<()> {
+    let gateway = match opts.pushgateway() {
+        Some(g) => g,
+        None => return Ok(()),
+    };
+
+    match gateway.push(client, opts.user.as_deref(), limit).await {
+        Err(e) if opts.push_strict => Err(e),
+        Err(e) => {
+            if !opts.quiet {
+                eprintln!("warning: couldn't push metrics: {}", e);
+            }
+            Ok(())
+        }
+        Ok(()) => Ok(()),
     }
 }
 
+/// Sends an alert if `limit` is below `--alert-below` and `armed` is set
+///
+/// Returns whether the limit is below it, so `--watch` only alerts when it first goes below.
+/// Failures only warn, the check itself went fine
+///
+/// # Arguments
+///
+/// * `client` - `DrlClient` to send the alert with
+/// * `limit` - `Limit` to check
+/// * `armed` - whether to alert, `false` if the last check already did
+/// * `opts` - `Opts` struct with parsed options
+/// * `log` - `LogSink` the alert and its failures are written to
+async fn send_alert(
+    client: &DrlClient,
+    limit: &Limit,
+    armed: bool,
+    opts: &Opts,
+    log: &LogSink,
+) -> bool {
+    let alerter = match opts.alerter() {
+        Some(a) => a,
+        None => return false,
+    };
+    let alert = match Alert::below(&alerter.threshold, opts.user.as_deref(), limit) {
+        Some(a) => a,
+        None => return false,
+    };
+
+    if armed {
+        if opts.verbose > 0 {
+            log.info(&format!("alerting: {}", alert.text));
+        }
+        if let Err(e) = alerter.send(client, &alert).await {
+            if !opts.quiet {
+                log.warn(&format!("couldn't send the alert: {}", e));
+            }
+        }
+    }
+    true
+}
+
+/// Stores `limit` for `--cached`, unless `--no-state` was passed
+///
+/// With `-v` the estimate of when the limit runs out is printed as well, from the samples of
+/// the earlier checks. Failures only warn, the check itself went fine
+///
+/// # Arguments
+///
+/// * `limit` - `Limit` to store
+/// * `opts` - `Opts` struct with parsed options
+fn save_state(limit: &Limit, opts: &Opts) {
+    if opts.no_state && opts.verbose == 0 {
+        return;
+    }
+
+    // a missing or unreadable state just starts the samples over
+    let state = State::after(state::load().ok(), opts.user.clone(), limit.clone());
+    if opts.verbose > 0 {
+        let identity = opts.user.as_deref().unwrap_or("anonymous");
+        let estimate = Estimate::new(&state);
+        if opts.human {
+            eprintln!("{}: {}", identity, estimate.human());
+        } else {
+            eprintln!("{}: {}", identity, estimate);
+        }
+    }
+    if opts.no_state {
+        return;
+    }
+
+    if let Err(e) = state::save(&state) {
+        if !opts.quiet {
+            eprintln!("warning: couldn't store the result: {}", e);
+        }
+    }
+}
+
+/// Appends `limit` to the `--log-file` history, if there is one
+///
+/// Failures only warn, the check itself went fine
+///
+/// # Arguments
+///
+/// * `limit` - `Limit` that was checked
+/// * `opts` - `Opts` struct with parsed options
+fn log_history(limit: &Limit, opts: &Opts) {
+    let path = match &opts.log_file {
+        Some(p) => p,
+        None => return,
+    };
+
+    let profile = opts.profile.first().cloned();
+    let entry = history::Entry::new(
+        profile,
+        opts.user.clone(),
+        limit,
+        SystemTime::now(),
+        &opts.timestamps(),
+    );
+    if let Err(e) = history::append(path, &entry) {
+        if !opts.quiet {
+            eprintln!("warning: {}", e);
+        }
+    }
+}
+
+/// Prints the result of the last check for `--cached`, without any requests
+///
+/// # Arguments
+///
+/// * `opts` - `Opts` struct with parsed options
+fn show_cached(opts: &Opts) -> DrlResult<()> {
+    let state = state::load()?;
+    if opts.user.is_some() && state.user != opts.user {
+        let stored = state.user.as_deref().unwrap_or("anonymous");
+        let msg = format!("no cached result for this user, it's for {}", stored);
+        let err = DrlErr::new(msg, ExitCode::Stale);
+        return Err(err);
+    }
+
+    let cached = Cached::new(state, SystemTime::now(), &opts.timestamps());
+    cached.check(opts.max_age)?;
+
+    if opts.nagios {
+        opts.nagios_thresholds().check(&cached.limit).exit();
+    }
+    if !opts.check {
+        match opts.format {
+            Format::Json | Format::Template => print_value(&cached, opts.format),
+            _ if opts.human => println!("{}", cached.human()),
+            _ => print_value(&cached, opts.format),
+        }
+    }
+
+    opts.threshold().check(&cached.limit)
+}
+
+/// Exits with the code of `err`, printing it unless `--quiet` was passed
+///
+/// With `--nagios` it is printed as the status line instead, and with `--format json` as an
+/// object on stderr
+///
+/// # Arguments
+///
+/// * `err` - `DrlErr` to exit with
+/// * `opts` - `Opts` struct with parsed options
+fn fail(err: &DrlErr, opts: &Opts) -> ! {
+    // plugins report failures on stdout, like any other status
+    if opts.nagios {
+        nagios::Output::failed(err).exit();
+    }
+    if opts.quiet {
+        err.exit();
+    }
+    match opts.format {
+        Format::Json => {
+            let failure = Failure { error: err };
+            eprintln!("{}", to_json_line(&Versioned::new(&failure)));
+        }
+        _ => eprintln!("{}", err),
+    }
+    err.exit();
+}
+
 /// Parses cmdline and prints rate limit
 #[tokio::main]
 async fn main() {
     // parse arguments
-    let opts = Opts::parse_args();
+    let mut opts = Opts::parse_args();
+    let format = opts.format;
 
-    // get auth token for docker hub
-    let result = get_token(opts).await;
-    let token = result.unwrap_or_else(|e| e.err_out());
+    if opts.schema {
+        print!("{}", SCHEMA);
+        return;
+    }
+    if let Some(Command::Completions { shell }) = opts.command {
+        options::completions(shell, &mut io::stdout());
+        return;
+    }
 
-    // get limit from token
-    let result = get_limit(&token).await;
-    let limit = result.unwrap_or_else(|e| e.err_out());
+    // every exit after this uses the plugin statuses
+    if opts.nagios {
+        ExitStyle::Plugin.install();
+    }
+    // a journal that isn't there fails before anything runs, not once it's written to
+    let log = LogSink::open(opts.log_target).unwrap_or_else(|e| fail(&e, &opts));
+    #[cfg(feature = "tracing")]
+    logging::init(opts.verbose, log.clone()).unwrap_or_else(|e| fail(&e, &opts));
+
+    // every output after this uses the template
+    if let Some(template) = opts.template.clone() {
+        template.install();
+    }
+
+    // the token cache is only files, nothing has to be reached
+    if let Some(Command::Cache(command)) = &opts.command {
+        cache(command, &opts).unwrap_or_else(|e| fail(&e, &opts));
+        return;
+    }
+
+    // every request after this is sent with the client of these settings
+    let config = opts.client_config().unwrap_or_else(|e| fail(&e, &opts));
+    config.validate().unwrap_or_else(|e| fail(&e, &opts));
+    if opts.insecure && !opts.quiet && !opts.nagios {
+        eprintln!("warning: TLS certificates aren't verified, --insecure is only for testing");
+    }
+    let builder = DrlClient::builder()
+        .config(config)
+        .registry(opts.registry())
+        .scope(opts.scope.clone());
+
+    // the doctor reports a client that can't be created as one of its checks
+    if let Some(Command::Doctor) = opts.command {
+        doctor(builder, &opts).await;
+        return;
+    }
+    let client = builder.build().unwrap_or_else(|e| fail(&e, &opts));
+
+    match opts.command {
+        Some(Command::Config) => {
+            print_value(&opts.settings(), format);
+            return;
+        }
+        Some(Command::Login) => {
+            login(&client, &opts)
+                .await
+                .unwrap_or_else(|e| fail(&e, &opts));
+            return;
+        }
+        Some(Command::Logout) => {
+            logout(&opts).unwrap_or_else(|e| fail(&e, &opts));
+            return;
+        }
+        // serve is --serve by now, and completions and cache were taken care of already
+        Some(
+            Command::Check
+            | Command::Doctor
+            | Command::Serve { .. }
+            | Command::Completions { .. }
+            | Command::Cache(_),
+        )
+        | None => (),
+    }
+
+    // every profile has its own client and registry
+    if !opts.per_profile.is_empty() {
+        check_profiles(std::mem::take(&mut opts.per_profile)).await;
+        return;
+    }
+
+    if opts.cached {
+        show_cached(&opts).unwrap_or_else(|e| fail(&e, &opts));
+        return;
+    }
+
+    // a dry run doesn't even ask the registry for its token service
+    let client = if opts.dry_run {
+        client
+    } else {
+        discover_registry(client, &opts).await
+    };
+
+    // the watch fills them in itself, again on SIGHUP
+    if opts.watch {
+        watch(&client, &opts, &log).await;
+        return;
+    }
+
+    // only the checks below need credentials
+    if !opts.users_from_stdin {
+        use_keyring_credentials(&mut opts);
+        use_docker_credentials(&mut opts);
+    }
+
+    if let Some(addr) = opts.serve {
+        serve(&client, addr, &opts, log).await;
+        return;
+    }
+
+    // resolve everything, but don't send anything
+    if opts.dry_run {
+        if opts.users_from_stdin {
+            let users = stdin_users().unwrap_or_else(|e| fail(&e, &opts));
+            let plan = BatchPlan {
+                concurrency: opts.concurrency,
+                stagger: opts.stagger,
+                checks: users
+                    .into_iter()
+                    .map(|u| Plan::new(Some(u), &client))
+                    .collect(),
+            };
+            print_value(&plan, format);
+        } else {
+            let plan = Plan::new(opts.user, &client);
+            print_value(&plan, format);
+        }
+        return;
+    }
+
+    if opts.users_from_stdin {
+        check_stdin_users(&client, &opts).await;
+        return;
+    }
+
+    if opts.compare {
+        // structopt makes sure the user is there
+        let user = opts.user.clone().unwrap_or_default();
+        check_compare(&client, user, &opts).await;
+        return;
+    }
+
+    if opts.compare_stacks {
+        check_stacks(&opts).await;
+        return;
+    }
 
-    println!("{}", limit);
+    check_single(&client, &opts).await;
 }
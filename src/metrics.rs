@@ -0,0 +1,219 @@
+//! The limit as Prometheus metrics, and pushing them to a Pushgateway
+
+use super::api::DrlClient;
+use super::client::BasicAuth;
+use super::err::{DrlErr, DrlResult, ExitCode};
+use super::limit::Limit;
+use reqwest::Url;
+use std::fmt::{self, Write};
+
+/// Job label used when none is given
+pub const DEFAULT_JOB: &str = "docker-rl";
+
+/// Escapes `value` for use inside a quoted label value
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Writes the metric `name` with a sample for each user, labelled `user="..."`
+///
+/// Nothing is written without samples
+///
+/// # Arguments
+///
+/// * `out` - exposition to append to
+/// * `name` - name of the metric
+/// * `kind` - Prometheus type of the metric, e.g. `gauge`
+/// * `help` - what the metric is
+/// * `samples` - user, `None` for anonymous, and value of every sample
+pub(crate) fn write_metric<T: fmt::Display>(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    samples: &[(Option<&str>, T)],
+) {
+    if samples.is_empty() {
+        return;
+    }
+
+    // writing to a String can't fail
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    for (user, value) in samples {
+        let label = escape_label(user.unwrap_or("anonymous"));
+        let _ = writeln!(out, "{}{{user=\"{}\"}} {}", name, label, value);
+    }
+}
+
+/// Renders `limit` in the Prometheus text exposition format
+///
+/// # Arguments
+///
+/// * `user` - user the limit was checked for, `None` for anonymous
+/// * `limit` - `Limit` to render
+pub fn exposition(user: Option<&str>, limit: &Limit) -> String {
+    exposition_all(&[(user, limit)])
+}
+
+/// Renders the limits of several users, with a sample of each metric for every user
+///
+/// # Arguments
+///
+/// * `limits` - user, `None` for anonymous, and `Limit` of each identity
+pub fn exposition_all(limits: &[(Option<&str>, &Limit)]) -> String {
+    let remaining: Vec<_> = limits.iter().map(|(u, l)| (*u, l.remaining)).collect();
+    let total: Vec<_> = limits.iter().map(|(u, l)| (*u, l.total)).collect();
+
+    let mut out = String::new();
+    write_metric(
+        &mut out,
+        "dockerhub_ratelimit_remaining",
+        "gauge",
+        "Pulls remaining in the current rate limit window",
+        &remaining,
+    );
+    write_metric(
+        &mut out,
+        "dockerhub_ratelimit_limit",
+        "gauge",
+        "Pulls allowed per rate limit window",
+        &total,
+    );
+    out
+}
+
+/// Where and how to push metrics
+#[derive(Debug, Clone)]
+pub struct Pushgateway {
+    /// Base URL of the Pushgateway
+    pub url: Url,
+    /// `job` of the grouping key
+    pub job: String,
+    /// `instance` of the grouping key, left out if `None`
+    pub instance: Option<String>,
+    /// Basic credentials for the Pushgateway
+    pub auth: Option<BasicAuth>,
+}
+
+impl Pushgateway {
+    /// URL the metrics are pushed to, `/metrics/job/<job>[/instance/<instance>]`
+    ///
+    /// Label values that can't be a path segment, those with a `/` or empty ones, use the
+    /// Pushgateway's base64 form
+    pub fn push_url(&self) -> Url {
+        let mut url = self.url.clone();
+        let mut labels = vec![("job", self.job.as_str())];
+        if let Some(instance) = &self.instance {
+            labels.push(("instance", instance));
+        }
+
+        // a base that can't have a path is rejected when parsing the options
+        if let Ok(mut segments) = url.path_segments_mut() {
+            segments.pop_if_empty().push("metrics");
+            for (name, value) in labels {
+                if value.is_empty() || value.contains('/') {
+                    let encoded = base64::encode_config(value, base64::URL_SAFE);
+                    segments.push(&format!("{}@base64", name));
+                    // `=` is the Pushgateway's way of spelling the empty value
+                    segments.push(if encoded.is_empty() { "=" } else { &encoded });
+                } else {
+                    segments.push(name).push(value);
+                }
+            }
+        }
+
+        url
+    }
+
+    /// Replaces the metrics of the grouping key with the ones for `limit`
+    ///
+    /// # Errors
+    ///
+    /// Returns `ExitCode::Connection` if the Pushgateway can't be reached or rejects the push
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - `DrlClient` to push with
+    /// * `user` - user the limit was checked for, `None` for anonymous
+    /// * `limit` - `Limit` to push
+    pub async fn push(
+        &self,
+        client: &DrlClient,
+        user: Option<&str>,
+        limit: &Limit,
+    ) -> DrlResult<()> {
+        let url = self.push_url();
+        let http = client.http();
+        let mut req = http
+            .client
+            .put(url.as_str())
+            .header("content-type", "text/plain; version=0.0.4")
+            .body(exposition(user, limit));
+        if let Some(auth) = &self.auth {
+            req = req.basic_auth(&auth.user, Some(&auth.pass));
+        }
+
+        let resp = match req.send().await {
+            Ok(r) => r,
+            Err(e) => return Err(http.connect_error("the pushgateway", e)),
+        };
+
+        if !resp.status().is_success() {
+            let msg = format!("pushgateway rejected the metrics: {}", resp.status());
+            let err = DrlErr::new(msg, ExitCode::Connection);
+            return Err(err);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gauges() {
+        let limit = Limit {
+            remaining: 42,
+            total: 100,
+            ..Limit::default()
+        };
+        let out = exposition(Some("ci\"bot"), &limit);
+        assert!(out.contains("# TYPE dockerhub_ratelimit_remaining gauge\n"));
+        assert!(out.contains("\ndockerhub_ratelimit_remaining{user=\"ci\\\"bot\"} 42\n"));
+        assert!(out.contains("\ndockerhub_ratelimit_limit{user=\"ci\\\"bot\"} 100\n"));
+
+        let out = exposition(None, &limit);
+        assert!(out.contains("dockerhub_ratelimit_limit{user=\"anonymous\"} 100\n"));
+    }
+
+    #[test]
+    fn one_family_for_many_users() {
+        let (a, b) = (
+            Limit {
+                remaining: 1,
+                total: 200,
+                ..Limit::default()
+            },
+            Limit {
+                remaining: 2,
+                total: 100,
+                ..Limit::default()
+            },
+        );
+        let out = exposition_all(&[(Some("line\nbreak\\"), &a), (None, &b)]);
+        assert_eq!(
+            out.matches("# TYPE dockerhub_ratelimit_remaining").count(),
+            1
+        );
+        assert!(out.contains("dockerhub_ratelimit_remaining{user=\"line\\nbreak\\\\\"} 1\n"));
+        assert!(out.contains("dockerhub_ratelimit_remaining{user=\"anonymous\"} 2\n"));
+
+        assert_eq!(exposition_all(&[]), "");
+    }
+}
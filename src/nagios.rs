@@ -0,0 +1,138 @@
+//! Output for Nagios and Icinga, which run `docker-rl` as a monitoring plugin
+//!
+//! A plugin prints one line, with the status, a summary and the perfdata after a `|`, and
+//! exits with the status. See `ExitStyle::Plugin` for the statuses
+
+use super::err::{self, DrlErr, ExitCode};
+use super::limit::Limit;
+use std::fmt;
+use std::process;
+
+/// Name the status line starts with
+const SERVICE: &str = "DOCKER-RL";
+
+/// Name of the status, as the plugin guidelines spell it
+fn status_name(code: ExitCode) -> &'static str {
+    match err::ExitStyle::Plugin.status(code) {
+        0 => "OK",
+        1 => "WARNING",
+        2 => "CRITICAL",
+        _ => "UNKNOWN",
+    }
+}
+
+/// Percentages of the limit below which the check warns or is critical
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Thresholds {
+    /// Warn when less than this percentage remains
+    pub warn: Option<f64>,
+    /// Critical when less than this percentage remains
+    pub crit: Option<f64>,
+}
+
+impl Thresholds {
+    /// Checks `limit` against the thresholds, critical first
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - `Limit` to check
+    pub fn check(&self, limit: &Limit) -> Output {
+        // a limit with a total of 0 has nothing left
+        let percent = limit.percent().unwrap_or(0.0);
+        let perfdata = Some(self.perfdata(limit));
+
+        let below = |threshold: Option<f64>| threshold.filter(|t| percent < *t);
+        let (code, summary) = if limit.remaining == 0 {
+            (
+                ExitCode::OverLimit,
+                format!("{} remaining, over limit", limit),
+            )
+        } else if let Some(crit) = below(self.crit) {
+            let summary = format!("{} remaining, {:.0}% is below {}%", limit, percent, crit);
+            (ExitCode::BelowThreshold, summary)
+        } else if let Some(warn) = below(self.warn) {
+            let summary = format!("{} remaining, {:.0}% is below {}%", limit, percent, warn);
+            (ExitCode::Warning, summary)
+        } else {
+            (
+                ExitCode::Ok,
+                format!("{} remaining ({:.0}%)", limit, percent),
+            )
+        };
+
+        Output {
+            code,
+            summary,
+            perfdata,
+        }
+    }
+
+    /// Perfdata for `limit`, e.g. `remaining=97;50:;10:;0;100`
+    ///
+    /// The thresholds are turned into counts, as ranges the value has to stay in
+    fn perfdata(&self, limit: &Limit) -> String {
+        let range = |threshold: Option<f64>| {
+            threshold
+                .map(|t| {
+                    let count = (limit.total as f64 * t / 100.0).round() as u64;
+                    format!("{}:", count)
+                })
+                .unwrap_or_default()
+        };
+        format!(
+            "remaining={};{};{};0;{}",
+            limit.remaining,
+            range(self.warn),
+            range(self.crit),
+            limit.total
+        )
+    }
+}
+
+/// The line a plugin prints, and the code it exits with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Output {
+    /// Code to exit with, turned into the plugin status
+    pub code: ExitCode,
+    /// What was found
+    pub summary: String,
+    /// Perfdata, `None` if the limit isn't known
+    pub perfdata: Option<String>,
+}
+
+impl Output {
+    /// Output for a check that failed before there was a limit to check
+    ///
+    /// # Arguments
+    ///
+    /// * `err` - `DrlErr` the check failed with
+    pub fn failed(err: &DrlErr) -> Output {
+        Output {
+            code: err.ret,
+            summary: err.msg.clone(),
+            perfdata: None,
+        }
+    }
+
+    /// Prints the line and exits with the plugin status
+    pub fn exit(&self) -> ! {
+        println!("{}", self);
+        process::exit(err::ExitStyle::Plugin.status(self.code));
+    }
+}
+
+impl fmt::Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} - {}",
+            SERVICE,
+            status_name(self.code),
+            self.summary
+        )?;
+        if let Some(perfdata) = &self.perfdata {
+            write!(f, " | {}", perfdata)?;
+        }
+        Ok(())
+    }
+}
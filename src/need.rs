@@ -0,0 +1,87 @@
+//! Whether a planned number of pulls fits in the remaining limit
+
+use super::err::{DrlErr, DrlResult, ExitCode};
+use super::limit::Probe;
+use reqwest::Method;
+use serde::Serialize;
+use std::fmt;
+
+/// Outcome of checking the limit against a number of planned pulls
+#[derive(Serialize, Debug, Clone)]
+pub struct Need {
+    /// User the limit was checked for, `None` for anonymous
+    pub user: Option<String>,
+    /// Number of pulls planned
+    pub needed: u64,
+    /// Pulls left after the check itself, `None` if the account has no limit
+    pub remaining: Option<u64>,
+    /// Whether `needed` pulls fit in `remaining`
+    pub sufficient: bool,
+}
+
+impl Need {
+    /// Checks whether `needed` pulls fit in the limit from `probe`
+    ///
+    /// After a `GET`, docker reports the limit before decrementing it for the manifest request
+    /// of the check, so one less than the reported count is actually left
+    ///
+    /// # Arguments
+    ///
+    /// * `user` - user the limit was checked for, `None` for anonymous
+    /// * `needed` - number of pulls planned
+    /// * `probe` - `Probe` from the check
+    pub fn new(user: Option<String>, needed: u64, probe: &Probe) -> Need {
+        let remaining = if probe.unlimited {
+            None
+        } else if probe.method == Method::GET {
+            Some(probe.limit.remaining.saturating_sub(1))
+        } else {
+            Some(probe.limit.remaining)
+        };
+
+        Need {
+            user,
+            needed,
+            remaining,
+            sufficient: remaining.is_none_or(|r| r >= needed),
+        }
+    }
+
+    /// Fails if the planned pulls don't fit
+    ///
+    /// # Errors
+    ///
+    /// Returns `ExitCode::BelowThreshold` if fewer than `needed` pulls remain
+    pub fn check(&self) -> DrlResult<()> {
+        match self.remaining {
+            Some(remaining) if !self.sufficient => {
+                let msg = format!("{} needed but only {} remaining", self.needed, remaining);
+                let err = DrlErr::new(msg, ExitCode::BelowThreshold);
+                Err(err)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Need {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.remaining {
+            None => write!(f, "ok: no limit, {} needed", self.needed),
+            Some(remaining) if self.sufficient => write!(
+                f,
+                "ok: {} remaining, {} needed ({} headroom)",
+                remaining,
+                self.needed,
+                remaining - self.needed
+            ),
+            Some(remaining) => write!(
+                f,
+                "not enough: {} remaining, {} needed ({} short)",
+                remaining,
+                self.needed,
+                self.needed - remaining
+            ),
+        }
+    }
+}
@@ -0,0 +1,262 @@
+//! Desktop notifications for `--notify-below`, when a watched limit runs low
+//!
+//! A notification is shown once when the limit first goes below the threshold, not on every
+//! check after, and again only once it has been back up. Like the keyring, they are shown by
+//! the tool of the platform: `notify-send` from libnotify, `osascript` on macOS and PowerShell
+//! on Windows. Only built with the `desktop-notify` feature
+
+use super::err::{DrlErr, DrlResult, ExitCode};
+use super::hook::{Crossing, Crossings};
+use super::limit::Limit;
+#[cfg(feature = "desktop-notify")]
+use tokio::process::Command;
+
+/// A run of the notification tool, the program and its arguments
+#[cfg(feature = "desktop-notify")]
+type Call = (&'static str, Vec<String>);
+
+/// Title of every notification
+pub const SUMMARY: &str = "docker-rl: pulls running low";
+
+/// A notification to show
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    /// Title, `SUMMARY`
+    pub summary: String,
+    /// The identity and the numbers, e.g. `ci-bot has 8/100 pulls left, below 10`
+    pub body: String,
+}
+
+/// Tracks the checks of one identity, to notify once per crossing of the threshold
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notifier {
+    /// Remaining pulls below which to notify
+    below: u64,
+    /// Crossings of `below` so far
+    crossings: Crossings,
+}
+
+impl Notifier {
+    /// Notifier for limits below `below`
+    ///
+    /// # Errors
+    ///
+    /// Returns `ExitCode::Input` if built without the `desktop-notify` feature
+    ///
+    /// # Arguments
+    ///
+    /// * `below` - remaining pulls below which to notify
+    pub fn new(below: u64) -> DrlResult<Notifier> {
+        if cfg!(not(feature = "desktop-notify")) {
+            let msg = "built without the desktop-notify feature, so --notify-below can't be used";
+            let err = DrlErr::new(msg.to_string(), ExitCode::Input);
+            return Err(err);
+        }
+        Ok(Notifier::unchecked(below))
+    }
+
+    /// Notifier for limits below `below`, whatever the build can show
+    fn unchecked(below: u64) -> Notifier {
+        Notifier {
+            below,
+            crossings: Crossings::new(below),
+        }
+    }
+
+    /// Counts a check, returning the notification to show if it crossed the threshold
+    ///
+    /// # Arguments
+    ///
+    /// * `user` - user the limit was checked for, `None` for anonymous
+    /// * `limit` - `Limit` from the check
+    pub fn check(&mut self, user: Option<&str>, limit: &Limit) -> Option<Notification> {
+        if self.crossings.check(limit) != Some(Crossing::Breach) {
+            return None;
+        }
+
+        let body = format!(
+            "{} has {} pulls left, below {}",
+            user.unwrap_or("anonymous"),
+            limit,
+            self.below
+        );
+        Some(Notification {
+            summary: String::from(SUMMARY),
+            body,
+        })
+    }
+}
+
+/// `s` as an AppleScript string literal
+#[cfg(all(feature = "desktop-notify", target_os = "macos"))]
+fn applescript(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// `s` as a PowerShell string literal
+#[cfg(all(feature = "desktop-notify", windows))]
+fn powershell(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+/// The program showing `n`, with its arguments
+#[cfg(all(feature = "desktop-notify", target_os = "macos"))]
+fn call(n: &Notification) -> Call {
+    let script = format!(
+        "display notification {} with title {}",
+        applescript(&n.body),
+        applescript(&n.summary)
+    );
+    ("osascript", vec!["-e".into(), script])
+}
+
+#[cfg(all(feature = "desktop-notify", windows))]
+fn call(n: &Notification) -> Call {
+    let script = format!(
+        "[void][Windows.UI.Notifications.ToastNotificationManager,Windows.UI.Notifications,\
+         ContentType=WindowsRuntime]; \
+         $t = [Windows.UI.Notifications.ToastNotificationManager]::GetTemplateContent(\
+         [Windows.UI.Notifications.ToastTemplateType]::ToastText02); \
+         $x = $t.GetElementsByTagName('text'); \
+         [void]$x.Item(0).AppendChild($t.CreateTextNode({})); \
+         [void]$x.Item(1).AppendChild($t.CreateTextNode({})); \
+         $n = [Windows.UI.Notifications.ToastNotification]::new($t); \
+         [Windows.UI.Notifications.ToastNotificationManager]::CreateToastNotifier('docker-rl').Show($n)",
+        powershell(&n.summary),
+        powershell(&n.body)
+    );
+    let args = ["-NoProfile", "-NonInteractive", "-Command"];
+    let mut args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+    args.push(script);
+    ("powershell", args)
+}
+
+#[cfg(all(feature = "desktop-notify", not(any(target_os = "macos", windows))))]
+fn call(n: &Notification) -> Call {
+    let args = ["--app-name", "docker-rl", "--", &n.summary, &n.body];
+    ("notify-send", args.iter().map(|a| a.to_string()).collect())
+}
+
+/// Shows `n` on the desktop
+///
+/// # Errors
+///
+/// Returns `ExitCode::Warning` if the tool of the platform isn't there or fails, e.g. without
+/// a desktop session, which only warrants a warning
+///
+/// # Arguments
+///
+/// * `n` - `Notification` to show
+#[cfg(feature = "desktop-notify")]
+pub async fn show(n: &Notification) -> DrlResult<()> {
+    let (program, args) = call(n);
+    let output = Command::new(program)
+        .args(&args)
+        .kill_on_drop(true)
+        .output()
+        .await;
+    let failure = match output {
+        Ok(o) if o.status.success() => return Ok(()),
+        Ok(o) => {
+            let said = String::from_utf8_lossy(&o.stderr);
+            match said.trim() {
+                "" => o.status.to_string(),
+                said => said.to_string(),
+            }
+        }
+        Err(e) => e.to_string(),
+    };
+
+    let msg = format!(
+        "couldn't show the notification with {}: {}",
+        program, failure
+    );
+    let err = DrlErr::new(msg, ExitCode::Warning);
+    Err(err)
+}
+
+#[cfg(not(feature = "desktop-notify"))]
+pub async fn show(_n: &Notification) -> DrlResult<()> {
+    let msg = "built without the desktop-notify feature, so notifications can't be shown";
+    let err = DrlErr::new(msg.to_string(), ExitCode::Input);
+    Err(err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn limit(remaining: u64) -> Limit {
+        Limit {
+            remaining,
+            total: 100,
+            window: Duration::from_secs(21600),
+            source: None,
+        }
+    }
+
+    #[test]
+    fn once_per_crossing() {
+        let mut n = Notifier::unchecked(10);
+        let checks = [50, 12, 8, 5, 3, 20, 9, 9];
+        let notified: Vec<bool> = checks
+            .iter()
+            .map(|r| n.check(Some("ci-bot"), &limit(*r)).is_some())
+            .collect();
+        assert_eq!(
+            notified,
+            [false, false, true, false, false, false, true, false]
+        );
+    }
+
+    #[test]
+    fn threshold_itself_isnt_below() {
+        let mut n = Notifier::unchecked(10);
+        assert_eq!(n.check(None, &limit(10)), None);
+        assert!(n.check(None, &limit(9)).is_some());
+    }
+
+    #[test]
+    fn identity_and_numbers_in_the_body() {
+        let mut n = Notifier::unchecked(10);
+        let shown = n.check(Some("ci-bot"), &limit(8)).unwrap();
+        assert_eq!(shown.summary, SUMMARY);
+        assert_eq!(shown.body, "ci-bot has 8/100 pulls left, below 10");
+
+        let mut n = Notifier::unchecked(10);
+        let shown = n.check(None, &limit(0)).unwrap();
+        assert!(
+            shown.body.starts_with("anonymous has 0/100"),
+            "{}",
+            shown.body
+        );
+    }
+
+    #[test]
+    fn needs_the_feature() {
+        assert_eq!(Notifier::new(10).is_ok(), cfg!(feature = "desktop-notify"));
+    }
+
+    #[cfg(all(feature = "desktop-notify", not(any(target_os = "macos", windows))))]
+    #[test]
+    fn notify_send_arguments() {
+        let n = Notification {
+            summary: String::from(SUMMARY),
+            body: String::from("-ci-bot has 8/100 pulls left"),
+        };
+        let (program, args) = call(&n);
+        assert_eq!(program, "notify-send");
+        // a body starting with a dash isn't taken for an option
+        assert_eq!(
+            args,
+            [
+                "--app-name",
+                "docker-rl",
+                "--",
+                SUMMARY,
+                "-ci-bot has 8/100 pulls left"
+            ]
+        );
+    }
+}
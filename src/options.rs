@@ -1,25 +1,2126 @@
 //! Options for CLI
 
+use super::alert::Alerter;
+use super::backoff::BackoffPolicy;
+use super::cacert::CaBundle;
+use super::client::{BasicAuth, ClientConfig, IpStack};
+use super::configfile::{self, Config, Profile, Value, DEFAULT_PROFILE};
+use super::duration::{format_duration, parse_duration};
+use super::err::{DrlErr, DrlResult, ExitCode};
+use super::hook::Hooks;
+use super::identity::ClientIdentity;
+use super::logtarget::LogTarget;
+use super::metrics::{Pushgateway, DEFAULT_JOB};
+use super::nagios::Thresholds;
+use super::notify::Notifier;
+use super::pace::Pacing;
+use super::registry::{Registry, DOCKER_HUB_URL};
+use super::retry::RetryPolicy;
+use super::serve::MetricsAuth;
+use super::settings::{Setting, Settings, Source, REDACTED};
+use super::template::{is_template, Template};
+use super::threshold::Threshold;
+use super::timestamp::{TimestampFormat, Timestamps};
+use super::token::{validate_access_token, Scope};
+use reqwest::Url;
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use structopt::clap::{AppSettings, ArgGroup, ArgMatches, ErrorKind, Shell};
 use structopt::StructOpt;
 
+/// Output formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Human readable output, e.g. `97/100`
+    Plain,
+    /// JSON document
+    Json,
+    /// Aligned columns, one row per identity
+    Table,
+    /// The `template::Template` given instead of a name, e.g. `{{.remaining}}/{{.total}}`
+    Template,
+}
+
+impl Format {
+    /// Names accepted on the command line
+    pub const VARIANTS: &'static [&'static str] = &["plain", "json", "table"];
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(Format::Plain),
+            "json" => Ok(Format::Json),
+            "table" => Ok(Format::Table),
+            s if is_template(s) => s.parse::<Template>().map(|_| Format::Template),
+            _ => Err(format!(
+                "unknown format: {}, expected {} or a template like '{{{{.remaining}}}}'",
+                s,
+                Format::VARIANTS.join(", ")
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Format::Plain => "plain",
+            Format::Json => "json",
+            Format::Table => "table",
+            Format::Template => "template",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Parses a concurrency limit, which has to be at least 1
+fn parse_concurrency(s: &str) -> Result<usize, String> {
+    match s.parse() {
+        Ok(0) | Err(_) => Err(format!("concurrency must be a positive integer: {}", s)),
+        Ok(n) => Ok(n),
+    }
+}
+
+/// Parses the failed checks in a row that make `--serve` unready, which has to be at least 1
+fn parse_ready_failures(s: &str) -> Result<u32, String> {
+    match s.parse() {
+        Ok(0) | Err(_) => Err(format!("ready failures must be a positive integer: {}", s)),
+        Ok(n) => Ok(n),
+    }
+}
+
+/// Parses a Pushgateway URL, which has to be `http` or `https`
+fn parse_pushgateway(s: &str) -> Result<Url, String> {
+    match Url::parse(s) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => Ok(url),
+        _ => Err(format!("invalid pushgateway URL: {}", s)),
+    }
+}
+
+/// Parses a webhook URL, which has to be `http` or `https`
+fn parse_webhook(s: &str) -> Result<Url, String> {
+    match Url::parse(s) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => Ok(url),
+        _ => Err(format!("invalid webhook URL: {}", s)),
+    }
+}
+
+/// Parses a registry, either a host like `ghcr.io` or an `http` or `https` URL
+///
+/// `docker.io` and `index.docker.io` are Docker Hub
+fn parse_registry(s: &str) -> Result<Url, String> {
+    let url = match s {
+        "docker.io" | "index.docker.io" => DOCKER_HUB_URL.to_string(),
+        _ if s.contains("://") => s.to_string(),
+        _ => format!("https://{}", s),
+    };
+    match Url::parse(&url) {
+        Ok(url)
+            if (url.scheme() == "http" || url.scheme() == "https")
+                && url.host_str().is_some()
+                && url.path() == "/" =>
+        {
+            Ok(url)
+        }
+        _ => Err(format!("invalid registry: {}", s)),
+    }
+}
+
+/// Parses the time between `--serve` or `--watch` checks, which can't be zero
+fn parse_interval(s: &str) -> Result<Duration, String> {
+    match parse_duration(s)? {
+        d if d.is_zero() => Err(format!("interval must be more than zero: {}", s)),
+        d => Ok(d),
+    }
+}
+
+/// Parses `--timeout` or `--connect-timeout`, which can't be zero
+fn parse_timeout(s: &str) -> Result<Duration, String> {
+    match parse_duration(s)? {
+        d if d.is_zero() => Err(format!("timeout must be more than zero: {}", s)),
+        d => Ok(d),
+    }
+}
+
+/// Parses a percentage between 0 and 100
+fn parse_percent(s: &str) -> Result<f64, String> {
+    match s.trim_end_matches('%').parse::<f64>() {
+        Ok(p) if (0.0..=100.0).contains(&p) => Ok(p),
+        _ => Err(format!("invalid percentage: {}", s)),
+    }
+}
+
+/// Parses a Docker Hub access token, which has to start with one of the known prefixes
+fn parse_access_token(s: &str) -> Result<String, String> {
+    validate_access_token(s)?;
+    Ok(s.into())
+}
+
+/// Options the config file can set to a value, by long name
+const CONFIG_OPTIONS: &[&str] = &[
+    "user",
+    "pass-file",
+    "registry",
+    "repository",
+    "scope",
+    "format",
+    "fail-below",
+    "fail-below-percent",
+    "need",
+    "expect-user",
+    "warn",
+    "crit",
+    "retries",
+    "retry-backoff",
+    "timeout",
+    "connect-timeout",
+    "bind-address",
+    "proxy",
+    "cacert",
+    "alert-below",
+    "alert-below-percent",
+    "webhook",
+    "exec",
+    "timestamp-format",
+    "log-file",
+];
+
+/// Flags the config file can turn on, by long name
+const CONFIG_FLAGS: &[&str] = &[
+    "no-docker-config",
+    "no-keyring",
+    "check",
+    "nagios",
+    "human",
+    "utc",
+    "wide",
+    "token-cache",
+    "ipv4",
+    "ipv6",
+];
+
+/// Values of the long option `--name` in `args`, without parsing them
+///
+/// Only for the options that have to be known before parsing, which have no short form.
+/// Values are split on commas, like clap does
+fn raw_options(args: &[OsString], name: &str) -> Vec<String> {
+    let flag = format!("--{}", name);
+    let prefix = format!("--{}=", name);
+    let mut values = Vec::new();
+    let mut args = args.iter().skip(1).take_while(|a| *a != "--");
+    while let Some(arg) = args.next() {
+        let arg = arg.to_string_lossy();
+        let value = if arg == flag {
+            args.next().map(|v| v.to_string_lossy().into_owned())
+        } else {
+            arg.strip_prefix(&prefix).map(String::from)
+        };
+        if let Some(value) = value {
+            values.extend(value.split(',').map(String::from));
+        }
+    }
+    values
+}
+
+/// Whether the flag `--name` is in `args`, without parsing them
+fn raw_flag(args: &[OsString], name: &str) -> bool {
+    let flag = format!("--{}", name);
+    args.iter()
+        .skip(1)
+        .take_while(|a| *a != "--")
+        .any(|a| *a == *flag)
+}
+
+/// The config file and the profiles of it to use with `args`
+///
+/// These are the ones named by `--profile`, every one but the default with `--all-profiles`,
+/// or else the default one. Returns `None` when there is no config file, or it has no
+/// default profile and none was named
+///
+/// # Errors
+///
+/// Returns `ExitCode::Input` if a named config file or profile doesn't exist, or a profile
+/// sets anything it can't
+fn config_profiles(args: &[OsString]) -> DrlResult<Option<(Config, Vec<Profile>)>> {
+    let named_path = raw_options(args, "config")
+        .pop()
+        .map(OsString::from)
+        .or_else(|| env::var_os("DOCKER_RL_CONFIG"))
+        .map(PathBuf::from);
+    let mut named = raw_options(args, "profile");
+    if named.is_empty() {
+        if let Some(var) = env::var_os("DOCKER_RL_PROFILE") {
+            named = var.to_string_lossy().split(',').map(String::from).collect();
+        }
+    }
+    let all = raw_flag(args, "all-profiles");
+
+    let path = match named_path {
+        Some(p) => p,
+        None => match configfile::path() {
+            Some(p) if p.exists() => p,
+            _ if named.is_empty() && !all => return Ok(None),
+            _ => {
+                let msg = String::from("profiles need a config file, none was found");
+                let err = DrlErr::new(msg, ExitCode::Input);
+                return Err(err);
+            }
+        },
+    };
+    let config = configfile::load(&path)?;
+
+    if all {
+        named = config
+            .profiles
+            .iter()
+            .map(|p| p.name.clone())
+            .filter(|n| n != DEFAULT_PROFILE)
+            .collect();
+        if named.is_empty() {
+            let msg = format!("no profiles in {}", path.display());
+            let err = DrlErr::new(msg, ExitCode::Input);
+            return Err(err);
+        }
+    }
+    let profiles = if named.is_empty() {
+        match config.profile(DEFAULT_PROFILE) {
+            Some(p) => vec![p],
+            None => return Ok(None),
+        }
+    } else {
+        let mut profiles = Vec::new();
+        for name in &named {
+            match config.profile(name) {
+                // checking a profile twice only prints it twice
+                Some(_) if profiles.iter().any(|p: &Profile| p.name == *name) => (),
+                Some(p) => profiles.push(p),
+                None => {
+                    let msg = format!("no profile '{}' in {}", name, path.display());
+                    let err = DrlErr::new(msg, ExitCode::Input);
+                    return Err(err);
+                }
+            }
+        }
+        profiles
+    };
+
+    for profile in &profiles {
+        for (key, value) in &profile.values {
+            let known = match value {
+                Value::Boolean(_) => CONFIG_FLAGS.contains(&key.as_str()),
+                _ => CONFIG_OPTIONS.contains(&key.as_str()),
+            };
+            if !known {
+                let msg = format!(
+                    "profile '{}' of {} can't set '{}' to {}",
+                    profile.name,
+                    path.display(),
+                    key,
+                    value
+                );
+                let err = DrlErr::new(msg, ExitCode::Input);
+                return Err(err);
+            }
+        }
+    }
+    Ok(Some((config, profiles)))
+}
+
+/// Arguments for the settings of `profile` that the command line and environment leave out
+///
+/// # Arguments
+///
+/// * `profile` - `Profile` checked by `config_profile`
+/// * `cli` - matches of the command line alone, `None` if they didn't parse without the profile
+fn config_args(profile: &Profile, cli: Option<&ArgMatches>) -> Vec<(&'static str, Vec<OsString>)> {
+    let names = CONFIG_OPTIONS.iter().chain(CONFIG_FLAGS);
+    names
+        .filter_map(|&key| {
+            let value = profile.get(key)?;
+            let on_cli = cli.is_some_and(|m| options_of(m).occurrences_of(key) > 0);
+            let in_env = OPTION_VARS
+                .iter()
+                .any(|(option, var)| *option == key && env::var_os(var).is_some());
+            if on_cli || in_env {
+                return None;
+            }
+
+            let flag = OsString::from(format!("--{}", key));
+            match value {
+                Value::Boolean(true) => Some((key, vec![flag])),
+                Value::Boolean(false) => None,
+                v => Some((key, vec![flag, v.to_string().into()])),
+            }
+        })
+        .collect()
+}
+
+/// Address `serve` listens on when none is given
+pub const DEFAULT_SERVE_ADDR: &str = "0.0.0.0:9101";
+
+/// Options `--serve` can't be used with, and neither can the `serve` subcommand
+const SERVE_CONFLICTS: &[&str] = &[
+    "compare",
+    "verify",
+    "need",
+    "expect-user",
+    "check",
+    "nagios",
+    "watch",
+    "cached",
+    "dry-run",
+    "show-plan",
+    "image",
+    "compare-stacks",
+    "pushgateway",
+    "webhook",
+    "exec",
+];
+
+/// Options read from the environment, and the option or group each requires
+///
+/// With a subcommand, clap reads the environment for the options before it too, where a
+/// `requires` would miss an option given after it, so `check_requires` checks these instead
+const ENV_REQUIRES: &[(&str, &str)] = &[
+    ("pass", "user"),
+    ("pass-file", "user"),
+    ("token", "user"),
+    ("client-p12-password", "client-p12"),
+    ("webhook", "alert-threshold"),
+];
+
+/// Options that only make sense while polling, with `--watch`, `--serve` or `serve`
+const POLLING_ONLY: &[&str] = &["notify-below", "on-breach", "on-recover"];
+
+/// What `docker-rl cache` was asked to do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheCommand {
+    /// `cache list`
+    List,
+    /// `cache clear`, of the tokens of `user` or every one
+    Clear { user: Option<String> },
+    /// `cache path`
+    Path,
+}
+
+/// Subcommand that was run, checking the limit is the default
+#[derive(Debug, Clone)]
+pub enum Command {
+    /// `check`
+    Check,
+    /// `serve`, whose address is also in `Opts::serve`
+    Serve { addr: SocketAddr },
+    /// `completions`
+    Completions { shell: Shell },
+    /// `doctor`
+    Doctor,
+    /// `config`
+    Config,
+    /// `login`
+    Login,
+    /// `logout`
+    Logout,
+    /// `cache` and what it was asked to do
+    Cache(CacheCommand),
+}
+
+// The command line, the options of a check with or without a subcommand
+//
+// Options go after the subcommand, e.g. `docker-rl check -u someuser`, so `check` and no
+// subcommand take the very same ones. Neither this nor `Subcommand` have doc comments, which
+// would replace the about of `Opts` in `--help`
 #[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ArgsNegateSubcommands)]
+struct Cli {
+    #[structopt(flatten)]
+    opts: Opts,
+
+    #[structopt(subcommand)]
+    command: Option<Subcommand>,
+}
+
+// Subcommands as parsed, with their options
+#[derive(Debug, StructOpt)]
+enum Subcommand {
+    /// Check the limit, the same as no subcommand
+    Check(Opts),
+    /// Serve Prometheus metrics until Ctrl-C, the same as --serve
+    Serve(ServeOpts),
+    /// Print a completion script for the shell to stdout
+    Completions {
+        /// Shell to complete in
+        #[structopt(possible_values = &Shell::variants(), case_insensitive = true)]
+        shell: Shell,
+    },
+    /// Run connectivity and configuration diagnostics
+    Doctor(Opts),
+    /// Print every effective setting and where it came from
+    Config(Opts),
+    /// Save the Docker Hub credentials in the OS keyring, for checks without -p
+    Login(Opts),
+    /// Remove the credentials saved by login from the OS keyring
+    Logout(Opts),
+    /// List or clear the tokens kept by --token-cache
+    Cache(CacheSubcommand),
+}
+
+// Subcommands of `cache`, which take no options from the config file
+//
+// Only ever parsed once, so the size of `List` doesn't matter
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, StructOpt)]
+enum CacheSubcommand {
+    /// List the cached tokens, with what they are for and when they expire, never the tokens
+    List(Opts),
+    /// Remove the cached tokens, safe to run while checks store them
+    Clear {
+        /// Only remove the tokens of this user
+        #[structopt(short, long, value_name = "user")]
+        user: Option<String>,
+    },
+    /// Print the path of the token cache
+    Path,
+}
+
+/// Options of `serve`
+#[derive(Debug, StructOpt)]
+struct ServeOpts {
+    /// Address to listen on
+    #[structopt(default_value = DEFAULT_SERVE_ADDR)]
+    addr: SocketAddr,
+
+    #[structopt(flatten)]
+    opts: Opts,
+}
+
+/// Environment variables options are read from, by option name
+const OPTION_VARS: &[(&str, &str)] = &[
+    ("user", "DOCKER_RL_USER"),
+    ("pass", "DOCKER_RL_PASS"),
+    ("pass-file", "DOCKER_RL_PASS_FILE"),
+    ("token", "DOCKER_RL_PAT"),
+    ("profile", "DOCKER_RL_PROFILE"),
+    ("config", "DOCKER_RL_CONFIG"),
+    ("proxy-user", "DOCKER_RL_PROXY_AUTH"),
+    ("client-p12-password", "DOCKER_RL_CLIENT_P12_PASSWORD"),
+    ("push-user", "DOCKER_RL_PUSH_AUTH"),
+    ("webhook", "DOCKER_RL_WEBHOOK"),
+    ("metrics-auth", "DOCKER_RL_METRICS_AUTH"),
+    ("metrics-auth-file", "DOCKER_RL_METRICS_AUTH_FILE"),
+];
+
+#[derive(Debug, Clone, StructOpt)]
+#[structopt(
+    group = ArgGroup::with_name("threshold").multiple(true),
+    group = ArgGroup::with_name("alert-threshold").multiple(true),
+    group = ArgGroup::with_name("alert-hook").multiple(true)
+)]
 /// gets ratelimit from docker hub
 pub struct Opts {
-    #[structopt(short, long, about = "user for basic authentication")]
+    #[structopt(
+        short,
+        long,
+        about = "user for basic authentication",
+        env = "DOCKER_RL_USER"
+    )]
     pub user: Option<String>,
 
     #[structopt(
         short,
         long,
         about = "password for basic authentication",
-        requires("user")
+        env = "DOCKER_RL_PASS",
+        hide_env_values = true
     )]
     pub pass: Option<String>,
+
+    #[structopt(
+        long,
+        about = "read the password for basic authentication from this file",
+        value_name = "path",
+        env = "DOCKER_RL_PASS_FILE",
+        conflicts_with_all(&["pass", "token", "users-from-stdin"])
+    )]
+    pub pass_file: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        about = "Docker Hub access token to use instead of the password, dckr_pat_... or dckr_oat_...",
+        value_name = "token",
+        env = "DOCKER_RL_PAT",
+        hide_env_values = true,
+        conflicts_with_all(&["pass", "users-from-stdin"]),
+        parse(try_from_str = parse_access_token)
+    )]
+    pub token: Option<String>,
+
+    #[structopt(
+        long,
+        about = "check the anonymous limit, ignoring any credentials",
+        conflicts_with_all(&["user", "pass", "pass-file", "token", "users-from-stdin"])
+    )]
+    pub anonymous: bool,
+
+    #[structopt(long, about = "don't use the credentials saved by docker login")]
+    pub no_docker_config: bool,
+
+    #[structopt(long, about = "don't use the credentials saved by docker-rl login")]
+    pub no_keyring: bool,
+
+    #[structopt(
+        short,
+        long,
+        about = "output format: plain, json, table, or a template like '{{.remaining}}/{{.total}}'",
+        visible_alias = "output",
+        default_value = "plain",
+        value_name = "format"
+    )]
+    pub format: Format,
+
+    #[structopt(
+        long,
+        about = "token scope: pull, push, pull,push, or repository:name:actions",
+        default_value = "pull"
+    )]
+    pub scope: Scope,
+
+    #[structopt(
+        long,
+        about = "registry to check instead of Docker Hub, e.g. ghcr.io or http://localhost:5000",
+        value_name = "registry",
+        parse(try_from_str = parse_registry),
+        conflicts_with("show-plan")
+    )]
+    pub registry: Option<Url>,
+
+    #[structopt(
+        long,
+        about = "repository whose manifest is checked, e.g. library/alpine:3",
+        value_name = "name[:tag]"
+    )]
+    pub repository: Option<String>,
+
+    #[structopt(
+        long,
+        about = "check whether pulling this image counts against the limit, e.g. ubuntu:22.04",
+        value_name = "name[:tag]",
+        conflicts_with_all(&[
+            "repository",
+            "users-from-stdin",
+            "compare",
+            "verify",
+            "need",
+            "nagios",
+            "watch",
+            "cached",
+            "show-plan",
+        ])
+    )]
+    pub image: Option<String>,
+
+    #[structopt(
+        short,
+        long,
+        about = "print more details to stderr, -vv logs every request and -vvv their headers",
+        parse(from_occurrences)
+    )]
+    pub verbose: u8,
+
+    #[structopt(
+        long,
+        about = "check the anonymous and the user's limit side by side",
+        requires("user"),
+        conflicts_with_all(&["anonymous", "verify"])
+    )]
+    pub compare: bool,
+
+    #[structopt(
+        long,
+        about = "look up the user's Docker Hub plan and show it with the limit",
+        requires("user"),
+        conflicts_with("anonymous")
+    )]
+    pub show_plan: bool,
+
+    #[structopt(
+        long,
+        about = "check twice to see if a cache is masking the real limit",
+        conflicts_with("users-from-stdin")
+    )]
+    pub verify: bool,
+
+    #[structopt(
+        long,
+        about = "time to wait between the --verify checks",
+        default_value = "5s",
+        value_name = "duration",
+        parse(try_from_str = parse_duration)
+    )]
+    pub verify_delay: Duration,
+
+    #[structopt(
+        long,
+        about = "fail when fewer than this many requests remain",
+        value_name = "count",
+        group = "threshold"
+    )]
+    pub fail_below: Option<u64>,
+
+    #[structopt(
+        long,
+        about = "fail when less than this percentage of the limit remains",
+        value_name = "percent",
+        group = "threshold",
+        parse(try_from_str = parse_percent)
+    )]
+    pub fail_below_percent: Option<f64>,
+
+    #[structopt(
+        long,
+        about = "fail unless this many pulls fit in the remaining limit",
+        value_name = "count",
+        group = "threshold",
+        conflicts_with_all(&["users-from-stdin", "compare", "verify", "show-plan"])
+    )]
+    pub need: Option<u64>,
+
+    #[structopt(
+        long,
+        about = "fail unless the limit belongs to this user, e.g. not an anonymous fallback",
+        value_name = "user",
+        group = "threshold",
+        conflicts_with_all(&["users-from-stdin", "compare"])
+    )]
+    pub expect_user: Option<String>,
+
+    #[structopt(
+        long,
+        about = "print nothing, only exit with the result of the thresholds",
+        requires("threshold"),
+        conflicts_with_all(&["users-from-stdin", "compare"])
+    )]
+    pub check: bool,
+
+    #[structopt(
+        long,
+        about = "behave like a Nagios plugin: print a status line with perfdata, exit 0-3",
+        conflicts_with_all(&[
+            "users-from-stdin",
+            "compare",
+            "verify",
+            "need",
+            "check",
+            "fail-below",
+            "fail-below-percent",
+            "quiet",
+            "show-plan",
+            "dry-run",
+            "watch",
+        ])
+    )]
+    pub nagios: bool,
+
+    #[structopt(
+        long,
+        about = "with --nagios, warn when less than this percentage of the limit remains",
+        value_name = "percent",
+        requires("nagios"),
+        parse(try_from_str = parse_percent)
+    )]
+    pub warn: Option<f64>,
+
+    #[structopt(
+        long,
+        about = "with --nagios, critical when less than this percentage of the limit remains",
+        value_name = "percent",
+        requires("nagios"),
+        parse(try_from_str = parse_percent)
+    )]
+    pub crit: Option<f64>,
+
+    #[structopt(short, long, about = "don't print errors")]
+    pub quiet: bool,
+
+    #[structopt(long, about = "don't show a spinner while waiting on the network")]
+    pub no_progress: bool,
+
+    #[structopt(
+        long,
+        about = "times to retry connection errors, timeouts and 5xx responses",
+        value_name = "count",
+        default_value = "2"
+    )]
+    pub retries: u32,
+
+    #[structopt(
+        long,
+        about = "wait before the first retry, doubled for every retry after it",
+        value_name = "duration",
+        default_value = "1s",
+        parse(try_from_str = parse_duration)
+    )]
+    pub retry_backoff: Duration,
+
+    #[structopt(
+        long,
+        about = "give up on a request after this, from connecting to the end of the answer",
+        value_name = "duration",
+        parse(try_from_str = parse_timeout)
+    )]
+    pub timeout: Option<Duration>,
+
+    #[structopt(
+        long,
+        about = "give up on connecting after this",
+        value_name = "duration",
+        parse(try_from_str = parse_timeout)
+    )]
+    pub connect_timeout: Option<Duration>,
+
+    #[structopt(
+        short = "4",
+        long,
+        about = "only connect over IPv4, Docker Hub keys the anonymous limit to the source IP",
+        conflicts_with_all(&["ipv6", "compare-stacks"])
+    )]
+    pub ipv4: bool,
+
+    #[structopt(
+        short = "6",
+        long,
+        about = "only connect over IPv6",
+        conflicts_with_all(&["ipv4", "compare-stacks"])
+    )]
+    pub ipv6: bool,
+
+    #[structopt(
+        long,
+        about = "local address to connect from, e.g. the one the docker daemon pulls from",
+        value_name = "ip",
+        conflicts_with("compare-stacks")
+    )]
+    pub bind_address: Option<IpAddr>,
+
+    #[structopt(
+        long,
+        about = "check the limit over IPv4 and IPv6 side by side",
+        conflicts_with_all(&[
+            "users-from-stdin",
+            "compare",
+            "verify",
+            "need",
+            "nagios",
+            "watch",
+            "cached",
+            "show-plan",
+            "image",
+        ])
+    )]
+    pub compare_stacks: bool,
+
+    #[structopt(long, about = "proxy for every request, instead of HTTPS_PROXY")]
+    pub proxy: Option<Url>,
+
+    #[structopt(
+        long,
+        about = "basic credentials for the proxy, as user:pass",
+        value_name = "user:pass",
+        visible_alias = "proxy-auth",
+        env = "DOCKER_RL_PROXY_AUTH",
+        hide_env_values = true
+    )]
+    pub proxy_user: Option<BasicAuth>,
+
+    #[structopt(
+        long,
+        about = "PEM client certificate, with the key too unless --client-key is passed",
+        value_name = "path",
+        visible_alias = "cert",
+        conflicts_with("client-p12")
+    )]
+    pub client_cert: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        about = "PEM private key for --client-cert",
+        value_name = "path",
+        visible_alias = "key",
+        requires("client-cert")
+    )]
+    pub client_key: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        about = "PKCS#12 client certificate and key",
+        value_name = "path"
+    )]
+    pub client_p12: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        about = "password for --client-p12",
+        value_name = "password",
+        env = "DOCKER_RL_CLIENT_P12_PASSWORD",
+        hide_env_values = true
+    )]
+    pub client_p12_password: Option<String>,
+
+    #[structopt(
+        long,
+        about = "PEM bundle of CA certificates to trust on top of the system's",
+        value_name = "path"
+    )]
+    pub cacert: Option<PathBuf>,
+
+    #[structopt(
+        short = "k",
+        long,
+        about = "don't verify TLS certificates, only for testing"
+    )]
+    pub insecure: bool,
+
+    #[structopt(
+        long,
+        about = "push the limit to this Prometheus Pushgateway after the check",
+        value_name = "url",
+        conflicts_with_all(&["users-from-stdin", "compare"]),
+        parse(try_from_str = parse_pushgateway)
+    )]
+    pub pushgateway: Option<Url>,
+
+    #[structopt(
+        long,
+        about = "job label for --pushgateway",
+        value_name = "job",
+        default_value = DEFAULT_JOB
+    )]
+    pub push_job: String,
+
+    #[structopt(
+        long,
+        about = "instance label for --pushgateway",
+        value_name = "instance",
+        requires("pushgateway")
+    )]
+    pub push_instance: Option<String>,
+
+    #[structopt(
+        long,
+        about = "basic credentials for --pushgateway, as user:pass",
+        value_name = "user:pass",
+        env = "DOCKER_RL_PUSH_AUTH",
+        hide_env_values = true
+    )]
+    pub push_user: Option<BasicAuth>,
+
+    #[structopt(
+        long,
+        about = "fail the run when the push to --pushgateway fails",
+        requires("pushgateway")
+    )]
+    pub push_strict: bool,
+
+    #[structopt(
+        long,
+        visible_alias = "below",
+        about = "alert when fewer than this many requests remain",
+        value_name = "count",
+        group = "alert-threshold",
+        requires("alert-hook")
+    )]
+    pub alert_below: Option<u64>,
+
+    #[structopt(
+        long,
+        about = "alert when less than this percentage of the limit remains",
+        value_name = "percent",
+        group = "alert-threshold",
+        requires("alert-hook"),
+        parse(try_from_str = parse_percent)
+    )]
+    pub alert_below_percent: Option<f64>,
+
+    #[structopt(
+        long,
+        about = "post alerts as JSON to this URL, e.g. a Slack or ntfy webhook",
+        value_name = "url",
+        env = "DOCKER_RL_WEBHOOK",
+        hide_env_values = true,
+        group = "alert-hook",
+        conflicts_with_all(&["users-from-stdin", "compare"]),
+        parse(try_from_str = parse_webhook)
+    )]
+    pub webhook: Option<Url>,
+
+    #[structopt(
+        long,
+        about = "run this shell command on alerts, with the limit in DOCKER_RL_ALERT_* variables",
+        value_name = "cmd",
+        group = "alert-hook",
+        requires("alert-threshold"),
+        conflicts_with_all(&["users-from-stdin", "compare"])
+    )]
+    pub exec: Option<String>,
+
+    #[structopt(
+        long,
+        about = "serve Prometheus metrics on this address, e.g. 0.0.0.0:9101",
+        value_name = "addr",
+        conflicts_with_all(SERVE_CONFLICTS)
+    )]
+    pub serve: Option<SocketAddr>,
+
+    #[structopt(
+        long,
+        about = "keep checking the limit every --interval, printing a line each time",
+        conflicts_with_all(&[
+            "users-from-stdin",
+            "compare",
+            "verify",
+            "need",
+            "expect-user",
+            "check",
+            "cached",
+            "dry-run",
+            "show-plan",
+        ])
+    )]
+    pub watch: bool,
+
+    #[structopt(
+        long,
+        about = "time between checks with --watch",
+        default_value = "5m",
+        value_name = "duration",
+        parse(try_from_str = parse_interval)
+    )]
+    pub interval: Duration,
+
+    #[structopt(
+        long,
+        about = "with --watch, check every 10m while the limit lasts and more often when it \
+                 runs out within the window",
+        requires("watch"),
+        conflicts_with("interval")
+    )]
+    pub adaptive: bool,
+
+    #[structopt(
+        long,
+        about = "least time between checks with --adaptive",
+        default_value = "30s",
+        value_name = "duration",
+        parse(try_from_str = parse_interval)
+    )]
+    pub min_interval: Duration,
+
+    #[structopt(
+        long,
+        about = "stop --watch after this long, with the summary",
+        value_name = "duration",
+        requires("watch"),
+        parse(try_from_str = parse_interval)
+    )]
+    pub duration: Option<Duration>,
+
+    #[structopt(
+        long,
+        about = "notify, or run the hooks, when fewer than this many requests remain",
+        value_name = "count"
+    )]
+    pub notify_below: Option<u64>,
+
+    #[structopt(
+        long,
+        about = "run this shell command when --notify-below is crossed, with DRL_* variables",
+        value_name = "cmd",
+        requires("notify-below")
+    )]
+    pub on_breach: Option<String>,
+
+    #[structopt(
+        long,
+        about = "run this shell command when the limit is back up to --notify-below",
+        value_name = "cmd",
+        requires("notify-below")
+    )]
+    pub on_recover: Option<String>,
+
+    #[structopt(
+        long,
+        about = "time between checks with --serve",
+        default_value = "60s",
+        value_name = "duration",
+        parse(try_from_str = parse_interval)
+    )]
+    pub serve_interval: Duration,
+
+    #[structopt(
+        long,
+        about = "longest time between checks with --watch or --serve while they keep failing",
+        default_value = "30m",
+        value_name = "duration",
+        parse(try_from_str = parse_interval)
+    )]
+    pub backoff_max: Duration,
+
+    #[structopt(
+        long,
+        about = "credentials scrapes of --serve have to send, bearer:<token> or basic:<user>:<pass>",
+        value_name = "scheme:secret",
+        env = "DOCKER_RL_METRICS_AUTH",
+        hide_env_values = true
+    )]
+    pub metrics_auth: Option<MetricsAuth>,
+
+    #[structopt(
+        long,
+        about = "read --metrics-auth from this file",
+        value_name = "path",
+        env = "DOCKER_RL_METRICS_AUTH_FILE",
+        conflicts_with("metrics-auth")
+    )]
+    pub metrics_auth_file: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        about = "answer /healthz and /readyz of --serve without --metrics-auth"
+    )]
+    pub metrics_auth_exempt_health: bool,
+
+    #[structopt(
+        long,
+        about = "failed checks in a row after which /readyz of --serve fails again",
+        default_value = "3",
+        value_name = "n",
+        parse(try_from_str = parse_ready_failures)
+    )]
+    pub ready_failures: u32,
+
+    #[structopt(
+        long,
+        about = "where --watch and --serve write their messages",
+        default_value = "stderr",
+        possible_values = LogTarget::VARIANTS
+    )]
+    pub log_target: LogTarget,
+
+    #[structopt(
+        long,
+        about = "format counts like 49,987/50,000 and show the percentage"
+    )]
+    pub human: bool,
+
+    #[structopt(
+        long,
+        about = "how timestamps are written",
+        default_value = "rfc3339",
+        possible_values = TimestampFormat::VARIANTS
+    )]
+    pub timestamp_format: TimestampFormat,
+
+    #[structopt(long, about = "write RFC 3339 timestamps in UTC instead of local time")]
+    pub utc: bool,
+
+    #[structopt(long, about = "don't truncate long table cells")]
+    pub wide: bool,
+
+    #[structopt(long, about = "print the requests that would be made and exit")]
+    pub dry_run: bool,
+
+    #[structopt(
+        long,
+        about = "print the result of the last check instead of checking",
+        conflicts_with_all(&[
+            "users-from-stdin",
+            "compare",
+            "verify",
+            "need",
+            "expect-user",
+            "show-plan",
+            "dry-run",
+        ])
+    )]
+    pub cached: bool,
+
+    #[structopt(
+        long,
+        about = "fail when the --cached result is older than this",
+        value_name = "duration",
+        requires("cached"),
+        parse(try_from_str = parse_duration)
+    )]
+    pub max_age: Option<Duration>,
+
+    #[structopt(long, about = "don't store the result for --cached")]
+    pub no_state: bool,
+
+    #[structopt(
+        long,
+        about = "append every check to this history, CSV or JSON lines for .jsonl",
+        value_name = "path",
+        visible_alias = "history",
+        conflicts_with_all(&["users-from-stdin", "compare", "cached", "dry-run"])
+    )]
+    pub log_file: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        about = "reuse tokens from earlier runs until shortly before they expire"
+    )]
+    pub token_cache: bool,
+
+    #[structopt(long, about = "print the JSON Schema of the JSON output and exit")]
+    pub schema: bool,
+
+    #[structopt(
+        long,
+        about = "check each user read from stdin, one per line",
+        conflicts_with("user"),
+        requires("password-env-prefix")
+    )]
+    pub users_from_stdin: bool,
+
+    #[structopt(
+        long,
+        about = "read the password for each user from <prefix><USER>, else docker login",
+        value_name = "prefix"
+    )]
+    pub password_env_prefix: Option<String>,
+
+    #[structopt(
+        long,
+        about = "maximum number of accounts checked at once",
+        default_value = "4",
+        parse(try_from_str = parse_concurrency)
+    )]
+    pub concurrency: usize,
+
+    #[structopt(
+        long,
+        about = "time to wait between starting account checks, e.g. 250ms",
+        default_value = "0s",
+        value_name = "duration",
+        parse(try_from_str = parse_duration)
+    )]
+    pub stagger: Duration,
+
+    #[structopt(
+        long,
+        about = "profile of the config file to use, default unless passed; repeat to check several",
+        value_name = "name",
+        env = "DOCKER_RL_PROFILE",
+        number_of_values = 1,
+        use_delimiter = true
+    )]
+    pub profile: Vec<String>,
+
+    #[structopt(
+        long,
+        about = "check every profile of the config file",
+        conflicts_with("profile")
+    )]
+    pub all_profiles: bool,
+
+    #[structopt(
+        long,
+        about = "config file to read profiles from, instead of ~/.config/docker-rl/config.toml",
+        value_name = "path",
+        env = "DOCKER_RL_CONFIG"
+    )]
+    pub config: Option<PathBuf>,
+
+    /// Subcommand that was run, `None` without one
+    #[structopt(skip)]
+    pub command: Option<Command>,
+
+    /// Matches the options were parsed from, to tell where values came from
+    #[structopt(skip)]
+    matches: Option<ArgMatches<'static>>,
+
+    /// Options that were set by the config file
+    #[structopt(skip)]
+    from_config: Vec<&'static str>,
+
+    /// Options of every profile to check, empty unless several were named
+    #[structopt(skip)]
+    pub per_profile: Vec<Opts>,
+
+    /// Template from `--format`, if it was one
+    #[structopt(skip)]
+    pub template: Option<Template>,
+}
+
+/// Matches of the options in `matches`, those of the innermost subcommand if there is one
+fn options_of<'m, 'a>(matches: &'m ArgMatches<'a>) -> &'m ArgMatches<'a> {
+    match matches.subcommand() {
+        (_, Some(sub)) => options_of(sub),
+        _ => matches,
+    }
+}
+
+/// The subcommand `args` start with, if any
+///
+/// Options go after the subcommand and there are no positional arguments without one, so
+/// the first argument can only be a subcommand if it isn't an option
+fn subcommand(args: &[OsString]) -> Option<&OsString> {
+    args.get(1)
+        .filter(|a| !a.to_string_lossy().starts_with('-'))
+}
+
+/// Fails like a conflict with `--serve` if the `serve` subcommand got an option it
+/// conflicts with, which clap can't tell as `--serve` isn't there
+fn check_serve(
+    matches: ArgMatches<'static>,
+) -> Result<ArgMatches<'static>, structopt::clap::Error> {
+    let sub = match matches.subcommand() {
+        ("serve", Some(sub)) => sub,
+        _ => return Ok(matches),
+    };
+    let conflict = SERVE_CONFLICTS
+        .iter()
+        .chain(&["serve"])
+        .find(|name| sub.occurrences_of(name) > 0);
+
+    match conflict {
+        Some(name) => {
+            let msg = format!(
+                "The argument '--{}' cannot be used with the serve subcommand\n\n\
+                 For more information try --help",
+                name
+            );
+            let mut e = structopt::clap::Error::with_description(&msg, ErrorKind::ArgumentConflict);
+            e.info = Some(vec![String::from(*name)]);
+            Err(e)
+        }
+        None => Ok(matches),
+    }
+}
+
+/// Fails if an option of `POLLING_ONLY` is there without `--watch`, `--serve` or `serve`
+fn check_polling(
+    matches: ArgMatches<'static>,
+) -> Result<ArgMatches<'static>, structopt::clap::Error> {
+    let options = options_of(&matches);
+    let polling = matches.subcommand_name() == Some("serve")
+        || options.is_present("watch")
+        || options.is_present("serve");
+    let found = POLLING_ONLY.iter().find(|name| options.is_present(name));
+
+    match found {
+        Some(name) if !polling => {
+            let msg = format!(
+                "The argument '--{}' requires '--watch' or '--serve'\n\n\
+                 For more information try --help",
+                name
+            );
+            let mut e =
+                structopt::clap::Error::with_description(&msg, ErrorKind::MissingRequiredArgument);
+            e.info = Some(vec![String::from("watch")]);
+            Err(e)
+        }
+        _ => Ok(matches),
+    }
+}
+
+/// Fails if an option of `ENV_REQUIRES` is there without the one it requires
+fn check_requires(
+    matches: ArgMatches<'static>,
+) -> Result<ArgMatches<'static>, structopt::clap::Error> {
+    let options = options_of(&matches);
+    let missing = ENV_REQUIRES
+        .iter()
+        .find(|(name, required)| options.is_present(name) && !options.is_present(required));
+
+    match missing {
+        Some((name, required)) => {
+            let msg = format!(
+                "The argument '--{}' requires '--{}'\n\nFor more information try --help",
+                name, required
+            );
+            let mut e =
+                structopt::clap::Error::with_description(&msg, ErrorKind::MissingRequiredArgument);
+            e.info = Some(vec![String::from(*required)]);
+            Err(e)
+        }
+        None => Ok(matches),
+    }
+}
+
+/// Writes the completion script for `shell` to `out`
+///
+/// # Arguments
+///
+/// * `shell` - `Shell` to complete in
+/// * `out` - where to write the script
+pub fn completions<W: io::Write>(shell: Shell, out: &mut W) {
+    Cli::clap().gen_completions_to("docker-rl", shell, out);
 }
 
 impl Opts {
     /// Parses arguments and returns `Opts` struct
+    ///
+    /// Options the command line and the environment leave out are taken from the profile of
+    /// the config file, if there is one. With several profiles, the options of each are in
+    /// `per_profile`, and the returned ones are those of the first
     pub fn parse_args() -> Opts {
-        Opts::from_args()
+        let args: Vec<OsString> = env::args_os().collect();
+        let (config, profiles) = match config_profiles(&args) {
+            Ok(Some(found)) => found,
+            Ok(None) => return Opts::parse_profile(&args, None, None),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                e.exit();
+            }
+        };
+        let path = Some(config.path.as_path());
+
+        if profiles.len() < 2 {
+            return Opts::parse_profile(&args, path, profiles.first());
+        }
+
+        let mut opts = Opts::parse_profile(&args, path, profiles.first());
+        opts.per_profile = profiles
+            .iter()
+            .map(|p| Opts::parse_profile(&args, path, Some(p)))
+            .collect();
+        opts
+    }
+
+    /// Parses `args` on top of the settings of `profile`, exiting if they are invalid
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - arguments, starting with the program name
+    /// * `path` - path of the config file `profile` is from
+    /// * `profile` - `Profile` to take the options left out from, `None` for none
+    fn parse_profile(args: &[OsString], path: Option<&Path>, profile: Option<&Profile>) -> Opts {
+        Opts::try_parse_profile(args, path, profile).unwrap_or_else(|e| e.exit())
+    }
+
+    /// Does the work of `parse_profile`, returning the error instead of exiting
+    fn try_parse_profile(
+        args: &[OsString],
+        path: Option<&Path>,
+        profile: Option<&Profile>,
+    ) -> Result<Opts, structopt::clap::Error> {
+        let app = || Cli::clap().global_setting(AppSettings::AllArgsOverrideSelf);
+        let cli = app().get_matches_from_safe(args).ok();
+        // the config goes right after the subcommand, and completions and cache take none
+        let sub = subcommand(args);
+        let at = if sub.is_some() { 2 } else { 1 };
+        let mut from_config: Vec<(&'static str, Vec<OsString>)> = match profile {
+            _ if sub.is_some_and(|s| s == "completions" || s == "cache") => Vec::new(),
+            Some(p) => config_args(p, cli.as_ref()),
+            None => Vec::new(),
+        };
+
+        // the config goes first, so the command line overrides it
+        let matches = loop {
+            let mut merged = args[..at].to_vec();
+            merged.extend(from_config.iter().flat_map(|(_, a)| a.iter().cloned()));
+            merged.extend(args[at..].iter().cloned());
+
+            let mut e = match app()
+                .get_matches_from_safe(merged)
+                .and_then(check_serve)
+                .and_then(check_polling)
+                .and_then(check_requires)
+            {
+                Ok(m) => break m,
+                Err(e) => e,
+            };
+            // options passed on the command line win over the ones they conflict with
+            let conflicting = match (&e.kind, &e.info) {
+                (ErrorKind::ArgumentConflict, Some(names)) => names.clone(),
+                _ => Vec::new(),
+            };
+            let before = from_config.len();
+            from_config.retain(|(key, _)| !conflicting.iter().any(|n| n == key));
+            if from_config.len() == before {
+                if let (Some(path), Some(profile), false) = (path, profile, from_config.is_empty())
+                {
+                    let note = format!(
+                        "\n\nsome options came from profile '{}' of {}",
+                        profile.name,
+                        path.display()
+                    );
+                    e.message.push_str(&note);
+                }
+                return Err(e);
+            }
+        };
+
+        let options = options_of(&matches).clone();
+        let cli = Cli::from_clap(&matches);
+        let (mut opts, command) = match cli.command {
+            None => (cli.opts, None),
+            Some(Subcommand::Check(opts)) => (opts, Some(Command::Check)),
+            Some(Subcommand::Serve(ServeOpts { addr, mut opts })) => {
+                opts.serve = Some(addr);
+                (opts, Some(Command::Serve { addr }))
+            }
+            Some(Subcommand::Completions { shell }) => {
+                (cli.opts, Some(Command::Completions { shell }))
+            }
+            Some(Subcommand::Doctor(opts)) => (opts, Some(Command::Doctor)),
+            Some(Subcommand::Config(opts)) => (opts, Some(Command::Config)),
+            Some(Subcommand::Login(opts)) => (opts, Some(Command::Login)),
+            Some(Subcommand::Logout(opts)) => (opts, Some(Command::Logout)),
+            Some(Subcommand::Cache(CacheSubcommand::List(opts))) => {
+                (opts, Some(Command::Cache(CacheCommand::List)))
+            }
+            Some(Subcommand::Cache(CacheSubcommand::Clear { user })) => {
+                (cli.opts, Some(Command::Cache(CacheCommand::Clear { user })))
+            }
+            Some(Subcommand::Cache(CacheSubcommand::Path)) => {
+                (cli.opts, Some(Command::Cache(CacheCommand::Path)))
+            }
+        };
+        opts.command = command;
+        opts.template = options
+            .value_of("format")
+            .filter(|f| is_template(f))
+            .map(|f| f.parse().expect("template checked when parsing"));
+        opts.matches = Some(options);
+        opts.from_config = from_config.into_iter().map(|(key, _)| key).collect();
+        if let Some(profile) = profile {
+            opts.profile = vec![profile.name.clone()];
+            opts.config = path.map(PathBuf::from);
+        }
+        Ok(opts)
+    }
+
+    /// Where the option `name` came from
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - long name of the option
+    fn source(&self, name: &str) -> Source {
+        let matches = match &self.matches {
+            Some(m) => m,
+            None => return Source::Default,
+        };
+
+        if self.from_config.contains(&name) {
+            return Source::Config;
+        }
+        if matches.occurrences_of(name) > 0 {
+            return Source::Cli;
+        }
+        let from_env = OPTION_VARS
+            .iter()
+            .any(|(option, var)| *option == name && env::var_os(var).is_some());
+        if from_env {
+            Source::Env
+        } else {
+            Source::Default
+        }
+    }
+
+    /// Every effective setting and where it came from, with secrets redacted
+    pub fn settings(&self) -> Settings {
+        fn shown<T: fmt::Display>(value: &Option<T>) -> Option<String> {
+            value.as_ref().map(|v| v.to_string())
+        }
+        fn path(value: &Option<PathBuf>) -> Option<String> {
+            value.as_ref().map(|p| p.display().to_string())
+        }
+        fn secret<T>(value: &Option<T>) -> Option<String> {
+            value.as_ref().map(|_| REDACTED.to_string())
+        }
+        fn set(value: bool) -> Option<String> {
+            Some(value.to_string())
+        }
+        fn list(values: &[String]) -> Option<String> {
+            Some(values.join(",")).filter(|v| !v.is_empty())
+        }
+
+        // reqwest falls back on the environment too
+        let (proxy, proxy_source) = match self.source("proxy") {
+            Source::Cli => (shown(&self.proxy), Source::Cli),
+            _ => match ClientConfig::default().redacted_proxy() {
+                Some(p) => (Some(p), Source::Env),
+                None => (None, Source::Default),
+            },
+        };
+        let proxy_user = self
+            .proxy_user
+            .as_ref()
+            .map(|a| format!("{}:{}", a.user, REDACTED));
+
+        let values = vec![
+            ("user", shown(&self.user)),
+            ("pass", secret(&self.pass)),
+            ("pass-file", path(&self.pass_file)),
+            ("token", secret(&self.token)),
+            ("anonymous", set(self.anonymous)),
+            ("no-docker-config", set(self.no_docker_config)),
+            ("no-keyring", set(self.no_keyring)),
+            (
+                "format",
+                match &self.template {
+                    Some(t) => shown(&Some(t)),
+                    None => shown(&Some(self.format)),
+                },
+            ),
+            ("scope", shown(&Some(&self.scope))),
+            ("registry", shown(&self.registry)),
+            ("repository", shown(&self.repository)),
+            ("image", shown(&self.image)),
+            ("verbose", shown(&Some(self.verbose))),
+            ("compare", set(self.compare)),
+            ("show-plan", set(self.show_plan)),
+            ("verify", set(self.verify)),
+            ("verify-delay", Some(format_duration(self.verify_delay))),
+            ("fail-below", shown(&self.fail_below)),
+            ("fail-below-percent", shown(&self.fail_below_percent)),
+            ("need", shown(&self.need)),
+            ("expect-user", shown(&self.expect_user)),
+            ("check", set(self.check)),
+            ("nagios", set(self.nagios)),
+            ("warn", shown(&self.warn)),
+            ("crit", shown(&self.crit)),
+            ("quiet", set(self.quiet)),
+            ("no-progress", set(self.no_progress)),
+            ("retries", shown(&Some(self.retries))),
+            ("retry-backoff", Some(format_duration(self.retry_backoff))),
+            ("timeout", self.timeout.map(format_duration)),
+            ("connect-timeout", self.connect_timeout.map(format_duration)),
+            ("ipv4", set(self.ipv4)),
+            ("ipv6", set(self.ipv6)),
+            ("bind-address", shown(&self.bind_address)),
+            ("compare-stacks", set(self.compare_stacks)),
+            ("proxy", proxy),
+            ("proxy-user", proxy_user),
+            ("client-cert", path(&self.client_cert)),
+            ("client-key", path(&self.client_key)),
+            ("client-p12", path(&self.client_p12)),
+            ("client-p12-password", secret(&self.client_p12_password)),
+            ("cacert", path(&self.cacert)),
+            ("insecure", set(self.insecure)),
+            ("pushgateway", shown(&self.pushgateway)),
+            ("push-job", Some(self.push_job.clone())),
+            ("push-instance", shown(&self.push_instance)),
+            (
+                "push-user",
+                self.push_user
+                    .as_ref()
+                    .map(|a| format!("{}:{}", a.user, REDACTED)),
+            ),
+            ("push-strict", set(self.push_strict)),
+            ("alert-below", shown(&self.alert_below)),
+            ("alert-below-percent", shown(&self.alert_below_percent)),
+            // the path of a webhook URL is often its secret
+            (
+                "webhook",
+                self.webhook
+                    .as_ref()
+                    .map(|u| format!("{}/{}", u.origin().ascii_serialization(), REDACTED)),
+            ),
+            ("exec", shown(&self.exec)),
+            ("serve", shown(&self.serve)),
+            ("serve-interval", Some(format_duration(self.serve_interval))),
+            ("watch", set(self.watch)),
+            ("interval", Some(format_duration(self.interval))),
+            ("adaptive", set(self.adaptive)),
+            ("min-interval", Some(format_duration(self.min_interval))),
+            ("duration", self.duration.map(format_duration)),
+            ("notify-below", shown(&self.notify_below)),
+            ("on-breach", shown(&self.on_breach)),
+            ("on-recover", shown(&self.on_recover)),
+            ("backoff-max", Some(format_duration(self.backoff_max))),
+            (
+                "metrics-auth",
+                self.metrics_auth.as_ref().map(MetricsAuth::redacted),
+            ),
+            ("metrics-auth-file", path(&self.metrics_auth_file)),
+            (
+                "metrics-auth-exempt-health",
+                set(self.metrics_auth_exempt_health),
+            ),
+            ("ready-failures", shown(&Some(self.ready_failures))),
+            ("log-target", shown(&Some(self.log_target))),
+            ("human", set(self.human)),
+            ("timestamp-format", shown(&Some(self.timestamp_format))),
+            ("utc", set(self.utc)),
+            ("wide", set(self.wide)),
+            ("dry-run", set(self.dry_run)),
+            ("cached", set(self.cached)),
+            ("max-age", self.max_age.map(format_duration)),
+            ("no-state", set(self.no_state)),
+            ("log-file", path(&self.log_file)),
+            ("token-cache", set(self.token_cache)),
+            ("users-from-stdin", set(self.users_from_stdin)),
+            ("password-env-prefix", shown(&self.password_env_prefix)),
+            ("concurrency", shown(&Some(self.concurrency))),
+            ("stagger", Some(format_duration(self.stagger))),
+            ("profile", list(&self.profile)),
+            ("all-profiles", set(self.all_profiles)),
+            ("config", path(&self.config)),
+        ];
+
+        let settings = values
+            .into_iter()
+            .map(|(name, value)| Setting {
+                name,
+                value,
+                source: match name {
+                    "proxy" => proxy_source,
+                    _ => self.source(name),
+                },
+            })
+            .collect();
+        Settings { settings }
+    }
+
+    /// Whether to show a spinner on stderr
+    ///
+    /// Only when stderr is a terminal, and never for machine readable output, which CI systems
+    /// might capture together with stderr
+    pub fn show_progress(&self) -> bool {
+        !self.no_progress
+            && !self.quiet
+            && !self.check
+            && !self.nagios
+            && self.format != Format::Json
+            && io::stderr().is_terminal()
+    }
+
+    /// IP version from `--ipv4` or `--ipv6`, `None` for either
+    pub fn stack(&self) -> Option<IpStack> {
+        match (self.ipv4, self.ipv6) {
+            (true, _) => Some(IpStack::V4),
+            (_, true) => Some(IpStack::V6),
+            _ => None,
+        }
+    }
+
+    /// Local address from `--bind-address`, or `--ipv4` and `--ipv6`
+    ///
+    /// # Errors
+    ///
+    /// Returns `ExitCode::Input` if the address is of the other IP version
+    fn local_address(&self) -> DrlResult<Option<IpAddr>> {
+        match (self.bind_address, self.stack()) {
+            (Some(addr), Some(stack)) if IpStack::of(addr) != stack => {
+                let msg = format!("--bind-address {} doesn't fit --{}", addr, stack);
+                let err = DrlErr::new(msg, ExitCode::Input);
+                Err(err)
+            }
+            (Some(addr), _) => Ok(Some(addr)),
+            (None, stack) => Ok(stack.map(IpStack::unspecified)),
+        }
+    }
+
+    /// HTTP client settings from the proxy, TLS and connection options
+    ///
+    /// # Errors
+    ///
+    /// Returns `ExitCode::Input` if the client certificate or CA bundle can't be loaded, or
+    /// `--bind-address` doesn't fit `--ipv4` or `--ipv6`
+    pub fn client_config(&self) -> DrlResult<ClientConfig> {
+        let identity = match (&self.client_cert, &self.client_p12) {
+            (Some(cert), _) => Some(ClientIdentity::from_pem(cert, self.client_key.as_deref())?),
+            (None, Some(p12)) => {
+                let password = self.client_p12_password.as_deref().unwrap_or_default();
+                Some(ClientIdentity::from_pkcs12(p12, password)?)
+            }
+            (None, None) => None,
+        };
+        let ca_bundle = match &self.cacert {
+            Some(path) => Some(CaBundle::from_pem(path)?),
+            None => None,
+        };
+
+        Ok(ClientConfig {
+            proxy: self.proxy.clone(),
+            proxy_auth: self.proxy_user.clone(),
+            identity,
+            ca_bundle,
+            insecure: self.insecure,
+            retry: RetryPolicy {
+                retries: self.retries,
+                backoff: self.retry_backoff,
+            },
+            timeout: self.timeout,
+            connect_timeout: self.connect_timeout,
+            local_address: self.local_address()?,
+        })
+    }
+
+    /// Registry from `--registry` and `--repository` or `--image`, not yet discovered
+    pub fn registry(&self) -> Registry {
+        let registry = match &self.registry {
+            Some(url) => Registry::new(url.clone()),
+            None => Registry::docker_hub(),
+        };
+        match self.image.as_ref().or(self.repository.as_ref()) {
+            Some(repository) => registry.with_repository(repository),
+            None => registry,
+        }
+    }
+
+    /// Pushgateway from `--pushgateway` and the options around it
+    pub fn pushgateway(&self) -> Option<Pushgateway> {
+        let url = self.pushgateway.clone()?;
+        Some(Pushgateway {
+            url,
+            job: self.push_job.clone(),
+            instance: self.push_instance.clone(),
+            auth: self.push_user.clone(),
+        })
+    }
+
+    /// Credentials for the scrapes of `--serve`, from `--metrics-auth` or `--metrics-auth-file`
+    ///
+    /// # Errors
+    ///
+    /// Returns `ExitCode::Input` if the file can't be read or doesn't hold valid credentials
+    pub fn metrics_auth(&self) -> DrlResult<Option<MetricsAuth>> {
+        let path = match &self.metrics_auth_file {
+            Some(p) => p,
+            None => return Ok(self.metrics_auth.clone()),
+        };
+
+        let contents = match fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) => {
+                let msg = format!("failed to read {}: {}", path.display(), e);
+                let err = DrlErr::new(msg, ExitCode::Input);
+                return Err(err);
+            }
+        };
+        match contents.trim_end_matches(&['\r', '\n'][..]).parse() {
+            Ok(auth) => Ok(Some(auth)),
+            Err(e) => {
+                let msg = format!("{} in {}", e, path.display());
+                let err = DrlErr::new(msg, ExitCode::Input);
+                Err(err)
+            }
+        }
+    }
+
+    /// Alerter from `--alert-below`, `--webhook` and `--exec`, `None` without a threshold
+    pub fn alerter(&self) -> Option<Alerter> {
+        let threshold = Threshold {
+            below: self.alert_below,
+            below_percent: self.alert_below_percent,
+        };
+        if !threshold.is_set() {
+            return None;
+        }
+        Some(Alerter {
+            threshold,
+            webhook: self.webhook.clone(),
+            exec: self.exec.clone(),
+        })
+    }
+
+    /// Hooks from `--on-breach` and `--on-recover`, `None` without either
+    pub fn hooks(&self) -> Option<Hooks> {
+        let below = self.notify_below?;
+        if self.on_breach.is_none() && self.on_recover.is_none() {
+            return None;
+        }
+        Some(Hooks {
+            below,
+            on_breach: self.on_breach.clone(),
+            on_recover: self.on_recover.clone(),
+        })
+    }
+
+    /// Desktop notifications for `--notify-below`, `None` without it
+    ///
+    /// Built without the `desktop-notify` feature, the hooks are all there is, and
+    /// `--notify-below` is only accepted with one
+    ///
+    /// # Errors
+    ///
+    /// Returns `ExitCode::Input` if there is neither the feature nor a hook
+    pub fn notifier(&self) -> DrlResult<Option<Notifier>> {
+        match self.notify_below {
+            Some(_) if cfg!(not(feature = "desktop-notify")) && self.hooks().is_some() => Ok(None),
+            Some(below) => Notifier::new(below).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// How to write timestamps, from `--timestamp-format` and `--utc`
+    pub fn timestamps(&self) -> Timestamps {
+        Timestamps {
+            format: self.timestamp_format,
+            utc: self.utc,
+        }
+    }
+
+    /// Thresholds from `--warn` and `--crit`
+    pub fn nagios_thresholds(&self) -> Thresholds {
+        Thresholds {
+            warn: self.warn,
+            crit: self.crit,
+        }
+    }
+
+    /// Pacing of `--adaptive`, `None` without it
+    pub fn pacing(&self) -> Option<Pacing> {
+        let pacing = Pacing {
+            min: self.min_interval,
+            ..Pacing::default()
+        };
+        Some(pacing).filter(|_| self.adaptive)
+    }
+
+    /// How `--watch` and `--serve` back off from checks that keep failing, see `--backoff-max`
+    pub fn backoff(&self) -> BackoffPolicy {
+        BackoffPolicy {
+            max: self.backoff_max,
+            ..BackoffPolicy::default()
+        }
+    }
+
+    /// Thresholds from `--fail-below` and `--fail-below-percent`
+    pub fn threshold(&self) -> Threshold {
+        Threshold {
+            below: self.fail_below,
+            below_percent: self.fail_below_percent,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Opts, structopt::clap::Error> {
+        let args: Vec<OsString> = std::iter::once("docker-rl")
+            .chain(args.iter().copied())
+            .map(OsString::from)
+            .collect();
+        Opts::try_parse_profile(&args, None, None)
+    }
+
+    fn settings(args: &[&str]) -> serde_json::Value {
+        let opts = parse(args).unwrap_or_else(|e| panic!("{:?}: {}", args, e));
+        serde_json::to_value(opts.settings()).unwrap()
+    }
+
+    #[test]
+    fn check_is_the_default() {
+        let cases: &[&[&str]] = &[
+            &[],
+            &["-u", "ci-bot", "--format", "json"],
+            &["--image", "library/ubuntu:22.04", "--timeout", "5s"],
+            &["--dry-run", "--verify"],
+            &["--users-from-stdin", "--password-env-prefix", "PASS_"],
+        ];
+        for args in cases {
+            let mut checked = vec!["check"];
+            checked.extend_from_slice(args);
+            assert_eq!(settings(args), settings(&checked), "{:?}", args);
+            assert!(matches!(
+                parse(&checked).unwrap().command,
+                Some(Command::Check)
+            ));
+        }
+    }
+
+    #[test]
+    fn requires() {
+        assert!(parse(&["check", "--pass", "secret", "--user", "ci-bot"]).is_ok());
+        assert!(parse(&["--webhook", "https://example.com", "--alert-below", "10"]).is_ok());
+
+        let cases: &[&[&str]] = &[
+            &["--pass", "secret"],
+            &["check", "--token", "dckr_pat_x"],
+            &["--client-p12-password", "secret"],
+            &["doctor", "--webhook", "https://example.com"],
+        ];
+        for args in cases {
+            let err = parse(args).unwrap_err();
+            assert_eq!(err.kind, ErrorKind::MissingRequiredArgument, "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn check_takes_options() {
+        let opts = parse(&["check", "--image", "library/ubuntu:22.04"]).unwrap();
+        assert_eq!(opts.image.as_deref(), Some("library/ubuntu:22.04"));
+        assert!(parse(&["check", "--dry-run"]).unwrap().dry_run);
+    }
+
+    #[test]
+    fn options_go_after_the_subcommand() {
+        assert!(parse(&["-u", "ci-bot", "check"]).is_err());
+        assert!(parse(&["--format", "json", "doctor"]).is_err());
+        assert!(parse(&["doctor", "--format", "json"]).is_ok());
+    }
+
+    #[test]
+    fn cache() {
+        let clear = parse(&["cache", "clear", "--user", "ci-bot"])
+            .unwrap()
+            .command;
+        let user = Some(String::from("ci-bot"));
+        assert!(
+            matches!(clear, Some(Command::Cache(CacheCommand::Clear { user: u })) if u == user)
+        );
+
+        let list = parse(&["cache", "list", "--format", "json"]).unwrap();
+        assert!(matches!(
+            list.command,
+            Some(Command::Cache(CacheCommand::List))
+        ));
+        assert_eq!(list.format, Format::Json);
+
+        assert!(parse(&["cache", "clear", "--format", "json"]).is_err());
+        assert!(parse(&["cache"]).is_err());
+    }
+
+    #[test]
+    fn serve() {
+        let opts = parse(&["serve"]).unwrap();
+        assert_eq!(opts.serve, Some(DEFAULT_SERVE_ADDR.parse().unwrap()));
+
+        let opts = parse(&["serve", "127.0.0.1:9000", "-u", "ci-bot"]).unwrap();
+        assert_eq!(opts.serve, Some("127.0.0.1:9000".parse().unwrap()));
+        assert_eq!(opts.user.as_deref(), Some("ci-bot"));
+
+        let conflicts: &[&[&str]] = &[
+            &["serve", "--verify"],
+            &["serve", "--dry-run"],
+            &["serve", "--serve", "127.0.0.1:9000"],
+        ];
+        for args in conflicts {
+            let err = parse(args).unwrap_err();
+            assert_eq!(err.kind, ErrorKind::ArgumentConflict, "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn metrics_auth_from_a_file() {
+        let path = env::temp_dir().join(format!("docker-rl-metrics-auth-{}", std::process::id()));
+        fs::write(&path, "bearer:s3cret\n").unwrap();
+        let file = path.to_str().unwrap();
+
+        let opts = parse(&["serve", "--metrics-auth-file", file]).unwrap();
+        let auth = opts.metrics_auth().unwrap();
+        assert_eq!(auth, Some(MetricsAuth::Bearer("s3cret".into())));
+
+        fs::write(&path, "s3cret\n").unwrap();
+        let err = opts.metrics_auth().unwrap_err();
+        assert_eq!(err.ret, ExitCode::Input);
+        fs::remove_file(&path).unwrap();
+
+        let args = [
+            "serve",
+            "--metrics-auth",
+            "bearer:a",
+            "--metrics-auth-file",
+            file,
+        ];
+        let err = parse(&args).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn notify_below_needs_watch() {
+        let opts = parse(&["--watch", "--notify-below", "10"]).unwrap();
+        assert_eq!(opts.notify_below, Some(10));
+        let err = parse(&["--notify-below", "10"]).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    fn hooks_need_a_threshold_and_polling() {
+        let args = ["--watch", "--notify-below", "10", "--on-recover", "true"];
+        let hooks = parse(&args).unwrap().hooks().unwrap();
+        assert_eq!(hooks.below, 10);
+        assert_eq!(hooks.on_breach, None);
+        assert_eq!(hooks.on_recover.as_deref(), Some("true"));
+        assert!(parse(&["--watch", "--notify-below", "10"])
+            .unwrap()
+            .hooks()
+            .is_none());
+
+        let args = ["serve", "--notify-below", "10", "--on-breach", "true"];
+        assert!(parse(&args).unwrap().hooks().is_some());
+        let args = [
+            "--serve",
+            "127.0.0.1:9101",
+            "--notify-below",
+            "10",
+            "--on-breach",
+            "true",
+        ];
+        assert!(parse(&args).unwrap().hooks().is_some());
+
+        let err = parse(&["--watch", "--on-breach", "true"]).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::MissingRequiredArgument);
+        let err = parse(&["--notify-below", "10", "--on-breach", "true"]).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::MissingRequiredArgument);
+        assert!(
+            err.message.contains("requires '--watch' or '--serve'"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn notifier_unless_only_hooks() {
+        let opts = parse(&["--watch", "--notify-below", "10"]).unwrap();
+        assert_eq!(opts.notifier().is_ok(), cfg!(feature = "desktop-notify"));
+
+        let args = ["--watch", "--notify-below", "10", "--on-breach", "true"];
+        let notifier = parse(&args).unwrap().notifier().unwrap();
+        assert_eq!(notifier.is_some(), cfg!(feature = "desktop-notify"));
+    }
+
+    #[test]
+    fn adaptive_pacing() {
+        assert_eq!(parse(&["--watch"]).unwrap().pacing(), None);
+        let pacing = parse(&["--watch", "--adaptive"]).unwrap().pacing().unwrap();
+        assert_eq!(pacing, Pacing::default());
+
+        let args = ["--watch", "--adaptive", "--min-interval", "1m"];
+        let pacing = parse(&args).unwrap().pacing().unwrap();
+        assert_eq!(pacing.min, Duration::from_secs(60));
+
+        let err = parse(&["--adaptive"]).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::MissingRequiredArgument);
+        let err = parse(&["--watch", "--adaptive", "--interval", "1m"]).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn duration_ends_a_watch() {
+        assert_eq!(parse(&["--watch"]).unwrap().duration, None);
+        let opts = parse(&["--watch", "--duration", "2h"]).unwrap();
+        assert_eq!(opts.duration, Some(Duration::from_secs(2 * 60 * 60)));
+
+        let err = parse(&["--duration", "2h"]).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::MissingRequiredArgument);
+        let err = parse(&["--watch", "--duration", "0s"]).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn ready_failures() {
+        let opts = parse(&["serve"]).unwrap();
+        assert_eq!(opts.ready_failures, 3);
+        let opts = parse(&["serve", "--ready-failures", "5"]).unwrap();
+        assert_eq!(opts.ready_failures, 5);
+        let err = parse(&["serve", "--ready-failures", "0"]).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::ValueValidation);
     }
 }
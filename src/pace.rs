@@ -0,0 +1,340 @@
+//! Time between checks for `--watch --adaptive`, from how fast the limit is being used
+//!
+//! While the remaining pulls hold steady, or are used slower than the window gives them back,
+//! the limit is checked every `Pacing::baseline`. When the burn rate of the recent checks would
+//! use it up within the window, the checks come closer together, so that there are a few of
+//! them before it runs out, but never closer than `Pacing::min`. `choose` makes the decision
+//! from the samples alone, `Paced` keeps the samples of a watch
+
+use super::burn::{Estimate, Rate};
+use super::duration::{format_age, format_duration};
+use super::limit::Limit;
+use super::state::Sample;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Time between checks while the limit doesn't run out, unless given
+pub const DEFAULT_BASELINE: Duration = Duration::from_secs(10 * 60);
+
+/// Least time between checks, unless given
+pub const DEFAULT_MIN: Duration = Duration::from_secs(30);
+
+/// Checks wanted before the limit runs out at the current burn rate
+const CHECKS_BEFORE_EMPTY: u32 = 10;
+
+/// Most recent samples the burn rate is worked out from
+const MAX_SAMPLES: usize = 12;
+
+/// Longest and shortest time between checks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pacing {
+    /// Time between checks while the limit doesn't run out
+    pub baseline: Duration,
+    /// Least time between checks, `baseline` if that is less
+    pub min: Duration,
+}
+
+impl Default for Pacing {
+    fn default() -> Pacing {
+        Pacing {
+            baseline: DEFAULT_BASELINE,
+            min: DEFAULT_MIN,
+        }
+    }
+}
+
+/// Why the interval was chosen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reason {
+    /// The samples don't span enough time for a burn rate yet
+    Warming,
+    /// No pulls were used over the samples
+    Steady,
+    /// Pulls are used, but the window gives them back before the limit runs out
+    Lasting {
+        /// Pulls used per hour
+        per_hour: f64,
+    },
+    /// The limit runs out within the window at the current burn rate
+    Draining {
+        /// Pulls used per hour
+        per_hour: f64,
+        /// Time until the limit runs out
+        runs_out_in: Duration,
+    },
+    /// Nothing remains
+    UsedUp,
+}
+
+/// Time until the next check and why
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pace {
+    /// Time until the next check
+    pub interval: Duration,
+    /// Why it is that long
+    pub reason: Reason,
+}
+
+impl fmt::Display for Pace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "next check in {}, ", format_duration(self.interval))?;
+        match self.reason {
+            Reason::Warming => write!(f, "not enough checks for a burn rate yet"),
+            Reason::Steady => write!(f, "no pulls used lately"),
+            Reason::Lasting { per_hour } => write!(
+                f,
+                "~{:.0} pulls/h won't use up the limit within the window",
+                per_hour.ceil()
+            ),
+            Reason::Draining {
+                per_hour,
+                runs_out_in,
+            } => write!(
+                f,
+                "~{:.0} pulls/h uses up the limit in ~{}",
+                per_hour.ceil(),
+                format_age(runs_out_in)
+            ),
+            Reason::UsedUp => write!(f, "the limit is used up"),
+        }
+    }
+}
+
+/// Chooses the time until the next check
+///
+/// Without a burn rate yet, or once the limit is used up, the next check is after
+/// `Pacing::min`. When the limit runs out within the window, it is after a tenth of the time
+/// left, between `Pacing::min` and `Pacing::baseline`, and after `Pacing::baseline` when it
+/// doesn't run out. Intervals are whole seconds
+///
+/// # Arguments
+///
+/// * `samples` - `Sample`s of the recent checks, oldest first, the latest one included
+/// * `limit` - `Limit` of the latest check
+/// * `pacing` - `Pacing` to keep to
+pub fn choose(samples: &[Sample], limit: &Limit, pacing: &Pacing) -> Pace {
+    let baseline = pacing.baseline.max(pacing.min);
+    let pace = |interval: Duration, reason| Pace {
+        interval: Duration::from_secs(interval.as_secs()).max(pacing.min),
+        reason,
+    };
+    if limit.remaining == 0 {
+        return pace(pacing.min, Reason::UsedUp);
+    }
+
+    let estimate = Estimate {
+        limit: limit.clone(),
+        rate: Rate::of(samples),
+    };
+    let rate = match estimate.rate {
+        Some(r) => r,
+        None => return pace(pacing.min, Reason::Warming),
+    };
+    if rate.used == 0 {
+        return pace(baseline, Reason::Steady);
+    }
+
+    let per_hour = rate.per_hour();
+    match estimate.runs_out_in() {
+        Some(runs_out_in) => {
+            let interval = (runs_out_in / CHECKS_BEFORE_EMPTY).min(baseline);
+            let reason = Reason::Draining {
+                per_hour,
+                runs_out_in,
+            };
+            pace(interval, reason)
+        }
+        None => pace(baseline, Reason::Lasting { per_hour }),
+    }
+}
+
+/// The recent samples of a watch, for `choose`
+#[derive(Debug, Clone)]
+pub struct Paced {
+    pacing: Pacing,
+    samples: Vec<Sample>,
+    /// Total of the limit the samples are of
+    total: Option<u64>,
+    last: Pace,
+}
+
+impl Paced {
+    /// Starts without samples, warming up
+    ///
+    /// # Arguments
+    ///
+    /// * `pacing` - `Pacing` to keep to
+    pub fn new(pacing: Pacing) -> Paced {
+        Paced {
+            pacing,
+            samples: Vec::new(),
+            total: None,
+            last: Pace {
+                interval: pacing.min,
+                reason: Reason::Warming,
+            },
+        }
+    }
+
+    /// Counts a check, returning the time until the next one
+    ///
+    /// The samples start over when the total changes, as for another user
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - `Limit` from the check
+    /// * `at` - when the check was made
+    pub fn record(&mut self, limit: &Limit, at: SystemTime) -> Pace {
+        let at = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        if self.total != Some(limit.total) {
+            self.samples.clear();
+        }
+        self.samples.push(Sample {
+            remaining: limit.remaining,
+            at,
+        });
+        if self.samples.len() > MAX_SAMPLES {
+            self.samples.remove(0);
+        }
+        self.total = Some(limit.total);
+
+        self.last = choose(&self.samples, limit, &self.pacing);
+        self.last
+    }
+
+    /// The pace chosen after the last check, warming up before the first
+    pub fn pace(&self) -> Pace {
+        self.last
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: u64 = 60;
+
+    fn limit(remaining: u64) -> Limit {
+        Limit {
+            remaining,
+            total: 100,
+            window: Duration::from_secs(6 * 60 * MINUTE),
+            source: None,
+        }
+    }
+
+    /// Samples `every` seconds apart with these remaining counts
+    fn samples(every: u64, remaining: &[u64]) -> Vec<Sample> {
+        let at = (0..).map(|i| 1_600_000_000 + i * every);
+        remaining
+            .iter()
+            .zip(at)
+            .map(|(remaining, at)| Sample {
+                remaining: *remaining,
+                at,
+            })
+            .collect()
+    }
+
+    fn choose_for(every: u64, remaining: &[u64]) -> Pace {
+        let last = *remaining.last().unwrap();
+        choose(&samples(every, remaining), &limit(last), &Pacing::default())
+    }
+
+    #[test]
+    fn baseline_while_steady() {
+        let pace = choose_for(10 * MINUTE, &[80, 80, 80]);
+        assert_eq!(pace.interval, DEFAULT_BASELINE);
+        assert_eq!(pace.reason, Reason::Steady);
+        assert_eq!(pace.to_string(), "next check in 10m, no pulls used lately");
+    }
+
+    #[test]
+    fn min_until_there_is_a_rate() {
+        let pace = choose_for(10, &[80, 79]);
+        assert_eq!(pace.interval, DEFAULT_MIN);
+        assert_eq!(pace.reason, Reason::Warming);
+    }
+
+    #[test]
+    fn baseline_while_the_window_keeps_up() {
+        // 6/h is 36 a window, the window gives them back before 100 are used
+        let pace = choose_for(10 * MINUTE, &[80, 79, 78]);
+        assert_eq!(pace.interval, DEFAULT_BASELINE);
+        assert_eq!(pace.reason, Reason::Lasting { per_hour: 6.0 });
+        assert_eq!(
+            pace.to_string(),
+            "next check in 10m, ~6 pulls/h won't use up the limit within the window"
+        );
+    }
+
+    #[test]
+    fn tightens_as_the_limit_drains() {
+        // 60/h with 60 left runs out in an hour, checked every tenth of that
+        let pace = choose_for(10 * MINUTE, &[80, 70, 60]);
+        assert_eq!(pace.interval, Duration::from_secs(6 * MINUTE));
+        assert_eq!(
+            pace.reason,
+            Reason::Draining {
+                per_hour: 60.0,
+                runs_out_in: Duration::from_secs(60 * MINUTE)
+            }
+        );
+        assert_eq!(
+            pace.to_string(),
+            "next check in 6m, ~60 pulls/h uses up the limit in ~1h"
+        );
+
+        // faster still, down to the minimum
+        let pace = choose_for(MINUTE, &[40, 20, 10]);
+        assert_eq!(pace.interval, DEFAULT_MIN);
+        assert!(matches!(pace.reason, Reason::Draining { .. }));
+    }
+
+    #[test]
+    fn min_once_used_up() {
+        let pace = choose_for(10 * MINUTE, &[80, 80, 0]);
+        assert_eq!(pace.interval, DEFAULT_MIN);
+        assert_eq!(pace.to_string(), "next check in 30s, the limit is used up");
+    }
+
+    #[test]
+    fn min_above_the_baseline_wins() {
+        let pacing = Pacing {
+            baseline: Duration::from_secs(MINUTE),
+            min: Duration::from_secs(5 * MINUTE),
+        };
+        let pace = choose(&samples(MINUTE, &[80, 80]), &limit(80), &pacing);
+        assert_eq!(pace.interval, pacing.min);
+    }
+
+    #[test]
+    fn intervals_are_whole_seconds() {
+        // 42/h with 66 left runs out in 5657.14s
+        let pace = choose_for(10 * MINUTE, &[80, 73, 66]);
+        assert_eq!(pace.interval, Duration::from_secs(565));
+    }
+
+    #[test]
+    fn paced_keeps_the_recent_samples() {
+        let mut paced = Paced::new(Pacing::default());
+        assert_eq!(paced.pace().reason, Reason::Warming);
+
+        let start = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let at = |minutes: u64| start + Duration::from_secs(minutes * MINUTE);
+        // drained fast long ago, steady since
+        paced.record(&limit(90), at(0));
+        paced.record(&limit(50), at(1));
+        for i in 2..20 {
+            paced.record(&limit(50), at(i * 10));
+        }
+        assert_eq!(paced.pace().reason, Reason::Steady);
+
+        // another user starts over
+        let other = Limit {
+            total: 200,
+            ..limit(150)
+        };
+        assert_eq!(paced.record(&other, at(200)).reason, Reason::Warming);
+    }
+}
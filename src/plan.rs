@@ -0,0 +1,151 @@
+//! Dry-run plan of the requests `docker-rl` would make
+//!
+//! Nothing in here touches the network
+
+use super::api::DrlClient;
+use super::duration::format_duration;
+use super::token::token_url;
+use reqwest::Url;
+use serde::{Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+/// A single HTTP request that would be sent
+#[derive(Serialize, Debug, Clone)]
+pub struct PlannedRequest {
+    /// HTTP method
+    pub method: String,
+    /// URL without the query string
+    pub url: String,
+    /// Query parameters
+    pub params: BTreeMap<String, String>,
+}
+
+impl PlannedRequest {
+    /// Creates a `PlannedRequest` by splitting the query string off of `url`
+    ///
+    /// # Arguments
+    ///
+    /// * `method` - HTTP method the request would use
+    /// * `url` - full URL, including any query parameters
+    pub fn new(method: &str, url: &str) -> PlannedRequest {
+        // the urls are constants, so failing to parse them is a bug
+        let mut parsed = Url::parse(url).expect("invalid request url");
+        let params = parsed
+            .query_pairs()
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect();
+        parsed.set_query(None);
+
+        PlannedRequest {
+            method: method.into(),
+            url: parsed.into(),
+            params,
+        }
+    }
+}
+
+impl fmt::Display for PlannedRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.method, self.url)?;
+        for (key, value) in &self.params {
+            write!(f, "\n  {}={}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Everything a check would do, resolved from the configuration
+#[derive(Serialize, Debug, Clone)]
+pub struct Plan {
+    /// Request asking the registry for its token service, only for registries other than
+    /// Docker Hub
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discovery: Option<PlannedRequest>,
+    /// Request for the JWT token, `None` until the token service is discovered
+    pub token: Option<PlannedRequest>,
+    /// Request for the rate limit manifest
+    pub manifest: PlannedRequest,
+    /// User to authenticate as, `None` for anonymous
+    pub user: Option<String>,
+    /// Whether the anonymous limit would be checked
+    pub anonymous: bool,
+    /// Proxy the requests would go through, without credentials
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+}
+
+impl Plan {
+    /// Builds the plan for a check
+    ///
+    /// # Arguments
+    ///
+    /// * `user` - user for basic authentication, if any. The password is never part of the plan
+    /// * `client` - `DrlClient` the check would be made with, before discovery
+    pub fn new(user: Option<String>, client: &DrlClient) -> Plan {
+        let registry = client.registry();
+        let discovery = match registry.realm {
+            Some(_) => None,
+            None => Some(PlannedRequest::new("GET", registry.api_url().as_str())),
+        };
+
+        Plan {
+            discovery,
+            token: token_url(registry, client.scope())
+                .map(|u| PlannedRequest::new("GET", u.as_str())),
+            manifest: PlannedRequest::new("HEAD", registry.manifest_url().as_str()),
+            anonymous: user.is_none(),
+            user,
+            proxy: client.config().redacted_proxy(),
+        }
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(discovery) = &self.discovery {
+            writeln!(f, "discovery: {}", discovery)?;
+        }
+        match &self.token {
+            Some(token) => writeln!(f, "token: {}", token)?,
+            None => writeln!(f, "token: from the discovery response")?,
+        }
+        writeln!(f, "manifest: {}", self.manifest)?;
+        if let Some(proxy) = &self.proxy {
+            writeln!(f, "proxy: {}", proxy)?;
+        }
+        match &self.user {
+            Some(user) => write!(f, "identity: {}", user),
+            None => write!(f, "identity: anonymous"),
+        }
+    }
+}
+
+/// Plan for checking several accounts in one run
+#[derive(Serialize, Debug, Clone)]
+pub struct BatchPlan {
+    /// Maximum number of checks in flight
+    pub concurrency: usize,
+    /// Minimum delay between starting two checks
+    #[serde(serialize_with = "human_duration")]
+    pub stagger: Duration,
+    /// Plan for each account, in order
+    pub checks: Vec<Plan>,
+}
+
+impl fmt::Display for BatchPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "concurrency: {}", self.concurrency)?;
+        write!(f, "stagger: {}", format_duration(self.stagger))?;
+        for plan in &self.checks {
+            write!(f, "\n\n{}", plan)?;
+        }
+        Ok(())
+    }
+}
+
+/// Serializes `d` in the same format the command line takes
+fn human_duration<S: Serializer>(d: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format_duration(*d))
+}
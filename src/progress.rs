@@ -0,0 +1,119 @@
+//! Spinner on stderr for checks that are taking a while
+
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time;
+
+/// How long a check runs before the spinner shows up
+const DELAY: Duration = Duration::from_millis(300);
+
+/// Time between spinner frames
+const TICK: Duration = Duration::from_millis(100);
+
+/// Spinner frames
+const FRAMES: &[char] = &['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+/// Clears the current line
+const CLEAR: &str = "\r\x1b[2K";
+
+/// State shared with the spinner task
+///
+/// The task only draws while holding the lock, so once `stopped` is set nothing else is drawn
+#[derive(Debug)]
+struct State {
+    phase: &'static str,
+    shown: bool,
+    stopped: bool,
+}
+
+/// Locks `state`, a panic while drawing doesn't make it unusable
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// A spinner showing the current phase of a check
+///
+/// The line is cleared when the `Progress` is finished or dropped
+#[derive(Debug)]
+pub struct Progress {
+    running: Option<(Arc<Mutex<State>>, JoinHandle<()>)>,
+}
+
+impl Progress {
+    /// Starts the spinner, which only draws once `DELAY` has passed
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - whether to show anything at all, a disabled `Progress` does nothing
+    /// * `phase` - what the check is doing, e.g. `authenticating…`
+    pub fn start(enabled: bool, phase: &'static str) -> Progress {
+        if !enabled {
+            return Progress { running: None };
+        }
+
+        let state = Arc::new(Mutex::new(State {
+            phase,
+            shown: false,
+            stopped: false,
+        }));
+
+        let task_state = Arc::clone(&state);
+        let handle = tokio::spawn(async move {
+            time::sleep(DELAY).await;
+
+            let mut ticker = time::interval(TICK);
+            for frame in FRAMES.iter().cycle() {
+                ticker.tick().await;
+
+                let mut state = lock(&task_state);
+                if state.stopped {
+                    return;
+                }
+
+                let mut stderr = io::stderr();
+                let _ = write!(stderr, "{}{} {}", CLEAR, frame, state.phase);
+                let _ = stderr.flush();
+                state.shown = true;
+            }
+        });
+
+        Progress {
+            running: Some((state, handle)),
+        }
+    }
+
+    /// Changes the phase shown next to the spinner
+    pub fn phase(&self, phase: &'static str) {
+        if let Some((state, _)) = &self.running {
+            lock(state).phase = phase;
+        }
+    }
+
+    /// Stops the spinner and clears its line
+    pub fn finish(mut self) {
+        self.stop();
+    }
+
+    /// Stops the spinner task, clearing the line if anything was drawn
+    fn stop(&mut self) {
+        if let Some((state, handle)) = self.running.take() {
+            let mut state = lock(&state);
+            state.stopped = true;
+            handle.abort();
+
+            if state.shown {
+                let mut stderr = io::stderr();
+                let _ = write!(stderr, "{}", CLEAR);
+                let _ = stderr.flush();
+            }
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
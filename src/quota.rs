@@ -0,0 +1,283 @@
+//! `QuotaGuard`, for gating pulls on the remaining limit from many tasks at once
+//!
+//! The limit is checked with `HEAD`, so checking doesn't use any of it up, and only when a pull
+//! is asked for and the last check is older than the refresh interval. In between, the pulls
+//! handed out are taken off what the last check said, and a reserve is always kept back for
+//! whatever else shares the limit. Clones share the same limit and `TokenProvider`
+//!
+//! ```no_run
+//! # async fn pull_all(images: &[&str]) -> libdocker_rl::err::DrlResult<()> {
+//! use libdocker_rl::api::DrlClient;
+//! use libdocker_rl::quota::QuotaGuard;
+//!
+//! let client = DrlClient::builder().build()?;
+//! let guard = QuotaGuard::new(client, None).with_reserve(10);
+//!
+//! for image in images {
+//!     let permit = guard.acquire(1).await?;
+//!     // pull image, or give the pull back with permit.release() if it isn't made
+//! #   drop(permit);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use super::api::DrlClient;
+use super::err::{DrlErr, ExitCode};
+use super::limit::{poll_probe, Limit, Probe};
+use super::token::TokenProvider;
+use reqwest::Method;
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Default time after which the limit is checked again, see `QuotaGuard::with_refresh`
+pub const DEFAULT_REFRESH: Duration = Duration::from_secs(60);
+
+/// What the last check said, and what was handed out since
+#[derive(Debug, Default)]
+struct Budget {
+    /// When the limit was last checked, `None` before the first check
+    checked: Option<Instant>,
+    /// The limit from the last check
+    limit: Limit,
+    /// Pulls left at the last check, `None` if the account has no limit
+    remaining: Option<u64>,
+    /// Pulls handed out since the last check
+    granted: u64,
+    /// Number of checks so far, so permits from before one don't give pulls back after it
+    generation: u64,
+}
+
+impl Budget {
+    /// Whether the limit has to be checked before handing out pulls
+    fn is_stale(&self, refresh: Duration) -> bool {
+        self.checked.is_none_or(|at| at.elapsed() >= refresh)
+    }
+
+    /// Starts over from the check in `probe`
+    ///
+    /// After a `GET`, docker reports the limit before decrementing it for the manifest request
+    /// of the check, so one less than the reported count is actually left
+    fn reset(&mut self, probe: &Probe) {
+        self.remaining = if probe.unlimited {
+            None
+        } else if probe.method == Method::GET {
+            Some(probe.limit.remaining.saturating_sub(1))
+        } else {
+            Some(probe.limit.remaining)
+        };
+        self.limit = probe.limit.clone();
+        self.checked = Some(Instant::now());
+        self.granted = 0;
+        self.generation += 1;
+    }
+}
+
+/// Locks `budget`, which is only ever left poisoned between two plain assignments
+fn lock(budget: &Mutex<Budget>) -> MutexGuard<'_, Budget> {
+    budget.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Why `QuotaGuard::acquire` didn't hand out the pulls
+#[derive(Debug, Clone)]
+pub enum QuotaExhausted {
+    /// Fewer pulls are left above the reserve than were asked for
+    Insufficient {
+        /// Pulls asked for
+        requested: u64,
+        /// Pulls that could be handed out
+        available: u64,
+        /// Pulls kept back
+        reserve: u64,
+        /// The limit from the last check
+        limit: Limit,
+    },
+    /// The limit couldn't be checked, so nothing is handed out
+    Unchecked(DrlErr),
+}
+
+impl fmt::Display for QuotaExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuotaExhausted::Insufficient {
+                requested,
+                available,
+                reserve,
+                ..
+            } => write!(
+                f,
+                "{} pulls requested but only {} available, keeping {} in reserve",
+                requested, available, reserve
+            ),
+            QuotaExhausted::Unchecked(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for QuotaExhausted {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            QuotaExhausted::Insufficient { .. } => None,
+            QuotaExhausted::Unchecked(e) => Some(e),
+        }
+    }
+}
+
+impl From<QuotaExhausted> for DrlErr {
+    /// `ExitCode::BelowThreshold` if there weren't enough pulls, else the error of the check
+    fn from(e: QuotaExhausted) -> DrlErr {
+        match e {
+            QuotaExhausted::Insufficient { .. } => {
+                DrlErr::new(e.to_string(), ExitCode::BelowThreshold)
+            }
+            QuotaExhausted::Unchecked(e) => e,
+        }
+    }
+}
+
+/// Pulls handed out by `QuotaGuard::acquire`
+///
+/// Dropping it counts the pulls as made, `release` gives back the ones that weren't
+#[derive(Debug)]
+#[must_use = "dropping a permit counts its pulls as made"]
+pub struct Permit {
+    pulls: u64,
+    generation: u64,
+    budget: Arc<Mutex<Budget>>,
+}
+
+impl Permit {
+    /// Number of pulls handed out
+    pub fn pulls(&self) -> u64 {
+        self.pulls
+    }
+
+    /// Gives `pulls` back to the guard, for pulls that won't be made
+    ///
+    /// Once the limit has been checked again they are already counted by the registry, so
+    /// there is nothing to give back
+    pub fn release(self) {
+        let mut budget = lock(&self.budget);
+        if budget.generation == self.generation {
+            budget.granted = budget.granted.saturating_sub(self.pulls);
+        }
+    }
+}
+
+/// Hands out pulls from the remaining limit, keeping a reserve back
+///
+/// Cheap to clone, every clone shares the same limit
+#[derive(Clone)]
+pub struct QuotaGuard {
+    /// Held while checking, so tasks waiting on a check share it rather than each making one
+    provider: Arc<tokio::sync::Mutex<TokenProvider>>,
+    budget: Arc<Mutex<Budget>>,
+    reserve: u64,
+    refresh: Duration,
+}
+
+impl fmt::Debug for QuotaGuard {
+    /// Leaves out the provider, which holds the credentials
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QuotaGuard")
+            .field("budget", &self.budget)
+            .field("reserve", &self.reserve)
+            .field("refresh", &self.refresh)
+            .finish_non_exhaustive()
+    }
+}
+
+impl QuotaGuard {
+    /// Creates a guard over the limit of `client`, without checking it yet
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - `DrlClient` for the registry whose limit is guarded
+    /// * `creds` - user and password, `None` for the anonymous limit
+    pub fn new(client: DrlClient, creds: Option<(String, String)>) -> QuotaGuard {
+        QuotaGuard {
+            provider: Arc::new(tokio::sync::Mutex::new(client.provider(creds))),
+            budget: Arc::default(),
+            reserve: 0,
+            refresh: DEFAULT_REFRESH,
+        }
+    }
+
+    /// Keeps `reserve` pulls back, none by default
+    pub fn with_reserve(mut self, reserve: u64) -> QuotaGuard {
+        self.reserve = reserve;
+        self
+    }
+
+    /// Checks the limit again once the last check is older than `refresh`, see
+    /// `DEFAULT_REFRESH`
+    pub fn with_refresh(mut self, refresh: Duration) -> QuotaGuard {
+        self.refresh = refresh;
+        self
+    }
+
+    /// Pulls kept back
+    pub fn reserve(&self) -> u64 {
+        self.reserve
+    }
+
+    /// The limit from the last check, `None` before the first
+    pub fn limit(&self) -> Option<Limit> {
+        let budget = lock(&self.budget);
+        budget.checked.map(|_| budget.limit.clone())
+    }
+
+    /// Hands out `pulls` pulls, checking the limit first if the last check is too old
+    ///
+    /// # Errors
+    ///
+    /// Returns `QuotaExhausted::Insufficient` if fewer than `pulls` are left above the reserve,
+    /// and `QuotaExhausted::Unchecked` if the limit had to be checked and that failed
+    ///
+    /// # Arguments
+    ///
+    /// * `pulls` - number of pulls about to be made
+    pub async fn acquire(&self, pulls: u64) -> Result<Permit, QuotaExhausted> {
+        if lock(&self.budget).is_stale(self.refresh) {
+            let mut provider = self.provider.lock().await;
+            // another task may have checked while this one waited
+            if lock(&self.budget).is_stale(self.refresh) {
+                let probe = poll_probe(&mut provider)
+                    .await
+                    .map_err(QuotaExhausted::Unchecked)?;
+                lock(&self.budget).reset(&probe);
+            }
+        }
+
+        let mut budget = lock(&self.budget);
+        let available = match budget.remaining {
+            Some(remaining) => remaining
+                .saturating_sub(budget.granted)
+                .saturating_sub(self.reserve),
+            None => u64::MAX,
+        };
+        if pulls > available {
+            return Err(QuotaExhausted::Insufficient {
+                requested: pulls,
+                available,
+                reserve: self.reserve,
+                limit: budget.limit.clone(),
+            });
+        }
+
+        budget.granted += pulls;
+        Ok(Permit {
+            pulls,
+            generation: budget.generation,
+            budget: self.budget.clone(),
+        })
+    }
+}
+
+/// `QuotaGuard` is meant to be shared between tasks
+const _: fn() = || {
+    fn shared<T: Clone + Send + Sync>() {}
+    shared::<QuotaGuard>();
+};
@@ -0,0 +1,266 @@
+//! The registry the limit is checked on, Docker Hub unless `--registry` names another
+//!
+//! An `api::DrlClient` sends every request to its registry. Other registries than Docker Hub
+//! are asked for their token endpoint with the `WWW-Authenticate` challenge of `/v2/`
+
+use super::client::Http;
+use super::err::{DrlErr, DrlResult, ExitCode};
+use super::token::{REPOSITORY, SERVICE, TOKEN_URL};
+use reqwest::header::WWW_AUTHENTICATE;
+use reqwest::{StatusCode, Url};
+
+/// API of Docker Hub's registry
+pub const DOCKER_HUB_URL: &str = "https://registry-1.docker.io";
+
+/// Reference of the manifest checked when none is given
+pub const DEFAULT_REFERENCE: &str = "latest";
+
+/// Where the limit is checked, and how tokens for it are requested
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Registry {
+    /// Name used in messages, `docker.io` for Docker Hub and the host otherwise
+    pub name: String,
+    /// Base URL of the registry API, without the `/v2/`
+    pub url: Url,
+    /// Repository whose manifest is checked
+    pub repository: String,
+    /// Tag or digest of the manifest
+    pub reference: String,
+    /// Token endpoint, `None` if the registry doesn't ask for a token or hasn't been asked
+    pub realm: Option<Url>,
+    /// Service tokens are requested for
+    pub service: Option<String>,
+}
+
+impl Default for Registry {
+    fn default() -> Registry {
+        Registry::docker_hub()
+    }
+}
+
+impl Registry {
+    /// Docker Hub, which needs no discovery
+    pub fn docker_hub() -> Registry {
+        // the urls are constants, so failing to parse them is a bug
+        Registry {
+            name: String::from("docker.io"),
+            url: Url::parse(DOCKER_HUB_URL).expect("invalid registry url"),
+            repository: REPOSITORY.into(),
+            reference: DEFAULT_REFERENCE.into(),
+            realm: Some(Url::parse(TOKEN_URL).expect("invalid token url")),
+            service: Some(SERVICE.into()),
+        }
+    }
+
+    /// The registry at `url`, with its token endpoint still to be discovered
+    ///
+    /// Docker Hub's own hosts give `docker_hub`, which needs no discovery
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - base URL of the registry API, e.g. `https://ghcr.io`
+    pub fn new(url: Url) -> Registry {
+        let hub = Registry::docker_hub();
+        if url.host_str() == hub.url.host_str() {
+            return hub;
+        }
+
+        Registry {
+            name: url.host_str().unwrap_or_default().to_string(),
+            url,
+            repository: REPOSITORY.into(),
+            reference: DEFAULT_REFERENCE.into(),
+            realm: None,
+            service: None,
+        }
+    }
+
+    /// Checks `repository` instead of the default
+    ///
+    /// On Docker Hub, official images can be named without `library/`, like `docker pull` does
+    ///
+    /// # Arguments
+    ///
+    /// * `repository` - repository to check, optionally with a `:tag` or `@digest`
+    pub fn with_repository(mut self, repository: &str) -> Registry {
+        let (repository, reference) = split_reference(repository);
+        self.repository = self.full_name(repository);
+        self.reference = reference.into();
+        self
+    }
+
+    /// `repository` with the `library/` Docker Hub puts official images under, if it's left out
+    ///
+    /// # Arguments
+    ///
+    /// * `repository` - name of the repository, e.g. `ubuntu`
+    pub fn full_name(&self, repository: &str) -> String {
+        if self.is_docker_hub() && !repository.contains('/') {
+            format!("library/{}", repository)
+        } else {
+            repository.into()
+        }
+    }
+
+    /// Requests tokens from `realm` for `service`, instead of discovering them
+    ///
+    /// For registries whose token endpoint is known, e.g. a mock server in tests, so nothing
+    /// else is asked first
+    ///
+    /// # Arguments
+    ///
+    /// * `realm` - URL of the token endpoint
+    /// * `service` - service to request tokens for, `None` to leave it out
+    pub fn with_realm(mut self, realm: Url, service: Option<&str>) -> Registry {
+        self.realm = Some(realm);
+        self.service = service.map(String::from);
+        self
+    }
+
+    /// Whether this is Docker Hub, the only registry `docker login` credentials are sent to
+    pub fn is_docker_hub(&self) -> bool {
+        self.host() == Registry::docker_hub().host()
+    }
+
+    /// URL of the API root, which answers with the auth challenge
+    pub fn api_url(&self) -> Url {
+        // joining a fixed relative path can't fail
+        self.url.join("/v2/").expect("invalid registry url")
+    }
+
+    /// URL of the manifest the limit is read from
+    pub fn manifest_url(&self) -> Url {
+        let path = format!("/v2/{}/manifests/{}", self.repository, self.reference);
+        self.url.join(&path).expect("invalid manifest url")
+    }
+
+    /// Host of the registry API
+    pub fn host(&self) -> &str {
+        self.url.host_str().unwrap_or_default()
+    }
+
+    /// Host of the token endpoint, if there is one
+    pub fn token_host(&self) -> Option<&str> {
+        self.realm.as_ref().and_then(|r| r.host_str())
+    }
+
+    /// Asks the registry for its token endpoint
+    ///
+    /// A registry whose token endpoint is already known, like Docker Hub, is left as it is. One
+    /// answering `/v2/` without a challenge doesn't ask for tokens
+    ///
+    /// # Errors
+    ///
+    /// Returns `ExitCode::Connection` if the registry can't be reached or its challenge can't
+    /// be parsed
+    ///
+    /// # Arguments
+    ///
+    /// * `http` - `Http` to send the request with
+    pub(crate) async fn discover(mut self, http: &Http) -> DrlResult<Registry> {
+        if self.realm.is_some() {
+            return Ok(self);
+        }
+
+        let resp = match http.send(http.client.get(self.api_url())).await {
+            Ok(r) => r,
+            Err(e) => return Err(http.connect_error(&self.name, e)),
+        };
+
+        match resp.status() {
+            StatusCode::UNAUTHORIZED => (),
+            StatusCode::PROXY_AUTHENTICATION_REQUIRED => {
+                return Err(http.proxy_auth_error(&self.name));
+            }
+            status if status.is_success() => return Ok(self),
+            status => {
+                let msg = format!("error connecting to {}: {}", self.name, status);
+                let err = DrlErr::new(msg, ExitCode::Connection);
+                return Err(err);
+            }
+        }
+
+        let challenge = resp
+            .headers()
+            .get(WWW_AUTHENTICATE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_challenge);
+        match challenge {
+            Some((realm, service)) => {
+                self.realm = Some(realm);
+                self.service = service;
+                Ok(self)
+            }
+            None => {
+                let msg = format!("{} sent no usable bearer challenge", self.name);
+                let err = DrlErr::new(msg, ExitCode::Connection);
+                Err(err)
+            }
+        }
+    }
+}
+
+/// Splits `name:tag` or `name@digest` into the name and the reference
+///
+/// A `:` before the last `/` is a port, not a tag
+fn split_reference(repository: &str) -> (&str, &str) {
+    if let Some((name, digest)) = repository.split_once('@') {
+        return (name, digest);
+    }
+    let last = repository.rfind('/').map_or(0, |i| i + 1);
+    match repository[last..].rfind(':') {
+        Some(i) => (&repository[..last + i], &repository[last + i + 1..]),
+        None => (repository, DEFAULT_REFERENCE),
+    }
+}
+
+/// Parses a `Bearer realm="...",service="..."` challenge
+///
+/// Returns `None` if it isn't a bearer challenge or has no valid realm
+///
+/// # Arguments
+///
+/// * `challenge` - value of the `WWW-Authenticate` header
+pub fn parse_challenge(challenge: &str) -> Option<(Url, Option<String>)> {
+    let (scheme, params) = challenge.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("bearer") {
+        return None;
+    }
+
+    let mut realm = None;
+    let mut service = None;
+    for (key, value) in challenge_params(params) {
+        match key.to_ascii_lowercase().as_str() {
+            "realm" => realm = Url::parse(&value).ok(),
+            "service" => service = Some(value),
+            _ => (),
+        }
+    }
+
+    Some((realm?, service))
+}
+
+/// Splits `key="value",key=value` pairs, where quoted values may contain commas
+fn challenge_params(params: &str) -> Vec<(String, String)> {
+    let mut pairs = Vec::new();
+    let mut rest = params.trim();
+
+    while let Some((key, after)) = rest.split_once('=') {
+        let key = key.trim().trim_start_matches(',').trim().to_string();
+        let after = after.trim_start();
+        let (value, next) = match after.strip_prefix('"') {
+            Some(quoted) => match quoted.find('"') {
+                Some(end) => (&quoted[..end], &quoted[end + 1..]),
+                None => (quoted, ""),
+            },
+            None => match after.find(',') {
+                Some(end) => (&after[..end], &after[end..]),
+                None => (after, ""),
+            },
+        };
+        pairs.push((key, value.to_string()));
+        rest = next.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+    }
+
+    pairs
+}
@@ -0,0 +1,213 @@
+//! Per-identity results for checks of several accounts
+
+use super::client::IpStack;
+use super::err::DrlResult;
+use super::human;
+use super::limit::Limit;
+use super::table::{Align, Table};
+use serde::{Serialize, Serializer};
+use std::fmt;
+
+/// The outcome of checking the limit for one identity
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// User the limit was checked for, `None` for anonymous
+    pub user: Option<String>,
+    /// The limit, or why it couldn't be checked
+    pub result: DrlResult<Limit>,
+    /// Docker Hub plan of the account, if it was looked up
+    pub plan: Option<String>,
+    /// Config file profile the check was made for, if several were checked
+    pub profile: Option<String>,
+    /// IP version the check was made over, if both were checked
+    pub stack: Option<IpStack>,
+}
+
+impl Report {
+    /// Creates a `Report`
+    pub fn new(user: Option<String>, result: DrlResult<Limit>) -> Report {
+        Report {
+            user,
+            result,
+            plan: None,
+            profile: None,
+            stack: None,
+        }
+    }
+
+    /// Name to show for the identity
+    pub fn identity(&self) -> &str {
+        self.user.as_deref().unwrap_or("anonymous")
+    }
+
+    /// Name to label the report with: the identity, after the profile or stack if there is one
+    fn label(&self) -> String {
+        match (&self.profile, self.stack) {
+            (Some(profile), _) => format!("{} ({})", profile, self.identity()),
+            (None, Some(stack)) => format!("{} ({})", stack, self.identity()),
+            (None, None) => self.identity().to_string(),
+        }
+    }
+
+    /// Same as the `Display` output, with the limit formatted by `human::limit`
+    pub fn human(&self) -> String {
+        match (&self.result, &self.plan) {
+            (Ok(limit), Some(plan)) => {
+                format!("{}: {} ({})", self.label(), human::limit(limit), plan)
+            }
+            (Ok(limit), None) => format!("{}: {}", self.label(), human::limit(limit)),
+            (Err(e), _) => format!("{}: {}", self.label(), e),
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.result, &self.plan) {
+            (Ok(limit), Some(plan)) => write!(f, "{}: {} ({})", self.label(), limit, plan),
+            (Ok(limit), None) => write!(f, "{}: {}", self.label(), limit),
+            (Err(e), _) => write!(f, "{}: {}", self.label(), e),
+        }
+    }
+}
+
+/// Serialized form of a `Report`, with the limit flattened in
+#[derive(Serialize)]
+struct FlatReport<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    profile: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stack: Option<String>,
+    user: &'a Option<String>,
+    anonymous: bool,
+    #[serde(flatten)]
+    limit: Option<&'a Limit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    plan: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+}
+
+impl Serialize for Report {
+    /// Flattens the limit into the report, or adds an `error` field on failure
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let flat = FlatReport {
+            profile: self.profile.as_deref(),
+            stack: self.stack.map(|s| s.to_string()),
+            user: &self.user,
+            anonymous: self.user.is_none(),
+            limit: self.result.as_ref().ok(),
+            plan: self.plan.as_deref(),
+            error: self.result.as_ref().err().map(|e| e.msg.as_str()),
+        };
+        flat.serialize(serializer)
+    }
+}
+
+/// Anonymous and authenticated limits, checked side by side
+#[derive(Serialize, Debug, Clone)]
+pub struct Comparison {
+    /// Report for the anonymous limit
+    pub anonymous: Report,
+    /// Report for the authenticated limit
+    pub authenticated: Report,
+    /// How much bigger the authenticated total is, `None` if either check failed
+    pub total_delta: Option<i64>,
+}
+
+impl Comparison {
+    /// Creates a `Comparison`, working out the delta between the totals
+    pub fn new(anonymous: Report, authenticated: Report) -> Comparison {
+        let total_delta = match (&anonymous.result, &authenticated.result) {
+            (Ok(anon), Ok(auth)) => Some(auth.total as i64 - anon.total as i64),
+            _ => None,
+        };
+
+        Comparison {
+            anonymous,
+            authenticated,
+            total_delta,
+        }
+    }
+
+    /// Both reports, anonymous first
+    pub fn reports(&self) -> [Report; 2] {
+        [self.anonymous.clone(), self.authenticated.clone()]
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}    {}", self.anonymous, self.authenticated)
+    }
+}
+
+/// Builds a table with a row per report
+///
+/// A `profile` or `stack` column is only added when a report has one, along with `source` for
+/// stacks, and an `error` column when at least one check failed
+///
+/// # Arguments
+///
+/// * `reports` - reports to put in the table
+/// * `human` - format counts with `human::count`
+pub fn table(reports: &[Report], human: bool) -> Table {
+    let count = |n: u64| {
+        if human {
+            human::count(n)
+        } else {
+            n.to_string()
+        }
+    };
+    let failed = reports.iter().any(|r| r.result.is_err());
+    let profiles = reports.iter().any(|r| r.profile.is_some());
+    let stacks = reports.iter().any(|r| r.stack.is_some());
+
+    let mut table = Table::new();
+    if profiles {
+        table = table.column("profile", Align::Left);
+    }
+    if stacks {
+        table = table.column("stack", Align::Left);
+    }
+    table = table
+        .column("identity", Align::Left)
+        .column("remaining", Align::Right)
+        .column("total", Align::Right)
+        .column("percent", Align::Right);
+    // the source IP is what differs between the stacks
+    if stacks {
+        table = table.column("source", Align::Left);
+    }
+    if failed {
+        table = table.truncated_column("error", Align::Left, 60);
+    }
+
+    for report in reports {
+        let mut cells = Vec::new();
+        if profiles {
+            cells.push(report.profile.clone().unwrap_or_default());
+        }
+        if stacks {
+            cells.push(report.stack.map(|s| s.to_string()).unwrap_or_default());
+        }
+        cells.push(report.identity().to_string());
+        match &report.result {
+            Ok(limit) => {
+                cells.push(count(limit.remaining));
+                cells.push(count(limit.total));
+                cells.push(human::percent(limit.percent()));
+                if stacks {
+                    cells.push(limit.source.clone().unwrap_or_else(|| String::from("-")));
+                }
+            }
+            Err(e) => {
+                cells.extend(vec![String::from("-"); if stacks { 4 } else { 3 }]);
+                cells.push(e.msg.clone());
+            }
+        }
+        table.row(cells);
+    }
+
+    table
+}
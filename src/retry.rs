@@ -0,0 +1,120 @@
+//! Retrying requests that failed for reasons that tend to go away, like a DNS hiccup or a `503`
+//!
+//! Anything the registry answered on purpose, like `401` or `429`, is returned right away
+
+use super::client;
+use super::trace;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+use tokio::time;
+
+/// Retries used when none are given
+pub const DEFAULT_RETRIES: u32 = 2;
+
+/// Backoff before the first retry when none is given
+pub const DEFAULT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Longest wait between two attempts, however many retries there were before
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// How often and how patiently to retry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt, 0 to never retry
+    pub retries: u32,
+    /// Wait before the first retry, doubled for every retry after it
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            retries: DEFAULT_RETRIES,
+            backoff: DEFAULT_BACKOFF,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries
+    pub fn none() -> RetryPolicy {
+        RetryPolicy {
+            retries: 0,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Wait before retry number `retry`, counting from 0
+    ///
+    /// The backoff doubles every retry, up to `MAX_BACKOFF`, and a random half of it is taken
+    /// off so that clients failing together don't retry together
+    pub fn delay(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry);
+        let full = self.backoff.saturating_mul(factor).min(MAX_BACKOFF);
+        full / 2 + full.mul_f64(jitter() / 2.0)
+    }
+}
+
+/// Random number in `[0, 1)`, from the random keys of the std hasher
+fn jitter() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u8(0);
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Whether `status` is a failure of the server that might not happen again
+///
+/// `501` is left out, it's how registries say they don't support `HEAD`
+fn is_transient_status(status: StatusCode) -> bool {
+    status.is_server_error() && status != StatusCode::NOT_IMPLEMENTED
+}
+
+/// Whether `e` is a failure to reach the server that might not happen again
+///
+/// A proxy refusing the connection will refuse it again
+fn is_transient_error(e: &reqwest::Error) -> bool {
+    (e.is_connect() || e.is_timeout()) && !client::is_proxy_error(e)
+}
+
+/// Sends `req`, retrying connection errors, timeouts and `5xx` responses as `policy` says
+///
+/// The last attempt's result is returned as it is, so a `5xx` still reaches the caller when
+/// every retry failed. Requests that can't be cloned are only sent once
+///
+/// # Arguments
+///
+/// * `req` - request to send
+/// * `policy` - `RetryPolicy` to retry with
+pub(crate) async fn send(req: RequestBuilder, policy: &RetryPolicy) -> reqwest::Result<Response> {
+    for retry in 0..policy.retries {
+        let attempt = match req.try_clone() {
+            Some(r) => r,
+            None => break,
+        };
+
+        let reason = match logged(attempt).await {
+            Ok(resp) if !is_transient_status(resp.status()) => return Ok(resp),
+            Err(e) if !is_transient_error(&e) => return Err(e),
+            Ok(resp) => format!("got {}", resp.status()),
+            Err(e) => e.to_string(),
+        };
+
+        trace::retry(&reason);
+        time::sleep(policy.delay(retry)).await;
+    }
+
+    logged(req).await
+}
+
+/// Sends `req` once, logging it and its response
+async fn logged(req: RequestBuilder) -> reqwest::Result<Response> {
+    trace::request(&req);
+    let result = req.send().await;
+    match &result {
+        Ok(resp) => trace::received(resp),
+        Err(e) => trace::failed(e),
+    }
+    result
+}
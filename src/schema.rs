@@ -0,0 +1,31 @@
+//! Versioning of the JSON output
+//!
+//! Fields may be added without changing `SCHEMA_VERSION`, renaming or removing one bumps it
+
+use serde::Serialize;
+
+/// Version of the JSON output described by `SCHEMA`
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// JSON Schema document for the JSON output
+pub const SCHEMA: &str = include_str!("schema.json");
+
+/// `T` with a `schema_version` field added next to its own
+#[derive(Serialize, Debug, Clone)]
+pub struct Versioned<'a, T> {
+    /// Always `SCHEMA_VERSION`
+    pub schema_version: u32,
+    /// The wrapped value, which has to serialize as a map
+    #[serde(flatten)]
+    pub value: &'a T,
+}
+
+impl<'a, T: Serialize> Versioned<'a, T> {
+    /// Wraps `value` with the current `SCHEMA_VERSION`
+    pub fn new(value: &'a T) -> Versioned<'a, T> {
+        Versioned {
+            schema_version: SCHEMA_VERSION,
+            value,
+        }
+    }
+}
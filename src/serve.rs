@@ -0,0 +1,746 @@
+//! Exporter mode for `--serve`, a `/metrics` endpoint for Prometheus to scrape
+//!
+//! The limit is checked every interval in the background, and scrapes only read the result of
+//! the last check, so scraping more often doesn't send more requests. With `MetricsAuth`, only
+//! scrapes sending its credentials get an answer. `/healthz` and `/readyz` are there for the
+//! probes of Kubernetes
+
+use super::backoff::{Backoff, BackoffPolicy};
+use super::client::BasicAuth;
+use super::err::{DrlErr, DrlResult, ExitCode};
+use super::hook::Hooks;
+use super::limit::{poll_limits_bounded, Limit};
+use super::logtarget::LogSink;
+use super::metrics::{self, write_metric};
+use super::settings::REDACTED;
+use super::token::TokenProvider;
+use futures::StreamExt;
+use hyper::header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::Serialize;
+use std::convert::Infallible;
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+/// Default time between checks
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Path the metrics are served on
+pub const METRICS_PATH: &str = "/metrics";
+
+/// Path of the liveness probe, answered while the exporter runs
+pub const HEALTH_PATH: &str = "/healthz";
+
+/// Path of the readiness probe, answered once the checks succeed
+pub const READY_PATH: &str = "/readyz";
+
+/// Failed checks in a row after which the exporter is no longer ready, unless given
+pub const DEFAULT_READY_FAILURES: u32 = 3;
+
+/// Realm of the `WWW-Authenticate` challenge sent with a `401`
+const REALM: &str = "docker-rl";
+
+/// Credentials every scrape has to send, given as `bearer:<token>` or `basic:<user>:<pass>`
+#[derive(Clone, PartialEq, Eq)]
+pub enum MetricsAuth {
+    /// `Authorization: Bearer <token>`
+    Bearer(String),
+    /// `Authorization: Basic <user:pass>`
+    Basic(BasicAuth),
+}
+
+impl FromStr for MetricsAuth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = "invalid metrics credentials: expected bearer:<token> or basic:<user>:<pass>";
+        match s.split_once(':') {
+            Some(("bearer", token)) if !token.is_empty() => Ok(MetricsAuth::Bearer(token.into())),
+            Some(("basic", creds)) => match creds.parse() {
+                Ok(auth) => Ok(MetricsAuth::Basic(auth)),
+                Err(_) => Err(String::from(invalid)),
+            },
+            _ => Err(String::from(invalid)),
+        }
+    }
+}
+
+impl fmt::Debug for MetricsAuth {
+    /// Leaves out the secret
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetricsAuth::Bearer(_) => write!(f, "Bearer(\"****\")"),
+            MetricsAuth::Basic(auth) => write!(f, "Basic({:?})", auth),
+        }
+    }
+}
+
+impl MetricsAuth {
+    /// The credentials as they were given, with the secret replaced by `REDACTED`
+    pub fn redacted(&self) -> String {
+        match self {
+            MetricsAuth::Bearer(_) => format!("bearer:{}", REDACTED),
+            MetricsAuth::Basic(auth) => format!("basic:{}:{}", auth.user, REDACTED),
+        }
+    }
+
+    /// Whether `header`, the `Authorization` of a scrape, has these credentials
+    ///
+    /// The secrets are compared in constant time, so the time of a rejection doesn't give away
+    /// how much of the secret was right
+    fn allows(&self, header: Option<&HeaderValue>) -> bool {
+        let header = match header.and_then(|h| h.to_str().ok()) {
+            Some(h) => h,
+            None => return false,
+        };
+        let (scheme, value) = match header.split_once(' ') {
+            Some((scheme, value)) => (scheme, value.trim()),
+            None => return false,
+        };
+
+        match self {
+            MetricsAuth::Bearer(token) => {
+                scheme.eq_ignore_ascii_case("bearer")
+                    && constant_time_eq(value.as_bytes(), token.as_bytes())
+            }
+            MetricsAuth::Basic(auth) => {
+                let expected = format!("{}:{}", auth.user, auth.pass);
+                scheme.eq_ignore_ascii_case("basic")
+                    && base64::decode(value)
+                        .is_ok_and(|sent| constant_time_eq(&sent, expected.as_bytes()))
+            }
+        }
+    }
+
+    /// `WWW-Authenticate` challenge for these credentials
+    fn challenge(&self) -> HeaderValue {
+        let scheme = match self {
+            MetricsAuth::Bearer(_) => "Bearer",
+            MetricsAuth::Basic(_) => "Basic",
+        };
+        // the realm is a constant, so it's always a valid header value
+        HeaderValue::from_str(&format!("{} realm=\"{}\"", scheme, REALM))
+            .expect("invalid challenge")
+    }
+}
+
+/// Whether `sent` and `expected` are the same, taking as long however much of them matches
+fn constant_time_eq(sent: &[u8], expected: &[u8]) -> bool {
+    // a length that's off is already a difference, the bytes are still all compared
+    let mut diff = sent.len() ^ expected.len();
+    for (i, b) in expected.iter().enumerate() {
+        diff |= usize::from(sent.get(i).copied().unwrap_or(0) ^ b);
+    }
+    diff == 0
+}
+
+/// What scrapes are answered with, for one identity
+#[derive(Debug, Default)]
+struct Scrape {
+    /// User that is checked, `None` for anonymous
+    user: Option<String>,
+    /// Limit from the last successful check
+    limit: Option<Limit>,
+    /// Whether the last check succeeded
+    up: bool,
+    /// Number of failed checks since the exporter started
+    errors: u64,
+    /// Failed checks in a row, and how far polling backed off because of them
+    backoff: Option<Backoff>,
+    /// Error of the last check, if it failed
+    error: Option<DrlErr>,
+}
+
+/// Renders the metrics of every identity in the Prometheus text exposition format
+///
+/// The limit of an identity is left out until its first check succeeds, and kept after that
+/// even while the checks fail, `dockerhub_ratelimit_up` tells which it is
+fn exposition(scrapes: &[Scrape]) -> String {
+    let limits: Vec<_> = scrapes
+        .iter()
+        .filter_map(|s| Some((s.user.as_deref(), s.limit.as_ref()?)))
+        .collect();
+    let mut out = metrics::exposition_all(&limits);
+
+    let up: Vec<_> = scrapes
+        .iter()
+        .map(|s| (s.user.as_deref(), u8::from(s.up)))
+        .collect();
+    let help = "Whether the last check of the rate limit succeeded";
+    write_metric(&mut out, "dockerhub_ratelimit_up", "gauge", help, &up);
+
+    let errors: Vec<_> = scrapes
+        .iter()
+        .map(|s| (s.user.as_deref(), s.errors))
+        .collect();
+    let name = "dockerhub_ratelimit_check_errors_total";
+    write_metric(
+        &mut out,
+        name,
+        "counter",
+        "Failed checks of the rate limit",
+        &errors,
+    );
+
+    let backoffs: Vec<_> = scrapes
+        .iter()
+        .filter_map(|s| Some((s.user.as_deref(), s.backoff?)))
+        .collect();
+    let failures: Vec<_> = backoffs.iter().map(|(u, b)| (*u, b.failures())).collect();
+    let name = "dockerhub_ratelimit_check_consecutive_failures";
+    let help = "Failed checks of the rate limit in a row";
+    write_metric(&mut out, name, "gauge", help, &failures);
+
+    let delays: Vec<_> = backoffs
+        .iter()
+        .map(|(u, b)| (*u, b.delay().as_secs_f64()))
+        .collect();
+    let name = "dockerhub_ratelimit_check_interval_seconds";
+    let help = "Time until the next check, longer than the interval while backing off";
+    write_metric(&mut out, name, "gauge", help, &delays);
+    out
+}
+
+/// Settings of the exporter
+#[derive(Debug, Clone)]
+pub struct Exporter {
+    /// Address to listen on, e.g. `0.0.0.0:9101`
+    pub addr: SocketAddr,
+    /// Time between checks
+    pub interval: Duration,
+    /// How to back off from checks that keep failing
+    pub backoff: BackoffPolicy,
+    /// Whether to keep the address and failed checks off stderr
+    pub quiet: bool,
+    /// Whether to print the failures in a row and the backoff after every failed check
+    pub verbose: bool,
+    /// Credentials scrapes have to send, `None` to answer every scrape
+    pub auth: Option<MetricsAuth>,
+    /// Maximum number of checks in flight, for several identities
+    pub concurrency: usize,
+    /// Failed checks in a row after which `/readyz` fails again
+    pub ready_failures: u32,
+    /// Whether `/healthz` and `/readyz` are answered without the credentials of `auth`
+    pub health_exempt: bool,
+    /// Hooks to run when the limit of an identity crosses their threshold
+    pub hooks: Option<Hooks>,
+    /// Where the exporter writes what it's doing and why checks failed
+    pub log: LogSink,
+}
+
+impl Exporter {
+    /// Serves `/metrics` until `shutdown` completes, checking the limit with tokens from
+    /// `provider`
+    ///
+    /// # Errors
+    ///
+    /// Returns `ExitCode::Connection` if `addr` can't be listened on, or the server fails
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` is zero
+    ///
+    /// # Arguments
+    ///
+    /// * `provider` - `TokenProvider` for the identity to check
+    /// * `shutdown` - future that completes when the exporter should stop
+    pub async fn run<F>(&self, provider: TokenProvider, shutdown: F) -> DrlResult<()>
+    where
+        F: Future<Output = ()>,
+    {
+        self.run_all(vec![provider], shutdown).await
+    }
+
+    /// Serves `/metrics` until `shutdown` completes, with the limits of every provider
+    ///
+    /// Each identity is checked on its own schedule, with no more than `concurrency` checks in
+    /// flight between them, and its samples are labelled with its user
+    ///
+    /// # Errors
+    ///
+    /// See `run`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` is zero
+    ///
+    /// # Arguments
+    ///
+    /// * `providers` - `TokenProvider` for each identity to check
+    /// * `shutdown` - future that completes when the exporter should stop
+    pub async fn run_all<F>(&self, providers: Vec<TokenProvider>, shutdown: F) -> DrlResult<()>
+    where
+        F: Future<Output = ()>,
+    {
+        let server = match Server::try_bind(&self.addr) {
+            Ok(s) => s,
+            Err(e) => {
+                let msg = format!("failed to listen on {}: {}", self.addr, e);
+                let err = DrlErr::new(msg, ExitCode::Connection);
+                return Err(err);
+            }
+        };
+        if !self.quiet {
+            let msg = format!("serving metrics on http://{}{}", self.addr, METRICS_PATH);
+            self.log.info(&msg);
+        }
+
+        let scrapes: Vec<Scrape> = providers
+            .iter()
+            .map(|p| Scrape {
+                user: p.user().map(String::from),
+                ..Scrape::default()
+            })
+            .collect();
+        let scrapes = Arc::new(Mutex::new(scrapes));
+        let permits = Arc::new(Semaphore::new(self.concurrency.max(1)));
+        let several = providers.len() > 1;
+
+        let pollers: Vec<_> = providers
+            .into_iter()
+            .enumerate()
+            .map(|(i, provider)| {
+                let scrapes = Arc::clone(&scrapes);
+                let user = provider.user().map(String::from);
+                let who = user.as_deref().unwrap_or("anonymous").to_string();
+                let permits = Some(Arc::clone(&permits));
+                let polls = poll_limits_bounded(provider, self.interval, self.backoff, permits);
+                let mut polls = Box::pin(polls);
+                let (quiet, verbose) = (self.quiet, self.verbose);
+                let log = self.log.clone();
+                let hooks = self.hooks.clone();
+                let mut crossings = hooks.as_ref().map(Hooks::crossings);
+                tokio::spawn(async move {
+                    while let Some((result, backoff)) = polls.next().await {
+                        if let Ok(limit) = &result {
+                            let crossed = crossings.as_mut().and_then(|c| c.check(limit));
+                            if let (Some(hooks), Some(crossing)) = (&hooks, crossed) {
+                                hooks.spawn(crossing, user.as_deref(), limit, &log);
+                            }
+                        }
+                        // a poisoned lock only means a scrape panicked, the numbers are still
+                        // fine
+                        let mut scrapes = scrapes.lock().unwrap_or_else(|e| e.into_inner());
+                        let scrape = &mut scrapes[i];
+                        scrape.backoff = Some(backoff);
+                        scrape.up = result.is_ok();
+                        scrape.error = result.as_ref().err().cloned();
+                        match result {
+                            Ok(limit) => scrape.limit = Some(limit),
+                            Err(e) => {
+                                scrape.errors += 1;
+                                if !quiet && several {
+                                    log.warn(&format!("check of {} failed: {}", who, e));
+                                } else if !quiet {
+                                    log.warn(&format!("check failed: {}", e));
+                                }
+                                if verbose {
+                                    log.info(&backoff.to_string());
+                                }
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        let routes = Routes {
+            scrapes,
+            auth: self.auth.clone(),
+            health_exempt: self.health_exempt,
+            ready_failures: self.ready_failures,
+        };
+        let make_service = make_service_fn(move |_| {
+            let routes = routes.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let resp = routes.respond(&req);
+                    async move { Ok::<_, Infallible>(resp) }
+                }))
+            }
+        });
+
+        let result = server
+            .serve(make_service)
+            .with_graceful_shutdown(shutdown)
+            .await;
+        for poller in pollers {
+            poller.abort();
+        }
+
+        result.map_err(|e| {
+            let msg = format!("exporter failed: {}", e);
+            DrlErr::new(msg, ExitCode::Connection)
+        })
+    }
+}
+
+/// What the exporter answers, for every request
+#[derive(Debug, Clone)]
+struct Routes {
+    /// Result of the checks of every identity
+    scrapes: Arc<Mutex<Vec<Scrape>>>,
+    /// Credentials every request has to send
+    auth: Option<MetricsAuth>,
+    /// Whether `/healthz` and `/readyz` are answered without the credentials
+    health_exempt: bool,
+    /// Failed checks in a row that make an identity no longer ready
+    ready_failures: u32,
+}
+
+/// Readiness of one identity, for `/readyz`
+#[derive(Serialize, Debug)]
+struct Readiness<'a> {
+    /// User the checks are for, `None` for anonymous
+    user: Option<&'a str>,
+    /// Whether a check succeeded, and fewer than `ready_failures` failed since
+    ready: bool,
+    /// Failed checks in a row
+    failures: u32,
+    /// Error of the last check, if it failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a DrlErr>,
+}
+
+impl Routes {
+    /// Answers one request
+    ///
+    /// `GET /metrics` has the metrics, `/healthz` is `200` as long as the exporter runs, and
+    /// `/readyz` is `200` only once every identity is ready, `503` otherwise. Both describe the
+    /// identities in JSON. Requests without the credentials of `auth` get a `401`, whatever they
+    /// ask for, unless they are for `/healthz` or `/readyz` and those are exempt
+    fn respond(&self, req: &Request<Body>) -> Response<Body> {
+        let path = req.uri().path();
+        let health = path == HEALTH_PATH || path == READY_PATH;
+        if let Some(auth) = &self.auth {
+            let exempt = health && self.health_exempt;
+            if !exempt && !auth.allows(req.headers().get(AUTHORIZATION)) {
+                let mut resp = Response::new(Body::empty());
+                *resp.status_mut() = StatusCode::UNAUTHORIZED;
+                resp.headers_mut()
+                    .insert(WWW_AUTHENTICATE, auth.challenge());
+                return resp;
+            }
+        }
+
+        let known = health || path == METRICS_PATH;
+        let status = match req.method() {
+            &Method::GET if known => StatusCode::OK,
+            _ if known => StatusCode::METHOD_NOT_ALLOWED,
+            _ => StatusCode::NOT_FOUND,
+        };
+        if status != StatusCode::OK {
+            let mut resp = Response::new(Body::empty());
+            *resp.status_mut() = status;
+            return resp;
+        }
+
+        // a poisoned lock only means a scrape panicked, the numbers are still fine
+        let scrapes = self.scrapes.lock().unwrap_or_else(|e| e.into_inner());
+        if path == METRICS_PATH {
+            let mut resp = Response::new(Body::from(exposition(&scrapes)));
+            resp.headers_mut().insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("text/plain; version=0.0.4"),
+            );
+            return resp;
+        }
+
+        let checks: Vec<Readiness> = scrapes.iter().map(|s| self.readiness(s)).collect();
+        let ready = path == HEALTH_PATH || checks.iter().all(|c| c.ready);
+        let body = serde_json::json!({ "ready": ready, "checks": checks });
+
+        let mut resp = Response::new(Body::from(body.to_string()));
+        if !ready {
+            *resp.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+        }
+        resp.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        resp
+    }
+
+    /// Readiness of the identity of `scrape`
+    fn readiness<'a>(&self, scrape: &'a Scrape) -> Readiness<'a> {
+        let failures = scrape.backoff.map(|b| b.failures()).unwrap_or_default();
+        Readiness {
+            user: scrape.user.as_deref(),
+            ready: scrape.limit.is_some() && failures < self.ready_failures.max(1),
+            failures,
+            error: scrape.error.as_ref(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_gauges() {
+        let interval = Duration::from_secs(60);
+        let mut backoff = Backoff::new(interval, BackoffPolicy::default());
+        for _ in 0..4 {
+            backoff.record(true);
+        }
+        let scrape = Scrape {
+            errors: 4,
+            backoff: Some(backoff),
+            ..Scrape::default()
+        };
+
+        let out = exposition(&[scrape]);
+        let user = "{user=\"anonymous\"}";
+        assert!(out.contains(&format!(
+            "dockerhub_ratelimit_check_errors_total{} 4\n",
+            user
+        )));
+        assert!(out.contains(&format!("dockerhub_ratelimit_up{} 0\n", user)));
+        let failures = "dockerhub_ratelimit_check_consecutive_failures";
+        assert!(out.contains(&format!("{}{} 4\n", failures, user)));
+        let interval = "dockerhub_ratelimit_check_interval_seconds";
+        assert!(out.contains(&format!("{}{} 240\n", interval, user)));
+        assert!(!out.contains("dockerhub_ratelimit_remaining"));
+    }
+
+    /// Routes of an exporter without any checks yet, wanting `auth` if given
+    fn routes(auth: Option<&str>) -> Routes {
+        Routes {
+            scrapes: Arc::new(Mutex::new(vec![Scrape::default()])),
+            auth: auth.map(|a| a.parse().unwrap()),
+            health_exempt: false,
+            ready_failures: DEFAULT_READY_FAILURES,
+        }
+    }
+
+    /// Response of `routes` to `GET path`
+    fn get(routes: &Routes, path: &str) -> Response<Body> {
+        let req = Request::get(path).body(Body::empty()).unwrap();
+        routes.respond(&req)
+    }
+
+    /// Body of `resp` as JSON
+    async fn json(resp: Response<Body>) -> serde_json::Value {
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    /// Records a check of the only identity of `routes`, `None` for a failed one
+    fn check(routes: &Routes, limit: Option<Limit>) {
+        let mut scrapes = routes.scrapes.lock().unwrap();
+        let scrape = &mut scrapes[0];
+        let interval = Duration::from_secs(60);
+        let mut backoff = scrape
+            .backoff
+            .unwrap_or_else(|| Backoff::new(interval, BackoffPolicy::default()));
+        backoff.record(limit.is_none());
+        scrape.backoff = Some(backoff);
+        scrape.up = limit.is_some();
+        match limit {
+            Some(limit) => {
+                scrape.limit = Some(limit);
+                scrape.error = None;
+            }
+            None => {
+                let err = DrlErr::new(String::from("registry down"), ExitCode::Connection);
+                scrape.error = Some(err);
+            }
+        }
+    }
+
+    fn limit() -> Limit {
+        Limit {
+            remaining: 42,
+            total: 100,
+            window: Duration::from_secs(21600),
+            source: None,
+        }
+    }
+
+    /// Response to `GET /metrics` with `authorization`, for an exporter that wants `auth`
+    fn scrape(auth: &str, authorization: Option<&str>) -> Response<Body> {
+        let mut req = Request::get(METRICS_PATH);
+        if let Some(value) = authorization {
+            req = req.header(AUTHORIZATION, value);
+        }
+        let req = req.body(Body::empty()).unwrap();
+        routes(Some(auth)).respond(&req)
+    }
+
+    #[test]
+    fn bearer_auth() {
+        let resp = scrape("bearer:s3cret", None);
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let challenge = resp.headers().get(WWW_AUTHENTICATE).unwrap();
+        assert_eq!(challenge, "Bearer realm=\"docker-rl\"");
+
+        for wrong in ["Bearer s3cre", "Bearer s3cret!", "Basic s3cret", "s3cret"] {
+            let resp = scrape("bearer:s3cret", Some(wrong));
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED, "{}", wrong);
+        }
+
+        let resp = scrape("bearer:s3cret", Some("Bearer s3cret"));
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get(WWW_AUTHENTICATE).is_none());
+    }
+
+    #[test]
+    fn basic_auth() {
+        let resp = scrape("basic:prom:s3cret", None);
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let challenge = resp.headers().get(WWW_AUTHENTICATE).unwrap();
+        assert_eq!(challenge, "Basic realm=\"docker-rl\"");
+
+        let wrong = [
+            format!("Basic {}", base64::encode("prom:wrong")),
+            format!("Basic {}", base64::encode("other:s3cret")),
+            String::from("Basic not base64"),
+            String::from("Bearer s3cret"),
+        ];
+        for wrong in &wrong {
+            let resp = scrape("basic:prom:s3cret", Some(wrong));
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED, "{}", wrong);
+        }
+
+        let right = format!("basic {}", base64::encode("prom:s3cret"));
+        let resp = scrape("basic:prom:s3cret", Some(&right));
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn unknown_paths_need_auth_too() {
+        let routes = routes(Some("bearer:s3cret"));
+        for path in ["/other", HEALTH_PATH, READY_PATH] {
+            let resp = get(&routes, path);
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED, "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn healthz_while_running() {
+        let routes = routes(None);
+        let resp = get(&routes, HEALTH_PATH);
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[CONTENT_TYPE], "application/json");
+        assert_eq!(json(resp).await["ready"], true);
+
+        // failing checks don't make it unhealthy, restarting wouldn't fix the registry
+        for _ in 0..10 {
+            check(&routes, None);
+        }
+        assert_eq!(get(&routes, HEALTH_PATH).status(), StatusCode::OK);
+
+        let req = Request::post(HEALTH_PATH).body(Body::empty()).unwrap();
+        let resp = routes.respond(&req);
+        assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[tokio::test]
+    async fn readyz_after_the_first_check() {
+        let routes = routes(None);
+        let resp = get(&routes, READY_PATH);
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = json(resp).await;
+        assert_eq!(body["ready"], false);
+        assert_eq!(body["checks"][0]["failures"], 0);
+        assert!(body["checks"][0].get("error").is_none());
+
+        check(&routes, None);
+        let body = json(get(&routes, READY_PATH)).await;
+        let error = &body["checks"][0]["error"];
+        assert_eq!(error["message"], "registry down");
+        assert_eq!(error["exit_code"], ExitCode::Connection as i32);
+
+        check(&routes, Some(limit()));
+        let resp = get(&routes, READY_PATH);
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = json(resp).await;
+        assert_eq!(body["ready"], true);
+        assert_eq!(body["checks"][0]["user"], serde_json::Value::Null);
+        assert!(body["checks"][0].get("error").is_none());
+    }
+
+    #[tokio::test]
+    async fn readyz_fails_again_after_failures_in_a_row() {
+        let routes = routes(None);
+        check(&routes, Some(limit()));
+        for _ in 1..DEFAULT_READY_FAILURES {
+            check(&routes, None);
+            assert_eq!(get(&routes, READY_PATH).status(), StatusCode::OK);
+        }
+
+        check(&routes, None);
+        let resp = get(&routes, READY_PATH);
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = json(resp).await;
+        assert_eq!(body["checks"][0]["failures"], DEFAULT_READY_FAILURES);
+        assert_eq!(body["checks"][0]["error"]["message"], "registry down");
+
+        check(&routes, Some(limit()));
+        assert_eq!(get(&routes, READY_PATH).status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn readyz_needs_every_identity() {
+        let routes = routes(None);
+        routes.scrapes.lock().unwrap().push(Scrape {
+            user: Some(String::from("ci-bot")),
+            ..Scrape::default()
+        });
+        check(&routes, Some(limit()));
+        assert_eq!(
+            get(&routes, READY_PATH).status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+
+    #[test]
+    fn health_can_be_exempt_from_auth() {
+        let mut routes = routes(Some("bearer:s3cret"));
+        routes.health_exempt = true;
+        check(&routes, Some(limit()));
+        assert_eq!(get(&routes, HEALTH_PATH).status(), StatusCode::OK);
+        assert_eq!(get(&routes, READY_PATH).status(), StatusCode::OK);
+        assert_eq!(
+            get(&routes, METRICS_PATH).status(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(get(&routes, "/other").status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn parses_metrics_auth() {
+        let bearer: MetricsAuth = "bearer:abc:def".parse().unwrap();
+        assert_eq!(bearer, MetricsAuth::Bearer("abc:def".into()));
+        assert_eq!(bearer.redacted(), "bearer:****");
+        assert_eq!(format!("{:?}", bearer), "Bearer(\"****\")");
+
+        let basic: MetricsAuth = "basic:prom:p:w".parse().unwrap();
+        assert_eq!(basic.redacted(), "basic:prom:****");
+        assert!(!format!("{:?}", basic).contains("p:w"));
+
+        for invalid in ["bearer:", "basic:nopass", "basic::pass", "token:abc", "abc"] {
+            assert!(invalid.parse::<MetricsAuth>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn compares_whole_secrets() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secre", b"secret"));
+        assert!(!constant_time_eq(b"secret\0", b"secret"));
+        assert!(!constant_time_eq(b"", b"secret"));
+        assert!(!constant_time_eq(b"Secret", b"secret"));
+    }
+
+    #[test]
+    fn no_backoff_gauges_before_the_first_check() {
+        let out = exposition(&[Scrape::default()]);
+        assert!(!out.contains("consecutive_failures"));
+    }
+}
@@ -0,0 +1,65 @@
+//! Effective settings and where they came from, for `docker-rl config`
+
+use serde::Serialize;
+use std::fmt;
+
+/// Shown in place of secrets
+pub const REDACTED: &str = "****";
+
+/// Where the value of a setting came from
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    /// Passed on the command line
+    Cli,
+    /// Read from an environment variable
+    Env,
+    /// Read from the profile of the config file
+    Config,
+    /// Nothing was passed, the default applies
+    Default,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Source::Cli => "cli",
+            Source::Env => "env",
+            Source::Config => "config",
+            Source::Default => "default",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// One setting with its final value
+#[derive(Serialize, Debug, Clone)]
+pub struct Setting {
+    /// Name of the option, e.g. `verify-delay`
+    pub name: &'static str,
+    /// Final value, `None` if unset. Secrets are `REDACTED`
+    pub value: Option<String>,
+    /// Where `value` came from
+    pub source: Source,
+}
+
+impl fmt::Display for Setting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self.value.as_deref().unwrap_or("-");
+        write!(f, "{} = {} ({})", self.name, value, self.source)
+    }
+}
+
+/// Every setting
+#[derive(Serialize, Debug, Clone)]
+pub struct Settings {
+    /// The settings
+    pub settings: Vec<Setting>,
+}
+
+impl fmt::Display for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines: Vec<String> = self.settings.iter().map(|s| s.to_string()).collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
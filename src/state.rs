@@ -0,0 +1,296 @@
+//! The last successful check, kept so `--cached` can show it without touching the network
+//!
+//! It lives in `$XDG_CACHE_HOME/docker-rl/last.json`, or `~/.cache/docker-rl/last.json`, along
+//! with the earlier samples of the window that `burn` estimates the burn rate from
+
+use super::duration::{format_age, format_duration};
+use super::err::{DrlErr, DrlResult, ExitCode};
+use super::human;
+use super::limit::Limit;
+use super::timestamp::{self, Timestamp, Timestamps};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Directory of the state file, inside the cache directory
+const DIR: &str = "docker-rl";
+
+/// Name of the state file
+const FILE: &str = "last.json";
+
+/// Most earlier samples kept in the state file
+const MAX_SAMPLES: usize = 100;
+
+/// How long samples are kept when the registry didn't say how long its window is
+const DEFAULT_WINDOW: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Remaining count of an earlier check
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    /// Number of remaining requests at the time
+    pub remaining: u64,
+    /// When the check was made, in seconds since the epoch on the local clock
+    pub at: u64,
+}
+
+/// Result of a check, as stored in the state file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct State {
+    /// User the limit was checked for, `None` for anonymous
+    pub user: Option<String>,
+    /// The limit
+    #[serde(flatten)]
+    pub limit: Limit,
+    /// When the check was made, RFC 3339 in UTC, any `timestamp` format is read
+    pub checked_at: String,
+    /// Earlier checks within the window, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub samples: Vec<Sample>,
+}
+
+impl State {
+    /// Result of a check made just now
+    ///
+    /// # Arguments
+    ///
+    /// * `user` - user the limit was checked for, `None` for anonymous
+    /// * `limit` - `Limit` from the check
+    pub fn new(user: Option<String>, limit: Limit) -> State {
+        State {
+            user,
+            limit,
+            checked_at: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            samples: Vec::new(),
+        }
+    }
+
+    /// Result of a check made just now, keeping the samples of `previous` that are still in
+    /// the window
+    ///
+    /// The samples are dropped if `previous` is for another user or limit
+    ///
+    /// # Arguments
+    ///
+    /// * `previous` - `State` of the check before, if any
+    /// * `user` - user the limit was checked for, `None` for anonymous
+    /// * `limit` - `Limit` from the check
+    pub fn after(previous: Option<State>, user: Option<String>, limit: Limit) -> State {
+        let mut state = State::new(user, limit);
+        let previous = match previous {
+            Some(p) if p.user == state.user && p.limit.total == state.limit.total => p,
+            _ => return state,
+        };
+
+        let window = if state.limit.window.is_zero() {
+            DEFAULT_WINDOW
+        } else {
+            state.limit.window
+        };
+        let now = SystemTime::now();
+        let oldest = epoch_secs(now.checked_sub(window).unwrap_or(UNIX_EPOCH));
+
+        state.samples = previous.samples;
+        if let Some(at) = timestamp::parse(&previous.checked_at) {
+            state.samples.push(Sample {
+                remaining: previous.limit.remaining,
+                at: epoch_secs(at),
+            });
+        }
+        state.samples.retain(|s| s.at >= oldest);
+        let excess = state.samples.len().saturating_sub(MAX_SAMPLES);
+        state.samples.drain(..excess);
+        state
+    }
+
+    /// Sample of this check
+    pub fn sample(&self) -> Sample {
+        // checked by `load`, and always valid for a new state
+        let at = timestamp::parse(&self.checked_at).unwrap_or_else(SystemTime::now);
+        Sample {
+            remaining: self.limit.remaining,
+            at: epoch_secs(at),
+        }
+    }
+}
+
+/// Seconds since the epoch of `time`
+fn epoch_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Directory `docker-rl` keeps its files in, `None` if there is no cache directory
+pub fn cache_dir() -> Option<PathBuf> {
+    // relative paths are invalid by the XDG spec
+    let cache = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".cache")))?;
+    Some(cache.join(DIR))
+}
+
+/// Path of the state file, `None` if there is no cache directory to put it in
+pub fn path() -> Option<PathBuf> {
+    Some(cache_dir()?.join(FILE))
+}
+
+/// Writes `state` to the state file, replacing the previous one
+///
+/// # Errors
+///
+/// Returns `ExitCode::Input` if there is no cache directory or the file can't be written
+///
+/// # Arguments
+///
+/// * `state` - `State` to store
+pub fn save(state: &State) -> DrlResult<()> {
+    let path = match path() {
+        Some(p) => p,
+        None => {
+            let msg = String::from("no cache directory, set XDG_CACHE_HOME or HOME");
+            let err = DrlErr::new(msg, ExitCode::Input);
+            return Err(err);
+        }
+    };
+
+    // written next to the file and renamed, so a concurrent reader never sees half of it
+    let tmp = path.with_extension("json.tmp");
+    let json = serde_json::to_vec_pretty(state).expect("failed to serialize state");
+    let written = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&tmp, json))
+        .and_then(|_| fs::rename(&tmp, &path));
+
+    written.map_err(|e| {
+        let msg = format!("failed to write {}: {}", path.display(), e);
+        DrlErr::new(msg, ExitCode::Input)
+    })
+}
+
+/// Reads the state file
+///
+/// # Errors
+///
+/// Returns `ExitCode::Stale` if there is no state file, or it can't be read
+pub fn load() -> DrlResult<State> {
+    let path = match path() {
+        Some(p) => p,
+        None => {
+            let msg = String::from("no cached result, there is no cache directory");
+            let err = DrlErr::new(msg, ExitCode::Stale);
+            return Err(err);
+        }
+    };
+
+    let json = match fs::read(&path) {
+        Ok(j) => j,
+        Err(_) => {
+            let msg = format!("no cached result in {}", path.display());
+            let err = DrlErr::new(msg, ExitCode::Stale);
+            return Err(err);
+        }
+    };
+
+    let state: State = match serde_json::from_slice(&json) {
+        Ok(s) => s,
+        Err(e) => {
+            let msg = format!("unreadable cached result in {}: {}", path.display(), e);
+            let err = DrlErr::new(msg, ExitCode::Stale);
+            return Err(err);
+        }
+    };
+
+    if timestamp::parse(&state.checked_at).is_none() {
+        let msg = format!("unreadable cached result in {}: bad time", path.display());
+        let err = DrlErr::new(msg, ExitCode::Stale);
+        return Err(err);
+    }
+
+    Ok(state)
+}
+
+/// A stored result along with its age, for `--cached`
+#[derive(Serialize, Debug, Clone)]
+pub struct Cached {
+    /// User the limit was checked for, `None` for anonymous
+    pub user: Option<String>,
+    /// The limit
+    #[serde(flatten)]
+    pub limit: Limit,
+    /// When the check was made
+    pub checked_at: Timestamp,
+    /// Seconds since the check was made
+    pub age_secs: u64,
+}
+
+impl Cached {
+    /// The stored result, aged as of `now`
+    ///
+    /// # Arguments
+    ///
+    /// * `state` - `State` read from the state file
+    /// * `now` - current time on the local clock
+    /// * `timestamps` - how to write when the check was made
+    pub fn new(state: State, now: SystemTime, timestamps: &Timestamps) -> Cached {
+        // checked by `load`
+        let checked_at = timestamp::parse(&state.checked_at).unwrap_or(now);
+        // a check from the future is taken to be fresh
+        let age = now.duration_since(checked_at).unwrap_or_default();
+
+        Cached {
+            user: state.user,
+            limit: state.limit,
+            checked_at: timestamps.render(checked_at),
+            age_secs: age.as_secs(),
+        }
+    }
+
+    /// Time since the check was made
+    pub fn age(&self) -> Duration {
+        Duration::from_secs(self.age_secs)
+    }
+
+    /// Fails if the result is older than `max_age`
+    ///
+    /// # Errors
+    ///
+    /// Returns `ExitCode::Stale` if the result is too old
+    ///
+    /// # Arguments
+    ///
+    /// * `max_age` - oldest result to accept, `None` for any
+    pub fn check(&self, max_age: Option<Duration>) -> DrlResult<()> {
+        match max_age {
+            Some(max_age) if self.age() > max_age => {
+                let msg = format!(
+                    "cached result is {} old, more than {}",
+                    format_age(self.age()),
+                    format_duration(max_age)
+                );
+                let err = DrlErr::new(msg, ExitCode::Stale);
+                Err(err)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Formats the result with `--human` counts, e.g. `49,987/50,000 (100%) (as of 12m ago)`
+    pub fn human(&self) -> String {
+        format!(
+            "{} (as of {} ago)",
+            human::limit(&self.limit),
+            format_age(self.age())
+        )
+    }
+}
+
+impl fmt::Display for Cached {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (as of {} ago)", self.limit, format_age(self.age()))
+    }
+}
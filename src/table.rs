@@ -0,0 +1,270 @@
+//! Plain text tables with aligned columns
+
+use std::fmt::Write;
+
+/// Characters used to draw the table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    /// `+`, `-`, and `|` only
+    Ascii,
+    /// Unicode box drawing characters
+    Unicode,
+}
+
+/// Pieces of a horizontal rule: left corner, line, junction, right corner
+type Rule = [char; 4];
+
+impl Style {
+    /// Vertical separator between cells
+    fn bar(self) -> char {
+        match self {
+            Style::Ascii => '|',
+            Style::Unicode => '│',
+        }
+    }
+
+    /// Rules for the top, below the header, and the bottom of the table
+    fn rules(self) -> (Rule, Rule, Rule) {
+        match self {
+            Style::Ascii => (
+                ['+', '-', '+', '+'],
+                ['+', '-', '+', '+'],
+                ['+', '-', '+', '+'],
+            ),
+            Style::Unicode => (
+                ['┌', '─', '┬', '┐'],
+                ['├', '─', '┼', '┤'],
+                ['└', '─', '┴', '┘'],
+            ),
+        }
+    }
+
+    /// Marker for truncated cells
+    fn ellipsis(self) -> &'static str {
+        match self {
+            Style::Ascii => "...",
+            Style::Unicode => "…",
+        }
+    }
+}
+
+/// Horizontal alignment of a column
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Right,
+}
+
+/// Header of a column
+#[derive(Debug, Clone)]
+struct Column {
+    name: String,
+    align: Align,
+    /// Cells longer than this are truncated, unless rendering wide
+    max: Option<usize>,
+}
+
+/// A table of text cells
+#[derive(Debug, Clone, Default)]
+pub struct Table {
+    columns: Vec<Column>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    /// Creates an empty table
+    pub fn new() -> Table {
+        Table::default()
+    }
+
+    /// Adds a column with header `name`
+    pub fn column(mut self, name: &str, align: Align) -> Table {
+        self.columns.push(Column {
+            name: name.into(),
+            align,
+            max: None,
+        });
+        self
+    }
+
+    /// Adds a column with header `name`, truncating cells longer than `max` characters
+    pub fn truncated_column(mut self, name: &str, align: Align, max: usize) -> Table {
+        self.columns.push(Column {
+            name: name.into(),
+            align,
+            max: Some(max),
+        });
+        self
+    }
+
+    /// Adds a row, missing cells are left blank and extra cells are dropped
+    pub fn row(&mut self, mut cells: Vec<String>) {
+        cells.resize(self.columns.len(), String::new());
+        self.rows.push(cells);
+    }
+
+    /// Width of each column, in characters
+    fn widths(rows: &[Vec<String>]) -> Vec<usize> {
+        let mut widths = Vec::new();
+        for row in rows {
+            widths.resize(row.len(), 0);
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+        widths
+    }
+
+    /// Renders the table, with a trailing newline
+    ///
+    /// # Arguments
+    ///
+    /// * `style` - `Style` of the borders
+    /// * `wide` - don't truncate long cells
+    pub fn render(&self, style: Style, wide: bool) -> String {
+        let headers: Vec<String> = self.columns.iter().map(|c| c.name.clone()).collect();
+        let rows: Vec<Vec<String>> = self
+            .rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .zip(&self.columns)
+                    .map(|(cell, column)| match column.max {
+                        Some(max) if !wide => truncate(cell, max, style.ellipsis()),
+                        _ => cell.clone(),
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let mut all = vec![headers];
+        all.extend(rows);
+        let widths = Table::widths(&all);
+        let (top, middle, bottom) = style.rules();
+
+        let mut out = String::new();
+        push_rule(&mut out, &widths, top);
+        for (i, row) in all.iter().enumerate() {
+            self.push_row(&mut out, &widths, row, style, i == 0);
+            if i == 0 {
+                push_rule(&mut out, &widths, middle);
+            }
+        }
+        push_rule(&mut out, &widths, bottom);
+        out
+    }
+
+    /// Appends one line of cells to `out`
+    ///
+    /// Headers are always left aligned
+    fn push_row(
+        &self,
+        out: &mut String,
+        widths: &[usize],
+        cells: &[String],
+        style: Style,
+        header: bool,
+    ) {
+        let bar = style.bar();
+        out.push(bar);
+        for ((cell, width), column) in cells.iter().zip(widths).zip(&self.columns) {
+            // writing to a String can't fail
+            let _ = match column.align {
+                Align::Right if !header => write!(out, " {:>w$} {}", cell, bar, w = *width),
+                _ => write!(out, " {:<w$} {}", cell, bar, w = *width),
+            };
+        }
+        out.push('\n');
+    }
+}
+
+/// Appends a horizontal rule to `out`
+fn push_rule(out: &mut String, widths: &[usize], rule: Rule) {
+    let [left, line, junction, right] = rule;
+    out.push(left);
+    for (i, width) in widths.iter().enumerate() {
+        if i > 0 {
+            out.push(junction);
+        }
+        out.extend(std::iter::repeat_n(line, width + 2));
+    }
+    out.push(right);
+    out.push('\n');
+}
+
+/// Shortens `cell` to `max` characters, ending it with `ellipsis` if anything was cut
+fn truncate(cell: &str, max: usize, ellipsis: &str) -> String {
+    if cell.chars().count() <= max {
+        return cell.to_string();
+    }
+
+    let keep = max.saturating_sub(ellipsis.chars().count());
+    let mut short: String = cell.chars().take(keep).collect();
+    short.push_str(ellipsis);
+    short
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> Table {
+        let mut table = Table::new()
+            .column("identity", Align::Left)
+            .column("remaining", Align::Right)
+            .truncated_column("source", Align::Left, 8);
+        table.row(vec!["alice".into(), "42".into(), "203.0.113.7".into()]);
+        table.row(vec!["bob".into(), "7".into()]);
+        table
+    }
+
+    #[test]
+    fn ascii() {
+        let expected = "\
++----------+-----------+----------+
+| identity | remaining | source   |
++----------+-----------+----------+
+| alice    |        42 | 203.0... |
+| bob      |         7 |          |
++----------+-----------+----------+
+";
+        assert_eq!(table().render(Style::Ascii, false), expected);
+    }
+
+    #[test]
+    fn unicode() {
+        let expected = "\
+┌──────────┬───────────┬──────────┐
+│ identity │ remaining │ source   │
+├──────────┼───────────┼──────────┤
+│ alice    │        42 │ 203.0.1… │
+│ bob      │         7 │          │
+└──────────┴───────────┴──────────┘
+";
+        assert_eq!(table().render(Style::Unicode, false), expected);
+    }
+
+    #[test]
+    fn wide() {
+        let rendered = table().render(Style::Ascii, true);
+        assert!(rendered.contains("| 203.0.113.7 |"), "{}", rendered);
+    }
+
+    #[test]
+    fn extra_cells_dropped() {
+        let mut table = Table::new().column("a", Align::Left);
+        table.row(vec!["x".into(), "y".into()]);
+        assert_eq!(
+            table.render(Style::Ascii, false),
+            "+---+\n| a |\n+---+\n| x |\n+---+\n"
+        );
+    }
+
+    #[test]
+    fn truncated() {
+        assert_eq!(truncate("short", 8, "..."), "short");
+        assert_eq!(truncate("exactly8", 8, "..."), "exactly8");
+        assert_eq!(truncate("ünïcödé!!", 8, "…"), "ünïcödé…");
+        assert_eq!(truncate("anything", 2, "..."), "...");
+    }
+}
@@ -0,0 +1,162 @@
+//! Output templates for `--format`, like docker's, e.g. `{{.remaining}}/{{.total}} ({{.percent}}%)`
+//!
+//! Fields are looked up in the JSON output, so every field of it can be used, along with
+//! `percent` and `window` worked out from the others. Nested fields are `{{.a.b}}`, and `{{.}}`
+//! is the whole value as JSON. Missing fields are written as `<no value>`
+
+use super::duration::format_duration;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Written for fields the value doesn't have
+pub const NO_VALUE: &str = "<no value>";
+
+/// The installed template, if any
+static TEMPLATE: OnceLock<Template> = OnceLock::new();
+
+/// One piece of a template
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    /// Text written as it is
+    Text(String),
+    /// Path of the field to write, empty for the whole value
+    Field(Vec<String>),
+}
+
+/// A parsed output template
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    /// The template as it was given
+    source: String,
+    parts: Vec<Part>,
+}
+
+/// Whether `s` is meant as a template rather than the name of a format
+///
+/// # Arguments
+///
+/// * `s` - value of `--format`
+pub fn is_template(s: &str) -> bool {
+    s.contains("{{")
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].into()));
+            }
+            let after = &rest[start + 2..];
+            let end = match after.find("}}") {
+                Some(e) => e,
+                None => return Err(format!("unclosed {{{{ in template: {}", s)),
+            };
+
+            let action = after[..end].trim();
+            let path = match action.strip_prefix('.') {
+                Some("") => Vec::new(),
+                Some(path) => path.split('.').map(String::from).collect(),
+                None => {
+                    return Err(format!(
+                        "expected a field like {{{{.remaining}}}}: {{{{{}}}}}",
+                        action
+                    ))
+                }
+            };
+            if path
+                .iter()
+                .any(|p| p.is_empty() || p.contains(char::is_whitespace))
+            {
+                return Err(format!("invalid field in template: {{{{{}}}}}", action));
+            }
+            parts.push(Part::Field(path));
+            rest = &after[end + 2..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.into()));
+        }
+
+        Ok(Template {
+            source: s.into(),
+            parts,
+        })
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl Template {
+    /// Writes `value` with the template, from its JSON along with the derived fields
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - value to write
+    pub fn render<T: Serialize + ?Sized>(&self, value: &T) -> String {
+        // only derived impls get here, which can't fail
+        let mut value = serde_json::to_value(value).expect("failed to serialize output");
+        if let Value::Object(map) = &mut value {
+            derive(map);
+        }
+
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => out.push_str(text),
+                Part::Field(path) => {
+                    let field = path.iter().try_fold(&value, |v, key| v.get(key));
+                    match field {
+                        Some(Value::String(s)) => out.push_str(s),
+                        Some(Value::Null) => (),
+                        Some(v) => out.push_str(&v.to_string()),
+                        None => out.push_str(NO_VALUE),
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    /// Makes this the template every output uses
+    ///
+    /// Can only be done once, returns `false` if a template was already installed
+    pub fn install(self) -> bool {
+        TEMPLATE.set(self).is_ok()
+    }
+}
+
+/// The installed `Template`, `None` if there is none
+pub fn installed() -> Option<&'static Template> {
+    TEMPLATE.get()
+}
+
+/// Adds `percent` and `window` to values with a limit, unless they have them already
+fn derive(map: &mut Map<String, Value>) {
+    let count = |key: &str| map.get(key).and_then(Value::as_u64);
+    let (remaining, total, window) = (count("remaining"), count("total"), count("window_seconds"));
+
+    if let (Some(remaining), Some(total)) = (remaining, total) {
+        if total > 0 && !map.contains_key("percent") {
+            let percent = (remaining as f64 * 100.0 / total as f64).round() as u64;
+            map.insert("percent".into(), Value::from(percent));
+        }
+    }
+    if let Some(secs) = window {
+        if !map.contains_key("window") {
+            let window = format_duration(Duration::from_secs(secs));
+            map.insert("window".into(), Value::String(window));
+        }
+    }
+}
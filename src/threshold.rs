@@ -0,0 +1,51 @@
+//! Thresholds the remaining limit has to stay above
+
+use super::err::{DrlErr, DrlResult, ExitCode};
+use super::limit::Limit;
+
+/// Minimum remaining limit, as a count and/or a percentage
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Threshold {
+    /// Fail when fewer than this many requests remain
+    pub below: Option<u64>,
+    /// Fail when less than this percentage of the limit remains
+    pub below_percent: Option<f64>,
+}
+
+impl Threshold {
+    /// Whether any threshold is set
+    pub fn is_set(&self) -> bool {
+        self.below.is_some() || self.below_percent.is_some()
+    }
+
+    /// Checks `limit` against the thresholds
+    ///
+    /// # Errors
+    ///
+    /// Returns `ExitCode::BelowThreshold` if the limit is below either threshold
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - `Limit` to check
+    pub fn check(&self, limit: &Limit) -> DrlResult<()> {
+        if let Some(below) = self.below {
+            if limit.remaining < below {
+                let msg = format!("{} remaining is below {}", limit.remaining, below);
+                let err = DrlErr::new(msg, ExitCode::BelowThreshold);
+                return Err(err);
+            }
+        }
+
+        if let Some(below_percent) = self.below_percent {
+            // a limit with a total of 0 has nothing left
+            let percent = limit.percent().unwrap_or(0.0);
+            if percent < below_percent {
+                let msg = format!("{:.0}% remaining is below {}%", percent, below_percent);
+                let err = DrlErr::new(msg, ExitCode::BelowThreshold);
+                return Err(err);
+            }
+        }
+
+        Ok(())
+    }
+}
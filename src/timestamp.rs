@@ -0,0 +1,322 @@
+//! Timestamps in the output, written the same way everywhere
+//!
+//! Every format is read back by `parse`, whatever `--timestamp-format` was when it was written
+
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Smallest number `parse` takes as milliseconds rather than seconds, 1973 or the year 5138
+const MIN_UNIX_MS: u64 = 100_000_000_000;
+
+/// How timestamps are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimestampFormat {
+    /// RFC 3339, e.g. `2021-08-06T17:04:05Z`
+    #[default]
+    Rfc3339,
+    /// Seconds since the Unix epoch
+    Unix,
+    /// Milliseconds since the Unix epoch
+    UnixMs,
+}
+
+impl TimestampFormat {
+    /// Names accepted on the command line
+    pub const VARIANTS: &'static [&'static str] = &["rfc3339", "unix", "unix-ms"];
+}
+
+impl FromStr for TimestampFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rfc3339" => Ok(TimestampFormat::Rfc3339),
+            "unix" => Ok(TimestampFormat::Unix),
+            "unix-ms" => Ok(TimestampFormat::UnixMs),
+            _ => Err(format!("unknown timestamp format: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for TimestampFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TimestampFormat::Rfc3339 => "rfc3339",
+            TimestampFormat::Unix => "unix",
+            TimestampFormat::UnixMs => "unix-ms",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A written timestamp, a string for RFC 3339 and a number otherwise
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Timestamp {
+    /// RFC 3339
+    Text(String),
+    /// Seconds or milliseconds since the Unix epoch
+    Number(u64),
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Timestamp::Text(t) => write!(f, "{}", t),
+            Timestamp::Number(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+/// How to write timestamps, from `--timestamp-format` and `--utc`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Timestamps {
+    /// Format to write them in
+    pub format: TimestampFormat,
+    /// Whether RFC 3339 timestamps are in UTC rather than the local time zone
+    pub utc: bool,
+}
+
+impl Timestamps {
+    /// Writes `t` in the configured format
+    ///
+    /// Times before the Unix epoch are written as the epoch
+    ///
+    /// # Arguments
+    ///
+    /// * `t` - time to write
+    pub fn render(&self, t: SystemTime) -> Timestamp {
+        let since_epoch = t.duration_since(UNIX_EPOCH).unwrap_or_default();
+        match self.format {
+            TimestampFormat::Rfc3339 => {
+                let offset = if self.utc { 0 } else { local_offset(t) };
+                Timestamp::Text(rfc3339(UNIX_EPOCH + since_epoch, offset))
+            }
+            TimestampFormat::Unix => Timestamp::Number(since_epoch.as_secs()),
+            TimestampFormat::UnixMs => Timestamp::Number(since_epoch.as_millis() as u64),
+        }
+    }
+}
+
+/// Writes `t` as RFC 3339 with a UTC offset of `offset` seconds, `Z` if there is none
+fn rfc3339(t: SystemTime, offset: i64) -> String {
+    let shifted = if offset >= 0 {
+        t + Duration::from_secs(offset as u64)
+    } else {
+        t.checked_sub(Duration::from_secs(offset.unsigned_abs()))
+            .unwrap_or(UNIX_EPOCH)
+    };
+    let utc = humantime::format_rfc3339_seconds(shifted).to_string();
+    if offset == 0 {
+        return utc;
+    }
+
+    let sign = if offset < 0 { '-' } else { '+' };
+    let minutes = offset.unsigned_abs() / 60;
+    format!(
+        "{}{}{:02}:{:02}",
+        utc.trim_end_matches('Z'),
+        sign,
+        minutes / 60,
+        minutes % 60
+    )
+}
+
+/// Offset of the local time zone from UTC at `t`, in seconds
+#[cfg(unix)]
+fn local_offset(t: SystemTime) -> i64 {
+    let secs = match t.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as libc::time_t,
+        Err(_) => return 0,
+    };
+
+    // localtime_r is the thread safe one, and only writes to `tm`
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let converted = unsafe { libc::localtime_r(&secs, &mut tm) };
+    if converted.is_null() {
+        return 0;
+    }
+    tm.tm_gmtoff as i64
+}
+
+/// Offset of the local time zone from UTC at `t`, always UTC where it can't be looked up
+#[cfg(not(unix))]
+fn local_offset(_t: SystemTime) -> i64 {
+    0
+}
+
+/// Reads a timestamp in any of the `TimestampFormat`s
+///
+/// Bare numbers from `MIN_UNIX_MS` up are milliseconds, smaller ones are seconds. RFC 3339
+/// timestamps may have any UTC offset. Returns `None` if `s` is neither
+///
+/// # Arguments
+///
+/// * `s` - timestamp to read
+pub fn parse(s: &str) -> Option<SystemTime> {
+    let s = s.trim();
+
+    if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+        let n: u64 = s.parse().ok()?;
+        let since_epoch = if n >= MIN_UNIX_MS {
+            Duration::from_millis(n)
+        } else {
+            Duration::from_secs(n)
+        };
+        return UNIX_EPOCH.checked_add(since_epoch);
+    }
+
+    // humantime only reads UTC, so the offset is split off and applied here
+    let (time, offset) = split_offset(s)?;
+    let utc = humantime::parse_rfc3339_weak(time).ok()?;
+    if offset >= 0 {
+        utc.checked_sub(Duration::from_secs(offset as u64))
+    } else {
+        utc.checked_add(Duration::from_secs(offset.unsigned_abs()))
+    }
+}
+
+/// Splits the UTC offset off an RFC 3339 timestamp, in seconds
+fn split_offset(s: &str) -> Option<(&str, i64)> {
+    if let Some(time) = s.strip_suffix('Z').or_else(|| s.strip_suffix('z')) {
+        return Some((time, 0));
+    }
+
+    // `+hh:mm` or `-hh:mm`, past the date's own dashes
+    let at = s.len().checked_sub(6)?;
+    let (time, offset) = (s.get(..at)?, s.get(at..)?);
+    let sign = match offset.as_bytes()[0] {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+    if offset.as_bytes()[3] != b':' {
+        return None;
+    }
+    let hours: i64 = offset[1..3].parse().ok()?;
+    let minutes: i64 = offset[4..].parse().ok()?;
+    Some((time, sign * (hours * 3600 + minutes * 60)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2021-08-06T17:04:05Z, plus `millis`
+    fn at(millis: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_628_269_445) + Duration::from_millis(millis)
+    }
+
+    fn utc(format: TimestampFormat) -> Timestamps {
+        Timestamps { format, utc: true }
+    }
+
+    #[test]
+    fn rendered() {
+        let t = at(250);
+        assert_eq!(
+            utc(TimestampFormat::Rfc3339).render(t),
+            Timestamp::Text("2021-08-06T17:04:05Z".into())
+        );
+        assert_eq!(
+            utc(TimestampFormat::Unix).render(t),
+            Timestamp::Number(1_628_269_445)
+        );
+        assert_eq!(
+            utc(TimestampFormat::UnixMs).render(t),
+            Timestamp::Number(1_628_269_445_250)
+        );
+    }
+
+    #[test]
+    fn before_epoch_is_epoch() {
+        let t = UNIX_EPOCH - Duration::from_secs(60);
+        assert_eq!(utc(TimestampFormat::Unix).render(t), Timestamp::Number(0));
+        assert_eq!(
+            utc(TimestampFormat::Rfc3339).render(t).to_string(),
+            "1970-01-01T00:00:00Z"
+        );
+    }
+
+    #[test]
+    fn offsets() {
+        let t = at(0);
+        assert_eq!(rfc3339(t, 0), "2021-08-06T17:04:05Z");
+        assert_eq!(rfc3339(t, 2 * 3600), "2021-08-06T19:04:05+02:00");
+        assert_eq!(
+            rfc3339(t, -(9 * 3600 + 30 * 60)),
+            "2021-08-06T07:34:05-09:30"
+        );
+    }
+
+    #[test]
+    fn parsed() {
+        let t = at(0);
+        for s in [
+            "2021-08-06T17:04:05Z",
+            "2021-08-06T17:04:05z",
+            "2021-08-06 17:04:05Z",
+            "2021-08-06T19:04:05+02:00",
+            "2021-08-06T07:34:05-09:30",
+            "1628269445",
+            " 1628269445000 ",
+        ] {
+            assert_eq!(parse(s), Some(t), "{}", s);
+        }
+        assert_eq!(parse("1628269445250"), Some(at(250)));
+    }
+
+    #[test]
+    fn seconds_or_milliseconds() {
+        let below = (MIN_UNIX_MS - 1).to_string();
+        assert_eq!(
+            parse(&below),
+            Some(UNIX_EPOCH + Duration::from_secs(MIN_UNIX_MS - 1))
+        );
+        let from = MIN_UNIX_MS.to_string();
+        assert_eq!(
+            parse(&from),
+            Some(UNIX_EPOCH + Duration::from_millis(MIN_UNIX_MS))
+        );
+    }
+
+    #[test]
+    fn unparsable() {
+        for s in [
+            "",
+            "yesterday",
+            "-1628269445",
+            "2021-08-06T17:04:05",
+            "2021-08-06T17:04:05+0200",
+            "2021-08-06T17:04:05*02:00",
+        ] {
+            assert_eq!(parse(s), None, "{}", s);
+        }
+    }
+
+    #[test]
+    fn read_back() {
+        let t = at(0);
+        for format in [
+            TimestampFormat::Rfc3339,
+            TimestampFormat::Unix,
+            TimestampFormat::UnixMs,
+        ] {
+            for utc in [true, false] {
+                let written = Timestamps { format, utc }.render(t).to_string();
+                assert_eq!(parse(&written), Some(t), "{}", written);
+            }
+        }
+    }
+
+    #[test]
+    fn format_names() {
+        for name in TimestampFormat::VARIANTS {
+            let format: TimestampFormat = name.parse().unwrap();
+            assert_eq!(format.to_string(), *name);
+        }
+        assert!("iso".parse::<TimestampFormat>().is_err());
+    }
+}
@@ -1,11 +1,168 @@
-//! Module to get JWT tokens from `docker.io`
+//! Module to get JWT tokens from `docker.io`, or the token service of another registry
 //!
-//! Supports usr/pass with basic authentication
+//! Supports usr/pass with basic authentication, and Docker Hub access tokens in place of the
+//! password
 
-use super::err::{DrlErr, DrlResult, ExitCode};
-use reqwest::{Client, StatusCode};
+use super::client::Http;
+use super::err::{DrlErr, DrlResult, ExitCode, Kind};
+use super::errbody;
+use super::registry::Registry;
+use super::retry::RetryPolicy;
+use super::trace;
+use reqwest::header::{HeaderMap, DATE};
+use reqwest::{Client, StatusCode, Url};
 use serde::{Deserialize, Serialize};
 use serde_json;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Default margin before the computed expiry at which a token is treated as expired
+pub const DEFAULT_SKEW: Duration = Duration::from_secs(30);
+
+/// Token endpoint on `auth.docker.io`, Docker Hub's realm
+pub const TOKEN_URL: &str = "https://auth.docker.io/token";
+
+/// Service Docker Hub tokens are requested for
+pub const SERVICE: &str = "registry.docker.io";
+
+/// Repository the rate limit is checked against, unless `--repository` names another
+pub const REPOSITORY: &str = "ratelimitpreview/test";
+
+/// Prefixes of Docker Hub access tokens, personal and organization ones
+pub const ACCESS_TOKEN_PREFIXES: &[&str] = &["dckr_pat_", "dckr_oat_"];
+
+/// Whether `secret` looks like a Docker Hub access token rather than a password
+///
+/// # Arguments
+///
+/// * `secret` - password or access token
+pub fn is_access_token(secret: &str) -> bool {
+    ACCESS_TOKEN_PREFIXES.iter().any(|p| secret.starts_with(p))
+}
+
+/// Checks that `pat` is a Docker Hub access token, e.g. `dckr_pat_...`
+///
+/// # Arguments
+///
+/// * `pat` - personal or organization access token
+pub fn validate_access_token(pat: &str) -> Result<(), String> {
+    let body = ACCESS_TOKEN_PREFIXES
+        .iter()
+        .find_map(|p| pat.strip_prefix(p));
+    match body {
+        Some(b) if !b.is_empty() => Ok(()),
+        Some(_) => Err(String::from("access token is empty after its prefix")),
+        None => Err(format!(
+            "not a Docker Hub access token, expected one starting with {}",
+            ACCESS_TOKEN_PREFIXES.join(" or ")
+        )),
+    }
+}
+
+/// Scope to request the token with
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Scope {
+    /// Pull access to the registry's repository
+    #[default]
+    Pull,
+    /// Push access to the registry's repository
+    Push,
+    /// Pull and push access to the registry's repository
+    PullPush,
+    /// Scope string passed through as is, e.g. `repository:name:actions`
+    Raw(String),
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    /// Parses `pull`, `push`, `pull,push`, or a full `repository:name:actions` scope
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pull" => Ok(Scope::Pull),
+            "push" => Ok(Scope::Push),
+            "pull,push" | "push,pull" => Ok(Scope::PullPush),
+            _ if s.splitn(3, ':').count() == 3 => Ok(Scope::Raw(s.into())),
+            _ => Err(format!(
+                "invalid scope '{}': expected pull, push, pull,push, or repository:name:actions",
+                s
+            )),
+        }
+    }
+}
+
+impl Scope {
+    /// Formats the scope as sent to the token service, for `repository`
+    ///
+    /// # Arguments
+    ///
+    /// * `repository` - repository whose manifest is checked
+    pub fn for_repository(&self, repository: &str) -> String {
+        match self {
+            Scope::Pull => format!("repository:{}:pull", repository),
+            Scope::Push => format!("repository:{}:push", repository),
+            Scope::PullPush => format!("repository:{}:pull,push", repository),
+            Scope::Raw(s) => s.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    /// Formats the scope as sent to the token service, for `REPOSITORY`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.for_repository(REPOSITORY))
+    }
+}
+
+/// Token endpoint of `registry` with the query parameters for `scope`
+///
+/// Returns `None` if the registry doesn't use tokens, or its realm hasn't been discovered
+///
+/// # Arguments
+///
+/// * `registry` - `Registry` whose token service to ask
+/// * `scope` - `Scope` to request
+pub fn token_url(registry: &Registry, scope: &Scope) -> Option<Url> {
+    let mut url = registry.realm.clone()?;
+    {
+        let mut query = url.query_pairs_mut();
+        if let Some(service) = &registry.service {
+            query.append_pair("service", service);
+        }
+        query.append_pair("scope", &scope.for_repository(&registry.repository));
+    }
+    Some(url)
+}
+
+/// Access granted for one resource, from the JWT claims
+#[derive(Deserialize, Debug)]
+struct Access {
+    #[serde(rename = "type")]
+    kind: String,
+    name: String,
+    actions: Vec<String>,
+}
+
+/// Docker's own claims, namespaced under `https://auth.docker.io`
+#[derive(Deserialize, Debug)]
+struct DockerClaims {
+    #[serde(default)]
+    username: Option<String>,
+}
+
+/// The JWT claims `docker-rl` cares about
+#[derive(Deserialize, Debug)]
+struct Claims {
+    #[serde(default)]
+    access: Vec<Access>,
+    #[serde(default)]
+    sub: Option<String>,
+    #[serde(default, rename = "https://auth.docker.io")]
+    docker: Option<DockerClaims>,
+    #[serde(default)]
+    exp: Option<u64>,
+}
 
 /// Struct to hold token information
 #[derive(Serialize, Deserialize, Debug, Default)]
@@ -13,6 +170,12 @@ pub struct Token {
     pub token: String,
     pub expires_in: usize,
     pub issued_at: String,
+    /// `Date` header of the token response, on the server's clock
+    #[serde(skip)]
+    pub server_date: Option<SystemTime>,
+    /// When the token response was received, on the local clock
+    #[serde(skip)]
+    pub received_at: Option<SystemTime>,
 }
 
 impl Token {
@@ -20,39 +183,195 @@ impl Token {
     pub fn new() -> Token {
         Token::default()
     }
+
+    /// When the token expires, on the local clock
+    ///
+    /// The JWT `exp` claim is used when the token can be decoded, otherwise `expires_in` from
+    /// `issued_at`. Either is measured on the server's clock when the response had a `Date`
+    /// header, so a local clock that is ahead or behind doesn't shorten or stretch its life.
+    /// Without one, the local clock is trusted.
+    ///
+    /// Returns `None` if neither `exp`, the receive time, nor `issued_at` are known, or if the
+    /// expiry is too far out for a `SystemTime`, so such a token counts as expired
+    pub fn expires_at(&self) -> Option<SystemTime> {
+        if let Some(exp) = self.claims().and_then(|c| c.exp) {
+            let exp = UNIX_EPOCH.checked_add(Duration::from_secs(exp))?;
+            return match (self.received_at, self.server_date) {
+                (Some(received), Some(date)) => {
+                    received.checked_add(exp.duration_since(date).unwrap_or_default())
+                }
+                _ => Some(exp),
+            };
+        }
+
+        let lifetime = Duration::from_secs(self.expires_in as u64);
+        let issued = humantime::parse_rfc3339(&self.issued_at).ok();
+
+        let received = match self.received_at {
+            Some(r) => r,
+            // e.g. a deserialized token, all that's left is trusting the local clock
+            None => return issued.and_then(|i| i.checked_add(lifetime)),
+        };
+
+        let age = match (issued, self.server_date) {
+            (Some(issued), Some(date)) => date.duration_since(issued).unwrap_or_default(),
+            (Some(issued), None) => received.duration_since(issued).unwrap_or_default(),
+            (None, _) => Duration::default(),
+        };
+
+        received.checked_add(lifetime.checked_sub(age).unwrap_or_default())
+    }
+
+    /// Whether the token is expired, or will expire within `skew`
+    ///
+    /// Tokens with an unknown or overflowing expiry are treated as expired. This is only an
+    /// estimate, a `401` from the registry always means the token has to be replaced.
+    ///
+    /// # Arguments
+    ///
+    /// * `skew` - margin before the expiry, see `DEFAULT_SKEW`
+    pub fn is_expired(&self, skew: Duration) -> bool {
+        self.is_expired_at(SystemTime::now(), skew)
+    }
+
+    /// Same as `is_expired`, but at `now` instead of the current time
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - current time on the local clock
+    /// * `skew` - margin before the expiry, see `DEFAULT_SKEW`
+    pub fn is_expired_at(&self, now: SystemTime, skew: Duration) -> bool {
+        match (self.expires_at(), now.checked_add(skew)) {
+            (Some(expires), Some(deadline)) => deadline >= expires,
+            _ => true,
+        }
+    }
+
+    /// Decodes the claims from the JWT, without verifying the signature
+    fn claims(&self) -> Option<Claims> {
+        let payload = self.token.split('.').nth(1)?;
+        let json = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+
+    /// Scopes granted by the token service, formatted like `Scope`
+    ///
+    /// The token service silently drops actions the identity isn't allowed, so this can be less
+    /// than what was requested. Returns `None` if the token can't be decoded
+    pub fn granted_scopes(&self) -> Option<Vec<String>> {
+        let claims = self.claims()?;
+        let scopes = claims
+            .access
+            .iter()
+            .map(|a| format!("{}:{}:{}", a.kind, a.name, a.actions.join(",")))
+            .collect();
+        Some(scopes)
+    }
+
+    /// User the token was issued to, from the JWT claims
+    ///
+    /// Docker's username claim is preferred over the subject, which can be an account id.
+    /// Returns `Some(None)` for anonymous tokens, whose subject is empty, and `None` if the
+    /// token can't be decoded
+    pub fn user(&self) -> Option<Option<String>> {
+        let claims = self.claims()?;
+        let username = claims.docker.and_then(|d| d.username);
+        let user = username.or(claims.sub).filter(|u| !u.trim().is_empty());
+        Some(user)
+    }
+
+    /// Records when and on which server date the token response was received
+    ///
+    /// # Arguments
+    ///
+    /// * `received_at` - when the response was received, on the local clock
+    /// * `server_date` - `Date` header of the response, see `server_date`
+    fn stamp(&mut self, received_at: SystemTime, server_date: Option<SystemTime>) {
+        self.received_at = Some(received_at);
+        self.server_date = server_date;
+    }
 }
 
-const DOCKER_URL: &str = "https://auth.docker.io/token?service=registry.docker.io&scope=repository:ratelimitpreview/test:pull";
+/// The `Date` header in `headers`, `None` if it's missing or malformed
+fn server_date(headers: &HeaderMap) -> Option<SystemTime> {
+    headers
+        .get(DATE)
+        .and_then(|d| d.to_str().ok())
+        .and_then(|d| httpdate::parse_http_date(d).ok())
+}
 
 /// Get anonymous token from `docker.io`
 ///
 /// Returns `Token` with JWT token info
 pub async fn get_anon_token() -> DrlResult<Token> {
-    let client = Client::new();
-    let req = client.get(DOCKER_URL);
+    get_anon_token_scoped(&Scope::default()).await
+}
+
+/// Get anonymous token from `docker.io` for `scope`
+///
+/// Returns `Token` with JWT token info
+///
+/// # Arguments
+///
+/// * `scope` - `Scope` to request
+pub async fn get_anon_token_scoped(scope: &Scope) -> DrlResult<Token> {
+    fetch_anon_token(&Http::default(), &Registry::default(), scope).await
+}
+
+/// Get anonymous token from `registry` for `scope`, using `http`
+///
+/// # Arguments
+///
+/// * `http` - `Http` to send the request with
+/// * `registry` - `Registry` whose token service to ask
+/// * `scope` - `Scope` to request
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        name = "get_anon_token",
+        skip_all,
+        fields(registry = %registry.token_host().unwrap_or_default(), anonymous = true, status = tracing::field::Empty, elapsed_ms = tracing::field::Empty)
+    )
+)]
+pub(crate) async fn fetch_anon_token(
+    http: &Http,
+    registry: &Registry,
+    scope: &Scope,
+) -> DrlResult<Token> {
+    // registries without a token service are checked without one
+    let url = match token_url(registry, scope) {
+        Some(u) => u,
+        None => return Ok(Token::new()),
+    };
+    let req = http.client.get(url);
 
     // send request
-    let resp = match req.send().await {
+    let started = Instant::now();
+    let resp = match http.send(req).await {
         Ok(r) => r,
-        Err(e) => {
-            let msg = format!("failed to connect to docker.io: {}", e);
-            let err = DrlErr::new(msg, ExitCode::Connection);
-            return Err(err);
-        }
+        Err(e) => return Err(http.connect_error(&registry.name, e)),
     };
 
+    trace::response(resp.status(), started.elapsed());
+
     // check status for errors
     match resp.status() {
         StatusCode::OK => (),
-        _ => {
-            let msg = format!("unknown response {:?}", resp.status());
+        StatusCode::PROXY_AUTHENTICATION_REQUIRED => {
+            return Err(http.proxy_auth_error(&registry.name));
+        }
+        status => {
+            let msg = format!("unknown response {:?}", status);
+            let msg = errbody::describe(msg, errbody::read(resp).await);
             let err = DrlErr::new(msg, ExitCode::Connection);
             return Err(err);
         }
     };
 
+    let headers = resp.headers().clone();
     let body = match resp.text().await {
         Ok(b) => b,
+        Err(e) if e.is_timeout() => return Err(http.connect_error(&registry.name, e)),
         Err(e) => {
             let msg = format!("failed to parse response: {}", e);
             let err = DrlErr::new(msg, ExitCode::Body);
@@ -61,7 +380,7 @@ pub async fn get_anon_token() -> DrlResult<Token> {
     };
 
     // unmarshal
-    let t: Token = match serde_json::from_str(body.as_str()) {
+    let mut t: Token = match serde_json::from_str(body.as_str()) {
         Ok(t) => t,
         Err(e) => {
             let msg = format!("failed to parse response: {}", e);
@@ -69,6 +388,7 @@ pub async fn get_anon_token() -> DrlResult<Token> {
             return Err(err);
         }
     };
+    t.stamp(SystemTime::now(), server_date(&headers));
 
     Ok(t)
 }
@@ -83,37 +403,97 @@ pub async fn get_anon_token() -> DrlResult<Token> {
 /// * `pass` - `String` with passphrase
 ///
 pub async fn get_userpass_token(user: String, pass: String) -> DrlResult<Token> {
-    let client = Client::new();
-    let req = client.get(DOCKER_URL);
-    let req = req.basic_auth(&user, Some(pass));
+    get_userpass_token_scoped(user, pass, &Scope::default()).await
+}
 
-    // actually send request
-    let resp = match req.send().await {
-        Ok(r) => r,
-        Err(e) => {
-            let msg = format!("failed to connect to docker.io: {}", e);
-            let err = DrlErr::new(msg, ExitCode::Connection);
+/// Get token from `docker.io` with user/pass for `scope`
+///
+/// Returns `Token` with JWT token info
+///
+/// # Arguments
+///
+/// * `user` - `String` with username
+/// * `pass` - `String` with passphrase
+/// * `scope` - `Scope` to request
+///
+pub async fn get_userpass_token_scoped(
+    user: String,
+    pass: String,
+    scope: &Scope,
+) -> DrlResult<Token> {
+    let (http, registry) = (Http::default(), Registry::default());
+    fetch_userpass_token(&http, &registry, &user, &pass, scope).await
+}
+
+/// Get token from `registry` with user/pass for `scope`, using `http`
+///
+/// # Arguments
+///
+/// * `http` - `Http` to send the request with
+/// * `registry` - `Registry` whose token service to ask
+/// * `user` - username
+/// * `pass` - passphrase
+/// * `scope` - `Scope` to request
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        name = "get_userpass_token",
+        skip_all,
+        fields(registry = %registry.token_host().unwrap_or_default(), anonymous = false, user = user, status = tracing::field::Empty, elapsed_ms = tracing::field::Empty)
+    )
+)]
+pub(crate) async fn fetch_userpass_token(
+    http: &Http,
+    registry: &Registry,
+    user: &str,
+    pass: &str,
+    scope: &Scope,
+) -> DrlResult<Token> {
+    let url = match token_url(registry, scope) {
+        Some(u) => u,
+        None => {
+            let msg = format!("{} doesn't use token authentication", registry.name);
+            let err = DrlErr::new(msg, ExitCode::Input);
             return Err(err);
         }
     };
+    let req = http.client.get(url);
+    let req = req.basic_auth(user, Some(pass));
+
+    // actually send request
+    let started = Instant::now();
+    let resp = match http.send(req).await {
+        Ok(r) => r,
+        Err(e) => return Err(http.connect_error(&registry.name, e)),
+    };
+
+    trace::response(resp.status(), started.elapsed());
 
     // check status for auth errors
     match resp.status() {
         StatusCode::OK => (),
+        StatusCode::PROXY_AUTHENTICATION_REQUIRED => {
+            return Err(http.proxy_auth_error(&registry.name));
+        }
         StatusCode::UNAUTHORIZED => {
-            let msg = format!("authentication failed for {}", &user);
-            let err = DrlErr::new(msg, ExitCode::Unauthorized);
+            let msg = rejected(user, pass);
+            let msg = errbody::describe(msg, errbody::read(resp).await);
+            let err =
+                DrlErr::new(msg, ExitCode::Unauthorized).with_kind(Kind::Auth { status: 401 });
             return Err(err);
         }
-        _ => {
-            let msg = format!("unknown response {:?}", resp.status());
+        status => {
+            let msg = format!("unknown response {:?}", status);
+            let msg = errbody::describe(msg, errbody::read(resp).await);
             let err = DrlErr::new(msg, ExitCode::Connection);
             return Err(err);
         }
     };
 
+    let headers = resp.headers().clone();
     let body = match resp.text().await {
         Ok(b) => b,
+        Err(e) if e.is_timeout() => return Err(http.connect_error(&registry.name, e)),
         Err(e) => {
             let msg = format!("failed to parse response: {}", e);
             let err = DrlErr::new(msg, ExitCode::Body);
@@ -121,7 +501,7 @@ pub async fn get_userpass_token(user: String, pass: String) -> DrlResult<Token>
         }
     };
 
-    let t: Token = match serde_json::from_str(body.as_str()) {
+    let mut t: Token = match serde_json::from_str(body.as_str()) {
         Ok(t) => t,
         Err(e) => {
             let msg = format!("failed to parse response: {}", e);
@@ -129,6 +509,311 @@ pub async fn get_userpass_token(user: String, pass: String) -> DrlResult<Token>
             return Err(err);
         }
     };
+    t.stamp(SystemTime::now(), server_date(&headers));
 
     Ok(t)
 }
+
+/// Get token from `docker.io` with a personal or organization access token
+///
+/// Access tokens are sent like a password, but are checked to look like one first and a
+/// rejected one is reported as expired or revoked rather than as a wrong password
+///
+/// Returns `Token` with JWT token info
+///
+/// # Errors
+///
+/// Returns `ExitCode::Input` if `pat` isn't an access token, and `ExitCode::Unauthorized` if
+/// it was rejected
+///
+/// # Arguments
+///
+/// * `user` - `String` with username, or the organization for an organization token
+/// * `pat` - `String` with the access token, `dckr_pat_...` or `dckr_oat_...`
+///
+pub async fn get_pat_token(user: String, pat: String) -> DrlResult<Token> {
+    get_pat_token_scoped(user, pat, &Scope::default()).await
+}
+
+/// Get token from `docker.io` with an access token for `scope`, see `get_pat_token`
+///
+/// Returns `Token` with JWT token info
+///
+/// # Arguments
+///
+/// * `user` - `String` with username, or the organization for an organization token
+/// * `pat` - `String` with the access token
+/// * `scope` - `Scope` to request
+///
+pub async fn get_pat_token_scoped(user: String, pat: String, scope: &Scope) -> DrlResult<Token> {
+    if let Err(msg) = validate_access_token(&pat) {
+        let err = DrlErr::new(msg, ExitCode::Input);
+        return Err(err);
+    }
+    get_userpass_token_scoped(user, pat, scope).await
+}
+
+/// Message for a `401` from the token service, telling a rejected access token from a wrong
+/// password
+fn rejected(user: &str, pass: &str) -> String {
+    if is_access_token(pass) {
+        let reason = "it may have expired or been revoked";
+        format!("access token for {} rejected, {}", user, reason)
+    } else {
+        format!("authentication failed for {}, wrong user or password", user)
+    }
+}
+
+/// Hands out tokens from one shared `Client`, requesting a new one when the current one expires
+pub struct TokenProvider {
+    http: Http,
+    registry: Registry,
+    creds: Option<(String, String)>,
+    scope: Scope,
+    skew: Duration,
+    token: Option<Token>,
+}
+
+impl TokenProvider {
+    /// Creates a provider of anonymous tokens for Docker Hub, with reqwest's defaults
+    ///
+    /// See `api::DrlClient::provider` for other registries and settings
+    ///
+    /// # Arguments
+    ///
+    /// * `scope` - `Scope` to request
+    pub fn anonymous(scope: Scope) -> TokenProvider {
+        TokenProvider::shared(Http::default(), Registry::default(), None, scope)
+    }
+
+    /// Creates a provider of tokens for user/pass for Docker Hub, with reqwest's defaults
+    ///
+    /// # Arguments
+    ///
+    /// * `user` - username
+    /// * `pass` - passphrase
+    /// * `scope` - `Scope` to request
+    pub fn userpass(user: String, pass: String, scope: Scope) -> TokenProvider {
+        TokenProvider {
+            creds: Some((user, pass)),
+            ..TokenProvider::anonymous(scope)
+        }
+    }
+
+    /// Creates a provider using `http` and `registry`
+    ///
+    /// # Arguments
+    ///
+    /// * `http` - `Http` to share
+    /// * `registry` - `Registry` the tokens are for
+    /// * `creds` - user and password, `None` for anonymous tokens
+    /// * `scope` - `Scope` to request
+    pub(crate) fn shared(
+        http: Http,
+        registry: Registry,
+        creds: Option<(String, String)>,
+        scope: Scope,
+    ) -> TokenProvider {
+        TokenProvider {
+            http,
+            registry,
+            creds,
+            scope,
+            skew: DEFAULT_SKEW,
+            token: None,
+        }
+    }
+
+    /// Sets the margin before expiry at which tokens are replaced, see `DEFAULT_SKEW`
+    pub fn with_skew(mut self, skew: Duration) -> TokenProvider {
+        self.skew = skew;
+        self
+    }
+
+    /// The shared `Client`, for requests made with the tokens
+    pub fn client(&self) -> &Client {
+        &self.http.client
+    }
+
+    /// How failed requests are retried, for requests made with the tokens
+    pub fn retry(&self) -> &RetryPolicy {
+        &self.http.retry
+    }
+
+    /// The client along with how its requests are retried
+    pub(crate) fn http(&self) -> &Http {
+        &self.http
+    }
+
+    /// The `Registry` the tokens are for
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// User the tokens are for, `None` for anonymous
+    pub fn user(&self) -> Option<&str> {
+        self.creds.as_ref().map(|(user, _)| user.as_str())
+    }
+
+    /// Gets the current token, requesting a new one if there is none or it has expired
+    pub async fn token(&mut self) -> DrlResult<&Token> {
+        if self.current(SystemTime::now()).is_none() {
+            trace::token_refresh(self.creds.is_none());
+            let token = match &self.creds {
+                Some((user, pass)) => {
+                    let (http, scope) = (&self.http, &self.scope);
+                    fetch_userpass_token(http, &self.registry, user, pass, scope).await?
+                }
+                None => fetch_anon_token(&self.http, &self.registry, &self.scope).await?,
+            };
+            self.token = Some(token);
+        }
+
+        // always set above
+        Ok(self.token.as_ref().expect("missing token"))
+    }
+
+    /// The token held, unless there is none or it's expired at `now`
+    fn current(&self, now: SystemTime) -> Option<&Token> {
+        self.token
+            .as_ref()
+            .filter(|t| !t.is_expired_at(now, self.skew))
+    }
+
+    /// Drops the current token, e.g. after the registry rejected it
+    pub fn invalidate(&mut self) {
+        self.token = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    /// 2026-01-01T00:00:00Z, when the test tokens are issued on the server's clock
+    fn issued() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_767_225_600)
+    }
+
+    /// A token valid for 300s, received at `local` with the server saying `server`
+    fn token(local: SystemTime, server: Option<SystemTime>) -> Token {
+        let mut t = Token {
+            token: String::from("opaque"),
+            expires_in: 300,
+            issued_at: String::from("2026-01-01T00:00:00Z"),
+            ..Token::new()
+        };
+        t.stamp(local, server);
+        t
+    }
+
+    /// Same as `token`, but a JWT whose `exp` claim is 300s after issuing
+    fn jwt(local: SystemTime, server: Option<SystemTime>) -> Token {
+        let exp = issued().duration_since(UNIX_EPOCH).unwrap().as_secs() + 300;
+        let claims = format!(r#"{{"exp":{}}}"#, exp);
+        let payload = base64::encode_config(claims, base64::URL_SAFE_NO_PAD);
+
+        Token {
+            token: format!("header.{}.signature", payload),
+            // ignored in favor of `exp`
+            expires_in: 60,
+            ..token(local, server)
+        }
+    }
+
+    #[test]
+    fn overflowing_expiry_is_expired() {
+        let claims = format!(r#"{{"exp":{}}}"#, u64::MAX);
+        let payload = base64::encode_config(claims, base64::URL_SAFE_NO_PAD);
+        let t = Token {
+            token: format!("header.{}.signature", payload),
+            ..token(issued(), Some(issued()))
+        };
+        assert_eq!(t.expires_at(), None);
+        assert!(t.is_expired_at(issued(), DEFAULT_SKEW));
+
+        let t = Token {
+            expires_in: usize::MAX,
+            ..token(issued(), None)
+        };
+        assert_eq!(t.expires_at(), None);
+        assert!(t.is_expired_at(issued(), DEFAULT_SKEW));
+
+        let t = Token {
+            received_at: None,
+            expires_in: usize::MAX,
+            ..token(issued(), None)
+        };
+        assert_eq!(t.expires_at(), None);
+
+        // nor does a skew that doesn't fit make a token valid forever
+        let t = token(issued(), Some(issued()));
+        assert!(t.is_expired_at(issued(), Duration::MAX));
+    }
+
+    #[test]
+    fn local_clock_ahead() {
+        let local = issued() + HOUR;
+        for t in [token(local, Some(issued())), jwt(local, Some(issued()))] {
+            assert_eq!(t.expires_at(), Some(local + Duration::from_secs(300)));
+            assert!(!t.is_expired_at(local, DEFAULT_SKEW));
+            assert!(t.is_expired_at(local + Duration::from_secs(280), DEFAULT_SKEW));
+        }
+    }
+
+    #[test]
+    fn local_clock_behind() {
+        let local = issued() - HOUR;
+        for t in [token(local, Some(issued())), jwt(local, Some(issued()))] {
+            assert_eq!(t.expires_at(), Some(local + Duration::from_secs(300)));
+            assert!(!t.is_expired_at(local + Duration::from_secs(200), DEFAULT_SKEW));
+            // still before the expiry on the server's clock, but long past it
+            assert!(t.is_expired_at(local + Duration::from_secs(400), DEFAULT_SKEW));
+        }
+    }
+
+    #[test]
+    fn age_on_server_clock() {
+        // received a minute after it was issued, by a local clock an hour ahead
+        let local = issued() + HOUR;
+        let t = token(local, Some(issued() + Duration::from_secs(60)));
+        assert_eq!(t.expires_at(), Some(local + Duration::from_secs(240)));
+    }
+
+    #[test]
+    fn local_clock_without_date() {
+        let local = issued() + Duration::from_secs(10);
+        for t in [token(local, None), jwt(local, None)] {
+            assert_eq!(t.expires_at(), Some(issued() + Duration::from_secs(300)));
+        }
+
+        // already expired by the local clock, which is all there is to go on
+        let t = token(issued() + HOUR, None);
+        assert!(t.is_expired_at(issued() + HOUR, DEFAULT_SKEW));
+    }
+
+    #[test]
+    fn unknown_expiry_is_expired() {
+        let t = Token {
+            issued_at: String::from("sometime"),
+            ..Token::new()
+        };
+        assert_eq!(t.expires_at(), None);
+        assert!(t.is_expired_at(issued(), Duration::default()));
+    }
+
+    #[test]
+    fn rejected_token_is_replaced() {
+        let mut provider =
+            TokenProvider::shared(Http::default(), Registry::docker_hub(), None, Scope::Pull);
+        let now = issued() + Duration::from_secs(10);
+        provider.token = Some(token(now, Some(now)));
+        assert!(provider.current(now).is_some());
+
+        // a 401 outweighs any expiry worked out from the clocks
+        provider.invalidate();
+        assert!(provider.current(now).is_none());
+    }
+}
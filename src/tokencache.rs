@@ -0,0 +1,412 @@
+//! Tokens kept between runs for `--token-cache`, so not every check asks the token service
+//!
+//! They live in `$XDG_CACHE_HOME/docker-rl/token.json`, or `~/.cache/docker-rl/token.json`,
+//! only readable by the user. Tokens are keyed by registry, user and scope, and dropped once
+//! they expire. `docker-rl cache` lists and clears them
+//!
+//! Changes to the file are made under an advisory lock on `token.json.lock` on unix, so runs
+//! clearing and storing tokens at the same time don't undo each other
+
+use super::err::{DrlErr, DrlResult, ExitCode};
+use super::state;
+use super::timestamp::{Timestamp, Timestamps};
+use super::token::{Scope, Token};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Name of the token cache, inside the cache directory
+const FILE: &str = "token.json";
+
+/// Name of the file locked while the token cache is changed, inside the cache directory
+const LOCK_FILE: &str = "token.json.lock";
+
+/// What a cached token is for
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Key {
+    /// Name of the registry, e.g. `docker.io`
+    pub registry: String,
+    /// User the token is for, `None` for anonymous
+    pub user: Option<String>,
+    /// Scope the token was requested with, as sent to the token service
+    pub scope: String,
+}
+
+impl Key {
+    /// Key for a token of `user` on `registry`
+    ///
+    /// # Arguments
+    ///
+    /// * `registry` - name of the registry, e.g. `docker.io`
+    /// * `user` - user the token is for, `None` for anonymous
+    /// * `scope` - `Scope` the token is requested with
+    pub fn new(registry: &str, user: Option<&str>, scope: &Scope) -> Key {
+        Key {
+            registry: registry.into(),
+            user: user.map(String::from),
+            scope: scope.to_string(),
+        }
+    }
+}
+
+/// One cached token
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Entry {
+    #[serde(flatten)]
+    key: Key,
+    /// The JWT
+    token: String,
+    /// When the token expires, in seconds since the epoch on the local clock
+    expires_at: u64,
+}
+
+/// Everything in the token cache
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Cache {
+    #[serde(default)]
+    tokens: Vec<Entry>,
+}
+
+/// Seconds since the epoch of `time`
+fn epoch_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Path of the token cache, `None` if there is no cache directory to put it in
+pub fn path() -> Option<PathBuf> {
+    Some(state::cache_dir()?.join(FILE))
+}
+
+/// Path of the token cache
+///
+/// # Errors
+///
+/// Returns `ExitCode::Input` if there is no cache directory
+pub fn required_path() -> DrlResult<PathBuf> {
+    match path() {
+        Some(p) => Ok(p),
+        None => {
+            let msg = String::from("no cache directory, set XDG_CACHE_HOME or HOME");
+            let err = DrlErr::new(msg, ExitCode::Input);
+            Err(err)
+        }
+    }
+}
+
+/// Reads the token cache, empty if it's missing or can't be read
+fn read(path: &Path) -> Cache {
+    read_checked(path).unwrap_or_default()
+}
+
+/// Reads the token cache, empty if it's missing
+///
+/// # Errors
+///
+/// Returns `ExitCode::Input` if it can't be read, and `ExitCode::Parsing` if it isn't a token
+/// cache
+fn read_checked(path: &Path) -> DrlResult<Cache> {
+    let json = match fs::read(path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Cache::default()),
+        Err(e) => {
+            let msg = format!("failed to read {}: {}", path.display(), e);
+            let err = DrlErr::new(msg, ExitCode::Input);
+            return Err(err);
+        }
+    };
+
+    serde_json::from_slice(&json).map_err(|e| {
+        let msg = format!(
+            "corrupt token cache {}: {}, `docker-rl cache clear` removes it",
+            path.display(),
+            e
+        );
+        DrlErr::new(msg, ExitCode::Parsing)
+    })
+}
+
+/// Runs `change` on the token cache at `path`, holding the lock next to it
+///
+/// Without unix, `change` runs unlocked
+fn locked<T>(path: &Path, change: impl FnOnce() -> DrlResult<T>) -> DrlResult<T> {
+    let failed = |e: io::Error| {
+        let lock = path.with_file_name(LOCK_FILE);
+        let msg = format!("failed to lock {}: {}", lock.display(), e);
+        DrlErr::new(msg, ExitCode::Input)
+    };
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(failed)?;
+    }
+    // released when closed
+    let _lock = lock(&path.with_file_name(LOCK_FILE)).map_err(failed)?;
+    change()
+}
+
+/// Opens `path` and waits for an exclusive lock on it
+#[cfg(unix)]
+fn lock(path: &Path) -> io::Result<fs::File> {
+    use std::os::unix::io::AsRawFd;
+
+    let file = create_private(path)?;
+    loop {
+        // the lock belongs to the open file, so it's released with it, even on a crash
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == 0 {
+            return Ok(file);
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn lock(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Writes `cache` to `path`, readable only by the user
+fn write(path: &Path, cache: &Cache) -> DrlResult<()> {
+    // written next to the file and renamed, so a concurrent reader never sees half of it
+    let tmp = path.with_extension("json.tmp");
+    let json = serde_json::to_vec_pretty(cache).expect("failed to serialize token cache");
+    let written = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| create_private(&tmp))
+        .and_then(|mut f| f.write_all(&json))
+        .and_then(|_| fs::rename(&tmp, path));
+
+    written.map_err(|e| {
+        let msg = format!("failed to write {}: {}", path.display(), e);
+        DrlErr::new(msg, ExitCode::Input)
+    })
+}
+
+/// Creates `path` with mode 0600, replacing it if it exists
+#[cfg(unix)]
+fn create_private(path: &Path) -> io::Result<fs::File> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    // the mode only applies to new files
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    Ok(file)
+}
+
+#[cfg(not(unix))]
+fn create_private(path: &Path) -> io::Result<fs::File> {
+    fs::File::create(path)
+}
+
+/// The cached token for `key`, `None` if there is none or it expires within `skew`
+///
+/// # Arguments
+///
+/// * `key` - `Key` of the token
+/// * `skew` - margin before the expiry, see `token::DEFAULT_SKEW`
+pub fn load(key: &Key, skew: Duration) -> Option<Token> {
+    let now = SystemTime::now();
+    let entry = read(&path()?).tokens.into_iter().find(|e| e.key == *key)?;
+
+    let expires_at = UNIX_EPOCH + Duration::from_secs(entry.expires_at);
+    let left = expires_at.duration_since(now + skew).ok()?;
+
+    // as if it had just been issued for the time it has left
+    Some(Token {
+        token: entry.token,
+        expires_in: left.as_secs() as usize + skew.as_secs() as usize,
+        issued_at: humantime::format_rfc3339_seconds(now).to_string(),
+        server_date: None,
+        received_at: Some(now),
+    })
+}
+
+/// Stores `token` under `key`, dropping any expired tokens
+///
+/// Tokens without a known expiry aren't stored
+///
+/// # Errors
+///
+/// Returns `ExitCode::Input` if there is no cache directory or the file can't be written
+///
+/// # Arguments
+///
+/// * `key` - `Key` of the token
+/// * `token` - `Token` to store
+pub fn store(key: &Key, token: &Token) -> DrlResult<()> {
+    let expires_at = match token.expires_at() {
+        Some(e) => epoch_secs(e),
+        None => return Ok(()),
+    };
+    let path = required_path()?;
+
+    locked(&path, || {
+        let now = epoch_secs(SystemTime::now());
+        let mut cache = read(&path);
+        cache.tokens.retain(|e| e.key != *key && e.expires_at > now);
+        cache.tokens.push(Entry {
+            key: key.clone(),
+            token: token.token.clone(),
+            expires_at,
+        });
+
+        write(&path, &cache)
+    })
+}
+
+/// Drops the cached token for `key`, e.g. after the registry rejected it
+///
+/// Returns whether there was one
+///
+/// # Errors
+///
+/// Returns `ExitCode::Input` if the file can't be written
+///
+/// # Arguments
+///
+/// * `key` - `Key` of the token
+pub fn forget(key: &Key) -> DrlResult<bool> {
+    let path = match path() {
+        Some(p) => p,
+        None => return Ok(false),
+    };
+
+    locked(&path, || {
+        let mut cache = read(&path);
+        let before = cache.tokens.len();
+        cache.tokens.retain(|e| e.key != *key);
+        if cache.tokens.len() == before {
+            return Ok(false);
+        }
+
+        write(&path, &cache)?;
+        Ok(true)
+    })
+}
+
+/// Removes the cached tokens of `user`, or every one, returning how many were removed
+///
+/// Clearing every token removes the file, even if it's corrupt
+///
+/// # Errors
+///
+/// Returns `ExitCode::Input` if there is no cache directory or the file can't be changed, and
+/// `ExitCode::Parsing` if only the tokens of `user` are removed and the file is corrupt
+///
+/// # Arguments
+///
+/// * `user` - user whose tokens are removed, `None` for every token
+pub fn clear(user: Option<&str>) -> DrlResult<usize> {
+    let path = required_path()?;
+
+    locked(&path, || {
+        let user = match user {
+            Some(u) => u,
+            None => {
+                let count = read(&path).tokens.len();
+                return match fs::remove_file(&path) {
+                    Ok(()) => Ok(count),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+                    Err(e) => {
+                        let msg = format!("failed to remove {}: {}", path.display(), e);
+                        let err = DrlErr::new(msg, ExitCode::Input);
+                        Err(err)
+                    }
+                };
+            }
+        };
+
+        let mut cache = read_checked(&path)?;
+        let before = cache.tokens.len();
+        cache.tokens.retain(|e| e.key.user.as_deref() != Some(user));
+        let removed = before - cache.tokens.len();
+        if removed > 0 {
+            write(&path, &cache)?;
+        }
+        Ok(removed)
+    })
+}
+
+/// A cached token, as listed by `docker-rl cache list`, without the token itself
+#[derive(Serialize, Debug, Clone)]
+pub struct Listed {
+    /// What the token is for
+    #[serde(flatten)]
+    pub key: Key,
+    /// When the token expires
+    pub expires_at: Timestamp,
+    /// Whether it has expired, it's dropped when the next token is stored
+    pub expired: bool,
+}
+
+/// Every cached token, for `docker-rl cache list`
+#[derive(Serialize, Debug, Clone)]
+pub struct Listing {
+    /// Path of the token cache
+    pub file: String,
+    /// The tokens, without the tokens themselves
+    pub tokens: Vec<Listed>,
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.tokens.is_empty() {
+            return write!(f, "no cached tokens in {}", self.file);
+        }
+
+        write!(f, "{}", self.file)?;
+        for listed in &self.tokens {
+            let user = listed.key.user.as_deref().unwrap_or("anonymous");
+            let expiry = if listed.expired { "expired" } else { "expires" };
+            write!(
+                f,
+                "\n{} {} {} {} {}",
+                listed.key.registry, user, listed.key.scope, expiry, listed.expires_at
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Lists the cached tokens, without the tokens themselves
+///
+/// # Errors
+///
+/// Returns `ExitCode::Input` if there is no cache directory or the file can't be read, and
+/// `ExitCode::Parsing` if it's corrupt
+///
+/// # Arguments
+///
+/// * `timestamps` - how to write the expiries
+pub fn list(timestamps: &Timestamps) -> DrlResult<Listing> {
+    let path = required_path()?;
+    let cache = read_checked(&path)?;
+
+    let now = epoch_secs(SystemTime::now());
+    let tokens = cache
+        .tokens
+        .into_iter()
+        .map(|e| Listed {
+            expires_at: timestamps.render(UNIX_EPOCH + Duration::from_secs(e.expires_at)),
+            expired: e.expires_at <= now,
+            key: e.key,
+        })
+        .collect();
+
+    Ok(Listing {
+        file: path.display().to_string(),
+        tokens,
+    })
+}
@@ -0,0 +1,225 @@
+//! Hooks for `tracing`, which do nothing without the `tracing` feature
+//!
+//! Spans are opened with `tracing::instrument` on the functions that talk to the registry, these
+//! fill in what is only known once a response arrives. Every request and response is logged at
+//! `debug` as well, with its headers at `trace`. Credentials never are, `SECRET_HEADERS` and
+//! passwords in URLs are replaced by `REDACTED`
+
+use reqwest::StatusCode;
+use std::time::Duration;
+#[cfg(feature = "tracing")]
+use {
+    reqwest::header::HeaderMap,
+    reqwest::{RequestBuilder, Response, Url},
+    std::fmt,
+};
+
+/// Headers whose values are never logged
+#[cfg(feature = "tracing")]
+const SECRET_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+];
+
+/// Logged in place of a secret
+#[cfg(feature = "tracing")]
+const REDACTED: &str = "[redacted]";
+
+/// Prefixes of the rate limit headers, logged at `debug`
+#[cfg(feature = "tracing")]
+const LIMIT_HEADERS: &[&str] = &["ratelimit-", "docker-ratelimit-"];
+
+/// `url` without any password
+#[cfg(feature = "tracing")]
+fn redact_url(url: &Url) -> Url {
+    let mut url = url.clone();
+    if url.password().is_some() {
+        // only fails for URLs that can't have one
+        let _ = url.set_password(Some(REDACTED));
+    }
+    url
+}
+
+/// `Display` of the headers of `HeaderMap` that `keep` lets through, as `name: value, ...`
+#[cfg(feature = "tracing")]
+struct Headers<'a> {
+    headers: &'a HeaderMap,
+    keep: fn(&str) -> bool,
+}
+
+#[cfg(feature = "tracing")]
+impl fmt::Display for Headers<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let headers = self.headers.iter().filter(|(k, _)| (self.keep)(k.as_str()));
+        for (i, (key, value)) in headers.enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            match value.to_str() {
+                _ if SECRET_HEADERS.contains(&key.as_str()) => write!(f, "{}: {}", key, REDACTED)?,
+                Ok(v) => write!(f, "{}: {}", key, v)?,
+                Err(_) => write!(f, "{}: {:?}", key, value)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `Display` of a request, built from a clone of the builder only once it's written
+#[cfg(feature = "tracing")]
+struct Request<'a> {
+    req: &'a RequestBuilder,
+    headers: bool,
+}
+
+#[cfg(feature = "tracing")]
+impl fmt::Display for Request<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let req = match self.req.try_clone().and_then(|r| r.build().ok()) {
+            Some(r) => r,
+            None => return write!(f, "?"),
+        };
+        if self.headers {
+            let headers = Headers {
+                headers: req.headers(),
+                keep: |_| true,
+            };
+            write!(f, "{}", headers)
+        } else {
+            write!(f, "{} {}", req.method(), redact_url(req.url()))
+        }
+    }
+}
+
+/// Logs a request about to be sent
+///
+/// # Arguments
+///
+/// * `req` - the request
+#[cfg(feature = "tracing")]
+pub(crate) fn request(req: &RequestBuilder) {
+    // only built if the event is enabled
+    tracing::debug!(request = %Request { req, headers: false }, "sending");
+    tracing::trace!(headers = %Request { req, headers: true }, "request headers");
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn request(_req: &reqwest::RequestBuilder) {}
+
+/// Logs a response, with its rate limit headers as they were sent
+///
+/// # Arguments
+///
+/// * `resp` - the response
+#[cfg(feature = "tracing")]
+pub(crate) fn received(resp: &Response) {
+    let limits = Headers {
+        headers: resp.headers(),
+        keep: |k| LIMIT_HEADERS.iter().any(|p| k.starts_with(p)),
+    };
+    tracing::debug!(
+        status = resp.status().as_u16(),
+        url = %redact_url(resp.url()),
+        ratelimit = %limits,
+        "received"
+    );
+    let all = Headers {
+        headers: resp.headers(),
+        keep: |_| true,
+    };
+    tracing::trace!(headers = %all, "response headers");
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn received(_resp: &reqwest::Response) {}
+
+/// Logs a request that failed without a response
+///
+/// # Arguments
+///
+/// * `e` - why it failed
+#[cfg(feature = "tracing")]
+pub(crate) fn failed(e: &reqwest::Error) {
+    tracing::debug!(error = %e, "request failed");
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn failed(_e: &reqwest::Error) {}
+
+/// Records the status and time taken on the current span
+///
+/// # Arguments
+///
+/// * `status` - status of the response
+/// * `elapsed` - time from sending the request to the response
+#[cfg(feature = "tracing")]
+pub(crate) fn response(status: StatusCode, elapsed: Duration) {
+    let span = tracing::Span::current();
+    span.record("status", &status.as_u16());
+    span.record("elapsed_ms", &(elapsed.as_millis() as u64));
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn response(_status: StatusCode, _elapsed: Duration) {}
+
+/// Emits a debug event for a token being requested to replace a missing or expired one
+///
+/// # Arguments
+///
+/// * `anonymous` - whether the token is anonymous
+#[cfg(feature = "tracing")]
+pub(crate) fn token_refresh(anonymous: bool) {
+    tracing::debug!(anonymous, "requesting a new token");
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn token_refresh(_anonymous: bool) {}
+
+/// Emits a debug event for a request that is retried
+///
+/// # Arguments
+///
+/// * `reason` - why the request is retried
+#[cfg(feature = "tracing")]
+pub(crate) fn retry(reason: &str) {
+    tracing::debug!(reason, "retrying");
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn retry(_reason: &str) {}
+
+/// Emits a debug event for polling that waits for the limit to reset
+///
+/// # Arguments
+///
+/// * `wait` - time until the next poll
+#[cfg(feature = "tracing")]
+pub(crate) fn backoff(wait: Duration) {
+    tracing::debug!(
+        wait_secs = wait.as_secs(),
+        "over limit, waiting for the reset"
+    );
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn backoff(_wait: Duration) {}
+
+/// Emits a debug event for polling that slows down because the checks keep failing
+///
+/// # Arguments
+///
+/// * `failures` - failed checks in a row
+/// * `wait` - time until the next poll
+#[cfg(feature = "tracing")]
+pub(crate) fn failing(failures: u32, wait: Duration) {
+    tracing::debug!(
+        failures,
+        wait_secs = wait.as_secs(),
+        "checks keep failing, backing off"
+    );
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn failing(_failures: u32, _wait: Duration) {}
@@ -0,0 +1,101 @@
+//! Checks whether a registry mirror or cache is masking the real limit
+//!
+//! Two `HEAD` checks are made a few seconds apart, so neither uses up the limit
+
+use super::api::DrlClient;
+use super::err::DrlResult;
+use super::limit::{Limit, Probe};
+use super::token::Token;
+use reqwest::Method;
+use serde::Serialize;
+use std::fmt;
+use std::time::Duration;
+use tokio::time;
+
+/// Outcome of the two checks
+#[derive(Serialize, Debug, Clone)]
+pub struct Verification {
+    /// User the limit was checked for, `None` for anonymous
+    pub user: Option<String>,
+    /// Limit from the second check, along with what it's keyed to
+    #[serde(flatten)]
+    pub limit: Limit,
+    /// Whether the numbers look like they come from a cache
+    pub cache_suspected: bool,
+    /// Why a cache is suspected, empty if it isn't
+    pub reasons: Vec<String>,
+}
+
+impl Verification {
+    /// Compares two probes of the same identity, `first` taken before `second`
+    ///
+    /// A cache is suspected if either response has cache headers, or if the limit is keyed to
+    /// an IP that is already being used but the remaining count didn't move between checks
+    ///
+    /// # Arguments
+    ///
+    /// * `user` - user the limit was checked for, `None` for anonymous
+    /// * `first` - `Probe` from the first check
+    /// * `second` - `Probe` from the second check
+    pub fn compare(user: Option<String>, first: &Probe, second: Probe) -> Verification {
+        let mut reasons = Vec::new();
+
+        for (name, value) in first.cache_headers.iter().chain(&second.cache_headers) {
+            let reason = format!("response has cache header {}: {}", name, value);
+            if !reasons.contains(&reason) {
+                reasons.push(reason);
+            }
+        }
+
+        let shared_ip = second.limit.keyed_to_ip();
+        let in_use = second.limit.remaining < second.limit.total;
+        if shared_ip && in_use && first.limit.remaining == second.limit.remaining {
+            let reason = format!(
+                "remaining stayed at {} for shared IP {}",
+                second.limit.remaining,
+                second.limit.source.as_deref().unwrap_or_default()
+            );
+            reasons.push(reason);
+        }
+
+        Verification {
+            user,
+            limit: second.limit,
+            cache_suspected: !reasons.is_empty(),
+            reasons,
+        }
+    }
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.limit)?;
+        if self.cache_suspected {
+            write!(f, " (cache suspected)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks the limit twice, `delay` apart, and compares the results
+///
+/// # Arguments
+///
+/// * `client` - `DrlClient` to check with
+/// * `t` - `Token` from `client`
+/// * `user` - user the token is for, `None` for anonymous
+/// * `delay` - time to wait between the checks
+pub async fn verify(
+    client: &DrlClient,
+    t: &Token,
+    user: Option<String>,
+    delay: Duration,
+) -> DrlResult<Verification> {
+    let first = client.probe(t, Method::HEAD).await?;
+    first.limited()?;
+    time::sleep(delay).await;
+    let second = client.probe(t, Method::HEAD).await?;
+    second.limited()?;
+
+    Ok(Verification::compare(user, &first, second))
+}
@@ -0,0 +1,256 @@
+//! Lines printed by `--watch`, one for every check, and the summary printed when it stops
+
+use super::err::DrlResult;
+use super::human;
+use super::limit::Limit;
+use super::timestamp::{Timestamp, Timestamps};
+use serde::Serialize;
+use std::fmt;
+use std::time::SystemTime;
+
+/// Outcome of one check while watching
+///
+/// With `--format json` each is one line of JSON, `error` in place of the limit for a failed
+/// check
+#[derive(Serialize, Debug, Clone)]
+pub struct Record {
+    /// When the check finished
+    pub checked_at: Timestamp,
+    /// User the limit was checked for, `None` for anonymous
+    pub user: Option<String>,
+    /// The limit, if the check succeeded
+    #[serde(flatten)]
+    pub limit: Option<Limit>,
+    /// Why the check failed, if it did
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Record {
+    /// Records `result`, checked at `now`
+    ///
+    /// # Arguments
+    ///
+    /// * `user` - user the limit was checked for, `None` for anonymous
+    /// * `result` - the limit, or why it couldn't be checked
+    /// * `now` - when the check finished
+    /// * `timestamps` - how to write `now`
+    pub fn new(
+        user: Option<String>,
+        result: &DrlResult<Limit>,
+        now: SystemTime,
+        timestamps: &Timestamps,
+    ) -> Record {
+        Record {
+            checked_at: timestamps.render(now),
+            user,
+            limit: result.as_ref().ok().cloned(),
+            error: result.as_ref().err().map(|e| e.msg.clone()),
+        }
+    }
+
+    /// Same as the `Display` output, with the limit formatted by `human::limit`
+    pub fn human(&self) -> String {
+        match &self.limit {
+            Some(limit) => format!("{} {}", self.checked_at, human::limit(limit)),
+            None => self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.limit, &self.error) {
+            (Some(limit), _) => write!(f, "{} {}", self.checked_at, limit),
+            (None, Some(error)) => write!(f, "{} error: {}", self.checked_at, error),
+            (None, None) => write!(f, "{}", self.checked_at),
+        }
+    }
+}
+
+/// What a watch saw, printed when it's stopped
+///
+/// With `--format json` it is also the last line, see `SummaryLine`
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct Summary {
+    /// Number of checks, failed ones included
+    pub polls: u64,
+    /// Number of failed checks
+    pub errors: u64,
+    /// Fewest pulls remaining at a check, `None` before the first that succeeded
+    pub min_remaining: Option<u64>,
+    /// Most pulls remaining at a check, `None` before the first that succeeded
+    pub max_remaining: Option<u64>,
+    /// Pulls used up between checks
+    ///
+    /// Counted from each drop in the remaining pulls, so pulls made before the window resets
+    /// in between two checks are missed
+    pub consumed: u64,
+    /// Pulls used up per hour, from the first check that succeeded to the last
+    ///
+    /// `None` until two checks that succeeded are apart in time
+    pub consumed_per_hour: Option<f64>,
+    /// Pulls remaining at the last check that succeeded
+    #[serde(skip)]
+    last: Option<u64>,
+    /// When the first check that succeeded finished
+    #[serde(skip)]
+    first_at: Option<SystemTime>,
+}
+
+impl Summary {
+    /// Counts the check that ended with `result`
+    ///
+    /// # Arguments
+    ///
+    /// * `result` - the limit, or why it couldn't be checked
+    /// * `at` - when the check finished
+    pub fn add(&mut self, result: &DrlResult<Limit>, at: SystemTime) {
+        self.polls += 1;
+        let remaining = match result {
+            Ok(limit) => limit.remaining,
+            Err(_) => {
+                self.errors += 1;
+                return;
+            }
+        };
+
+        if let Some(last) = self.last {
+            self.consumed += last.saturating_sub(remaining);
+        }
+        self.last = Some(remaining);
+        let first_at = *self.first_at.get_or_insert(at);
+        let span = at.duration_since(first_at).unwrap_or_default();
+        if !span.is_zero() {
+            let per_hour = self.consumed as f64 * 3600.0 / span.as_secs_f64();
+            self.consumed_per_hour = Some(per_hour);
+        }
+        self.min_remaining = Some(self.min_remaining.map_or(remaining, |m| m.min(remaining)));
+        self.max_remaining = Some(self.max_remaining.map_or(remaining, |m| m.max(remaining)));
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural = if self.polls == 1 { "" } else { "s" };
+        write!(f, "{} poll{}", self.polls, plural)?;
+        if self.errors > 0 {
+            write!(f, " ({} failed)", self.errors)?;
+        }
+        match (self.min_remaining, self.max_remaining) {
+            (Some(min), Some(max)) => write!(
+                f,
+                ", remaining min {} max {}, {} consumed",
+                min, max, self.consumed
+            )?,
+            _ => return write!(f, ", no limit seen"),
+        }
+        match self.consumed_per_hour {
+            Some(per_hour) => write!(f, " (~{:.0}/h)", per_hour.ceil()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The last line of `--format json`, the summary under `summary` to tell it from the records
+#[derive(Serialize, Debug, Clone)]
+pub struct SummaryLine<'a> {
+    pub summary: &'a Summary,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::err::{DrlErr, ExitCode};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    /// `minutes` after the first check
+    fn at(minutes: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_600_000_000 + minutes * 60)
+    }
+
+    fn remaining(remaining: u64) -> DrlResult<Limit> {
+        Ok(Limit {
+            remaining,
+            total: 100,
+            ..Limit::default()
+        })
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(Summary::default().to_string(), "0 polls, no limit seen");
+    }
+
+    #[test]
+    fn consumed_across_a_reset() {
+        let mut summary = Summary::default();
+        let checks = [
+            remaining(90),
+            remaining(85),
+            Err(DrlErr::new("timed out".into(), ExitCode::Timeout)),
+            remaining(80),
+            // the window reset in between
+            remaining(100),
+            remaining(97),
+        ];
+        for (minutes, result) in (0..).step_by(10).zip(&checks) {
+            summary.add(result, at(minutes));
+        }
+
+        assert_eq!(summary.consumed, 13);
+        // 13 in the 50 minutes from the first check to the last
+        assert_eq!(summary.consumed_per_hour, Some(15.6));
+        assert_eq!(
+            summary.to_string(),
+            "6 polls (1 failed), remaining min 80 max 100, 13 consumed (~16/h)"
+        );
+    }
+
+    #[test]
+    fn rate_from_the_first_success() {
+        let mut summary = Summary::default();
+        summary.add(
+            &Err(DrlErr::new("refused".into(), ExitCode::Connection)),
+            at(0),
+        );
+        summary.add(&remaining(90), at(60));
+        assert_eq!(summary.consumed_per_hour, None);
+        assert_eq!(
+            summary.to_string(),
+            "2 polls (1 failed), remaining min 90 max 90, 0 consumed"
+        );
+
+        summary.add(&remaining(60), at(90));
+        assert_eq!(summary.consumed_per_hour, Some(60.0));
+    }
+
+    #[test]
+    fn only_failures() {
+        let mut summary = Summary::default();
+        let refused = Err(DrlErr::new("refused".into(), ExitCode::Connection));
+        summary.add(&refused, at(0));
+        assert_eq!(summary.to_string(), "1 poll (1 failed), no limit seen");
+    }
+
+    #[test]
+    fn summary_line() {
+        let mut summary = Summary::default();
+        summary.add(&remaining(90), at(0));
+        summary.add(&remaining(80), at(30));
+        let line = serde_json::to_value(SummaryLine { summary: &summary }).unwrap();
+        assert_eq!(
+            line,
+            serde_json::json!({
+                "summary": {
+                    "polls": 2,
+                    "errors": 0,
+                    "min_remaining": 80,
+                    "max_remaining": 90,
+                    "consumed": 10,
+                    "consumed_per_hour": 20.0,
+                }
+            })
+        );
+    }
+}
@@ -0,0 +1,109 @@
+//! Checks of many accounts at once, through the binary against a mock registry
+
+mod common;
+
+use common::{Cli, MockServer};
+use libdocker_rl::err::ExitCode;
+use std::time::Duration;
+
+#[tokio::test]
+async fn concurrency_caps_requests_in_flight() {
+    let registry = common::registry(42, 100);
+    let mock = MockServer::start(move |req| match req.path.as_str() {
+        // slow enough for every check to pile up on the token service without a cap
+        "/token" => registry(req).delay(Duration::from_millis(200)),
+        _ => registry(req),
+    })
+    .await;
+    let cli = Cli::new(&mock);
+
+    let users: Vec<_> = (0..6).map(|i| format!("user{}", i)).collect();
+    let mut cmd = cli.command(&[
+        "--users-from-stdin",
+        "--password-env-prefix",
+        "PASS_",
+        "--concurrency",
+        "2",
+    ]);
+    for user in &users {
+        cmd.env(format!("PASS_{}", user.to_uppercase()), "secret");
+    }
+    let out = common::output(cmd, Some(&users.join("\n"))).await;
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    assert_eq!(out.stdout.lines().count(), users.len());
+
+    let tokens = mock
+        .requests()
+        .iter()
+        .filter(|r| r.path == "/token")
+        .count();
+    assert_eq!(tokens, users.len());
+    assert_eq!(mock.max_in_flight(), 2);
+}
+
+/// Runs `--users-from-stdin` for `users`, with `PASS_` as the prefix and nothing in it
+async fn check_users(cli: &Cli, users: &str) -> common::Output {
+    cli.run_with_stdin(
+        &["--users-from-stdin", "--password-env-prefix", "PASS_"],
+        users,
+    )
+    .await
+}
+
+#[tokio::test]
+async fn missing_variables_fall_back_to_docker_login() {
+    let mock = MockServer::start(common::registry(42, 100)).await;
+    let cli = Cli::new(&mock);
+
+    let docker = cli.dir().join("docker");
+    std::fs::create_dir_all(&docker).unwrap();
+    let auth = base64::encode("ci-bot:secret");
+    let config = format!(
+        r#"{{"auths": {{"https://index.docker.io/v1/": {{"auth": "{}"}}}}}}"#,
+        auth
+    );
+    std::fs::write(docker.join("config.json"), config).unwrap();
+
+    let out = check_users(&cli, "ci-bot\nrelease\n").await;
+    assert_eq!(out.code, ExitCode::Input as i32, "{}", out.stderr);
+    let lines: Vec<_> = out.stdout.lines().collect();
+    assert_eq!(lines[0], "ci-bot: 42/100");
+    assert_eq!(
+        lines[1],
+        "release: no password for release in PASS_RELEASE or the docker credentials"
+    );
+
+    let tokens: Vec<_> = mock
+        .requests()
+        .into_iter()
+        .filter(|r| r.path == "/token")
+        .collect();
+    assert_eq!(tokens.len(), 1);
+    let expected = format!("Basic {}", auth);
+    assert_eq!(tokens[0].header("authorization"), Some(expected.as_str()));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn missing_variables_fall_back_to_the_credential_helper() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mock = MockServer::start(common::registry(42, 100)).await;
+    let cli = Cli::new(&mock);
+
+    let docker = cli.dir().join("docker");
+    std::fs::create_dir_all(&docker).unwrap();
+    std::fs::write(docker.join("config.json"), r#"{"credsStore": "stub"}"#).unwrap();
+    let helper = cli.dir().join("docker-credential-stub");
+    let script =
+        "#!/bin/sh\nread server\necho '{\"Username\": \"ci-bot\", \"Secret\": \"secret\"}'\n";
+    std::fs::write(&helper, script).unwrap();
+    std::fs::set_permissions(&helper, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let mut cmd = cli.command(&["--users-from-stdin", "--password-env-prefix", "PASS_"]);
+    let path = std::env::var("PATH").unwrap_or_default();
+    cmd.env("PATH", format!("{}:{}", cli.dir().display(), path));
+    let out = common::output(cmd, Some("ci-bot\n")).await;
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    assert_eq!(out.stdout, "ci-bot: 42/100\n");
+}
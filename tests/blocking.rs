@@ -0,0 +1,64 @@
+//! The blocking client against a mock registry, from threads without a runtime
+//!
+//! Only built with the `blocking` feature
+
+#![cfg(feature = "blocking")]
+
+mod common;
+
+use common::MockServer;
+use libdocker_rl::blocking::DrlClient;
+use libdocker_rl::err::ExitCode;
+
+#[test]
+fn checks_on_one_runtime() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let mock = rt.block_on(MockServer::start(common::registry(42, 100)));
+    let client = DrlClient::new(mock.client()).unwrap();
+
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let client = DrlClient::new(mock.client()).unwrap();
+            std::thread::spawn(move || {
+                let token = client.token(None).unwrap();
+                client.limit(&token).unwrap()
+            })
+        })
+        .collect();
+    for thread in threads {
+        assert_eq!(thread.join().unwrap().remaining, 42);
+    }
+
+    let token = client.token(None).unwrap();
+    assert_eq!(client.limit(&token).unwrap().total, 100);
+}
+
+#[tokio::test]
+async fn refused_inside_a_runtime() {
+    let mock = MockServer::start(common::registry(42, 100)).await;
+
+    let err = DrlClient::new(mock.client()).err().unwrap();
+    assert_eq!(err.ret, ExitCode::Input);
+    let err = libdocker_rl::blocking::get_anon_token().unwrap_err();
+    assert_eq!(err.ret, ExitCode::Input);
+    assert!(err.msg.contains("within an async runtime"), "{}", err.msg);
+
+    // a blocking task of the runtime is still inside it
+    let err = tokio::task::spawn_blocking(libdocker_rl::blocking::get_anon_token)
+        .await
+        .unwrap()
+        .unwrap_err();
+    assert_eq!(err.ret, ExitCode::Input);
+}
+
+#[test]
+fn client_made_outside_used_inside_a_runtime() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let mock = rt.block_on(MockServer::start(common::registry(42, 100)));
+    let client = DrlClient::new(mock.client()).unwrap();
+
+    let err = rt.block_on(async { client.token(None) }).unwrap_err();
+    assert_eq!(err.ret, ExitCode::Input);
+    // fine again once outside
+    assert!(client.token(None).is_ok());
+}
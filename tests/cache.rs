@@ -0,0 +1,103 @@
+//! `docker-rl cache` on the tokens checks against a mock registry keep with `--token-cache`
+
+mod common;
+
+use common::{Cli, MockServer, Output};
+use serde_json::Value;
+
+/// Checks as `user`, keeping the token
+async fn check(cli: &Cli, user: &str) -> Output {
+    let mut cmd = cli.command(&["--token-cache", "--user", user]);
+    cmd.env("DOCKER_RL_PASS", "secret");
+    common::output(cmd, None).await
+}
+
+/// Runs `cache` with `args`
+async fn cache(cli: &Cli, args: &[&str]) -> Output {
+    let mut all = vec!["cache"];
+    all.extend_from_slice(args);
+    common::output(cli.bare_command(&all), None).await
+}
+
+/// Users of the tokens `cache list` shows
+async fn listed(cli: &Cli) -> Vec<String> {
+    let out = cache(cli, &["list", "--format", "json"]).await;
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    let listing: Value = serde_json::from_str(&out.stdout).unwrap();
+    let mut users: Vec<_> = listing["tokens"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|t| t["user"].as_str().unwrap().to_string())
+        .collect();
+    users.sort();
+    users
+}
+
+#[tokio::test]
+async fn list_and_clear() {
+    let mock = MockServer::start(common::registry(42, 100)).await;
+    let cli = Cli::new(&mock);
+    for user in ["alice", "bob"] {
+        let out = check(&cli, user).await;
+        assert_eq!(out.code, 0, "{}", out.stderr);
+    }
+
+    let path = cache(&cli, &["path"]).await;
+    let file = cli.dir().join("cache/docker-rl/token.json");
+    assert_eq!(path.stdout.trim(), file.to_str().unwrap());
+
+    let out = cache(&cli, &["list"]).await;
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    assert!(!out.stdout.contains("mock-token"), "{}", out.stdout);
+    let lines: Vec<_> = out.stdout.lines().collect();
+    assert_eq!(lines[0], file.to_str().unwrap());
+    assert!(lines[1].contains(" alice repository:ratelimitpreview/test:pull expires 20"));
+    assert_eq!(listed(&cli).await, ["alice", "bob"]);
+
+    let out = cache(&cli, &["clear", "--user", "alice"]).await;
+    assert_eq!(out.stdout, "removed 1 cached token of alice\n");
+    assert_eq!(listed(&cli).await, ["bob"]);
+
+    let out = cache(&cli, &["clear"]).await;
+    assert_eq!(out.stdout, "removed 1 cached token\n");
+    assert!(!file.exists());
+    assert!(listed(&cli).await.is_empty());
+}
+
+#[tokio::test]
+async fn corrupt_file_reported() {
+    let mock = MockServer::start(common::registry(42, 100)).await;
+    let cli = Cli::new(&mock);
+    let dir = cli.dir().join("cache/docker-rl");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("token.json"), "{not json").unwrap();
+
+    let out = cache(&cli, &["list"]).await;
+    assert_eq!(out.code, 5, "{}", out.stderr);
+    assert!(out.stderr.contains("corrupt token cache"), "{}", out.stderr);
+    let out = cache(&cli, &["clear", "--user", "alice"]).await;
+    assert_eq!(out.code, 5, "{}", out.stderr);
+
+    let out = cache(&cli, &["clear"]).await;
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    assert!(listed(&cli).await.is_empty());
+}
+
+#[tokio::test]
+async fn concurrent_changes_kept() {
+    let mock = MockServer::start(common::registry(42, 100)).await;
+    let cli = Cli::new(&mock);
+    let out = check(&cli, "victim").await;
+    assert_eq!(out.code, 0, "{}", out.stderr);
+
+    let users: Vec<_> = (0..8).map(|i| format!("user{}", i)).collect();
+    let checks = users.iter().map(|user| check(&cli, user));
+    let clear = cache(&cli, &["clear", "--user", "victim"]);
+    let (outputs, cleared) = futures::join!(futures::future::join_all(checks), clear);
+    for out in outputs.iter().chain([&cleared]) {
+        assert_eq!(out.code, 0, "{}", out.stderr);
+    }
+
+    assert_eq!(listed(&cli).await, users);
+}
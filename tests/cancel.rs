@@ -0,0 +1,131 @@
+//! Cancelling checks against a mock registry that never answers them
+
+mod common;
+
+use common::{Cli, MockServer, Reply};
+use libdocker_rl::cancel::cancellable;
+use libdocker_rl::err::ExitCode;
+use std::future;
+use std::time::Duration;
+use tokio::time::{self, Instant};
+
+/// Longer than any test runs, so a request answered after it never finished
+const HANG: Duration = Duration::from_secs(3600);
+
+/// Longest a cancelled check may take to give up
+const PROMPTLY: Duration = Duration::from_secs(5);
+
+/// A registry that can be discovered and hands out tokens, but never answers the checks
+async fn hanging() -> MockServer {
+    let registry = common::registry(42, 100);
+    MockServer::start(move |req| match req.path.as_str() {
+        path if path.contains("/manifests/") => Reply::limit(42, 100).delay(HANG),
+        _ => registry(req),
+    })
+    .await
+}
+
+/// Waits until `mock` got a check
+async fn checking(mock: &MockServer) {
+    let deadline = Instant::now() + PROMPTLY;
+    while Instant::now() < deadline {
+        if mock
+            .requests()
+            .iter()
+            .any(|r| r.path.contains("/manifests/"))
+        {
+            return;
+        }
+        time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("no check reached the mock");
+}
+
+#[tokio::test]
+async fn cancels_a_request_in_flight() {
+    let mock = hanging().await;
+    let client = mock.client();
+    let token = client.token(None).await.unwrap();
+
+    let start = Instant::now();
+    let cancel = async {
+        checking(&mock).await;
+        time::sleep(Duration::from_millis(50)).await;
+    };
+    let err = cancellable(client.limit(&token), cancel).await.unwrap_err();
+
+    assert_eq!(err.ret, ExitCode::Cancelled);
+    assert!(start.elapsed() < PROMPTLY, "{:?}", start.elapsed());
+}
+
+#[tokio::test]
+async fn cancels_before_the_token() {
+    let mock = MockServer::start(|_| Reply::token().delay(HANG)).await;
+    let client = mock.client();
+
+    let start = Instant::now();
+    let cancel = time::sleep(Duration::from_millis(50));
+    let err = cancellable(client.token(None), cancel).await.unwrap_err();
+    assert_eq!(err.ret, ExitCode::Cancelled);
+    assert!(start.elapsed() < PROMPTLY);
+}
+
+#[tokio::test]
+async fn cancellation_wins_a_tie() {
+    let done = async { Ok::<_, libdocker_rl::err::DrlErr>(42) };
+    let err = cancellable(done, future::ready(())).await.unwrap_err();
+    assert_eq!(err.ret, ExitCode::Cancelled);
+
+    let done = async { Ok::<_, libdocker_rl::err::DrlErr>(42) };
+    let value = cancellable(done, future::pending::<()>()).await.unwrap();
+    assert_eq!(value, 42);
+}
+
+/// Starts a one-shot check of `mock`, sends it SIGINT once `ready` and returns its output
+#[cfg(unix)]
+async fn interrupted<F: std::future::Future>(mock: &MockServer, ready: F) -> common::Output {
+    let cli = Cli::new(mock);
+    let mut cmd = cli.command(&[]);
+    cmd.stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped());
+    let child = cmd.spawn().unwrap();
+
+    ready.await;
+    let start = Instant::now();
+    // a child of this process, which hasn't been waited for yet
+    let pid = child.id().unwrap() as libc::pid_t;
+    assert_eq!(unsafe { libc::kill(pid, libc::SIGINT) }, 0);
+
+    let out = time::timeout(PROMPTLY, child.wait_with_output())
+        .await
+        .expect("the check wasn't cancelled")
+        .unwrap();
+    assert!(start.elapsed() < PROMPTLY);
+    common::Output {
+        code: out.status.code().unwrap_or(-1),
+        stdout: String::from_utf8_lossy(&out.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&out.stderr).into_owned(),
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn ctrl_c_ends_a_one_shot_check() {
+    let mock = hanging().await;
+    let out = interrupted(&mock, checking(&mock)).await;
+    assert_eq!(out.code, ExitCode::Cancelled as i32, "{}", out.stderr);
+    assert!(out.stderr.contains("cancelled"), "{}", out.stderr);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn ctrl_c_ends_discovery() {
+    let mock = MockServer::start(|_| Reply::status(401).delay(HANG)).await;
+    let discovering = async {
+        while mock.requests().is_empty() {
+            time::sleep(Duration::from_millis(20)).await;
+        }
+    };
+    let out = interrupted(&mock, discovering).await;
+    assert_eq!(out.code, ExitCode::Cancelled as i32, "{}", out.stderr);
+}
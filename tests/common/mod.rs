@@ -0,0 +1,329 @@
+//! A registry and token service on localhost, so checks can be tested without docker.io
+//!
+//! Every file under `tests/` is its own crate, so not every helper is used by every one
+
+#![allow(dead_code)]
+
+pub mod schema;
+#[cfg(not(any(target_os = "macos", windows)))]
+pub mod tls;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, HeaderMap, Server};
+use libdocker_rl::api::DrlClient;
+use libdocker_rl::registry::Registry;
+use libdocker_rl::retry::RetryPolicy;
+use reqwest::Url;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// Service the mock hands out tokens for
+pub const SERVICE: &str = "mock";
+
+/// A request the mock received
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    pub headers: HeaderMap,
+    pub body: String,
+}
+
+impl Request {
+    /// Value of header `name`, if it's there and text
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|v| v.to_str().ok())
+    }
+}
+
+/// What the mock answers a request with
+#[derive(Debug, Clone)]
+pub struct Reply {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+    delay: Duration,
+}
+
+impl Reply {
+    /// An empty answer with `status`
+    pub fn status(status: u16) -> Reply {
+        Reply {
+            status,
+            headers: Vec::new(),
+            body: String::new(),
+            delay: Duration::default(),
+        }
+    }
+
+    /// A token valid for five minutes from now
+    pub fn token() -> Reply {
+        let issued_at = humantime::format_rfc3339_seconds(SystemTime::now());
+        let body = format!(
+            r#"{{"token":"mock-token","expires_in":300,"issued_at":"{}"}}"#,
+            issued_at
+        );
+        Reply::status(200)
+            .header("content-type", "application/json")
+            .body(&body)
+    }
+
+    /// A manifest reporting `remaining` out of `total` pulls in a six hour window
+    pub fn limit(remaining: u64, total: u64) -> Reply {
+        Reply::status(200)
+            .header("ratelimit-limit", &format!("{};w=21600", total))
+            .header("ratelimit-remaining", &format!("{};w=21600", remaining))
+    }
+
+    /// Adds header `name`
+    pub fn header(mut self, name: &str, value: &str) -> Reply {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Answers with `body`
+    pub fn body(mut self, body: &str) -> Reply {
+        self.body = body.into();
+        self
+    }
+
+    /// Waits `delay` before answering
+    pub fn delay(mut self, delay: Duration) -> Reply {
+        self.delay = delay;
+        self
+    }
+}
+
+/// Answers a registry that asks for tokens from `/token`, and reports `remaining`/`total`
+///
+/// `/v2/` sends the bearer challenge, so the binary can discover the realm like for any
+/// other registry
+pub fn registry(remaining: u64, total: u64) -> impl Fn(&Request) -> Reply + Send + Sync {
+    move |req: &Request| match req.path.as_str() {
+        "/v2/" => {
+            let host = req.header("host").unwrap_or_default();
+            let challenge = format!(
+                "Bearer realm=\"http://{}/token\",service=\"{}\"",
+                host, SERVICE
+            );
+            Reply::status(401).header("www-authenticate", &challenge)
+        }
+        "/token" => Reply::token(),
+        _ => Reply::limit(remaining, total),
+    }
+}
+
+#[derive(Default)]
+struct State {
+    requests: Mutex<Vec<Request>>,
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+}
+
+/// A mock server on a free port of localhost, stopped with the runtime it was started on
+pub struct MockServer {
+    pub url: Url,
+    state: Arc<State>,
+}
+
+impl MockServer {
+    /// Starts the server, answering every request with `handler`
+    pub async fn start<F>(handler: F) -> MockServer
+    where
+        F: Fn(&Request) -> Reply + Send + Sync + 'static,
+    {
+        let handler = Arc::new(handler);
+        let state = Arc::new(State::default());
+
+        let (handler2, state2) = (handler.clone(), state.clone());
+        let make = make_service_fn(move |_| {
+            let (handler, state) = (handler2.clone(), state2.clone());
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: hyper::Request<Body>| {
+                    let (handler, state) = (handler.clone(), state.clone());
+                    async move { Ok::<_, Infallible>(answer(&*handler, &state, req).await) }
+                }))
+            }
+        });
+
+        let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+        let server = Server::bind(&addr).serve(make);
+        let url = Url::parse(&format!("http://{}", server.local_addr())).unwrap();
+        tokio::spawn(server);
+
+        MockServer { url, state }
+    }
+
+    /// The mock as a `Registry`, with its token endpoint so nothing has to be discovered
+    pub fn registry(&self) -> Registry {
+        let realm = self.url.join("/token").unwrap();
+        Registry::new(self.url.clone()).with_realm(realm, Some(SERVICE))
+    }
+
+    /// A `DrlClient` for the mock that never retries
+    pub fn client(&self) -> DrlClient {
+        DrlClient::builder()
+            .registry(self.registry())
+            .retry(RetryPolicy::none())
+            .build()
+            .unwrap()
+    }
+
+    /// Every request received so far, in order
+    pub fn requests(&self) -> Vec<Request> {
+        self.state.requests.lock().unwrap().clone()
+    }
+
+    /// Most requests that were being answered at the same time
+    pub fn max_in_flight(&self) -> usize {
+        self.state.max_in_flight.load(Ordering::SeqCst)
+    }
+}
+
+async fn answer<F>(handler: &F, state: &State, req: hyper::Request<Body>) -> hyper::Response<Body>
+where
+    F: Fn(&Request) -> Reply,
+{
+    let in_flight = state.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+    state.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+
+    let (parts, body) = req.into_parts();
+    let body = hyper::body::to_bytes(body).await.unwrap_or_default();
+    let req = Request {
+        method: parts.method.to_string(),
+        path: parts.uri.path().into(),
+        query: parts.uri.query().unwrap_or_default().into(),
+        headers: parts.headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    };
+    let reply = handler(&req);
+    state.requests.lock().unwrap().push(req);
+    tokio::time::sleep(reply.delay).await;
+
+    let mut resp = hyper::Response::builder().status(reply.status);
+    for (name, value) in &reply.headers {
+        resp = resp.header(name.as_str(), value.as_str());
+    }
+    let resp = resp.body(Body::from(reply.body)).unwrap();
+
+    state.in_flight.fetch_sub(1, Ordering::SeqCst);
+    resp
+}
+
+/// What a run of the binary printed, and its exit code
+#[derive(Debug)]
+pub struct Output {
+    pub code: i32,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Runs the binary against a mock, away from the docker config, keyring and caches of the
+/// machine, in a directory of its own that is removed with it
+pub struct Cli {
+    dir: PathBuf,
+    registry: Url,
+}
+
+impl Cli {
+    /// Runs against `mock`
+    pub fn new(mock: &MockServer) -> Cli {
+        Cli::with_registry(mock.url.clone())
+    }
+
+    /// Runs against the registry at `registry`, for mocks other than `MockServer`
+    pub fn with_registry(registry: Url) -> Cli {
+        static RUNS: AtomicUsize = AtomicUsize::new(0);
+        let run = RUNS.fetch_add(1, Ordering::SeqCst);
+        let name = format!("docker-rl-test-{}-{}", std::process::id(), run);
+        let dir = std::env::temp_dir().join(name);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        Cli { dir, registry }
+    }
+
+    /// The directory used as home, with `cache`, `config` and `docker` under it
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The binary with `args`, and the options pointing it at the mock after its subcommand
+    pub fn command(&self, args: &[&str]) -> Command {
+        let registry = self.registry.as_str();
+        let base = ["--registry", registry, "--retries", "0", "--no-keyring"];
+        self.bare_command(&with_options(args, &base))
+    }
+
+    /// The binary with only `args`, for subcommands that don't take the options of a check
+    pub fn bare_command(&self, args: &[&str]) -> Command {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_docker-rl"));
+        cmd.env_clear()
+            .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+            .env("HOME", &self.dir)
+            .env("XDG_CACHE_HOME", self.dir.join("cache"))
+            .env("XDG_CONFIG_HOME", self.dir.join("config"))
+            .env("DOCKER_CONFIG", self.dir.join("docker"))
+            .env("TZ", "UTC")
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        cmd
+    }
+
+    /// Runs the binary with `args` to the end
+    pub async fn run(&self, args: &[&str]) -> Output {
+        output(self.command(args), None).await
+    }
+
+    /// Runs the binary with `args`, writing `stdin` to it
+    pub async fn run_with_stdin(&self, args: &[&str], stdin: &str) -> Output {
+        output(self.command(args), Some(stdin)).await
+    }
+}
+
+/// `args` with `options` added, after the subcommand if `args` starts with one
+///
+/// Options can only be given after the subcommand
+pub fn with_options<'a>(args: &[&'a str], options: &[&'a str]) -> Vec<&'a str> {
+    let at = match args.first() {
+        Some(first) if !first.starts_with('-') => 1,
+        _ => 0,
+    };
+    let mut all = args.to_vec();
+    all.splice(at..at, options.iter().copied());
+    all
+}
+
+/// Runs `cmd` to the end, writing `stdin` to it if there is one
+pub async fn output(mut cmd: Command, stdin: Option<&str>) -> Output {
+    if stdin.is_some() {
+        cmd.stdin(Stdio::piped());
+    }
+    let mut child = cmd.spawn().unwrap();
+    if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        pipe.write_all(input.as_bytes()).await.unwrap();
+    }
+
+    let output = child.wait_with_output().await.unwrap();
+    Output {
+        code: output.status.code().unwrap_or(-1),
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    }
+}
+
+impl Drop for Cli {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
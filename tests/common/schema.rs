@@ -0,0 +1,131 @@
+//! Checks JSON against the `--schema` document, for the keywords it uses
+//!
+//! There is no JSON Schema crate among the dependencies, and the document only uses a small
+//! part of draft 2020-12: `$ref` within the document, `allOf`, `anyOf`, `oneOf`, `type`,
+//! `enum`, `const`, `minimum`, `required`, `properties`, `additionalProperties` and `items`
+
+use serde_json::Value;
+
+/// Every way `value` doesn't match `schema`, empty if it does
+pub fn violations(schema: &Value, value: &Value) -> Vec<String> {
+    let mut found = Vec::new();
+    check(schema, schema, value, "$", &mut found);
+    found
+}
+
+fn resolve<'a>(root: &'a Value, reference: &str) -> &'a Value {
+    let pointer = reference
+        .strip_prefix('#')
+        .unwrap_or_else(|| panic!("only local references are supported: {}", reference));
+    root.pointer(pointer)
+        .unwrap_or_else(|| panic!("dangling reference {}", reference))
+}
+
+fn has_type(value: &Value, name: &str) -> bool {
+    match name {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "string" => value.is_string(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        other => panic!("unknown type {}", other),
+    }
+}
+
+fn matches(root: &Value, schema: &Value, value: &Value) -> bool {
+    let mut found = Vec::new();
+    check(root, schema, value, "", &mut found);
+    found.is_empty()
+}
+
+fn check(root: &Value, schema: &Value, value: &Value, path: &str, found: &mut Vec<String>) {
+    let schema = match schema {
+        Value::Bool(true) => return,
+        Value::Bool(false) => return found.push(format!("{}: nothing is allowed", path)),
+        Value::Object(map) => map,
+        other => panic!("invalid schema {}", other),
+    };
+
+    if let Some(Value::String(reference)) = schema.get("$ref") {
+        check(root, resolve(root, reference), value, path, found);
+    }
+    if let Some(Value::Array(all)) = schema.get("allOf") {
+        for s in all {
+            check(root, s, value, path, found);
+        }
+    }
+    if let Some(Value::Array(any)) = schema.get("anyOf") {
+        if !any.iter().any(|s| matches(root, s, value)) {
+            found.push(format!("{}: matches none of anyOf", path));
+        }
+    }
+    if let Some(Value::Array(one)) = schema.get("oneOf") {
+        let count = one.iter().filter(|s| matches(root, s, value)).count();
+        if count != 1 {
+            found.push(format!("{}: matches {} of oneOf", path, count));
+        }
+    }
+
+    match schema.get("type") {
+        Some(Value::String(t)) if !has_type(value, t) => {
+            found.push(format!("{}: expected {}, got {}", path, t, value))
+        }
+        Some(Value::Array(types))
+            if !types.iter().any(|t| has_type(value, t.as_str().unwrap())) =>
+        {
+            found.push(format!(
+                "{}: expected one of {:?}, got {}",
+                path, types, value
+            ))
+        }
+        _ => (),
+    }
+    if let Some(Value::Array(options)) = schema.get("enum") {
+        if !options.contains(value) {
+            found.push(format!("{}: {} not in {:?}", path, value, options));
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if expected != value {
+            found.push(format!("{}: expected {}, got {}", path, expected, value));
+        }
+    }
+    if let (Some(min), Some(n)) = (
+        schema.get("minimum").and_then(Value::as_f64),
+        value.as_f64(),
+    ) {
+        if n < min {
+            found.push(format!("{}: {} is below {}", path, n, min));
+        }
+    }
+
+    if let Value::Object(object) = value {
+        if let Some(Value::Array(required)) = schema.get("required") {
+            for key in required.iter().filter_map(Value::as_str) {
+                if !object.contains_key(key) {
+                    found.push(format!("{}: missing {}", path, key));
+                }
+            }
+        }
+        let properties = schema.get("properties").and_then(Value::as_object);
+        for (key, v) in object {
+            let at = format!("{}.{}", path, key);
+            match (
+                properties.and_then(|p| p.get(key)),
+                schema.get("additionalProperties"),
+            ) {
+                (Some(s), _) => check(root, s, v, &at, found),
+                (None, Some(s)) => check(root, s, v, &at, found),
+                (None, None) => (),
+            }
+        }
+    }
+
+    if let (Value::Array(items), Some(s)) = (value, schema.get("items")) {
+        for (i, item) in items.iter().enumerate() {
+            check(root, s, item, &format!("{}[{}]", path, i), found);
+        }
+    }
+}
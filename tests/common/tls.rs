@@ -0,0 +1,242 @@
+//! A registry on localhost that only talks to clients with a certificate, for mTLS tests
+//!
+//! There is no certificate crate among the dependencies, so the certificates are made with
+//! openssl, which the TLS backend links on these platforms anyway. The server is a blocking
+//! openssl one on threads of its own, since reqwest's TLS backend can't ask clients for
+//! certificates, and it answers one request per connection
+
+use super::SERVICE;
+use openssl::asn1::Asn1Time;
+use openssl::bn::BigNum;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::rsa::Rsa;
+use openssl::ssl::{SslAcceptor, SslMethod, SslVerifyMode};
+use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName};
+use openssl::x509::{X509NameBuilder, X509};
+use reqwest::Url;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// Serial number of the next certificate made
+static SERIALS: AtomicU32 = AtomicU32::new(1);
+
+/// A certificate and its key
+pub struct Pair {
+    pub cert: X509,
+    pub key: PKey<Private>,
+}
+
+impl Pair {
+    /// A certificate for `name`, signed by `issuer` or else by itself as a CA
+    fn new(name: &str, issuer: Option<&Pair>) -> Pair {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut subject = X509NameBuilder::new().unwrap();
+        subject.append_entry_by_text("CN", name).unwrap();
+        let subject = subject.build();
+
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        let serial = BigNum::from_u32(SERIALS.fetch_add(1, Ordering::SeqCst)).unwrap();
+        cert.set_serial_number(&serial.to_asn1_integer().unwrap())
+            .unwrap();
+        cert.set_subject_name(&subject).unwrap();
+        cert.set_pubkey(&key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        match issuer {
+            Some(ca) => {
+                cert.set_issuer_name(ca.cert.subject_name()).unwrap();
+                let san = SubjectAlternativeName::new()
+                    .dns("localhost")
+                    .ip("127.0.0.1")
+                    .build(&cert.x509v3_context(Some(&ca.cert), None))
+                    .unwrap();
+                cert.append_extension(san).unwrap();
+                cert.sign(&ca.key, MessageDigest::sha256()).unwrap();
+            }
+            None => {
+                cert.set_issuer_name(&subject).unwrap();
+                let ca = BasicConstraints::new().critical().ca().build().unwrap();
+                cert.append_extension(ca).unwrap();
+                cert.sign(&key, MessageDigest::sha256()).unwrap();
+            }
+        }
+        Pair {
+            cert: cert.build(),
+            key,
+        }
+    }
+
+    /// Writes the certificate and key to `dir` as `<name>.pem` and `<name>.key`
+    pub fn write(&self, dir: &Path, name: &str) -> (PathBuf, PathBuf) {
+        let cert = dir.join(format!("{}.pem", name));
+        let key = dir.join(format!("{}.key", name));
+        fs::write(&cert, self.cert.to_pem().unwrap()).unwrap();
+        fs::write(&key, self.key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        (cert, key)
+    }
+}
+
+/// A CA, the registry's certificate and a client's, all signed by the CA
+pub struct Pki {
+    pub ca: Pair,
+    pub server: Pair,
+    pub client: Pair,
+}
+
+impl Pki {
+    pub fn new() -> Pki {
+        let ca = Pair::new("docker-rl test CA", None);
+        let server = Pair::new("localhost", Some(&ca));
+        let client = Pair::new("ci-bot", Some(&ca));
+        Pki { ca, server, client }
+    }
+}
+
+/// A request the registry answered, with the name of the client certificate it came with
+#[derive(Debug, Clone)]
+pub struct Seen {
+    pub path: String,
+    pub client: Option<String>,
+}
+
+/// The registry, answering `/v2/` with a challenge, `/token` with a token and anything else
+/// with a limit of 42/100, to clients with a certificate of `Pki::ca` only
+pub struct TlsRegistry {
+    pub url: Url,
+    seen: Arc<Mutex<Vec<Seen>>>,
+    refused: Arc<Mutex<usize>>,
+}
+
+impl TlsRegistry {
+    /// Starts serving on a free port of localhost, until the test ends
+    pub fn start(pki: &Pki) -> TlsRegistry {
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+        acceptor.set_certificate(&pki.server.cert).unwrap();
+        acceptor.set_private_key(&pki.server.key).unwrap();
+        acceptor
+            .cert_store_mut()
+            .add_cert(pki.ca.cert.clone())
+            .unwrap();
+        acceptor.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+        let acceptor = Arc::new(acceptor.build());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let url = Url::parse(&format!("https://localhost:{}", port)).unwrap();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let refused = Arc::new(Mutex::new(0));
+
+        let (registry, state, count) = (url.clone(), seen.clone(), refused.clone());
+        std::thread::spawn(move || {
+            for conn in listener.incoming().flatten() {
+                let (acceptor, registry) = (acceptor.clone(), registry.clone());
+                let (state, count) = (state.clone(), count.clone());
+                std::thread::spawn(move || match acceptor.accept(conn) {
+                    Ok(stream) => {
+                        let client = stream
+                            .ssl()
+                            .peer_certificate()
+                            .and_then(|c| common_name(&c));
+                        let mut stream = BufReader::new(stream);
+                        if let Some(path) = read_path(&mut stream) {
+                            let reply = answer(&path, &registry);
+                            // kept before answering, the binary may be done right after
+                            state.lock().unwrap().push(Seen { path, client });
+                            let _ = stream.get_mut().write_all(reply.as_bytes());
+                            let _ = stream.get_mut().shutdown();
+                        }
+                    }
+                    Err(_) => *count.lock().unwrap() += 1,
+                });
+            }
+        });
+
+        TlsRegistry { url, seen, refused }
+    }
+
+    /// Every request answered so far, in order
+    pub fn seen(&self) -> Vec<Seen> {
+        self.seen.lock().unwrap().clone()
+    }
+
+    /// Connections whose handshake failed, e.g. for lack of a client certificate
+    ///
+    /// The client may give up before the server is done failing the handshake, so this
+    /// waits up to a second for the first one
+    pub fn refused(&self) -> usize {
+        for _ in 0..20 {
+            let refused = *self.refused.lock().unwrap();
+            if refused > 0 {
+                return refused;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        0
+    }
+}
+
+/// The common name of `cert`
+fn common_name(cert: &X509) -> Option<String> {
+    let entry = cert.subject_name().entries_by_nid(Nid::COMMONNAME).next()?;
+    entry.data().as_utf8().ok().map(|s| s.to_string())
+}
+
+/// Reads a request up to the end of its headers, returning its path
+fn read_path(stream: &mut impl BufRead) -> Option<String> {
+    let mut line = String::new();
+    stream.read_line(&mut line).ok()?;
+    let path = line.split_whitespace().nth(1)?.to_string();
+    loop {
+        line.clear();
+        match stream.read_line(&mut line) {
+            Ok(0) | Err(_) => return None,
+            Ok(_) if line == "\r\n" => break,
+            Ok(_) => (),
+        }
+    }
+    Some(path.split('?').next().unwrap_or_default().to_string())
+}
+
+/// The whole response to a request for `path`
+fn answer(path: &str, registry: &Url) -> String {
+    let (status, headers, body) = match path {
+        "/v2/" => {
+            let realm = registry.join("/token").unwrap();
+            let challenge = format!(
+                "www-authenticate: Bearer realm=\"{}\",service=\"{}\"\r\n",
+                realm, SERVICE
+            );
+            ("401 Unauthorized", challenge, String::new())
+        }
+        "/token" => {
+            let issued_at = humantime::format_rfc3339_seconds(SystemTime::now());
+            let body = format!(
+                r#"{{"token":"mock-token","expires_in":300,"issued_at":"{}"}}"#,
+                issued_at
+            );
+            let headers = String::from("content-type: application/json\r\n");
+            ("200 OK", headers, body)
+        }
+        _ => {
+            let headers = "ratelimit-limit: 100;w=21600\r\nratelimit-remaining: 42;w=21600\r\n";
+            ("200 OK", headers.to_string(), String::new())
+        }
+    };
+    format!(
+        "HTTP/1.1 {}\r\n{}content-length: {}\r\nconnection: close\r\n\r\n{}",
+        status,
+        headers,
+        body.len(),
+        body
+    )
+}
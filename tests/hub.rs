@@ -0,0 +1,123 @@
+//! Plan lookups against a mock of the Docker Hub API, through `hub`
+
+mod common;
+
+use common::{MockServer, Reply};
+use libdocker_rl::api::DrlClient;
+use libdocker_rl::err::{ExitCode, Kind};
+use libdocker_rl::hub::{get_hub_user, get_plan};
+use libdocker_rl::retry::RetryPolicy;
+
+/// JWT the mock's login hands out
+const JWT: &str = "hub.jwt";
+
+/// A mock of the API answering the user endpoint with `user`
+async fn hub(user: &'static str) -> MockServer {
+    MockServer::start(move |req| match req.path.as_str() {
+        "/v2/users/login" if req.body.contains(r#""password":"secret""#) => {
+            Reply::status(200).body(&format!(r#"{{"token":"{}"}}"#, JWT))
+        }
+        "/v2/users/login" => Reply::status(401).body(r#"{"detail":"Incorrect authentication"}"#),
+        "/v2/user/" if req.header("authorization") == Some("Bearer hub.jwt") => {
+            Reply::status(200).body(user)
+        }
+        "/v2/user/" => Reply::status(401),
+        _ => Reply::status(404),
+    })
+    .await
+}
+
+/// A client looking up plans on `mock`
+fn client(mock: &MockServer) -> DrlClient {
+    DrlClient::builder()
+        .hub_url(mock.url.join("/v2").unwrap())
+        .retry(RetryPolicy::none())
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn plan_of_the_user() {
+    let mock = hub(r#"{"username":"dorrella","plan":"pro"}"#).await;
+    let client = client(&mock);
+    assert!(client.hub_url().ends_with("/v2"));
+
+    let user = get_hub_user(&client, "dorrella", "secret").await.unwrap();
+    assert_eq!(user.username, "dorrella");
+    assert_eq!(user.plan.as_deref(), Some("pro"));
+
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 2);
+    let login = &requests[0];
+    assert_eq!(login.method, "POST");
+    assert!(
+        login.body.contains(r#""username":"dorrella""#),
+        "{}",
+        login.body
+    );
+    assert_eq!(requests[1].method, "GET");
+}
+
+#[tokio::test]
+async fn plan_name_is_a_plan_too() {
+    let mock = hub(r#"{"username":"dorrella","plan_name":"free"}"#).await;
+    let plan = get_plan(&client(&mock), "dorrella", "secret")
+        .await
+        .unwrap();
+    assert_eq!(plan, "free");
+}
+
+#[tokio::test]
+async fn user_without_a_plan() {
+    let mock = hub(r#"{"username":"dorrella"}"#).await;
+    let client = client(&mock);
+
+    let user = get_hub_user(&client, "dorrella", "secret").await.unwrap();
+    assert_eq!(user.plan, None);
+
+    let err = get_plan(&client, "dorrella", "secret").await.unwrap_err();
+    assert_eq!(err.ret, ExitCode::Body);
+    assert!(err.msg.contains("no plan for dorrella"), "{}", err.msg);
+}
+
+#[tokio::test]
+async fn rejected_login() {
+    let mock = hub(r#"{"username":"dorrella","plan":"pro"}"#).await;
+    let err = get_plan(&client(&mock), "dorrella", "wrong")
+        .await
+        .unwrap_err();
+    assert_eq!(err.ret, ExitCode::Unauthorized);
+    assert_eq!(err.kind, Kind::Auth { status: 401 });
+    assert!(err.msg.contains("login failed for dorrella"), "{}", err.msg);
+
+    // the user endpoint isn't asked without a JWT
+    assert_eq!(mock.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn unparseable_user() {
+    let mock = hub("<html>maintenance</html>").await;
+    let err = get_plan(&client(&mock), "dorrella", "secret")
+        .await
+        .unwrap_err();
+    assert_eq!(err.ret, ExitCode::Body);
+    assert!(err.msg.starts_with("failed to parse"), "{}", err.msg);
+}
+
+#[tokio::test]
+async fn unparseable_login() {
+    let mock = MockServer::start(|_| Reply::status(200).body("not json")).await;
+    let err = get_plan(&client(&mock), "dorrella", "secret")
+        .await
+        .unwrap_err();
+    assert_eq!(err.ret, ExitCode::Body);
+}
+
+#[tokio::test]
+async fn unexpected_status() {
+    let mock = MockServer::start(|_| Reply::status(503)).await;
+    let err = get_plan(&client(&mock), "dorrella", "secret")
+        .await
+        .unwrap_err();
+    assert_eq!(err.ret, ExitCode::Connection);
+}
@@ -0,0 +1,30 @@
+//! `--image` through the binary against a mock registry
+
+mod common;
+
+use common::{Cli, MockServer};
+
+#[tokio::test]
+async fn limit_kept_like_any_check() {
+    let mock = MockServer::start(common::registry(42, 100)).await;
+    let cli = Cli::new(&mock);
+    let log = cli.dir().join("history.jsonl");
+
+    let out = cli
+        .run(&[
+            "--image",
+            "library/ubuntu:22.04",
+            "--log-file",
+            log.to_str().unwrap(),
+        ])
+        .await;
+    assert_eq!(out.code, 0, "{}", out.stderr);
+
+    let history = std::fs::read_to_string(&log).unwrap();
+    assert_eq!(history.lines().count(), 1, "{}", history);
+    assert!(history.contains("\"remaining\":42"), "{}", history);
+
+    let cached = cli.run(&["--cached"]).await;
+    assert_eq!(cached.code, 0, "{}", cached.stderr);
+    assert!(cached.stdout.starts_with("42/100"), "{}", cached.stdout);
+}
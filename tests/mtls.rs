@@ -0,0 +1,170 @@
+//! Client certificates through the binary, against a registry that insists on one
+
+#![cfg(not(any(target_os = "macos", windows)))]
+
+mod common;
+
+use common::tls::{Pki, TlsRegistry};
+use common::Cli;
+use openssl::pkcs12::Pkcs12;
+use std::path::{Path, PathBuf};
+
+/// PEM files of `pki` in the directory of `cli`: the CA, the client certificate and its key
+fn pem_files(cli: &Cli, pki: &Pki) -> (PathBuf, PathBuf, PathBuf) {
+    let (ca, _) = pki.ca.write(cli.dir(), "ca");
+    let (cert, key) = pki.client.write(cli.dir(), "client");
+    (ca, cert, key)
+}
+
+/// Checks the limit with `args`, trusting the CA in `ca`
+async fn check(cli: &Cli, ca: &Path, args: &[&str]) -> common::Output {
+    let mut all = vec!["--anonymous", "--cacert", ca.to_str().unwrap()];
+    all.extend(args);
+    cli.run(&all).await
+}
+
+#[tokio::test]
+async fn certificate_for_the_registry_and_the_token_service() {
+    let pki = Pki::new();
+    let registry = TlsRegistry::start(&pki);
+    let cli = Cli::with_registry(registry.url.clone());
+    let (ca, cert, key) = pem_files(&cli, &pki);
+
+    let args = [
+        "--client-cert",
+        cert.to_str().unwrap(),
+        "--client-key",
+        key.to_str().unwrap(),
+    ];
+    let out = check(&cli, &ca, &args).await;
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    assert_eq!(out.stdout, "42/100\n");
+
+    let seen = registry.seen();
+    let paths: Vec<&str> = seen.iter().map(|s| s.path.as_str()).collect();
+    assert_eq!(
+        paths,
+        [
+            "/v2/",
+            "/token",
+            "/v2/ratelimitpreview/test/manifests/latest"
+        ]
+    );
+    for request in &seen {
+        assert_eq!(request.client.as_deref(), Some("ci-bot"), "{:?}", request);
+    }
+}
+
+#[tokio::test]
+async fn combined_pem_file() {
+    let pki = Pki::new();
+    let registry = TlsRegistry::start(&pki);
+    let cli = Cli::with_registry(registry.url.clone());
+    let (ca, cert, key) = pem_files(&cli, &pki);
+    let combined = cli.dir().join("combined.pem");
+    let both = [std::fs::read(&cert).unwrap(), std::fs::read(&key).unwrap()].concat();
+    std::fs::write(&combined, both).unwrap();
+
+    let out = check(&cli, &ca, &["--client-cert", combined.to_str().unwrap()]).await;
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    assert_eq!(out.stdout, "42/100\n");
+}
+
+#[tokio::test]
+async fn pkcs12_bundle() {
+    let pki = Pki::new();
+    let registry = TlsRegistry::start(&pki);
+    let cli = Cli::with_registry(registry.url.clone());
+    let (ca, _) = pki.ca.write(cli.dir(), "ca");
+    let bundle = Pkcs12::builder()
+        .build("sesame", "ci-bot", &pki.client.key, &pki.client.cert)
+        .unwrap();
+    let p12 = cli.dir().join("client.p12");
+    std::fs::write(&p12, bundle.to_der().unwrap()).unwrap();
+
+    let mut all = vec!["--anonymous", "--cacert", ca.to_str().unwrap()];
+    all.extend(["--client-p12", p12.to_str().unwrap()]);
+    let mut cmd = cli.command(&all);
+    cmd.env("DOCKER_RL_CLIENT_P12_PASSWORD", "sesame");
+    let out = common::output(cmd, None).await;
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    assert_eq!(out.stdout, "42/100\n");
+    assert!(registry
+        .seen()
+        .iter()
+        .all(|s| s.client.as_deref() == Some("ci-bot")));
+}
+
+#[tokio::test]
+async fn refused_without_a_certificate() {
+    let pki = Pki::new();
+    let registry = TlsRegistry::start(&pki);
+    let cli = Cli::with_registry(registry.url.clone());
+    let (ca, _, _) = pem_files(&cli, &pki);
+
+    let out = check(&cli, &ca, &[]).await;
+    assert_eq!(out.code, 3, "{}", out.stderr);
+    assert!(registry.seen().is_empty());
+    assert!(registry.refused() > 0);
+}
+
+#[tokio::test]
+async fn refused_with_a_certificate_of_another_ca() {
+    let pki = Pki::new();
+    let registry = TlsRegistry::start(&pki);
+    let cli = Cli::with_registry(registry.url.clone());
+    let (ca, _) = pki.ca.write(cli.dir(), "ca");
+    let (cert, key) = Pki::new().client.write(cli.dir(), "stranger");
+
+    let args = [
+        "--client-cert",
+        cert.to_str().unwrap(),
+        "--client-key",
+        key.to_str().unwrap(),
+    ];
+    let out = check(&cli, &ca, &args).await;
+    assert_eq!(out.code, 3, "{}", out.stderr);
+    assert!(registry.seen().is_empty());
+}
+
+#[tokio::test]
+async fn bad_files_fail_before_connecting() {
+    let pki = Pki::new();
+    let registry = TlsRegistry::start(&pki);
+    let cli = Cli::with_registry(registry.url.clone());
+    let (ca, cert, _) = pem_files(&cli, &pki);
+    let bundle = Pkcs12::builder()
+        .build("sesame", "ci-bot", &pki.client.key, &pki.client.cert)
+        .unwrap();
+    let p12 = cli.dir().join("client.p12");
+    std::fs::write(&p12, bundle.to_der().unwrap()).unwrap();
+    let garbled = cli.dir().join("garbled.key");
+    std::fs::write(&garbled, "not a key\n").unwrap();
+
+    let mut wrong_password = cli.command(&[
+        "--anonymous",
+        "--client-p12",
+        p12.to_str().unwrap(),
+        "--cacert",
+        ca.to_str().unwrap(),
+    ]);
+    wrong_password.env("DOCKER_RL_CLIENT_P12_PASSWORD", "open");
+    let out = common::output(wrong_password, None).await;
+    assert_eq!(out.code, 6, "{}", out.stderr);
+    let msg = format!("wrong password for {}", p12.display());
+    assert!(out.stderr.contains(&msg), "{}", out.stderr);
+
+    let args = [
+        "--client-cert",
+        cert.to_str().unwrap(),
+        "--client-key",
+        garbled.to_str().unwrap(),
+    ];
+    let out = check(&cli, &ca, &args).await;
+    assert_eq!(out.code, 6, "{}", out.stderr);
+    let msg = format!("no unencrypted PEM private key in {}", garbled.display());
+    assert!(out.stderr.contains(&msg), "{}", out.stderr);
+
+    assert!(registry.seen().is_empty());
+    assert_eq!(registry.refused(), 0);
+}
@@ -0,0 +1,208 @@
+//! Every output format against a mock registry, compared to snapshots and the `--schema`
+//!
+//! Snapshots are kept per schema version under `tests/snapshots/v<SCHEMA_VERSION>`, and
+//! `UPDATE_SNAPSHOTS=1 cargo test` rewrites the ones that changed. Adding a JSON field only
+//! needs updating them, renaming or removing one fails until `SCHEMA_VERSION` is bumped, like
+//! `src/schema.rs` asks
+
+mod common;
+
+use common::schema::violations;
+use common::{Cli, MockServer, Output};
+use libdocker_rl::schema::SCHEMA_VERSION;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+/// Password of every user the tests check
+const PASS: &str = "secret";
+
+fn snapshot_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/snapshots")
+        .join(format!("v{}", SCHEMA_VERSION))
+        .join(name)
+}
+
+/// Paths of every object key in `value`, with array items under `[]`
+fn keys(value: &Value, path: &str, found: &mut BTreeSet<String>) {
+    match value {
+        Value::Object(map) => {
+            for (key, item) in map {
+                let path = format!("{}.{}", path, key);
+                keys(item, &path, found);
+                found.insert(path);
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                keys(item, &format!("{}[]", path), found);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Compares `actual` to snapshot `name`, or writes it with `UPDATE_SNAPSHOTS` set
+fn assert_snapshot(name: &str, actual: &str) {
+    let path = snapshot_path(name);
+    let expected = std::fs::read_to_string(&path).ok();
+
+    if name.ends_with(".json") {
+        if let Some(expected) = &expected {
+            let (mut old, mut new) = (BTreeSet::new(), BTreeSet::new());
+            keys(&serde_json::from_str(expected).unwrap(), "$", &mut old);
+            keys(&serde_json::from_str(actual).unwrap(), "$", &mut new);
+            let gone: Vec<_> = old.difference(&new).collect();
+            assert!(
+                gone.is_empty(),
+                "{} lost {:?} without a SCHEMA_VERSION bump",
+                name,
+                gone
+            );
+        }
+    }
+
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+    match expected {
+        Some(expected) => assert_eq!(
+            actual, expected,
+            "{} changed, rerun with UPDATE_SNAPSHOTS=1 if that's intended",
+            name
+        ),
+        None => panic!(
+            "no snapshot {}, run with UPDATE_SNAPSHOTS=1",
+            path.display()
+        ),
+    }
+}
+
+/// `output` with the address of `mock` replaced, so snapshots don't depend on the port
+fn normalized(mock: &MockServer, output: &str) -> String {
+    let host = format!(
+        "{}:{}",
+        mock.url.host_str().unwrap(),
+        mock.url.port().unwrap()
+    );
+    output.replace(&host, "registry.test")
+}
+
+/// Runs `args` with every user from `users` on stdin
+async fn run_users(cli: &Cli, users: &[&str], args: &[&str]) -> Output {
+    let options = ["--users-from-stdin", "--password-env-prefix", "PASS_"];
+    let mut cmd = cli.command(&common::with_options(args, &options));
+    for user in users {
+        cmd.env(format!("PASS_{}", user.to_uppercase()), PASS);
+    }
+
+    let stdin = users.join("\n");
+    common::output(cmd, Some(&stdin)).await
+}
+
+/// Runs `args` as `ci-bot`
+async fn run_user(cli: &Cli, args: &[&str]) -> Output {
+    let mut cmd = cli.command(&common::with_options(args, &["--user", "ci-bot"]));
+    cmd.env("DOCKER_RL_PASS", PASS);
+    common::output(cmd, None).await
+}
+
+#[tokio::test]
+async fn snapshots() {
+    let mock = MockServer::start(common::registry(42, 100)).await;
+    let cli = Cli::new(&mock);
+
+    let cases: &[(&str, &[&str])] = &[
+        ("plain.txt", &[]),
+        ("json.json", &["--format", "json"]),
+        ("human.txt", &["--human"]),
+        (
+            "template.txt",
+            &["--format", "{{.remaining}} of {{.total}}"],
+        ),
+        ("compare.json", &["--compare", "--format", "json"]),
+        ("plan.json", &["--dry-run", "--format", "json"]),
+    ];
+    for (name, args) in cases {
+        let out = run_user(&cli, args).await;
+        assert_eq!(out.code, 0, "{}: {}", name, out.stderr);
+        assert_snapshot(name, &normalized(&mock, &out.stdout));
+    }
+
+    let users = ["alice", "bob"];
+    let cases: &[(&str, &[&str])] = &[
+        ("users.txt", &[]),
+        ("users.json", &["--format", "json"]),
+        ("users-table.txt", &["--format", "table"]),
+    ];
+    for (name, args) in cases {
+        let out = run_users(&cli, &users, args).await;
+        assert_eq!(out.code, 0, "{}: {}", name, out.stderr);
+        assert_snapshot(name, &out.stdout);
+    }
+
+    let out = cli
+        .run(&["--anonymous", "--format", "json", "--fail-below", "50"])
+        .await;
+    assert_eq!(out.code, 7, "{}", out.stderr);
+    assert_snapshot("failure.json", &out.stderr);
+}
+
+#[tokio::test]
+async fn json_matches_schema() {
+    let mock = MockServer::start(common::registry(42, 100)).await;
+    let cli = Cli::new(&mock);
+
+    let out = cli.run(&["--schema"]).await;
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    let schema: Value = serde_json::from_str(&out.stdout).unwrap();
+    let wrong = serde_json::json!({"schema_version": 1, "remaining": "42"});
+    assert!(!violations(&schema, &wrong).is_empty());
+
+    let mut outputs = Vec::new();
+    let single: &[&[&str]] = &[
+        &[],
+        &["--verify", "--verify-delay", "0s"],
+        &["--need", "10"],
+        &["--dry-run"],
+        &["--image", "library/ubuntu:22.04"],
+        &["--compare-stacks"],
+        &["--expect-user", "someone-else"],
+        &["--compare"],
+        // after the first check stored its result
+        &["--cached"],
+        &["doctor"],
+        &["config"],
+    ];
+    for args in single {
+        let all = common::with_options(args, &["--format", "json"]);
+        outputs.push((args.join(" "), run_user(&cli, &all).await));
+    }
+    for args in [&[][..], &["--dry-run"]] {
+        let all = common::with_options(args, &["--format", "json"]);
+        let out = run_users(&cli, &["alice", "bob"], &all).await;
+        outputs.push((format!("--users-from-stdin {}", args.join(" ")), out));
+    }
+    let cache = cli.bare_command(&["cache", "list", "--format", "json"]);
+    outputs.push(("cache list".into(), common::output(cache, None).await));
+
+    for (args, out) in outputs {
+        let mut documents = Vec::new();
+        for stream in [&out.stdout, &out.stderr] {
+            if !stream.trim().is_empty() {
+                let doc: Value = serde_json::from_str(stream)
+                    .unwrap_or_else(|e| panic!("{}: {}: {}", args, e, stream));
+                documents.push(doc);
+            }
+        }
+        assert!(!documents.is_empty(), "{}: no output", args);
+
+        for doc in documents {
+            let found = violations(&schema, &doc);
+            assert!(found.is_empty(), "{}: {:#?}\n{:#}", args, found, doc);
+        }
+    }
+}
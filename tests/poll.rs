@@ -0,0 +1,194 @@
+//! `limit::poll_limits` on a paused clock against a mock registry, so no interval is waited out
+//!
+//! The paused clock jumps to the next timer whenever the runtime waits, for the mock too, so
+//! `paused_client` keeps a timer a few milliseconds out at all times. A check waiting for the
+//! mock then only moves the clock by that much, rather than to when the next check is due
+
+mod common;
+
+use common::{MockServer, Reply};
+use futures::{Stream, StreamExt};
+use libdocker_rl::api::DrlClient;
+use libdocker_rl::err::{DrlResult, ExitCode, Kind};
+use libdocker_rl::limit::{poll_limits, Limit};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::time;
+
+const MINUTE: Duration = Duration::from_secs(60);
+
+/// How far the paused clock moves at most while a check waits for the mock
+const TICK: Duration = Duration::from_millis(10);
+
+/// A client for `mock`, with the clock paused and moving by `TICK` at most
+fn paused_client(mock: &MockServer) -> DrlClient {
+    time::pause();
+    tokio::spawn(async {
+        loop {
+            time::sleep(TICK).await;
+        }
+    });
+    mock.client()
+}
+
+/// Number of requests `mock` got for `path`
+fn requests_for(mock: &MockServer, path: &str) -> usize {
+    mock.requests().iter().filter(|r| r.path == path).count()
+}
+
+/// A mock registry answering the checks with `replies`, in turn, then with a limit
+async fn registry(replies: Vec<Reply>) -> MockServer {
+    let checks = AtomicUsize::new(0);
+    MockServer::start(move |req| match req.path.as_str() {
+        "/token" => Reply::token(),
+        _ => {
+            let n = checks.fetch_add(1, Ordering::SeqCst);
+            replies
+                .get(n)
+                .cloned()
+                .unwrap_or_else(|| Reply::limit(42, 100))
+        }
+    })
+    .await
+}
+
+/// Whether `polls` yields nothing for `quiet`
+async fn quiet_for<S>(polls: &mut S, quiet: Duration) -> bool
+where
+    S: Stream<Item = DrlResult<Limit>> + Unpin,
+{
+    time::timeout(quiet, polls.next()).await.is_err()
+}
+
+#[tokio::test]
+async fn failed_checks_dont_end_the_stream() {
+    let mock = registry(vec![Reply::status(500), Reply::status(404)]).await;
+    let client = paused_client(&mock);
+    let polls = poll_limits(client.provider(None), MINUTE);
+
+    let results: Vec<_> = polls.take(3).collect().await;
+    assert_eq!(results[0].as_ref().unwrap_err().ret, ExitCode::Connection);
+    assert!(results[1].is_err());
+    let limit = results[2].as_ref().unwrap();
+    assert_eq!((limit.remaining, limit.total), (42, 100));
+}
+
+#[tokio::test]
+async fn polls_every_interval() {
+    let mock = registry(Vec::new()).await;
+    let client = paused_client(&mock);
+    let mut polls = Box::pin(poll_limits(client.provider(None), MINUTE));
+
+    // the first check is right away
+    let first = time::timeout(Duration::from_secs(1), polls.next()).await;
+    first.unwrap().unwrap().unwrap();
+
+    for _ in 0..3 {
+        assert!(quiet_for(&mut polls, MINUTE - Duration::from_secs(1)).await);
+        polls.next().await.unwrap().unwrap();
+    }
+
+    // one token for all of them, it's valid for five minutes
+    assert_eq!(requests_for(&mock, "/token"), 1);
+}
+
+#[tokio::test]
+async fn expired_tokens_are_replaced() {
+    let mock = MockServer::start(|req| match req.path.as_str() {
+        "/token" => {
+            // expired by the time it's used
+            let issued_at = humantime::format_rfc3339_seconds(SystemTime::now());
+            let body = format!(
+                r#"{{"token":"short","expires_in":1,"issued_at":"{}"}}"#,
+                issued_at
+            );
+            Reply::status(200).body(&body)
+        }
+        _ => Reply::limit(42, 100),
+    })
+    .await;
+    let client = paused_client(&mock);
+    let polls = poll_limits(client.provider(None), MINUTE);
+
+    let results: Vec<_> = polls.take(3).collect().await;
+    assert!(results.iter().all(|r| r.is_ok()));
+    assert_eq!(requests_for(&mock, "/token"), 3);
+}
+
+#[tokio::test]
+async fn rejected_tokens_are_replaced() {
+    let mock = registry(vec![Reply::status(401)]).await;
+    let client = paused_client(&mock);
+    let mut polls = Box::pin(poll_limits(client.provider(None), MINUTE));
+
+    // the token is replaced and the check made again, within the same poll
+    let limit = polls.next().await.unwrap().unwrap();
+    assert_eq!(limit.remaining, 42);
+    assert_eq!(requests_for(&mock, "/token"), 2);
+}
+
+#[tokio::test]
+async fn waits_out_retry_after() {
+    let over = Reply::status(429).header("retry-after", "600");
+    let mock = registry(vec![over]).await;
+    let client = paused_client(&mock);
+    let mut polls = Box::pin(poll_limits(client.provider(None), MINUTE));
+
+    let err = polls.next().await.unwrap().unwrap_err();
+    assert_eq!(err.ret, ExitCode::OverLimit);
+    let ten_minutes = Duration::from_secs(600);
+    let kind = Kind::RateLimited {
+        retry_after: Some(ten_minutes),
+    };
+    assert_eq!(err.kind, kind);
+
+    // no check before the limit resets, though the interval is shorter
+    assert!(quiet_for(&mut polls, ten_minutes - Duration::from_secs(1)).await);
+    polls.next().await.unwrap().unwrap();
+
+    // and back to the interval after that
+    assert!(quiet_for(&mut polls, MINUTE - Duration::from_secs(1)).await);
+    polls.next().await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn retry_after_sooner_than_the_interval_keeps_the_interval() {
+    let over = Reply::status(429).header("retry-after", "5");
+    let mock = registry(vec![over]).await;
+    let client = paused_client(&mock);
+    let mut polls = Box::pin(poll_limits(client.provider(None), MINUTE));
+
+    polls.next().await.unwrap().unwrap_err();
+    assert!(quiet_for(&mut polls, MINUTE - Duration::from_secs(1)).await);
+    polls.next().await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn dropping_the_stream_stops_polling() {
+    let checks = Arc::new(AtomicUsize::new(0));
+    let counted = Arc::clone(&checks);
+    let mock = MockServer::start(move |req| match req.path.as_str() {
+        "/token" => Reply::token(),
+        _ => {
+            counted.fetch_add(1, Ordering::SeqCst);
+            Reply::limit(42, 100)
+        }
+    })
+    .await;
+    let client = paused_client(&mock);
+
+    let polls = poll_limits(client.provider(None), MINUTE);
+    let results: Vec<_> = polls.take(2).collect().await;
+    assert_eq!(results.len(), 2);
+
+    time::sleep(MINUTE * 10).await;
+    assert_eq!(checks.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+#[should_panic(expected = "interval must be non-zero")]
+async fn zero_interval_panics() {
+    let mock = registry(Vec::new()).await;
+    let _ = poll_limits(mock.client().provider(None), Duration::ZERO);
+}
@@ -0,0 +1,151 @@
+//! Proxy credentials through the binary, with the mock standing in for a proxy that insists on
+//! them
+//!
+//! Requests for a plain HTTP registry are sent to the proxy whole, so the mock answers them as
+//! the registry would once they carry the right `Proxy-Authorization`
+
+mod common;
+
+use common::{Cli, MockServer, Reply};
+
+/// Registry checked through the proxy, which is never looked up
+const REGISTRY: &str = "http://registry.test";
+
+const PASSWORD: &str = "hunter2";
+
+/// A proxy wanting `proxy-bot` and `PASSWORD`, answering like `common::registry` once given them
+async fn proxy() -> MockServer {
+    let expected = format!(
+        "Basic {}",
+        base64::encode(format!("proxy-bot:{}", PASSWORD))
+    );
+    let registry = common::registry(42, 100);
+    MockServer::start(move |req| match req.header("proxy-authorization") {
+        Some(auth) if auth == expected => registry(req),
+        _ => Reply::status(407).header("proxy-authenticate", "Basic realm=\"proxy\""),
+    })
+    .await
+}
+
+/// `args` for the binary, checking `REGISTRY` through `mock`
+fn command(cli: &Cli, mock: &MockServer, args: &[&str]) -> tokio::process::Command {
+    let proxy = mock.url.as_str();
+    let base = [
+        "--registry",
+        REGISTRY,
+        "--proxy",
+        proxy,
+        "--retries",
+        "0",
+        "--no-keyring",
+    ];
+    cli.bare_command(&common::with_options(args, &base))
+}
+
+#[tokio::test]
+async fn credentials_are_sent_to_the_proxy() {
+    let mock = proxy().await;
+    let cli = Cli::new(&mock);
+
+    let auth = format!("proxy-bot:{}", PASSWORD);
+    let cmd = command(&cli, &mock, &["--anonymous", "--proxy-user", &auth]);
+    let out = common::output(cmd, None).await;
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    assert_eq!(out.stdout, "42/100\n");
+
+    let expected = format!("Basic {}", base64::encode(&auth));
+    let requests = mock.requests();
+    let paths: Vec<&str> = requests.iter().map(|r| r.path.as_str()).collect();
+    assert_eq!(
+        paths,
+        [
+            "/v2/",
+            "/token",
+            "/v2/ratelimitpreview/test/manifests/latest"
+        ]
+    );
+    for req in &requests {
+        assert_eq!(req.header("proxy-authorization"), Some(expected.as_str()));
+        assert_eq!(req.header("host"), Some("registry.test"));
+    }
+}
+
+#[tokio::test]
+async fn credentials_from_the_environment() {
+    let mock = proxy().await;
+    let cli = Cli::new(&mock);
+
+    let mut cmd = command(&cli, &mock, &["--anonymous"]);
+    cmd.env("DOCKER_RL_PROXY_AUTH", format!("proxy-bot:{}", PASSWORD));
+    let out = common::output(cmd, None).await;
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    assert_eq!(out.stdout, "42/100\n");
+}
+
+#[tokio::test]
+async fn rejected_credentials_are_a_proxy_failure() {
+    let mock = proxy().await;
+    let cli = Cli::new(&mock);
+
+    let cmd = command(
+        &cli,
+        &mock,
+        &["--anonymous", "--proxy-user", "proxy-bot:wrong"],
+    );
+    let out = common::output(cmd, None).await;
+    assert_eq!(out.code, 12, "{}", out.stderr);
+    assert!(
+        out.stderr
+            .contains("proxy authentication failed connecting to registry.test"),
+        "{}",
+        out.stderr
+    );
+    // not retried, and never sent on to the registry
+    assert_eq!(mock.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn missing_credentials_are_a_proxy_failure() {
+    let mock = proxy().await;
+    let cli = Cli::new(&mock);
+
+    let out = common::output(command(&cli, &mock, &["--anonymous"]), None).await;
+    assert_eq!(out.code, 12, "{}", out.stderr);
+    assert!(
+        out.stderr.contains("proxy authentication required"),
+        "{}",
+        out.stderr
+    );
+}
+
+#[tokio::test]
+async fn password_is_never_printed() {
+    let mock = proxy().await;
+    let cli = Cli::new(&mock);
+    let auth = format!("proxy-bot:{}", PASSWORD);
+
+    let runs = [
+        vec!["--anonymous", "--dry-run"],
+        vec!["--anonymous", "--dry-run", "--format", "json"],
+        vec!["--anonymous", "-v"],
+        vec!["--anonymous", "-vv"],
+        vec!["--anonymous", "-vvv"],
+        vec!["config", "--anonymous"],
+    ];
+    for args in &runs {
+        let mut all = args.clone();
+        all.extend(["--proxy-user", auth.as_str()]);
+        let out = common::output(command(&cli, &mock, &all), None).await;
+        assert_eq!(out.code, 0, "{:?}: {}", args, out.stderr);
+        for printed in [&out.stdout, &out.stderr] {
+            assert!(!printed.contains(PASSWORD), "{:?}: {}", args, printed);
+            let encoded = base64::encode(&auth);
+            assert!(!printed.contains(&encoded), "{:?}: {}", args, printed);
+        }
+        // the user is still shown, to tell which credentials were used
+        let printed = format!("{}{}", out.stdout, out.stderr);
+        if args.contains(&"--dry-run") || args[0] == "config" {
+            assert!(printed.contains("proxy-bot"), "{:?}: {}", args, printed);
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! Pushing to a Pushgateway, with the mock standing in for it
+
+mod common;
+
+use common::{Cli, MockServer, Reply};
+use libdocker_rl::client::BasicAuth;
+use libdocker_rl::err::ExitCode;
+use libdocker_rl::limit::Limit;
+use libdocker_rl::metrics::Pushgateway;
+use reqwest::Url;
+
+fn limit() -> Limit {
+    Limit {
+        remaining: 42,
+        total: 100,
+        ..Limit::default()
+    }
+}
+
+/// A Pushgateway at `url` for `job` and `instance`, without credentials
+fn gateway(url: &Url, job: &str, instance: Option<&str>) -> Pushgateway {
+    Pushgateway {
+        url: url.clone(),
+        job: job.into(),
+        instance: instance.map(String::from),
+        auth: None,
+    }
+}
+
+#[tokio::test]
+async fn puts_the_gauges_to_the_grouping_key() {
+    let mock = MockServer::start(|_| Reply::status(200)).await;
+    let mut push = gateway(&mock.url, "docker-rl", Some("ci-host"));
+    push.auth = Some(BasicAuth {
+        user: "pusher".into(),
+        pass: "secret".into(),
+    });
+
+    push.push(&mock.client(), Some("ci-bot"), &limit())
+        .await
+        .unwrap();
+
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 1);
+    let req = &requests[0];
+    assert_eq!(req.method, "PUT");
+    assert_eq!(req.path, "/metrics/job/docker-rl/instance/ci-host");
+    assert_eq!(
+        req.header("content-type"),
+        Some("text/plain; version=0.0.4")
+    );
+    let basic = format!("Basic {}", base64::encode("pusher:secret"));
+    assert_eq!(req.header("authorization"), Some(basic.as_str()));
+
+    let body = &req.body;
+    assert!(body.contains("# TYPE dockerhub_ratelimit_remaining gauge\n"));
+    assert!(body.contains("dockerhub_ratelimit_remaining{user=\"ci-bot\"} 42\n"));
+    assert!(body.contains("dockerhub_ratelimit_limit{user=\"ci-bot\"} 100\n"));
+}
+
+#[tokio::test]
+async fn awkward_label_values_are_base64() {
+    let mock = MockServer::start(|_| Reply::status(202)).await;
+    let client = mock.client();
+
+    let cases = [
+        (
+            gateway(&mock.url, "nightly/pulls", Some("")),
+            "/metrics/job@base64/bmlnaHRseS9wdWxscw==/instance@base64/=",
+        ),
+        (
+            gateway(&mock.url, "docker-rl", Some("ci/host")),
+            "/metrics/job/docker-rl/instance@base64/Y2kvaG9zdA==",
+        ),
+        // without an instance the grouping key is just the job
+        (
+            gateway(&mock.url, "docker-rl", None),
+            "/metrics/job/docker-rl",
+        ),
+        (
+            gateway(&mock.url.join("/gateway/").unwrap(), "docker-rl", None),
+            "/gateway/metrics/job/docker-rl",
+        ),
+    ];
+    for (push, _) in &cases {
+        push.push(&client, None, &limit()).await.unwrap();
+    }
+
+    let paths: Vec<String> = mock.requests().into_iter().map(|r| r.path).collect();
+    let expected: Vec<&str> = cases.iter().map(|(_, path)| *path).collect();
+    assert_eq!(paths, expected);
+}
+
+#[tokio::test]
+async fn rejected_push() {
+    let mock = MockServer::start(|_| Reply::status(400)).await;
+    let push = gateway(&mock.url, "docker-rl", None);
+
+    let err = push.push(&mock.client(), None, &limit()).await.unwrap_err();
+    assert_eq!(err.ret, ExitCode::Connection);
+    assert_eq!(err.msg, "pushgateway rejected the metrics: 400 Bad Request");
+}
+
+/// A registry, pushing to which answers `status`
+async fn registry_and_gateway(status: u16) -> MockServer {
+    let registry = common::registry(42, 100);
+    MockServer::start(move |req| match req.path.starts_with("/metrics/") {
+        true => Reply::status(status),
+        false => registry(req),
+    })
+    .await
+}
+
+#[tokio::test]
+async fn pushed_after_a_check() {
+    let mock = registry_and_gateway(200).await;
+    let cli = Cli::new(&mock);
+
+    let gateway = mock.url.as_str();
+    let args = [
+        "--anonymous",
+        "--pushgateway",
+        gateway,
+        "--push-job",
+        "pulls",
+        "--push-instance",
+        "ci-host",
+    ];
+    let out = cli.run(&args).await;
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    assert_eq!(out.stdout, "42/100\n");
+    assert_eq!(out.stderr, "");
+
+    let pushes: Vec<_> = mock
+        .requests()
+        .into_iter()
+        .filter(|r| r.method == "PUT")
+        .collect();
+    assert_eq!(pushes.len(), 1);
+    assert_eq!(pushes[0].path, "/metrics/job/pulls/instance/ci-host");
+    let body = &pushes[0].body;
+    assert!(body.contains("dockerhub_ratelimit_remaining{user=\"anonymous\"} 42\n"));
+}
+
+#[tokio::test]
+async fn failed_push_only_warns() {
+    let mock = registry_and_gateway(500).await;
+    let cli = Cli::new(&mock);
+
+    let out = cli
+        .run(&["--anonymous", "--pushgateway", mock.url.as_str()])
+        .await;
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    assert_eq!(out.stdout, "42/100\n");
+    assert_eq!(
+        out.stderr,
+        "warning: couldn't push metrics: pushgateway rejected the metrics: \
+         500 Internal Server Error\n"
+    );
+
+    // and quietly with -q
+    let out = cli
+        .run(&["--anonymous", "--pushgateway", mock.url.as_str(), "-q"])
+        .await;
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    assert_eq!(out.stderr, "");
+}
+
+#[tokio::test]
+async fn failed_push_fails_the_run_when_strict() {
+    let mock = registry_and_gateway(500).await;
+    let cli = Cli::new(&mock);
+
+    let args = [
+        "--anonymous",
+        "--pushgateway",
+        mock.url.as_str(),
+        "--push-strict",
+    ];
+    let out = cli.run(&args).await;
+    assert_eq!(out.code, 3, "{}", out.stderr);
+    assert!(
+        out.stderr
+            .contains("pushgateway rejected the metrics: 500 Internal Server Error"),
+        "{}",
+        out.stderr
+    );
+}
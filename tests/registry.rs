@@ -0,0 +1,273 @@
+//! Checks against a mock registry, through `DrlClient`
+
+mod common;
+
+use common::{MockServer, Reply};
+use libdocker_rl::err::{ExitCode, Kind};
+use libdocker_rl::limit::SOURCE_HEADER;
+use libdocker_rl::quota::QuotaGuard;
+use reqwest::Method;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+#[tokio::test]
+async fn limit_from_headers() {
+    let mock = MockServer::start(|req| match req.path.as_str() {
+        "/token" => Reply::token(),
+        _ => Reply::limit(42, 100).header(SOURCE_HEADER, "203.0.113.7"),
+    })
+    .await;
+    let client = mock.client();
+
+    let token = client.token(None).await.unwrap();
+    let limit = client.limit(&token).await.unwrap();
+
+    assert_eq!((limit.remaining, limit.total), (42, 100));
+    assert_eq!(limit.source.as_deref(), Some("203.0.113.7"));
+    assert!(limit.keyed_to_ip());
+
+    let requests = mock.requests();
+    let token_req = &requests[0];
+    assert_eq!(token_req.path, "/token");
+    assert!(token_req.query.contains("service=mock"));
+    assert!(token_req
+        .query
+        .contains("scope=repository%3Aratelimitpreview%2Ftest%3Apull"));
+    let manifest = &requests[1];
+    assert_eq!(manifest.method, "HEAD");
+    assert_eq!(manifest.path, "/v2/ratelimitpreview/test/manifests/latest");
+    assert_eq!(manifest.header("authorization"), Some("Bearer mock-token"));
+}
+
+#[tokio::test]
+async fn window_from_w_parameter() {
+    let mock = MockServer::start(|req| match req.path.as_str() {
+        "/token" => Reply::token(),
+        _ => Reply::status(200)
+            .header("ratelimit-limit", "200;w=3600")
+            .header("ratelimit-remaining", "150; w=3600"),
+    })
+    .await;
+    let client = mock.client();
+
+    let token = client.token(None).await.unwrap();
+    let limit = client.limit(&token).await.unwrap();
+
+    assert_eq!((limit.remaining, limit.total), (150, 200));
+    assert_eq!(limit.window, Duration::from_secs(3600));
+}
+
+#[tokio::test]
+async fn no_window_without_w_parameter() {
+    let mock = MockServer::start(|req| match req.path.as_str() {
+        "/token" => Reply::token(),
+        _ => Reply::status(200)
+            .header("ratelimit-limit", "100")
+            .header("ratelimit-remaining", "99"),
+    })
+    .await;
+    let client = mock.client();
+
+    let token = client.token(None).await.unwrap();
+    let limit = client.limit(&token).await.unwrap();
+
+    assert_eq!((limit.remaining, limit.total), (99, 100));
+    assert!(limit.window.is_zero());
+}
+
+#[tokio::test]
+async fn missing_headers_fall_back_to_get() {
+    let mock = MockServer::start(|req| match req.path.as_str() {
+        "/token" => Reply::token(),
+        _ => Reply::status(200),
+    })
+    .await;
+    let client = mock.client();
+
+    let token = client.token(None).await.unwrap();
+    let probe = client.peek(&token).await.unwrap();
+    assert!(probe.unlimited);
+
+    let err = client.limit(&token).await.unwrap_err();
+    assert_eq!(err.ret, ExitCode::Parsing);
+
+    let methods: Vec<_> = mock.requests().into_iter().map(|r| r.method).collect();
+    assert_eq!(methods, ["GET", "HEAD", "GET", "HEAD", "GET"]);
+}
+
+#[tokio::test]
+async fn missing_remaining_header() {
+    let mock = MockServer::start(|req| match req.path.as_str() {
+        "/token" => Reply::token(),
+        _ => Reply::status(200).header("ratelimit-limit", "100;w=21600"),
+    })
+    .await;
+    let client = mock.client();
+
+    let token = client.token(None).await.unwrap();
+    let err = client.limit(&token).await.unwrap_err();
+
+    assert_eq!(err.ret, ExitCode::Parsing);
+    assert_eq!(
+        err.msg,
+        "error parsing rate limit: no ratelimit-remaining header"
+    );
+    assert_eq!(
+        err.kind,
+        Kind::Parse {
+            header: "ratelimit-remaining".into(),
+            raw_value: None,
+        }
+    );
+}
+
+#[tokio::test]
+async fn unparsable_header() {
+    let mock = MockServer::start(|req| match req.path.as_str() {
+        "/token" => Reply::token(),
+        _ => Reply::status(200)
+            .header("ratelimit-limit", "100;w=21600")
+            .header("ratelimit-remaining", "lots;w=21600"),
+    })
+    .await;
+    let client = mock.client();
+
+    let token = client.token(None).await.unwrap();
+    let err = client.limit(&token).await.unwrap_err();
+
+    assert_eq!(err.ret, ExitCode::Parsing);
+    assert_eq!(
+        err.kind,
+        Kind::Parse {
+            header: "ratelimit-remaining".into(),
+            raw_value: Some("lots;w=21600".into()),
+        }
+    );
+}
+
+#[tokio::test]
+async fn token_rejected_by_registry() {
+    let mock = MockServer::start(|req| match req.path.as_str() {
+        "/token" => Reply::token(),
+        _ => Reply::status(401)
+            .body(r#"{"errors":[{"code":"UNAUTHORIZED","message":"authentication required"}]}"#),
+    })
+    .await;
+    let client = mock.client();
+
+    let token = client.token(None).await.unwrap();
+    let err = client.limit(&token).await.unwrap_err();
+
+    assert_eq!(err.ret, ExitCode::Unauthorized);
+    assert_eq!(err.kind, Kind::Auth { status: 401 });
+
+    // only a `GET` gets the error body
+    let err = client.probe(&token, Method::GET).await.unwrap_err();
+    assert_eq!(err.ret, ExitCode::Unauthorized);
+    assert!(err.msg.contains("authentication required"), "{}", err.msg);
+}
+
+#[tokio::test]
+async fn credentials_rejected_by_token_service() {
+    let mock = MockServer::start(|req| match req.path.as_str() {
+        "/token" => Reply::status(401),
+        _ => Reply::limit(42, 100),
+    })
+    .await;
+    let client = mock.client();
+
+    let err = client.token(Some(("ci-bot", "wrong"))).await.unwrap_err();
+
+    assert_eq!(err.ret, ExitCode::Unauthorized);
+    assert_eq!(err.kind, Kind::Auth { status: 401 });
+    assert!(err.msg.contains("ci-bot"), "{}", err.msg);
+
+    let auth = mock.requests()[0].header("authorization").map(String::from);
+    assert_eq!(auth.as_deref(), Some("Basic Y2ktYm90Ondyb25n"));
+}
+
+#[tokio::test]
+async fn over_limit_with_retry_after() {
+    let mock = MockServer::start(|req| match req.path.as_str() {
+        "/token" => Reply::token(),
+        _ => Reply::status(429)
+            .header("retry-after", "120")
+            .header("ratelimit-limit", "100;w=21600")
+            .header("ratelimit-remaining", "0;w=21600"),
+    })
+    .await;
+    let client = mock.client();
+
+    let token = client.token(None).await.unwrap();
+    let err = client.limit(&token).await.unwrap_err();
+
+    assert_eq!(err.ret, ExitCode::OverLimit);
+    assert_eq!(
+        err.kind,
+        Kind::RateLimited {
+            retry_after: Some(Duration::from_secs(120))
+        }
+    );
+    assert!(
+        err.msg.starts_with("over limit, resets in ~2m"),
+        "{}",
+        err.msg
+    );
+}
+
+#[tokio::test]
+async fn over_limit_without_retry_after() {
+    let mock = MockServer::start(|req| match req.path.as_str() {
+        "/token" => Reply::token(),
+        _ => Reply::status(429).header("ratelimit-limit", "100;w=21600"),
+    })
+    .await;
+    let client = mock.client();
+
+    let token = client.token(None).await.unwrap();
+    let err = client.limit(&token).await.unwrap_err();
+
+    assert_eq!(err.ret, ExitCode::OverLimit);
+    assert_eq!(err.kind, Kind::RateLimited { retry_after: None });
+    assert_eq!(err.msg, "over limit, resets within 6h");
+}
+
+#[tokio::test]
+async fn registry_without_token_service() {
+    let mock = MockServer::start(|_| Reply::limit(7, 10)).await;
+    let client = libdocker_rl::api::DrlClient::builder()
+        .registry(libdocker_rl::registry::Registry::new(mock.url.clone()))
+        .build()
+        .unwrap()
+        .discover()
+        .await
+        .unwrap();
+
+    let token = client.token(None).await.unwrap();
+    let limit = client.limit(&token).await.unwrap();
+
+    assert_eq!((limit.remaining, limit.total), (7, 10));
+    assert!(mock.requests()[1].header("authorization").is_none());
+}
+
+#[tokio::test]
+async fn rejected_token_replaced_before_expiry() {
+    let rejected = AtomicBool::new(false);
+    let mock = MockServer::start(move |req| match req.path.as_str() {
+        "/token" => Reply::token(),
+        _ if !rejected.swap(true, Ordering::SeqCst) => Reply::status(401),
+        _ => Reply::limit(42, 100),
+    })
+    .await;
+    let guard = QuotaGuard::new(mock.client(), None);
+
+    let permit = guard.acquire(1).await.unwrap();
+    permit.release();
+
+    let tokens = mock
+        .requests()
+        .iter()
+        .filter(|r| r.path == "/token")
+        .count();
+    assert_eq!(tokens, 2);
+}
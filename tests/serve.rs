@@ -0,0 +1,174 @@
+//! The exporter of `serve`, through the binary against a mock registry
+
+mod common;
+
+use common::{Cli, MockServer, Reply};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Child;
+use tokio::time::{self, Instant};
+
+/// An address on localhost that nothing listens on
+fn free_addr() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+/// Starts the binary with `args`, writing `stdin` to it and closing it
+async fn start(cli: &Cli, args: &[&str], stdin: &str) -> Child {
+    let mut cmd = cli.command(args);
+    cmd.stdin(Stdio::piped());
+    for user in ["ci-bot", "locked"] {
+        cmd.env(
+            format!("PASS_{}", user.replace('-', "_").to_uppercase()),
+            "secret",
+        );
+    }
+    let mut child = cmd.spawn().unwrap();
+    let mut pipe = child.stdin.take().unwrap();
+    pipe.write_all(stdin.as_bytes()).await.unwrap();
+    drop(pipe);
+    child
+}
+
+/// Scrapes `url` until `done` holds for the body, giving up after ten seconds
+async fn scrape_until(url: &str, done: impl Fn(&str) -> bool) -> String {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut last = String::new();
+    while Instant::now() < deadline {
+        if let Ok(resp) = reqwest::get(url).await {
+            last = resp.text().await.unwrap_or_default();
+            if done(&last) {
+                return last;
+            }
+        }
+        time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("gave up scraping {}, last got:\n{}", url, last);
+}
+
+#[tokio::test]
+async fn one_series_per_user() {
+    let registry = common::registry(42, 100);
+    let locked = format!("Basic {}", base64::encode("locked:secret"));
+    let mock = MockServer::start(move |req| match req.path.as_str() {
+        "/token" if req.header("authorization") == Some(locked.as_str()) => Reply::status(401),
+        _ => registry(req),
+    })
+    .await;
+    let cli = Cli::new(&mock);
+
+    let addr = free_addr();
+    let args = [
+        "serve",
+        addr.as_str(),
+        "--users-from-stdin",
+        "--password-env-prefix",
+        "PASS_",
+        "-q",
+    ];
+    let mut child = start(&cli, &args, "ci-bot\nlocked\n").await;
+
+    let url = format!("http://{}/metrics", addr);
+    // every identity has a series from the start, only the first checks fill them in
+    let checked =
+        |b: &str| b.contains("up{user=\"ci-bot\"} 1") && b.contains("total{user=\"locked\"} 1");
+    let body = scrape_until(&url, checked).await;
+    assert!(body.contains("dockerhub_ratelimit_remaining{user=\"ci-bot\"} 42\n"));
+    assert!(!body.contains("dockerhub_ratelimit_remaining{user=\"locked\"}"));
+    assert!(body.contains("dockerhub_ratelimit_up{user=\"ci-bot\"} 1\n"));
+    assert!(body.contains("dockerhub_ratelimit_up{user=\"locked\"} 0\n"));
+    assert!(body.contains("dockerhub_ratelimit_check_errors_total{user=\"locked\"} 1\n"));
+    assert_eq!(
+        body.matches("# TYPE dockerhub_ratelimit_up gauge").count(),
+        1
+    );
+
+    child.kill().await.unwrap();
+}
+
+#[tokio::test]
+async fn missing_password_fails_before_serving() {
+    let mock = MockServer::start(common::registry(42, 100)).await;
+    let cli = Cli::new(&mock);
+
+    let addr = free_addr();
+    let args = [
+        "serve",
+        addr.as_str(),
+        "--users-from-stdin",
+        "--password-env-prefix",
+        "PASS_",
+    ];
+    let child = start(&cli, &args, "ci-bot\nnobody\n").await;
+    let out = child.wait_with_output().await.unwrap();
+    assert_eq!(out.status.code(), Some(6));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("no password for nobody"), "{}", stderr);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn hooks_run_on_a_breach() {
+    let mock = MockServer::start(common::registry(5, 100)).await;
+    let cli = Cli::new(&mock);
+
+    let breached = cli.dir().join("breached");
+    let hook = format!(
+        "echo \"$DRL_USER $DRL_REMAINING\" >> '{}'",
+        breached.display()
+    );
+    let addr = free_addr();
+    let args = [
+        "serve",
+        addr.as_str(),
+        "--users-from-stdin",
+        "--password-env-prefix",
+        "PASS_",
+        "--serve-interval",
+        "1s",
+        "--notify-below",
+        "10",
+        "--on-breach",
+        &hook,
+        "-q",
+    ];
+    let mut child = start(&cli, &args, "ci-bot\n").await;
+
+    let url = format!("http://{}/metrics", addr);
+    scrape_until(&url, |b| b.contains("up{user=\"ci-bot\"} 1")).await;
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !breached.exists() && Instant::now() < deadline {
+        time::sleep(Duration::from_millis(50)).await;
+    }
+    // a few more polls, still breached, don't run it again
+    time::sleep(Duration::from_millis(2500)).await;
+    child.kill().await.unwrap();
+
+    let breached = std::fs::read_to_string(&breached).unwrap();
+    assert_eq!(breached, "ci-bot 5\n");
+}
+
+#[tokio::test]
+async fn notify_below_needs_a_hook_when_serving() {
+    let mock = MockServer::start(common::registry(42, 100)).await;
+    let cli = Cli::new(&mock);
+
+    let addr = free_addr();
+    let args = [
+        "serve",
+        addr.as_str(),
+        "--anonymous",
+        "--notify-below",
+        "10",
+    ];
+    let out = cli.run(&args).await;
+    assert_eq!(out.code, 6, "{}", out.stderr);
+    assert!(
+        out.stderr
+            .contains("--notify-below needs --on-breach or --on-recover with --serve"),
+        "{}",
+        out.stderr
+    );
+}
@@ -0,0 +1,264 @@
+//! The spans and events of the `tracing` feature, seen by a subscriber recording them
+
+#![cfg(feature = "tracing")]
+
+mod common;
+
+use common::{MockServer, Reply};
+use libdocker_rl::api::DrlClient;
+use libdocker_rl::retry::RetryPolicy;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+use tracing_core::dispatcher::{self, Dispatch};
+use tracing_core::span::Current;
+
+/// Name and fields of a span or event, the fields as they were recorded
+#[derive(Debug, Clone, Default)]
+struct Recorded {
+    name: String,
+    fields: BTreeMap<String, String>,
+    /// Where it was declared, for spans
+    metadata: Option<&'static Metadata<'static>>,
+}
+
+impl Recorded {
+    fn field(&self, name: &str) -> Option<&str> {
+        self.fields.get(name).map(String::as_str)
+    }
+}
+
+impl Visit for Recorded {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.fields
+            .insert(field.name().to_string(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.fields
+            .insert(field.name().to_string(), format!("{:?}", value));
+    }
+}
+
+#[derive(Default)]
+struct State {
+    next: AtomicU64,
+    spans: Mutex<BTreeMap<u64, Recorded>>,
+    events: Mutex<Vec<Recorded>>,
+    /// Spans entered on the test's single thread, innermost last
+    entered: Mutex<Vec<u64>>,
+}
+
+/// A subscriber keeping every span and event, at every level
+#[derive(Clone, Default)]
+struct Recorder {
+    state: Arc<State>,
+}
+
+impl Recorder {
+    /// Spans named `name`, in the order they were opened
+    fn spans(&self, name: &str) -> Vec<Recorded> {
+        let spans = self.state.spans.lock().unwrap();
+        spans.values().filter(|s| s.name == name).cloned().collect()
+    }
+
+    /// Events whose message is `message`, the span they were in as their name
+    fn events(&self, message: &str) -> Vec<Recorded> {
+        let events = self.state.events.lock().unwrap();
+        let found = events
+            .iter()
+            .filter(|e| e.field("message") == Some(message));
+        found.cloned().collect()
+    }
+
+    /// Every value of every span and event
+    fn values(&self) -> Vec<String> {
+        let spans = self.state.spans.lock().unwrap();
+        let events = self.state.events.lock().unwrap();
+        let all = spans.values().chain(events.iter());
+        all.flat_map(|r| r.fields.values().cloned()).collect()
+    }
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let id = self.state.next.fetch_add(1, Ordering::SeqCst) + 1;
+        let mut recorded = Recorded {
+            name: span.metadata().name().to_string(),
+            metadata: Some(span.metadata()),
+            ..Recorded::default()
+        };
+        span.record(&mut recorded);
+        self.state.spans.lock().unwrap().insert(id, recorded);
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        if let Some(s) = self.state.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            values.record(s);
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let stack = self.state.entered.lock().unwrap();
+        let spans = self.state.spans.lock().unwrap();
+        let name = stack.last().and_then(|id| spans.get(id));
+        let mut recorded = Recorded {
+            name: name.map(|s| s.name.clone()).unwrap_or_default(),
+            ..Recorded::default()
+        };
+        event.record(&mut recorded);
+        self.state.events.lock().unwrap().push(recorded);
+    }
+
+    fn enter(&self, span: &Id) {
+        self.state.entered.lock().unwrap().push(span.into_u64());
+    }
+
+    fn exit(&self, span: &Id) {
+        let mut stack = self.state.entered.lock().unwrap();
+        if let Some(at) = stack.iter().rposition(|id| *id == span.into_u64()) {
+            stack.remove(at);
+        }
+    }
+
+    fn current_span(&self) -> Current {
+        let stack = self.state.entered.lock().unwrap();
+        let spans = self.state.spans.lock().unwrap();
+        let current = stack
+            .last()
+            .and_then(|id| Some((*id, spans.get(id)?.metadata?)));
+        match current {
+            Some((id, metadata)) => Current::new(Id::from_u64(id), metadata),
+            None => Current::none(),
+        }
+    }
+}
+
+/// Runs `f` on a runtime of this thread with `recorder` as the default subscriber
+fn recorded<F, Fut>(recorder: &Recorder, f: F)
+where
+    F: FnOnce() -> Fut,
+    Fut: std::future::Future<Output = ()>,
+{
+    let dispatch = Dispatch::new(recorder.clone());
+    dispatcher::with_default(&dispatch, || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(f());
+    });
+}
+
+#[test]
+fn anonymous_token_and_limit_spans() {
+    let recorder = Recorder::default();
+    recorded(&recorder, || async {
+        let mock = MockServer::start(common::registry(42, 100)).await;
+        let client = mock.client();
+        let token = client.token(None).await.unwrap();
+        client.limit(&token).await.unwrap();
+    });
+
+    let tokens = recorder.spans("get_anon_token");
+    assert_eq!(tokens.len(), 1, "{:?}", tokens);
+    assert_eq!(tokens[0].field("registry"), Some("127.0.0.1"));
+    assert_eq!(tokens[0].field("anonymous"), Some("true"));
+    assert_eq!(tokens[0].field("status"), Some("200"));
+    assert!(tokens[0].field("elapsed_ms").is_some(), "{:?}", tokens[0]);
+
+    let limits = recorder.spans("get_limit");
+    assert_eq!(limits.len(), 1, "{:?}", limits);
+    assert_eq!(limits[0].field("registry"), Some("127.0.0.1"));
+    assert_eq!(limits[0].field("method"), Some("HEAD"));
+    assert_eq!(limits[0].field("status"), Some("200"));
+    assert!(limits[0].field("elapsed_ms").is_some(), "{:?}", limits[0]);
+
+    // every request is logged in the span it was sent from
+    let sent = recorder.events("sending");
+    let from: Vec<&str> = sent.iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(from, ["get_anon_token", "get_limit"]);
+}
+
+#[test]
+fn userpass_token_records_no_secrets() {
+    let recorder = Recorder::default();
+    recorded(&recorder, || async {
+        let mock = MockServer::start(common::registry(42, 100)).await;
+        let client = mock.client();
+        let token = client.token(Some(("ci-bot", "hunter2"))).await.unwrap();
+        client.limit(&token).await.unwrap();
+    });
+
+    let tokens = recorder.spans("get_userpass_token");
+    assert_eq!(tokens.len(), 1, "{:?}", tokens);
+    assert_eq!(tokens[0].field("registry"), Some("127.0.0.1"));
+    assert_eq!(tokens[0].field("anonymous"), Some("false"));
+    assert_eq!(tokens[0].field("user"), Some("ci-bot"));
+    assert_eq!(tokens[0].field("status"), Some("200"));
+    assert!(!tokens[0].fields.contains_key("pass"), "{:?}", tokens[0]);
+
+    // the headers are recorded at `trace`, so the credentials would be there if anywhere
+    let headers = recorder.events("request headers");
+    assert!(headers.iter().any(|e| e
+        .field("headers")
+        .unwrap_or_default()
+        .contains("authorization: [redacted]")));
+
+    let basic = base64::encode("ci-bot:hunter2");
+    for value in recorder.values() {
+        for secret in ["hunter2", basic.as_str(), "mock-token"] {
+            assert!(!value.contains(secret), "{} recorded in {}", secret, value);
+        }
+    }
+}
+
+#[test]
+fn retries_and_refreshes_are_events() {
+    let recorder = Recorder::default();
+    recorded(&recorder, || async {
+        let registry = common::registry(42, 100);
+        let failed = AtomicBool::new(false);
+        let mock = MockServer::start(move |req| {
+            if req.path.ends_with("/manifests/latest") && !failed.swap(true, Ordering::SeqCst) {
+                return Reply::status(503);
+            }
+            registry(req)
+        })
+        .await;
+        let retry = RetryPolicy {
+            retries: 1,
+            backoff: Duration::from_millis(1),
+        };
+        let client = DrlClient::builder()
+            .registry(mock.registry())
+            .retry(retry)
+            .build()
+            .unwrap();
+        let mut provider = client.provider(None);
+        let token = provider.token().await.unwrap();
+        client.limit(token).await.unwrap();
+    });
+
+    let refreshes = recorder.events("requesting a new token");
+    assert_eq!(refreshes.len(), 1, "{:?}", refreshes);
+    assert_eq!(refreshes[0].field("anonymous"), Some("true"));
+
+    let retries = recorder.events("retrying");
+    assert_eq!(retries.len(), 1, "{:?}", retries);
+    assert_eq!(retries[0].name, "get_limit");
+    let reason = retries[0].field("reason").unwrap_or_default();
+    assert!(reason.contains("503"), "{}", reason);
+}
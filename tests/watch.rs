@@ -0,0 +1,327 @@
+//! `--watch` through the binary against a mock registry, stopped and reloaded with signals
+
+#![cfg(unix)]
+
+mod common;
+
+use common::{Cli, MockServer, Reply, Request};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Waits until `mock` has received `count` requests for `path`
+async fn requests_for(mock: &MockServer, path: &str, count: usize) -> Vec<Request> {
+    for _ in 0..100 {
+        let found: Vec<_> = mock
+            .requests()
+            .into_iter()
+            .filter(|r| r.path == path)
+            .collect();
+        if found.len() >= count {
+            return found;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("no {} requests for {}", count, path);
+}
+
+/// A registry reporting `remaining` out of 100 at each check, and the last once they run out
+async fn counting_down(remaining: &'static [u64]) -> MockServer {
+    let manifests = AtomicUsize::new(0);
+    let registry = common::registry(42, 100);
+    MockServer::start(move |req| {
+        if !req.path.ends_with("/manifests/latest") {
+            return registry(req);
+        }
+        let at = manifests.fetch_add(1, Ordering::SeqCst);
+        Reply::limit(remaining[at.min(remaining.len() - 1)], 100)
+    })
+    .await
+}
+
+/// Sends `signal` to the process `pid`
+fn kill(pid: u32, signal: libc::c_int) {
+    // a child of this process, which hasn't been waited for yet
+    assert_eq!(unsafe { libc::kill(pid as libc::pid_t, signal) }, 0);
+}
+
+#[tokio::test]
+async fn reloaded_on_sighup_and_summarized_on_sigterm() {
+    let mock = MockServer::start(common::registry(42, 100)).await;
+    let cli = Cli::new(&mock);
+    let pass_file = cli.dir().join("pass");
+    std::fs::write(&pass_file, "first\n").unwrap();
+
+    let args = [
+        "--watch",
+        "--interval",
+        "1h",
+        "--user",
+        "ci-bot",
+        "--pass-file",
+        pass_file.to_str().unwrap(),
+    ];
+    let child = cli.command(&args).stdin(Stdio::null()).spawn().unwrap();
+    let pid = child.id().unwrap();
+    requests_for(&mock, "/v2/ratelimitpreview/test/manifests/latest", 1).await;
+
+    std::fs::write(&pass_file, "second\n").unwrap();
+    kill(pid, libc::SIGHUP);
+    let tokens = requests_for(&mock, "/token", 2).await;
+    // ci-bot:first, then ci-bot:second
+    assert_eq!(
+        tokens[0].header("authorization"),
+        Some("Basic Y2ktYm90OmZpcnN0")
+    );
+    assert_eq!(
+        tokens[1].header("authorization"),
+        Some("Basic Y2ktYm90OnNlY29uZA==")
+    );
+    requests_for(&mock, "/v2/ratelimitpreview/test/manifests/latest", 2).await;
+
+    kill(pid, libc::SIGTERM);
+    let out = child.wait_with_output().await.unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(out.status.code(), Some(0), "{}", stderr);
+    assert!(
+        stderr.contains("reloaded credentials, checking as ci-bot"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.ends_with("stopped after 2 polls, remaining min 42 max 42, 0 consumed (~0/h)\n"),
+        "{}",
+        stderr
+    );
+    assert_eq!(String::from_utf8_lossy(&out.stdout).lines().count(), 2);
+}
+
+#[tokio::test]
+async fn json_lines_with_failures_on_stdout() {
+    use serde_json::Value;
+    use std::sync::atomic::AtomicBool;
+
+    let failed = AtomicBool::new(false);
+    let registry = common::registry(42, 100);
+    let mock = MockServer::start(move |req| {
+        if req.path.ends_with("/manifests/latest") && !failed.swap(true, Ordering::SeqCst) {
+            return Reply::status(503);
+        }
+        registry(req)
+    })
+    .await;
+    let cli = Cli::new(&mock);
+
+    let args = ["--watch", "--interval", "1s", "--format", "json"];
+    let child = cli.command(&args).spawn().unwrap();
+    let pid = child.id().unwrap();
+    requests_for(&mock, "/v2/ratelimitpreview/test/manifests/latest", 2).await;
+    // the second check may still be printing
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    kill(pid, libc::SIGTERM);
+    let out = child.wait_with_output().await.unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(out.status.code(), Some(0), "{}", stderr);
+    assert!(stderr.starts_with("stopped after 2 polls"), "{}", stderr);
+
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let lines: Vec<Value> = stdout
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 3, "{}", stdout);
+    for line in &lines[..2] {
+        assert_eq!(line["schema_version"], 1);
+        assert!(line["checked_at"].is_string(), "{}", line);
+        assert!(line["user"].is_null(), "{}", line);
+    }
+    assert!(lines[0]["error"].as_str().unwrap().contains("503"));
+    assert!(lines[0].get("remaining").is_none(), "{}", lines[0]);
+    assert_eq!(lines[1]["remaining"], 42);
+    assert!(lines[1].get("error").is_none(), "{}", lines[1]);
+    // and the summary last
+    assert_eq!(lines[2]["summary"]["polls"], 2);
+    assert_eq!(lines[2]["summary"]["errors"], 1);
+}
+
+#[cfg(not(feature = "desktop-notify"))]
+#[tokio::test]
+async fn notify_below_needs_the_feature() {
+    let mock = MockServer::start(common::registry(42, 100)).await;
+    let cli = Cli::new(&mock);
+
+    let out = cli.run(&["--watch", "--notify-below", "10"]).await;
+    assert_eq!(out.code, 6, "{}", out.stderr);
+    assert!(out.stderr.contains("desktop-notify"), "{}", out.stderr);
+    // it fails before the first check
+    let checks = mock.requests();
+    assert!(!checks.iter().any(|r| r.path.contains("/manifests/")));
+}
+
+#[cfg(all(feature = "desktop-notify", target_os = "linux"))]
+#[tokio::test]
+async fn notified_once_per_crossing() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mock = counting_down(&[50, 8, 5, 20, 9]).await;
+    let cli = Cli::new(&mock);
+
+    // a notify-send writing down what it was asked to show
+    let bin = cli.dir().join("bin");
+    std::fs::create_dir_all(&bin).unwrap();
+    let shown = cli.dir().join("shown");
+    let stub = bin.join("notify-send");
+    let script = format!("#!/bin/sh\necho \"$@\" >> '{}'\n", shown.display());
+    std::fs::write(&stub, script).unwrap();
+    std::fs::set_permissions(&stub, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!(
+        "{}:{}",
+        bin.display(),
+        std::env::var("PATH").unwrap_or_default()
+    );
+
+    let args = ["--watch", "--interval", "1s", "--notify-below", "10"];
+    let mut cmd = cli.command(&args);
+    let child = cmd.env("PATH", path).spawn().unwrap();
+    let pid = child.id().unwrap();
+    requests_for(&mock, "/v2/ratelimitpreview/test/manifests/latest", 5).await;
+    // the last check may still be notifying
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    kill(pid, libc::SIGTERM);
+    let out = child.wait_with_output().await.unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(out.status.code(), Some(0), "{}", stderr);
+    let shown = std::fs::read_to_string(&shown).unwrap();
+    let shown: Vec<&str> = shown.lines().collect();
+    assert_eq!(
+        shown,
+        [
+            "--app-name docker-rl -- docker-rl: pulls running low \
+             anonymous has 8/100 pulls left, below 10",
+            "--app-name docker-rl -- docker-rl: pulls running low \
+             anonymous has 9/100 pulls left, below 10",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn hooks_once_per_crossing() {
+    let mock = counting_down(&[50, 8, 5, 20, 9]).await;
+    let cli = Cli::new(&mock);
+
+    let crossed = cli.dir().join("crossed");
+    let breach = format!(
+        "echo \"breach $DRL_REMAINING/$DRL_TOTAL<$DRL_THRESHOLD [$DRL_USER]\" >> '{}'",
+        crossed.display()
+    );
+    let recover = format!(
+        "echo \"recover $DRL_REMAINING\" >> '{}'; echo 'pager is down' >&2; exit 2",
+        crossed.display()
+    );
+    let args = [
+        "--watch",
+        "--interval",
+        "1s",
+        "--notify-below",
+        "10",
+        "--on-breach",
+        &breach,
+        "--on-recover",
+        &recover,
+    ];
+    let child = cli.command(&args).spawn().unwrap();
+    let pid = child.id().unwrap();
+    requests_for(&mock, "/v2/ratelimitpreview/test/manifests/latest", 5).await;
+    // the last hook may still be running
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    kill(pid, libc::SIGTERM);
+    let out = child.wait_with_output().await.unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    // a failing hook only warns
+    assert_eq!(out.status.code(), Some(0), "{}", stderr);
+    assert!(
+        stderr.contains("warning: --on-recover hook failed: exit status: 2: pager is down\n"),
+        "{}",
+        stderr
+    );
+    assert_eq!(stderr.matches("hook failed").count(), 1, "{}", stderr);
+
+    let crossed = std::fs::read_to_string(&crossed).unwrap();
+    let crossed: Vec<&str> = crossed.lines().collect();
+    assert_eq!(
+        crossed,
+        ["breach 8/100<10 []", "recover 20", "breach 9/100<10 []"]
+    );
+}
+
+#[tokio::test]
+async fn adaptive_says_why() {
+    let mock = counting_down(&[50, 40]).await;
+    let cli = Cli::new(&mock);
+
+    let args = ["--watch", "--adaptive", "--min-interval", "1s", "-v"];
+    let child = cli.command(&args).spawn().unwrap();
+    let pid = child.id().unwrap();
+    // without a burn rate yet the checks are as close as allowed
+    requests_for(&mock, "/v2/ratelimitpreview/test/manifests/latest", 2).await;
+
+    kill(pid, libc::SIGTERM);
+    let out = child.wait_with_output().await.unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(out.status.code(), Some(0), "{}", stderr);
+    assert!(
+        stderr.contains("next check in 1s, not enough checks for a burn rate yet\n"),
+        "{}",
+        stderr
+    );
+}
+
+#[tokio::test]
+async fn ends_after_the_duration_with_a_summary() {
+    use libdocker_rl::schema::SCHEMA;
+    use serde_json::Value;
+
+    let mock = counting_down(&[50, 45, 40]).await;
+    let cli = Cli::new(&mock);
+
+    let args = [
+        "--watch",
+        "--interval",
+        "1s",
+        "--duration",
+        "2500ms",
+        "--format",
+        "json",
+    ];
+    let out = tokio::time::timeout(Duration::from_secs(10), cli.run(&args))
+        .await
+        .expect("the watch didn't end");
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    assert!(
+        out.stderr
+            .starts_with("stopped after 3 polls, remaining min 40 max 50, 10 consumed (~"),
+        "{}",
+        out.stderr
+    );
+
+    let schema: Value = serde_json::from_str(SCHEMA).unwrap();
+    let lines: Vec<Value> = out
+        .stdout
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 4, "{}", out.stdout);
+    for line in &lines {
+        let wrong = common::schema::violations(&schema, line);
+        assert!(wrong.is_empty(), "{}: {:?}", line, wrong);
+    }
+    let summary = &lines[3]["summary"];
+    assert_eq!(summary["polls"], 3);
+    assert_eq!(summary["consumed"], 10);
+    // 10 pulls in about two seconds
+    let per_hour = summary["consumed_per_hour"].as_f64().unwrap();
+    assert!(per_hour > 10_000.0, "{}", summary);
+}